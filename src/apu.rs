@@ -0,0 +1,1787 @@
+/*
+
+The APU (Audio Processing Unit) lives inside the 2A03 alongside the CPU and is clocked at the CPU rate.
+
+- Pulse 1 and 2: square waves with a configurable duty cycle, volume envelope and frequency sweep.
+- Triangle: a fixed volume triangle wave, silenced by its linear counter.
+- Noise: pseudo-random output from a linear feedback shift register.
+- DMC (Delta Modulation Channel): plays 1-bit delta encoded samples that are fetched straight from CPU memory.
+  Each fetch is performed by the bus on behalf of the APU, and halts the CPU for a few cycles while it happens.
+- Length counters: automatically silence the pulse, triangle and noise channels after a programmed duration.
+- Frame counter: a sequencer configured through $4017 that generates the "quarter frame" and "half frame" signals
+  that clock the envelopes, sweeps and length counters of the channels. In 4-step mode it can also raise an IRQ.
+
+The output of all channels is mixed on every CPU cycle, and then downsampled to the configured output sample rate.
+Output samples are buffered until someone (the audio backend, a WAV writer, a test...) collects them with take_samples.
+At 44.1kHz that is about 735 samples per 60Hz frame (44100 / 60), or 733.8 for an exact NTSC frame of 29780.5 CPU cycles.
+
+Reference: https://www.nesdev.org/wiki/APU
+
+*/
+
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use crate::{device::{Device, DeviceError, private::Sealed}, wav_writer::WavWriter, audio_filter::OutputFilterChain};
+
+pub const APU_MEMORY_BOUNDS: (u16, u16) = (0x4000, 0x4013);
+pub const APU_STATUS_ADDR: u16 = 0x4015;
+pub const FRAME_COUNTER_ADDR: u16 = 0x4017;
+
+// NTSC's, see cpu_clock_rate for PAL consoles
+pub const CPU_CLOCK_RATE: f64 = 1789773.0;
+pub const DEFAULT_OUTPUT_SAMPLE_RATE: u32 = 44100;
+
+// Number of CPU cycles the CPU is halted for while the DMC reads a sample byte
+pub const DMC_DMA_STALL_CYCLES: u8 = 4;
+
+// Periods (in CPU cycles) between output level updates, for NTSC consoles
+// Reference: https://www.nesdev.org/wiki/APU_DMC
+const DMC_RATE_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+// Reference: https://www.nesdev.org/wiki/APU_Pulse
+const PULSE_DUTY_TABLE: [[u8; 8]; 4] = [
+  [0, 1, 0, 0, 0, 0, 0, 0],
+  [0, 1, 1, 0, 0, 0, 0, 0],
+  [0, 1, 1, 1, 1, 0, 0, 0],
+  [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Reference: https://www.nesdev.org/wiki/APU_Triangle
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+  15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+  0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+// Periods (in CPU cycles) of the noise timer, for NTSC consoles
+// Reference: https://www.nesdev.org/wiki/APU_Noise
+const NOISE_PERIOD_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+
+// Values loaded into the length counters, indexed by the upper 5 bits of $4003/$4007/$400B/$400F
+// Reference: https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_COUNTER_TABLE: [u8; 32] = [
+  10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+  12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+// Indices of each channel's length counter, which also match their bits in $4015
+pub const PULSE_1: usize = 0;
+pub const PULSE_2: usize = 1;
+pub const TRIANGLE: usize = 2;
+pub const NOISE: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+  Pulse1,
+  Pulse2,
+  Triangle,
+  Noise,
+  Dmc,
+}
+
+impl Channel {
+  pub const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+  pub fn name(&self) -> &'static str {
+    return match self {
+      Channel::Pulse1 => "Pulse 1",
+      Channel::Pulse2 => "Pulse 2",
+      Channel::Triangle => "Triangle",
+      Channel::Noise => "Noise",
+      Channel::Dmc => "DMC",
+    };
+  }
+}
+
+// Settings that only affect the mixer, so that the emulation itself (and its timing) stays the same
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MixerSettings {
+  pub muted: bool,
+  pub master_volume: f32,
+  // Indexed by Channel
+  pub channel_muted: [bool; 5],
+  // Use the linear approximation instead of the lookup tables, mostly for comparing both
+  pub linear_mixing: bool,
+  // Emulates the high-pass and low-pass filters of the console's audio output
+  pub output_filters_enabled: bool,
+}
+
+impl Default for MixerSettings {
+  fn default() -> Self {
+    return MixerSettings {
+      muted: false,
+      master_volume: 1.0,
+      channel_muted: [false; 5],
+      linear_mixing: false,
+      output_filters_enabled: true,
+    }
+  }
+}
+
+// The DACs of the 2A03 aren't linear, and the channels affect each other's loudness. The lookup tables
+// below implement the same formulas used by the hardware, indexed by the (integer) sum of the channel outputs.
+// Reference: https://www.nesdev.org/wiki/APU_Mixer
+#[derive(Clone)]
+pub struct MixerTables {
+  pulse_table: [f32; 31],
+  tnd_table: [f32; 203],
+}
+
+impl MixerTables {
+  pub fn new() -> MixerTables {
+    let mut pulse_table = [0.0; 31];
+    for n in 1..pulse_table.len() {
+      pulse_table[n] = 95.52 / (8128.0 / n as f32 + 100.0);
+    }
+    let mut tnd_table = [0.0; 203];
+    for n in 1..tnd_table.len() {
+      tnd_table[n] = 163.67 / (24329.0 / n as f32 + 100.0);
+    }
+    return MixerTables { pulse_table, tnd_table };
+  }
+
+  // Mixes the outputs of all channels (pulse 1, pulse 2, triangle, noise and DMC) into a value in the range 0.0-1.0
+  pub fn mix(&self, pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse_out = self.pulse_table[(pulse_1 + pulse_2) as usize];
+    let tnd_out = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+    return pulse_out + tnd_out;
+  }
+
+  // Linear approximation of the mix, louder than the tables for the triangle and DMC
+  pub fn linear_mix(pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse_out = 0.00752 * (pulse_1 + pulse_2) as f32;
+    let tnd_out = 0.00851 * triangle as f32 + 0.00494 * noise as f32 + 0.00335 * dmc as f32;
+    return pulse_out + tnd_out;
+  }
+}
+
+// Frame counter step timings (in CPU cycles since the start of the sequence), for NTSC consoles
+// Reference: https://www.nesdev.org/wiki/APU_Frame_Counter
+const FRAME_COUNTER_QUARTER_1: u32 = 7457;
+const FRAME_COUNTER_HALF_1: u32 = 14913;
+const FRAME_COUNTER_QUARTER_3: u32 = 22371;
+const FRAME_COUNTER_4_STEP_LAST: u32 = 29829;
+const FRAME_COUNTER_4_STEP_PERIOD: u32 = 29830;
+const FRAME_COUNTER_5_STEP_LAST: u32 = 37281;
+const FRAME_COUNTER_5_STEP_PERIOD: u32 = 37282;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameSignal {
+  pub quarter_frame: bool,
+  pub half_frame: bool,
+}
+
+#[derive(Clone)]
+pub struct FrameCounter {
+  five_step_mode: bool,
+  irq_inhibit: bool,
+  cycle: u32,
+
+  // A write to $4017 resets the sequence 3 or 4 CPU cycles later, depending on the cycle it happened on
+  reset_delay: u8,
+  odd_cycle: bool,
+
+  pub irq_pending: bool,
+}
+
+impl FrameCounter {
+  fn new() -> FrameCounter {
+    return FrameCounter {
+      five_step_mode: false,
+      irq_inhibit: false,
+      cycle: 0,
+      reset_delay: 0,
+      odd_cycle: false,
+      irq_pending: false,
+    }
+  }
+
+  // The sequence starts over and the interrupt is cleared, but the mode last written to $4017 is kept
+  fn reset(&mut self) {
+    self.cycle = 0;
+    self.reset_delay = 0;
+    self.irq_pending = false;
+  }
+
+  // Writing with bit 7 set clocks both the quarter and half frame units immediately
+  fn write(&mut self, data: u8) -> FrameSignal {
+    self.five_step_mode = (data & 0x80) != 0;
+    self.irq_inhibit = (data & 0x40) != 0;
+    if self.irq_inhibit {
+      self.irq_pending = false;
+    }
+    self.reset_delay = if self.odd_cycle { 4 } else { 3 };
+
+    return FrameSignal {
+      quarter_frame: self.five_step_mode,
+      half_frame: self.five_step_mode,
+    };
+  }
+
+  fn clock_cycle(&mut self) -> FrameSignal {
+    self.odd_cycle = !self.odd_cycle;
+    if self.reset_delay > 0 {
+      self.reset_delay -= 1;
+      if self.reset_delay == 0 {
+        self.cycle = 0;
+      }
+    }
+
+    self.cycle += 1;
+    let mut signal = FrameSignal::default();
+    match self.cycle {
+      FRAME_COUNTER_QUARTER_1 | FRAME_COUNTER_QUARTER_3 => {
+        signal.quarter_frame = true;
+      },
+      FRAME_COUNTER_HALF_1 => {
+        signal.quarter_frame = true;
+        signal.half_frame = true;
+      },
+      _ => {}
+    }
+
+    if self.five_step_mode {
+      if self.cycle == FRAME_COUNTER_5_STEP_LAST {
+        signal.quarter_frame = true;
+        signal.half_frame = true;
+      } else if self.cycle == FRAME_COUNTER_5_STEP_PERIOD {
+        self.cycle = 0;
+      }
+    } else {
+      // The frame interrupt flag is set during the last 3 cycles of the sequence
+      if self.cycle >= FRAME_COUNTER_4_STEP_LAST - 1 && !self.irq_inhibit {
+        self.irq_pending = true;
+      }
+      if self.cycle == FRAME_COUNTER_4_STEP_LAST {
+        signal.quarter_frame = true;
+        signal.half_frame = true;
+      } else if self.cycle == FRAME_COUNTER_4_STEP_PERIOD {
+        self.cycle = 0;
+      }
+    }
+    return signal;
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LengthCounter {
+  enabled: bool,
+  pub halt: bool,
+  pub counter: u8,
+}
+
+impl LengthCounter {
+  fn new() -> LengthCounter {
+    return LengthCounter {
+      enabled: false,
+      halt: false,
+      counter: 0,
+    }
+  }
+
+  fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+    if !enabled {
+      self.counter = 0;
+    }
+  }
+
+  // Loads are ignored while the channel is disabled through $4015
+  fn load(&mut self, table_index: u8) {
+    if self.enabled {
+      self.counter = LENGTH_COUNTER_TABLE[(table_index & 0x1F) as usize];
+    }
+  }
+
+  fn clock(&mut self) {
+    if !self.halt && self.counter > 0 {
+      self.counter -= 1;
+    }
+  }
+}
+
+// Reference: https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+  start: bool,
+  divider: u8,
+  decay_level: u8,
+
+  loop_flag: bool,
+  constant_volume: bool,
+  // Used both as the constant volume and as the divider period
+  volume: u8,
+}
+
+impl Envelope {
+  fn new() -> Envelope {
+    return Envelope {
+      start: false,
+      divider: 0,
+      decay_level: 0,
+      loop_flag: false,
+      constant_volume: false,
+      volume: 0,
+    }
+  }
+
+  fn write_control(&mut self, data: u8) {
+    self.loop_flag = (data & 0x20) != 0;
+    self.constant_volume = (data & 0x10) != 0;
+    self.volume = data & 0x0F;
+  }
+
+  fn restart(&mut self) {
+    self.start = true;
+  }
+
+  fn clock(&mut self) {
+    if self.start {
+      self.start = false;
+      self.decay_level = 15;
+      self.divider = self.volume;
+    } else if self.divider == 0 {
+      self.divider = self.volume;
+      if self.decay_level > 0 {
+        self.decay_level -= 1;
+      } else if self.loop_flag {
+        self.decay_level = 15;
+      }
+    } else {
+      self.divider -= 1;
+    }
+  }
+
+  pub fn output(&self) -> u8 {
+    if self.constant_volume {
+      return self.volume;
+    }
+    return self.decay_level;
+  }
+}
+
+// Reference: https://www.nesdev.org/wiki/APU_Sweep
+#[derive(Clone, Copy, Debug)]
+pub struct Sweep {
+  pub enabled: bool,
+  pub period: u8,
+  pub negate: bool,
+  pub shift: u8,
+  divider: u8,
+  reload: bool,
+  // Pulse 1 negates with one's complement (subtracting an extra 1), pulse 2 with two's complement
+  ones_complement: bool,
+}
+
+impl Sweep {
+  fn new(ones_complement: bool) -> Sweep {
+    return Sweep {
+      enabled: false,
+      period: 0,
+      negate: false,
+      shift: 0,
+      divider: 0,
+      reload: false,
+      ones_complement,
+    }
+  }
+
+  fn write_control(&mut self, data: u8) {
+    self.enabled = (data & 0x80) != 0;
+    self.period = (data >> 4) & 0x07;
+    self.negate = (data & 0x08) != 0;
+    self.shift = data & 0x07;
+    self.reload = true;
+  }
+
+  // The target period is computed continuously, even when the sweep is disabled
+  fn target_period(&self, timer_period: u16) -> u16 {
+    let change = timer_period >> self.shift;
+    if self.negate {
+      let change = if self.ones_complement { change + 1 } else { change };
+      return timer_period.saturating_sub(change);
+    }
+    return timer_period + change;
+  }
+
+  // The channel is silenced when its period is too low, or when the sweep would take it out of range
+  fn is_muting(&self, timer_period: u16) -> bool {
+    return timer_period < 8 || self.target_period(timer_period) > 0x7FF;
+  }
+
+  // Clocked by the half frame signal
+  fn clock(&mut self, timer_period: &mut u16) {
+    if self.divider == 0 && self.enabled && self.shift != 0 && !self.is_muting(*timer_period) {
+      *timer_period = self.target_period(*timer_period);
+    }
+    if self.divider == 0 || self.reload {
+      self.divider = self.period;
+      self.reload = false;
+    } else {
+      self.divider -= 1;
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct PulseChannel {
+  duty: u8,
+  sequence_step: u8,
+  // Clocked every other CPU cycle
+  timer: u16,
+  pub timer_period: u16,
+  pub envelope: Envelope,
+  pub sweep: Sweep,
+}
+
+impl PulseChannel {
+  fn new(sweep: Sweep) -> PulseChannel {
+    return PulseChannel {
+      duty: 0,
+      sequence_step: 0,
+      timer: 0,
+      timer_period: 0,
+      envelope: Envelope::new(),
+      sweep,
+    }
+  }
+
+  // Receives the register offset within the channel (0-3)
+  fn write_register(&mut self, register: u16, data: u8) {
+    match register {
+      0 => {
+        self.duty = data >> 6;
+        self.envelope.write_control(data);
+      },
+      1 => {
+        self.sweep.write_control(data);
+      },
+      2 => {
+        self.timer_period = (self.timer_period & 0x0700) | (data as u16);
+      },
+      3 => {
+        self.timer_period = (self.timer_period & 0x00FF) | (((data & 0x07) as u16) << 8);
+        self.sequence_step = 0;
+        self.envelope.restart();
+      },
+      _ => {}
+    }
+  }
+
+  fn clock_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      self.sequence_step = (self.sequence_step + 1) % 8;
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  fn output(&self, length_counter: &LengthCounter) -> u8 {
+    if length_counter.counter == 0 || self.sweep.is_muting(self.timer_period) || PULSE_DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0 {
+      return 0;
+    }
+    return self.envelope.output();
+  }
+}
+
+#[derive(Clone)]
+pub struct TriangleChannel {
+  sequence_step: u8,
+  timer: u16,
+  pub timer_period: u16,
+
+  // Reference: https://www.nesdev.org/wiki/APU_Triangle
+  control_flag: bool,
+  linear_counter_reload_value: u8,
+  linear_counter_reload: bool,
+  pub linear_counter: u8,
+}
+
+impl TriangleChannel {
+  fn new() -> TriangleChannel {
+    return TriangleChannel {
+      sequence_step: 0,
+      timer: 0,
+      timer_period: 0,
+      control_flag: false,
+      linear_counter_reload_value: 0,
+      linear_counter_reload: false,
+      linear_counter: 0,
+    }
+  }
+
+  fn write_register(&mut self, register: u16, data: u8) {
+    match register {
+      0 => {
+        self.control_flag = (data & 0x80) != 0;
+        self.linear_counter_reload_value = data & 0x7F;
+      },
+      2 => {
+        self.timer_period = (self.timer_period & 0x0700) | (data as u16);
+      },
+      3 => {
+        self.timer_period = (self.timer_period & 0x00FF) | (((data & 0x07) as u16) << 8);
+        self.linear_counter_reload = true;
+      },
+      _ => {}
+    }
+  }
+
+  fn clock_linear_counter(&mut self) {
+    if self.linear_counter_reload {
+      self.linear_counter = self.linear_counter_reload_value;
+    } else if self.linear_counter > 0 {
+      self.linear_counter -= 1;
+    }
+    if !self.control_flag {
+      self.linear_counter_reload = false;
+    }
+  }
+
+  // Unlike the other channels, the triangle's timer is clocked on every CPU cycle
+  fn clock_timer(&mut self, length_counter: &LengthCounter) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      // Silencing the triangle freezes the sequencer instead of muting the output, which avoids pops
+      if self.linear_counter > 0 && length_counter.counter > 0 {
+        self.sequence_step = (self.sequence_step + 1) % 32;
+      }
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  fn output(&self) -> u8 {
+    return TRIANGLE_SEQUENCE[self.sequence_step as usize];
+  }
+}
+
+#[derive(Clone)]
+pub struct NoiseChannel {
+  mode_flag: bool,
+  shift_register: u16,
+  timer: u16,
+  pub timer_period: u16,
+  pub envelope: Envelope,
+}
+
+impl NoiseChannel {
+  fn new() -> NoiseChannel {
+    return NoiseChannel {
+      mode_flag: false,
+      shift_register: 1,
+      timer: 0,
+      timer_period: NOISE_PERIOD_TABLE[0],
+      envelope: Envelope::new(),
+    }
+  }
+
+  fn write_register(&mut self, register: u16, data: u8) {
+    match register {
+      0 => {
+        self.envelope.write_control(data);
+      },
+      2 => {
+        self.mode_flag = (data & 0x80) != 0;
+        self.timer_period = NOISE_PERIOD_TABLE[(data & 0x0F) as usize];
+      },
+      3 => {
+        self.envelope.restart();
+      },
+      _ => {}
+    }
+  }
+
+  fn clock_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      // In mode 1 the feedback comes from bit 6 instead of bit 1, which produces a shorter, more metallic sequence
+      let other_bit = if self.mode_flag { 6 } else { 1 };
+      let feedback = (self.shift_register & 0x01) ^ ((self.shift_register >> other_bit) & 0x01);
+      self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  fn output(&self, length_counter: &LengthCounter) -> u8 {
+    if length_counter.counter == 0 || (self.shift_register & 0x01) != 0 {
+      return 0;
+    }
+    return self.envelope.output();
+  }
+}
+
+// Averages all the samples produced by the APU during each output sample period.
+// Works as a (very) simple low-pass filter on top of the decimation, which gets rid of most of the aliasing.
+#[derive(Clone)]
+pub struct Resampler {
+  nominal_input_samples_per_output_sample: f64,
+  input_samples_per_output_sample: f64,
+  cycle_accumulator: f64,
+  sample_sum: f32,
+  sample_count: u32,
+}
+
+impl Resampler {
+  pub fn new(input_rate: f64, output_rate: f64) -> Resampler {
+    return Resampler {
+      nominal_input_samples_per_output_sample: input_rate / output_rate,
+      input_samples_per_output_sample: input_rate / output_rate,
+      cycle_accumulator: 0.0,
+      sample_sum: 0.0,
+      sample_count: 0,
+    }
+  }
+
+  // Speeds up (positive adjustment) or slows down the output rate relative to the nominal one, used for dynamic rate control
+  pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+    self.input_samples_per_output_sample = self.nominal_input_samples_per_output_sample / (1.0 + adjustment);
+  }
+
+  pub fn push(&mut self, sample: f32) -> Option<f32> {
+    self.sample_sum += sample;
+    self.sample_count += 1;
+    self.cycle_accumulator += 1.0;
+    if self.cycle_accumulator >= self.input_samples_per_output_sample {
+      self.cycle_accumulator -= self.input_samples_per_output_sample;
+      let output = self.sample_sum / self.sample_count as f32;
+      self.sample_sum = 0.0;
+      self.sample_count = 0;
+      return Some(output);
+    }
+    return None;
+  }
+}
+
+#[derive(Clone)]
+pub struct DmcChannel {
+  irq_enabled: bool,
+  loop_flag: bool,
+  rate_index: u8,
+  timer: u16,
+
+  pub output_level: u8,
+
+  // Sample address and length as configured through $4012/$4013
+  sample_address: u16,
+  sample_length: u16,
+
+  // Memory reader
+  current_address: u16,
+  bytes_remaining: u16,
+  sample_buffer: Option<u8>,
+
+  // Output unit
+  shift_register: u8,
+  bits_remaining: u8,
+  silence: bool,
+
+  pub irq_pending: bool,
+}
+
+impl DmcChannel {
+  fn new() -> DmcChannel {
+    return DmcChannel {
+      irq_enabled: false,
+      loop_flag: false,
+      rate_index: 0,
+      timer: DMC_RATE_TABLE[0],
+      output_level: 0,
+      sample_address: 0xC000,
+      sample_length: 1,
+      current_address: 0xC000,
+      bytes_remaining: 0,
+      sample_buffer: None,
+      shift_register: 0,
+      bits_remaining: 8,
+      silence: true,
+      irq_pending: false,
+    }
+  }
+
+  fn write_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x4010 => {
+        self.irq_enabled = (data & 0x80) != 0;
+        self.loop_flag = (data & 0x40) != 0;
+        self.rate_index = data & 0x0F;
+        if !self.irq_enabled {
+          self.irq_pending = false;
+        }
+      },
+      0x4011 => {
+        self.output_level = data & 0x7F;
+      },
+      0x4012 => {
+        self.sample_address = 0xC000 + (data as u16) * 64;
+      },
+      0x4013 => {
+        self.sample_length = (data as u16) * 16 + 1;
+      },
+      _ => {}
+    }
+  }
+
+  // Equivalent to writing the DMC bit of $4015
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.irq_pending = false;
+    if !enabled {
+      self.bytes_remaining = 0;
+    } else if self.bytes_remaining == 0 {
+      self.restart_sample();
+    }
+  }
+
+  pub fn bytes_remaining(&self) -> u16 {
+    return self.bytes_remaining;
+  }
+
+  fn restart_sample(&mut self) {
+    self.current_address = self.sample_address;
+    self.bytes_remaining = self.sample_length;
+  }
+
+  fn clock_cycle(&mut self) {
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = DMC_RATE_TABLE[self.rate_index as usize];
+      self.clock_output_unit();
+    }
+  }
+
+  fn clock_output_unit(&mut self) {
+    if !self.silence {
+      if (self.shift_register & 0x01) != 0 {
+        if self.output_level <= 125 {
+          self.output_level += 2;
+        }
+      } else if self.output_level >= 2 {
+        self.output_level -= 2;
+      }
+    }
+    self.shift_register >>= 1;
+    self.bits_remaining -= 1;
+
+    if self.bits_remaining == 0 {
+      // Output cycle ended, we start a new one with whatever the memory reader left in the buffer
+      self.bits_remaining = 8;
+      match self.sample_buffer.take() {
+        Some(sample) => {
+          self.silence = false;
+          self.shift_register = sample;
+        },
+        None => {
+          self.silence = true;
+        }
+      }
+    }
+  }
+
+  // Address the memory reader wants to fetch, if the sample buffer is empty and there are bytes left to play
+  fn fetch_address(&self) -> Option<u16> {
+    if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+      return Some(self.current_address);
+    }
+    return None;
+  }
+
+  fn load_sample(&mut self, data: u8) {
+    self.sample_buffer = Some(data);
+    // The address wraps around to $8000, not $0000
+    self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+    self.bytes_remaining -= 1;
+    if self.bytes_remaining == 0 {
+      if self.loop_flag {
+        self.restart_sample();
+      } else if self.irq_enabled {
+        self.irq_pending = true;
+      }
+    }
+  }
+}
+
+/*
+Snapshots of the internal state of each channel, for the debug view.
+The registers alone don't tell us much, since most of what's interesting (timers, counters, envelopes) is internal.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PulseDebugState {
+  pub enabled: bool,
+  pub duty: u8,
+  pub timer: u16,
+  pub timer_period: u16,
+  pub length_counter: u8,
+  pub envelope_volume: u8,
+  pub sweep_enabled: bool,
+  pub sweep_period: u8,
+  pub sweep_negate: bool,
+  pub sweep_shift: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TriangleDebugState {
+  pub enabled: bool,
+  pub timer: u16,
+  pub timer_period: u16,
+  pub length_counter: u8,
+  pub linear_counter: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoiseDebugState {
+  pub enabled: bool,
+  pub mode_flag: bool,
+  pub timer: u16,
+  pub timer_period: u16,
+  pub length_counter: u8,
+  pub envelope_volume: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DmcDebugState {
+  pub enabled: bool,
+  pub timer: u16,
+  pub timer_period: u16,
+  pub output_level: u8,
+  pub current_address: u16,
+  pub bytes_remaining: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ApuDebugState {
+  pub pulse_1: PulseDebugState,
+  pub pulse_2: PulseDebugState,
+  pub triangle: TriangleDebugState,
+  pub noise: NoiseDebugState,
+  pub dmc: DmcDebugState,
+}
+
+impl PulseChannel {
+  fn debug_state(&self, length_counter: &LengthCounter) -> PulseDebugState {
+    return PulseDebugState {
+      enabled: length_counter.enabled,
+      duty: self.duty,
+      timer: self.timer,
+      timer_period: self.timer_period,
+      length_counter: length_counter.counter,
+      envelope_volume: self.envelope.output(),
+      sweep_enabled: self.sweep.enabled,
+      sweep_period: self.sweep.period,
+      sweep_negate: self.sweep.negate,
+      sweep_shift: self.sweep.shift,
+    }
+  }
+}
+
+pub struct Apu {
+  memory_bounds: (u16, u16),
+  pub frame_counter: FrameCounter,
+  pub length_counters: [LengthCounter; 4],
+  odd_cycle: bool,
+
+  pub pulse_1: PulseChannel,
+  pub pulse_2: PulseChannel,
+  pub triangle: TriangleChannel,
+  pub noise: NoiseChannel,
+  pub dmc: DmcChannel,
+
+  pub mixer_settings: MixerSettings,
+  mixer_tables: MixerTables,
+
+  cpu_clock_rate: f64,
+  resampler: Resampler,
+  output_filters: OutputFilterChain,
+  output_sample_rate: u32,
+  output_samples: Vec<f32>,
+  wav_writer: Option<WavWriter>,
+}
+
+impl Apu {
+  pub fn new() -> Apu {
+    return Apu {
+      memory_bounds: APU_MEMORY_BOUNDS,
+      frame_counter: FrameCounter::new(),
+      length_counters: [LengthCounter::new(); 4],
+      odd_cycle: false,
+      pulse_1: PulseChannel::new(Sweep::new(true)),
+      pulse_2: PulseChannel::new(Sweep::new(false)),
+      triangle: TriangleChannel::new(),
+      noise: NoiseChannel::new(),
+      dmc: DmcChannel::new(),
+      mixer_settings: MixerSettings::default(),
+      mixer_tables: MixerTables::new(),
+      cpu_clock_rate: CPU_CLOCK_RATE,
+      resampler: Resampler::new(CPU_CLOCK_RATE, DEFAULT_OUTPUT_SAMPLE_RATE as f64),
+      output_filters: OutputFilterChain::new(DEFAULT_OUTPUT_SAMPLE_RATE),
+      output_sample_rate: DEFAULT_OUTPUT_SAMPLE_RATE,
+      output_samples: vec![],
+      wav_writer: None,
+    }
+  }
+
+  // Called once per CPU cycle
+  pub fn clock_cycle(&mut self) {
+    let frame_signal = self.frame_counter.clock_cycle();
+    self.clock_frame_units(frame_signal);
+
+    self.odd_cycle = !self.odd_cycle;
+    if self.odd_cycle {
+      self.pulse_1.clock_timer();
+      self.pulse_2.clock_timer();
+    }
+    self.triangle.clock_timer(&self.length_counters[TRIANGLE]);
+    self.noise.clock_timer();
+    self.dmc.clock_cycle();
+
+    let sample = self.sample();
+    if let Some(mut output_sample) = self.resampler.push(sample) {
+      // The filters keep running while disabled, so toggling them doesn't cause a pop
+      let filtered_sample = self.output_filters.process(output_sample);
+      if self.mixer_settings.output_filters_enabled {
+        output_sample = filtered_sample;
+      }
+      self.output_samples.push(output_sample);
+      self.record_sample(output_sample);
+    }
+  }
+
+  // Every output sample from now on is also written to a WAV file, at the output sample rate.
+  // If the emulation is paused no samples are produced, so nothing gets written but the file stays valid.
+  pub fn start_wav_recording(&mut self, path: &Path) -> Result<(), String> {
+    self.stop_wav_recording()?;
+    self.wav_writer = Some(WavWriter::create(path, self.output_sample_rate)?);
+    return Ok(());
+  }
+
+  // Can be called at any point, even mid-frame, since samples are written as they're produced
+  pub fn stop_wav_recording(&mut self) -> Result<(), String> {
+    return match self.wav_writer.take() {
+      Some(wav_writer) => wav_writer.finish(),
+      None => Ok(()),
+    };
+  }
+
+  pub fn is_recording_wav(&self) -> bool {
+    return self.wav_writer.is_some();
+  }
+
+  fn record_sample(&mut self, sample: f32) {
+    if let Some(wav_writer) = self.wav_writer.as_mut() {
+      if let Err(error) = wav_writer.write_samples(&[sample]) {
+        log::error!("{}, stopping the recording", error);
+        self.wav_writer = None;
+      }
+    }
+  }
+
+  pub fn output_sample_rate(&self) -> u32 {
+    return self.output_sample_rate;
+  }
+
+  pub fn set_output_sample_rate(&mut self, sample_rate: u32) {
+    self.output_sample_rate = sample_rate;
+    self.resampler = Resampler::new(self.cpu_clock_rate, sample_rate as f64);
+    self.output_filters = OutputFilterChain::new(sample_rate);
+  }
+
+  // The APU is clocked by the CPU, so its clock rate decides how many cycles go into each output sample
+  pub fn set_cpu_clock_rate(&mut self, cpu_clock_rate: f64) {
+    self.cpu_clock_rate = cpu_clock_rate;
+    self.resampler = Resampler::new(cpu_clock_rate, self.output_sample_rate as f64);
+  }
+
+  pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+    self.resampler.set_rate_adjustment(adjustment);
+  }
+
+  // Returns every output sample generated since the last call
+  pub fn take_samples(&mut self) -> Vec<f32> {
+    return std::mem::take(&mut self.output_samples);
+  }
+
+  pub fn debug_snapshot(&self) -> ApuDebugState {
+    return ApuDebugState {
+      pulse_1: self.pulse_1.debug_state(&self.length_counters[PULSE_1]),
+      pulse_2: self.pulse_2.debug_state(&self.length_counters[PULSE_2]),
+      triangle: TriangleDebugState {
+        enabled: self.length_counters[TRIANGLE].enabled,
+        timer: self.triangle.timer,
+        timer_period: self.triangle.timer_period,
+        length_counter: self.length_counters[TRIANGLE].counter,
+        linear_counter: self.triangle.linear_counter,
+      },
+      noise: NoiseDebugState {
+        enabled: self.length_counters[NOISE].enabled,
+        mode_flag: self.noise.mode_flag,
+        timer: self.noise.timer,
+        timer_period: self.noise.timer_period,
+        length_counter: self.length_counters[NOISE].counter,
+        envelope_volume: self.noise.envelope.output(),
+      },
+      dmc: DmcDebugState {
+        enabled: self.dmc.bytes_remaining > 0,
+        timer: self.dmc.timer,
+        timer_period: DMC_RATE_TABLE[self.dmc.rate_index as usize],
+        output_level: self.dmc.output_level,
+        current_address: self.dmc.current_address,
+        bytes_remaining: self.dmc.bytes_remaining,
+      },
+    }
+  }
+
+  pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+    self.mixer_settings.channel_muted[channel as usize] = muted;
+  }
+
+  pub fn is_channel_muted(&self, channel: Channel) -> bool {
+    return self.mixer_settings.channel_muted[channel as usize];
+  }
+
+  // Mutes every channel except the given one
+  pub fn solo(&mut self, channel: Channel) {
+    for other_channel in Channel::ALL {
+      self.set_channel_muted(other_channel, other_channel != channel);
+    }
+  }
+
+  // Output level of a channel as seen by the mixer
+  fn mixer_input(&self, channel: Channel) -> u8 {
+    if self.is_channel_muted(channel) {
+      return 0;
+    }
+    let output = match channel {
+      Channel::Pulse1 => self.pulse_1.output(&self.length_counters[PULSE_1]),
+      Channel::Pulse2 => self.pulse_2.output(&self.length_counters[PULSE_2]),
+      Channel::Triangle => self.triangle.output(),
+      Channel::Noise => self.noise.output(&self.length_counters[NOISE]),
+      Channel::Dmc => self.dmc.output_level,
+    };
+    return output;
+  }
+
+  // Mixes the current output of all channels into a sample in the range 0.0-1.0 (before applying the master volume)
+  pub fn sample(&self) -> f32 {
+    if self.mixer_settings.muted {
+      return 0.0;
+    }
+    let pulse_1 = self.mixer_input(Channel::Pulse1);
+    let pulse_2 = self.mixer_input(Channel::Pulse2);
+    let triangle = self.mixer_input(Channel::Triangle);
+    let noise = self.mixer_input(Channel::Noise);
+    let dmc = self.mixer_input(Channel::Dmc);
+    let mix = if self.mixer_settings.linear_mixing {
+      MixerTables::linear_mix(pulse_1, pulse_2, triangle, noise, dmc)
+    } else {
+      self.mixer_tables.mix(pulse_1, pulse_2, triangle, noise, dmc)
+    };
+    return mix * self.mixer_settings.master_volume;
+  }
+
+
+  pub fn write_frame_counter(&mut self, data: u8) {
+    let frame_signal = self.frame_counter.write(data);
+    self.clock_frame_units(frame_signal);
+  }
+
+  // Quarter frames clock the envelopes and the triangle's linear counter, half frames clock the length counters and sweeps.
+  fn clock_frame_units(&mut self, frame_signal: FrameSignal) {
+    if frame_signal.quarter_frame {
+      self.pulse_1.envelope.clock();
+      self.pulse_2.envelope.clock();
+      self.noise.envelope.clock();
+      self.triangle.clock_linear_counter();
+    }
+    if frame_signal.half_frame {
+      for length_counter in self.length_counters.iter_mut() {
+        length_counter.clock();
+      }
+      self.pulse_1.sweep.clock(&mut self.pulse_1.timer_period);
+      self.pulse_2.sweep.clock(&mut self.pulse_2.timer_period);
+    }
+  }
+
+  fn write_channel_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      // The halt flag doubles as the envelope loop flag (or the linear counter control flag for the triangle)
+      0x4000 => self.length_counters[PULSE_1].halt = (data & 0x20) != 0,
+      0x4004 => self.length_counters[PULSE_2].halt = (data & 0x20) != 0,
+      0x4008 => self.length_counters[TRIANGLE].halt = (data & 0x80) != 0,
+      0x400C => self.length_counters[NOISE].halt = (data & 0x20) != 0,
+
+      0x4003 => self.length_counters[PULSE_1].load(data >> 3),
+      0x4007 => self.length_counters[PULSE_2].load(data >> 3),
+      0x400B => self.length_counters[TRIANGLE].load(data >> 3),
+      0x400F => self.length_counters[NOISE].load(data >> 3),
+
+      _ => {}
+    }
+
+    match addr {
+      0x4000..=0x4003 => self.pulse_1.write_register(addr - 0x4000, data),
+      0x4004..=0x4007 => self.pulse_2.write_register(addr - 0x4004, data),
+      0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, data),
+      0x400C..=0x400F => self.noise.write_register(addr - 0x400C, data),
+      0x4010..=0x4013 => self.dmc.write_register(addr, data),
+      _ => {}
+    }
+  }
+
+  // What the console's reset button does to the APU: it's silenced as if $4015 was written with 0, and the frame counter restarts
+  pub fn reset(&mut self) {
+    self.write_status(0x00);
+    self.frame_counter.reset();
+  }
+
+  fn write_status(&mut self, data: u8) {
+    for channel in 0..self.length_counters.len() {
+      self.length_counters[channel].set_enabled((data & (1 << channel)) != 0);
+    }
+    // Also acknowledges the DMC interrupt
+    self.dmc.set_enabled((data & 0x10) != 0);
+  }
+
+  // State of the APU's IRQ output, which is wired to the CPU's IRQ line
+  pub fn irq_pending(&self) -> bool {
+    return self.frame_counter.irq_pending || self.dmc.irq_pending;
+  }
+
+  fn read_status(&mut self) -> u8 {
+    let status = self.peek_status();
+    // Reading the status register acknowledges the frame interrupt
+    self.frame_counter.irq_pending = false;
+    return status;
+  }
+
+  // Value of $4015, without acknowledging the frame interrupt
+  pub fn peek_status(&self) -> u8 {
+    let mut status = 0;
+    for channel in 0..self.length_counters.len() {
+      if self.length_counters[channel].counter > 0 {
+        status |= 1 << channel;
+      }
+    }
+    if self.dmc.bytes_remaining() > 0 {
+      status |= 0x10;
+    }
+    if self.dmc.irq_pending {
+      status |= 0x80;
+    }
+    if self.frame_counter.irq_pending {
+      status |= 0x40;
+    }
+    return status;
+  }
+
+  pub fn dmc_fetch_address(&self) -> Option<u16> {
+    return self.dmc.fetch_address();
+  }
+
+  pub fn dmc_load_sample(&mut self, data: u8) {
+    self.dmc.load_sample(data);
+  }
+}
+
+// Copies everything but the WAV recording, which stays with the original since both can't write to the same file
+impl Clone for Apu {
+  fn clone(&self) -> Apu {
+    return Apu {
+      memory_bounds: self.memory_bounds,
+      frame_counter: self.frame_counter.clone(),
+      length_counters: self.length_counters,
+      odd_cycle: self.odd_cycle,
+      pulse_1: self.pulse_1.clone(),
+      pulse_2: self.pulse_2.clone(),
+      triangle: self.triangle.clone(),
+      noise: self.noise.clone(),
+      dmc: self.dmc.clone(),
+      mixer_settings: self.mixer_settings.clone(),
+      mixer_tables: self.mixer_tables.clone(),
+      cpu_clock_rate: self.cpu_clock_rate,
+      resampler: self.resampler.clone(),
+      output_filters: self.output_filters.clone(),
+      output_sample_rate: self.output_sample_rate,
+      output_samples: self.output_samples.clone(),
+      wav_writer: None,
+    };
+  }
+}
+
+impl Sealed for Apu {}
+
+impl Device for Apu {
+  fn clone_device(&self) -> Box<dyn Device + Send> {
+    return Box::new(self.clone());
+  }
+
+  fn in_memory_bounds(&self, addr: u16)-> bool {
+    return (addr >= self.memory_bounds.0 && addr <= self.memory_bounds.1) || addr == APU_STATUS_ADDR;
+  }
+
+  fn write(&mut self, addr: u16, data: u8) -> Result<(), DeviceError> {
+    if addr == APU_STATUS_ADDR {
+      self.write_status(data);
+      return Ok(());
+    }
+    if self.in_memory_bounds(addr) {
+      self.write_channel_register(addr, data);
+      return Ok(());
+    }
+    return Err(DeviceError::OutOfBounds);
+  }
+
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError> {
+    if addr == APU_STATUS_ADDR {
+      return Ok(self.read_status());
+    }
+    if self.in_memory_bounds(addr) {
+      // The channel registers are write-only
+      return Ok(0);
+    }
+    return Err(DeviceError::OutOfBounds);
+  }
+}
+
+#[cfg(test)]
+mod apu_tests {
+  use crate::bus::Bus16Bit;
+  use crate::device::Device;
+  use super::{Apu, Channel, Envelope, LengthCounter, Sweep, FrameCounter, FrameSignal, Resampler, MixerTables, CPU_CLOCK_RATE, DEFAULT_OUTPUT_SAMPLE_RATE};
+
+  const CPU_CYCLES_PER_SECOND: u32 = 1789773;
+
+  fn count_frame_signals(frame_counter: &mut FrameCounter, initial_signal: FrameSignal) -> (u32, u32) {
+    let mut quarter_frames = initial_signal.quarter_frame as u32;
+    let mut half_frames = initial_signal.half_frame as u32;
+    for _ in 0..CPU_CYCLES_PER_SECOND {
+      let signal = frame_counter.clock_cycle();
+      quarter_frames += signal.quarter_frame as u32;
+      half_frames += signal.half_frame as u32;
+    }
+    return (quarter_frames, half_frames);
+  }
+
+  #[test]
+  fn test_frame_counter_4_step_mode() {
+    let mut frame_counter = FrameCounter::new();
+    let initial_signal = frame_counter.write(0x40);
+    // Almost 60 sequences of 4 quarter frames and 2 half frames, the last one is missing its final step
+    assert_eq!(count_frame_signals(&mut frame_counter, initial_signal), (59 * 4 + 3, 59 * 2 + 1));
+  }
+
+  #[test]
+  fn test_frame_counter_5_step_mode() {
+    let mut frame_counter = FrameCounter::new();
+    let initial_signal = frame_counter.write(0xC0);
+    // 48 sequences, plus the signals generated immediately by the write
+    assert_eq!(count_frame_signals(&mut frame_counter, initial_signal), (48 * 4 + 1, 48 * 2 + 1));
+  }
+
+  #[test]
+  fn test_frame_irq_set_and_cleared_by_status_read() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4017, 0x00).unwrap();
+    for _ in 0..super::FRAME_COUNTER_4_STEP_PERIOD {
+      bus.clock_apu().unwrap();
+    }
+    assert!(bus.apu().irq_pending());
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x40, 0x40);
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x40, 0x00);
+    assert!(!bus.apu().irq_pending());
+  }
+
+  #[test]
+  fn test_frame_irq_inhibit() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4017, 0x40).unwrap();
+    for _ in 0..(super::FRAME_COUNTER_4_STEP_PERIOD * 2) {
+      bus.clock_apu().unwrap();
+    }
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x40, 0x00);
+
+    // 5-step mode never raises the frame interrupt
+    bus.write(0x4017, 0x80).unwrap();
+    for _ in 0..(super::FRAME_COUNTER_5_STEP_PERIOD * 2) {
+      bus.clock_apu().unwrap();
+    }
+    assert!(!bus.apu().irq_pending());
+  }
+
+  #[test]
+  fn test_reset_silences_the_apu() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4015, 0x0F).unwrap();
+    bus.write(0x4003, 0x08).unwrap();
+    bus.write(0x400F, 0x08).unwrap();
+    bus.write(0x4017, 0x00).unwrap();
+    for _ in 0..super::FRAME_COUNTER_4_STEP_PERIOD {
+      bus.clock_apu().unwrap();
+    }
+    assert!(bus.apu().irq_pending());
+
+    bus.apu_mut().reset();
+    assert!(!bus.apu().irq_pending());
+    assert_eq!(bus.read(0x4015, false).unwrap(), 0x00);
+    // The channels stay disabled until the game enables them again
+    bus.write(0x4003, 0x08).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap(), 0x00);
+  }
+
+  #[test]
+  fn test_dmc_plays_sample_from_prg_space() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+
+    // Sample bits are played least significant first: four "down" deltas followed by four "up" deltas
+    bus.write(0xC000, 0xF0).unwrap();
+
+    bus.write(0x4010, 0x0F).unwrap(); // Fastest rate, no IRQ, no loop
+    bus.write(0x4011, 64).unwrap();   // Initial output level
+    bus.write(0x4012, 0x00).unwrap(); // Sample address $C000
+    bus.write(0x4013, 0x00).unwrap(); // Sample length of 1 byte
+    bus.write(0x4015, 0x10).unwrap(); // Enable the DMC
+
+    let mut levels = vec![];
+    let mut stolen_cycles: u32 = 0;
+    for _ in 0..(54 * 40) {
+      let stall_before = bus.dmc_stall_cycles;
+      bus.clock_apu().unwrap();
+      stolen_cycles += (bus.dmc_stall_cycles - stall_before) as u32;
+      bus.dmc_stall_cycles = 0;
+
+      let level = bus.apu().dmc.output_level;
+      if levels.last() != Some(&level) {
+        levels.push(level);
+      }
+    }
+
+    assert_eq!(levels, vec![64, 62, 60, 58, 56, 58, 60, 62, 64]);
+    assert_eq!(stolen_cycles, super::DMC_DMA_STALL_CYCLES as u32);
+    assert_eq!(bus.apu().dmc.bytes_remaining(), 0);
+  }
+
+  #[test]
+  fn test_dmc_irq_on_sample_end() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+
+    bus.write(0x4010, 0x8F).unwrap(); // IRQ enabled, fastest rate
+    bus.write(0x4013, 0x00).unwrap(); // 1 byte sample
+    bus.write(0x4015, 0x10).unwrap();
+
+    bus.clock_apu().unwrap();
+    assert!(bus.apu().dmc.irq_pending);
+
+    // Clearing the IRQ enable flag acknowledges the interrupt
+    bus.write(0x4010, 0x0F).unwrap();
+    assert!(!bus.apu().dmc.irq_pending);
+  }
+
+  #[test]
+  fn test_status_reports_length_counters() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+
+    // Loads are ignored while a channel is disabled
+    bus.write(0x4003, 0x08).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x0F, 0x00);
+
+    bus.write(0x4015, 0x0F).unwrap();
+    bus.write(0x4003, 0x08).unwrap(); // Pulse 1, length index 1 (254)
+    bus.write(0x400B, 0x18).unwrap(); // Triangle, length index 3 (2)
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x0F, 0b0101);
+
+    // Two half frames silence the triangle, but not the pulse
+    bus.apu_mut().write_frame_counter(0x80);
+    // The sequence restarts a few cycles after the write
+    for _ in 0..(super::FRAME_COUNTER_HALF_1 + 4) {
+      bus.clock_apu().unwrap();
+    }
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x0F, 0b0001);
+
+    // Disabling a channel clears its length counter immediately
+    bus.write(0x4015, 0x00).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x0F, 0x00);
+  }
+
+  #[test]
+  fn test_length_counter_halt() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4015, 0x08).unwrap();
+    bus.write(0x400C, 0x20).unwrap(); // Noise, halted
+    bus.write(0x400F, 0x18).unwrap(); // Length of 2
+    for _ in 0..4 {
+      bus.apu_mut().write_frame_counter(0x80);
+    }
+    assert_eq!(bus.apu().length_counters[super::NOISE].counter, 2);
+  }
+
+  #[test]
+  fn test_status_dmc_enable_and_irq() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4010, 0x8F).unwrap();
+    bus.write(0x4013, 0x01).unwrap(); // 17 byte sample
+
+    bus.write(0x4015, 0x10).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x10, 0x10);
+    bus.clock_apu().unwrap();
+    assert_eq!(bus.apu().dmc.bytes_remaining(), 16);
+
+    // Enabling again while bytes remain doesn't restart the sample
+    bus.write(0x4015, 0x10).unwrap();
+    assert_eq!(bus.apu().dmc.bytes_remaining(), 16);
+
+    bus.write(0x4015, 0x00).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x10, 0x00);
+
+    // Writing $4015 acknowledges the DMC interrupt, but reading it doesn't
+    bus.apu_mut().dmc.irq_pending = true;
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x80, 0x80);
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x80, 0x80);
+    bus.write(0x4015, 0x00).unwrap();
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x80, 0x00);
+  }
+
+  #[test]
+  fn test_resampler_output_count_for_one_frame() {
+    let mut resampler = Resampler::new(CPU_CLOCK_RATE, DEFAULT_OUTPUT_SAMPLE_RATE as f64);
+    // An NTSC frame lasts 29780.5 CPU cycles
+    let cpu_cycles_per_frame = 29781;
+    let mut output_samples = 0;
+    for _ in 0..cpu_cycles_per_frame {
+      if resampler.push(0.5).is_some() {
+        output_samples += 1;
+      }
+    }
+    let expected = cpu_cycles_per_frame as f64 * DEFAULT_OUTPUT_SAMPLE_RATE as f64 / CPU_CLOCK_RATE;
+    assert!((output_samples as f64 - expected).abs() <= 1.0);
+  }
+
+  #[test]
+  fn test_mixer_mute_and_master_volume() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4011, 100).unwrap(); // DMC output level
+    let full_volume_sample = bus.apu().sample();
+    assert!(full_volume_sample > 0.0);
+
+    bus.apu_mut().mixer_settings.master_volume = 0.5;
+    assert_eq!(bus.apu().sample(), full_volume_sample * 0.5);
+
+    bus.apu_mut().mixer_settings.muted = true;
+    assert_eq!(bus.apu().sample(), 0.0);
+  }
+
+  #[test]
+  fn test_pulse_constant_volume_output() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4015, 0x01).unwrap();
+    bus.write(0x4000, 0b1011_1010).unwrap(); // 50% duty, constant volume of 10
+    bus.write(0x4002, 0x10).unwrap();
+    bus.write(0x4003, 0x08).unwrap();
+
+    let mut outputs = vec![];
+    for _ in 0..(0x11 * 2 * 8) {
+      bus.clock_apu().unwrap();
+      outputs.push(bus.apu().pulse_1.output(&bus.apu().length_counters[super::PULSE_1]));
+    }
+    // Half of the sequence is high
+    assert_eq!(outputs.iter().filter(|output| **output == 10).count(), outputs.len() / 2);
+    assert!(outputs.iter().all(|output| *output == 0 || *output == 10));
+  }
+
+  #[test]
+  fn test_take_samples_over_10_frames() {
+    let mut apu = Apu::new();
+    // 10 NTSC frames of 29780.5 CPU cycles
+    let cpu_cycles = 297805;
+    for _ in 0..cpu_cycles {
+      apu.clock_cycle();
+    }
+    let expected = cpu_cycles as f64 * DEFAULT_OUTPUT_SAMPLE_RATE as f64 / CPU_CLOCK_RATE;
+    assert!((apu.take_samples().len() as f64 - expected).abs() <= 1.0);
+
+    // Samples are only returned once
+    assert_eq!(apu.take_samples().len(), 0);
+  }
+
+  #[test]
+  fn test_take_samples_at_custom_rate() {
+    let mut apu = Apu::new();
+    apu.set_output_sample_rate(48000);
+    for _ in 0..(CPU_CLOCK_RATE as u32) {
+      apu.clock_cycle();
+    }
+    assert!((apu.take_samples().len() as i32 - 48000).abs() <= 1);
+  }
+
+  #[test]
+  fn test_muted_channel_is_excluded_from_mix() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4015, 0x05).unwrap(); // Pulse 1 and triangle enabled, noise and DMC silent
+    bus.write(0x4000, 0b1101_1111).unwrap(); // 75% duty, constant volume of 15
+    bus.write(0x4002, 0x40).unwrap();
+    bus.write(0x4003, 0x08).unwrap();
+    // The triangle's linear counter stays at 0, which freezes its sequencer at the first step (output of 15)
+    bus.write(0x4008, 0x00).unwrap();
+    bus.write(0x400B, 0x08).unwrap();
+
+    bus.apu_mut().set_channel_muted(Channel::Pulse1, true);
+    // The output filters would turn the constant triangle output into silence
+    bus.apu_mut().mixer_settings.output_filters_enabled = false;
+    for _ in 0..29781 {
+      bus.clock_apu().unwrap();
+    }
+    let samples = bus.apu_mut().take_samples();
+    assert!(samples.len() > 0);
+
+    let expected = MixerTables::new().mix(0, 0, 15, 0, 0);
+    assert!(samples.iter().all(|sample| (sample - expected).abs() < 1e-5));
+
+    // The pulse channel kept running while muted, so it's heard as soon as it's unmuted
+    bus.apu_mut().set_channel_muted(Channel::Pulse1, false);
+    for _ in 0..29781 {
+      bus.clock_apu().unwrap();
+    }
+    let samples = bus.apu_mut().take_samples();
+    assert!(samples.iter().any(|sample| (sample - expected).abs() > 1e-3));
+  }
+
+  #[test]
+  fn test_mixer_tables() {
+    let mixer_tables = MixerTables::new();
+    assert_eq!(mixer_tables.mix(0, 0, 0, 0, 0), 0.0);
+    // 95.52 / (8128 / 15 + 100)
+    assert!((mixer_tables.mix(15, 0, 0, 0, 0) - 0.148816).abs() < 1e-5);
+    // The pulse channels aren't additive: both at full volume are quieter than twice one of them
+    assert!((mixer_tables.mix(15, 15, 0, 0, 0) - 0.257513).abs() < 1e-5);
+    // 163.67 / (24329 / 45 + 100)
+    assert!((mixer_tables.mix(0, 0, 15, 0, 0) - 0.255477).abs() < 1e-5);
+    // Everything at its maximum output: 0.257513 + 163.67 / (24329 / 202 + 100)
+    assert!((mixer_tables.mix(15, 15, 15, 15, 127) - 1.0).abs() < 1e-3);
+  }
+
+  #[test]
+  fn test_linear_mixing_switch() {
+    let mut apu = Apu::new();
+    // The triangle outputs its first step even while silent, so only the DMC is left
+    apu.solo(Channel::Dmc);
+    apu.dmc.output_level = 64;
+    let table_sample = apu.sample();
+    apu.mixer_settings.linear_mixing = true;
+    assert!((table_sample - MixerTables::new().mix(0, 0, 0, 0, 64)).abs() < 1e-6);
+    assert!((apu.sample() - 0.00335 * 64.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn test_solo_channel() {
+    let mut apu = Apu::new();
+    apu.solo(Channel::Noise);
+    for channel in Channel::ALL {
+      assert_eq!(apu.is_channel_muted(channel), channel != Channel::Noise);
+    }
+  }
+
+  #[test]
+  fn test_debug_snapshot_reflects_register_writes() {
+    let mut apu = Apu::new();
+    apu.write(0x4015, 0x11).unwrap(); // Pulse 1 and DMC enabled
+    apu.write(0x4000, 0b1001_0111).unwrap(); // 50% duty, constant volume of 7
+    apu.write(0x4001, 0b1010_1011).unwrap(); // Sweep enabled, period 2, negate, shift 3
+    apu.write(0x4002, 0x34).unwrap();
+    apu.write(0x4003, 0b0000_1010).unwrap(); // Length index 1 (254), timer high bits 2
+
+    let snapshot = apu.debug_snapshot();
+    assert_eq!(snapshot.pulse_1.enabled, true);
+    assert_eq!(snapshot.pulse_1.duty, 2);
+    assert_eq!(snapshot.pulse_1.timer_period, 0x234);
+    assert_eq!(snapshot.pulse_1.length_counter, 254);
+    assert_eq!(snapshot.pulse_1.envelope_volume, 7);
+    assert_eq!((snapshot.pulse_1.sweep_enabled, snapshot.pulse_1.sweep_period, snapshot.pulse_1.sweep_negate, snapshot.pulse_1.sweep_shift), (true, 2, true, 3));
+    assert_eq!(snapshot.pulse_2.enabled, false);
+
+    // Sample address $C000 + 0x10 * 64, length 0x01 * 16 + 1
+    assert_eq!(snapshot.dmc.enabled, true);
+    assert_eq!(snapshot.dmc.current_address, 0xC000);
+    apu.write(0x4015, 0x00).unwrap();
+    apu.write(0x4012, 0x10).unwrap();
+    apu.write(0x4013, 0x01).unwrap();
+    apu.write(0x4015, 0x10).unwrap();
+    let snapshot = apu.debug_snapshot();
+    assert_eq!(snapshot.dmc.current_address, 0xC400);
+    assert_eq!(snapshot.dmc.bytes_remaining, 17);
+  }
+
+  #[test]
+  fn test_envelope_start_flag_and_decay() {
+    let mut envelope = Envelope::new();
+    envelope.write_control(0b0000_0010); // Decaying volume, divider period of 2
+    envelope.restart();
+
+    // The first clock after a restart reloads the decay level instead of decrementing it
+    envelope.clock();
+    assert_eq!(envelope.output(), 15);
+    // From then on, the decay level drops once every (period + 1) clocks
+    for _ in 0..3 {
+      envelope.clock();
+    }
+    assert_eq!(envelope.output(), 14);
+    for _ in 0..(14 * 3) {
+      envelope.clock();
+    }
+    assert_eq!(envelope.output(), 0);
+    // Without the loop flag it stays at 0
+    for _ in 0..3 {
+      envelope.clock();
+    }
+    assert_eq!(envelope.output(), 0);
+  }
+
+  #[test]
+  fn test_envelope_loop_and_constant_volume() {
+    let mut envelope = Envelope::new();
+    envelope.write_control(0b0010_0000); // Looping, divider period of 0
+    envelope.restart();
+    for _ in 0..16 {
+      envelope.clock();
+    }
+    assert_eq!(envelope.output(), 0);
+    envelope.clock();
+    assert_eq!(envelope.output(), 15);
+
+    envelope.write_control(0b0011_0101);
+    assert_eq!(envelope.output(), 5);
+  }
+
+  #[test]
+  fn test_length_counter_load_and_clock() {
+    let mut length_counter = LengthCounter::new();
+    // Loads are ignored while disabled
+    length_counter.load(0x01);
+    assert_eq!(length_counter.counter, 0);
+
+    length_counter.set_enabled(true);
+    length_counter.load(0x01);
+    assert_eq!(length_counter.counter, 254);
+    length_counter.load(0x1F);
+    assert_eq!(length_counter.counter, 30);
+
+    length_counter.clock();
+    assert_eq!(length_counter.counter, 29);
+    length_counter.halt = true;
+    length_counter.clock();
+    assert_eq!(length_counter.counter, 29);
+
+    length_counter.set_enabled(false);
+    assert_eq!(length_counter.counter, 0);
+  }
+
+  #[test]
+  fn test_sweep_negate_differs_between_pulse_channels() {
+    let mut pulse_1_sweep = Sweep::new(true);
+    let mut pulse_2_sweep = Sweep::new(false);
+    pulse_1_sweep.write_control(0b1000_1001); // Enabled, period 0, negate, shift 1
+    pulse_2_sweep.write_control(0b1000_1001);
+
+    assert_eq!(pulse_1_sweep.target_period(0x100), 0x100 - 0x80 - 1);
+    assert_eq!(pulse_2_sweep.target_period(0x100), 0x100 - 0x80);
+
+    let mut pulse_1_period = 0x100;
+    let mut pulse_2_period = 0x100;
+    pulse_1_sweep.clock(&mut pulse_1_period);
+    pulse_2_sweep.clock(&mut pulse_2_period);
+    assert_eq!(pulse_1_period, 0x7F);
+    assert_eq!(pulse_2_period, 0x80);
+  }
+
+  #[test]
+  fn test_sweep_target_overflow_mutes() {
+    let mut sweep = Sweep::new(false);
+    // Even with the sweep disabled, a target period over $7FF silences the channel
+    sweep.write_control(0b0000_0000);
+    assert!(sweep.is_muting(0x400));
+    assert!(!sweep.is_muting(0x3FF));
+    assert!(sweep.is_muting(7));
+
+    // A muting sweep doesn't update the period
+    sweep.write_control(0b1000_0001);
+    let mut period = 0x600;
+    sweep.clock(&mut period);
+    assert_eq!(period, 0x600);
+  }
+
+  #[test]
+  fn test_sweep_divider_period() {
+    let mut sweep = Sweep::new(false);
+    sweep.write_control(0b1010_0010); // Enabled, period 2, shift 2
+    let mut period: u16 = 0x100;
+
+    // The divider starts at 0 after a write, so the first clock updates the period and reloads the divider
+    sweep.clock(&mut period);
+    assert_eq!(period, 0x140);
+    sweep.clock(&mut period);
+    sweep.clock(&mut period);
+    assert_eq!(period, 0x140);
+    sweep.clock(&mut period);
+    assert_eq!(period, 0x190);
+  }
+
+  #[test]
+  fn test_pulse_sweep_is_clocked_by_half_frames() {
+    let mut apu = Apu::new();
+    apu.write(0x4015, 0x01).unwrap();
+    apu.write(0x4000, 0b1011_1111).unwrap();
+    apu.write(0x4001, 0b1000_0001).unwrap(); // Enabled, period 0, shift 1
+    apu.write(0x4002, 0x00).unwrap();
+    apu.write(0x4003, 0x01).unwrap(); // Period $100
+
+    // Two half frames in 4-step mode
+    for _ in 0..(super::FRAME_COUNTER_4_STEP_PERIOD + 1) {
+      apu.clock_cycle();
+    }
+    assert_eq!(apu.pulse_1.timer_period, 0x240);
+  }
+
+  #[test]
+  fn test_wav_recording_of_constant_tone() {
+    let file_path = std::env::temp_dir().join("rustness_apu_recording_test.wav");
+    let mut apu = Apu::new();
+    apu.write(0x4015, 0x01).unwrap();
+    apu.write(0x4000, 0b1011_1111).unwrap(); // Constant volume of 15, length counter halted
+    apu.write(0x4002, 0xFD).unwrap(); // ~440Hz
+    apu.write(0x4003, 0x00).unwrap();
+
+    apu.start_wav_recording(&file_path).unwrap();
+    let mut samples_produced = 0;
+    for _ in 0..60 {
+      for _ in 0..29781 {
+        apu.clock_cycle();
+      }
+      samples_produced += apu.take_samples().len() as u32;
+    }
+    apu.stop_wav_recording().unwrap();
+    assert!(!apu.is_recording_wav());
+
+    let contents = std::fs::read(&file_path).unwrap();
+    let data_size = samples_produced * 2;
+    let read_u32 = |offset: usize| u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap());
+    let read_u16 = |offset: usize| u16::from_le_bytes(contents[offset..offset + 2].try_into().unwrap());
+    assert_eq!(contents.len() as u32, 44 + data_size);
+    assert_eq!(&contents[0..4], b"RIFF");
+    assert_eq!(read_u32(4), 36 + data_size);
+    assert_eq!(&contents[8..16], b"WAVEfmt ");
+    assert_eq!(read_u16(20), 1); // PCM
+    assert_eq!(read_u16(22), 1); // Mono
+    assert_eq!(read_u32(24), DEFAULT_OUTPUT_SAMPLE_RATE);
+    assert_eq!(read_u32(28), DEFAULT_OUTPUT_SAMPLE_RATE * 2);
+    assert_eq!(read_u16(34), 16);
+    assert_eq!(&contents[36..40], b"data");
+    assert_eq!(read_u32(40), data_size);
+    // About a second of audio
+    assert!((samples_produced as i32 - 44100).abs() < 200);
+    std::fs::remove_file(file_path).unwrap();
+  }
+}
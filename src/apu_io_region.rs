@@ -0,0 +1,151 @@
+/*
+
+Address decoding for the 2A03 I/O registers ($4000-$401F), which are shared between several components:
+
+- $4000-$4013, $4015: APU channel and status registers.
+- $4014: OAM DMA. The transfer itself is performed by the bus, which picks up the request after the write.
+- $4016: writes strobe both controllers, reads return the first controller's serial data.
+- $4017: writes go to the APU frame counter, reads return the second controller's serial data.
+- $4018-$401F: normally disabled CPU test registers.
+
+Anything that isn't readable returns open bus. We don't keep track of the actual data bus, so we approximate it
+with the high byte of the address, which is what's left on the bus after the CPU fetches an absolute address.
+
+Reference: https://www.nesdev.org/wiki/2A03
+
+*/
+
+use crate::{apu::{Apu, APU_MEMORY_BOUNDS, APU_STATUS_ADDR, FRAME_COUNTER_ADDR}, controller::Controller, device::{Device, DeviceError}};
+
+pub const IO_REGION_BOUNDS: (u16, u16) = (0x4000, 0x401F);
+pub const OAM_DMA_ADDR: u16 = 0x4014;
+pub const CONTROLLER_1_ADDR: u16 = 0x4016;
+pub const CONTROLLER_2_ADDR: u16 = 0x4017;
+
+// The APU and controllers belong to the bus, and are passed in on every access
+#[derive(Clone)]
+pub struct ApuIoRegion {
+  // Page written to $4014, waiting for the bus to start the transfer
+  oam_dma_request: Option<u8>,
+}
+
+impl ApuIoRegion {
+  pub fn new() -> ApuIoRegion {
+    return ApuIoRegion {
+      oam_dma_request: None,
+    }
+  }
+
+  pub fn take_oam_dma_request(&mut self) -> Option<u8> {
+    return self.oam_dma_request.take();
+  }
+
+  fn open_bus(addr: u16) -> u8 {
+    return (addr >> 8) as u8;
+  }
+
+  fn is_apu_register(addr: u16) -> bool {
+    return (addr >= APU_MEMORY_BOUNDS.0 && addr <= APU_MEMORY_BOUNDS.1) || addr == APU_STATUS_ADDR;
+  }
+
+  pub fn in_memory_bounds(&self, addr: u16)-> bool {
+    return addr >= IO_REGION_BOUNDS.0 && addr <= IO_REGION_BOUNDS.1;
+  }
+
+  pub fn write(&mut self, addr: u16, data: u8, apu: &mut Apu, controller: &mut Controller) -> Result<(), DeviceError> {
+    if ApuIoRegion::is_apu_register(addr) {
+      return apu.write(addr, data);
+    }
+    match addr {
+      OAM_DMA_ADDR => self.oam_dma_request = Some(data),
+      CONTROLLER_1_ADDR => controller.write(addr, data)?,
+      FRAME_COUNTER_ADDR => apu.write_frame_counter(data),
+      _ => {}
+    }
+    return Ok(());
+  }
+
+  pub fn read(&mut self, addr: u16, apu: &mut Apu, controller: &mut Controller) -> Result<u8, DeviceError> {
+    if addr == APU_STATUS_ADDR {
+      return apu.read(addr);
+    }
+    if addr == CONTROLLER_1_ADDR || addr == CONTROLLER_2_ADDR {
+      // Only the lowest bits are driven by the controller port
+      let data = controller.read(addr)?;
+      return Ok((ApuIoRegion::open_bus(addr) & 0xE0) | data);
+    }
+    return Ok(ApuIoRegion::open_bus(addr));
+  }
+
+  pub fn read_debug(&self, addr: u16, apu: &Apu, controller: &Controller) -> Result<u8, DeviceError> {
+    if addr == APU_STATUS_ADDR {
+      return Ok(apu.peek_status());
+    }
+    if addr == CONTROLLER_1_ADDR || addr == CONTROLLER_2_ADDR {
+      let data = controller.read_debug(addr)?;
+      return Ok((ApuIoRegion::open_bus(addr) & 0xE0) | data);
+    }
+    return Ok(ApuIoRegion::open_bus(addr));
+  }
+}
+
+
+#[cfg(test)]
+mod apu_io_region_tests {
+  use crate::bus::Bus16Bit;
+
+  #[test]
+  fn test_apu_registers_reach_the_apu() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4015, 0x01).unwrap();
+    bus.write(0x4000, 0b1000_0000).unwrap();
+    bus.write(0x4003, 0x08).unwrap();
+    assert_eq!(bus.apu().debug_snapshot().pulse_1.duty, 2);
+    assert_eq!(bus.read(0x4015, false).unwrap() & 0x01, 0x01);
+  }
+
+  #[test]
+  fn test_oam_dma_reaches_the_dma_engine() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x4014, 0x02).unwrap();
+    let dma = bus.pending_dma.as_ref().unwrap();
+    assert_eq!(dma.page, 0x02);
+    assert_eq!(dma.cycles_elapsed, 0);
+  }
+
+  #[test]
+  fn test_4016_write_strobes_both_controllers() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.controller_mut().emulator_input = [0b1000_0001, 0b0100_0000];
+    bus.write(0x4016, 0x01).unwrap();
+    bus.write(0x4016, 0x00).unwrap();
+
+    let controller_1: Vec<u8> = (0..8).map(|_| bus.read(0x4016, false).unwrap() & 0x01).collect();
+    let controller_2: Vec<u8> = (0..8).map(|_| bus.read(0x4017, false).unwrap() & 0x01).collect();
+    assert_eq!(controller_1, vec![1, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(controller_2, vec![0, 1, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_4017_write_reaches_the_frame_counter() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.controller_mut().emulator_input = [0, 0xFF];
+    // IRQ inhibit, so a whole 4-step sequence shouldn't raise the frame interrupt
+    bus.write(0x4017, 0x40).unwrap();
+    for _ in 0..30000 {
+      bus.clock_apu().unwrap();
+    }
+    assert!(!bus.apu().irq_pending());
+    // And the second controller wasn't strobed
+    assert_eq!(bus.read(0x4017, false).unwrap() & 0x01, 0);
+  }
+
+  #[test]
+  fn test_unmapped_io_reads_return_open_bus() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    for addr in [0x4000, 0x4013, 0x4014, 0x4018, 0x401F] {
+      assert_eq!(bus.read(addr, false).unwrap(), 0x40);
+    }
+    bus.write(0x4018, 0xFF).unwrap();
+  }
+}
@@ -0,0 +1,74 @@
+/*
+
+Audio backend, which plays the samples generated by the APU through the default output device using cpal.
+
+The emulation thread collects the APU samples (see Apu::take_samples) and queues them into a
+single-producer/single-consumer ring buffer, which the audio callback, running on its own thread, drains.
+Neither side ever blocks on the other.
+
+*/
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapRb, HeapProducer};
+
+use crate::apu::DEFAULT_OUTPUT_SAMPLE_RATE;
+
+// Roughly 90ms of audio at 44.1kHz
+const RING_BUFFER_CAPACITY: usize = 4096;
+
+pub struct AudioOutput {
+  // The stream stops playing as soon as it's dropped, so we need to keep it around
+  _stream: cpal::Stream,
+  sample_producer: HeapProducer<f32>,
+  pub sample_rate: u32,
+}
+
+impl AudioOutput {
+  pub fn new() -> Result<AudioOutput, String> {
+    let host = cpal::default_host();
+    let device = host.default_output_device().ok_or(String::from("No audio output device available"))?;
+    let channels = device.default_output_config().map_err(|e| e.to_string())?.channels();
+    let config = cpal::StreamConfig {
+      channels,
+      sample_rate: cpal::SampleRate(DEFAULT_OUTPUT_SAMPLE_RATE),
+      buffer_size: cpal::BufferSize::Default,
+    };
+
+    let (sample_producer, mut consumer) = HeapRb::<f32>::new(RING_BUFFER_CAPACITY).split();
+    let mut last_sample: f32 = 0.0;
+
+    let stream = device.build_output_stream(
+      &config,
+      move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+        for frame in data.chunks_mut(channels as usize) {
+          // On underruns we keep repeating the last sample, dropping to silence would cause a click
+          if let Some(sample) = consumer.pop() {
+            last_sample = sample;
+          }
+          for channel_sample in frame.iter_mut() {
+            *channel_sample = last_sample;
+          }
+        }
+      },
+      |error| log::error!("Audio stream error: {}", error),
+      None
+    ).map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+
+    return Ok(AudioOutput {
+      _stream: stream,
+      sample_producer,
+      sample_rate: DEFAULT_OUTPUT_SAMPLE_RATE,
+    });
+  }
+
+  // If the audio device can't keep up, the samples that don't fit are dropped
+  pub fn queue_samples(&mut self, samples: &[f32]) {
+    self.sample_producer.push_slice(samples);
+  }
+
+  // Fraction of the ring buffer that is currently filled, used to pace the emulation
+  pub fn fill_level(&self) -> f32 {
+    return self.sample_producer.len() as f32 / self.sample_producer.capacity() as f32;
+  }
+}
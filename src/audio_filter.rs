@@ -0,0 +1,123 @@
+/*
+
+Filters applied by the NES to its audio output, after the channels are mixed.
+
+The console's output stage has two first-order high-pass filters (90Hz and 440Hz), which remove the DC offset
+of the mixer and make the output less boomy, followed by a first-order low-pass filter at 14kHz, which softens
+the harsh edges of the square waves. We emulate them with simple one-pole IIR filters running at the output sample rate.
+
+Reference: https://www.nesdev.org/wiki/APU_Mixer
+
+*/
+
+use std::f32::consts::PI;
+
+const HIGH_PASS_1_CUTOFF: f32 = 90.0;
+const HIGH_PASS_2_CUTOFF: f32 = 440.0;
+const LOW_PASS_CUTOFF: f32 = 14000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+  HighPass,
+  LowPass,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FirstOrderFilter {
+  kind: FilterKind,
+  alpha: f32,
+  prev_input: f32,
+  prev_output: f32,
+}
+
+impl FirstOrderFilter {
+  pub fn new(kind: FilterKind, cutoff_frequency: f32, sample_rate: f32) -> FirstOrderFilter {
+    let rc = 1.0 / (2.0 * PI * cutoff_frequency);
+    let dt = 1.0 / sample_rate;
+    let alpha = match kind {
+      FilterKind::HighPass => rc / (rc + dt),
+      FilterKind::LowPass => dt / (rc + dt),
+    };
+    return FirstOrderFilter {
+      kind,
+      alpha,
+      prev_input: 0.0,
+      prev_output: 0.0,
+    }
+  }
+
+  pub fn process(&mut self, input: f32) -> f32 {
+    let output = match self.kind {
+      FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+      FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+    };
+    self.prev_input = input;
+    self.prev_output = output;
+    return output;
+  }
+}
+
+#[derive(Clone)]
+pub struct OutputFilterChain {
+  filters: [FirstOrderFilter; 3],
+}
+
+impl OutputFilterChain {
+  pub fn new(sample_rate: u32) -> OutputFilterChain {
+    let sample_rate = sample_rate as f32;
+    return OutputFilterChain {
+      filters: [
+        FirstOrderFilter::new(FilterKind::HighPass, HIGH_PASS_1_CUTOFF, sample_rate),
+        FirstOrderFilter::new(FilterKind::HighPass, HIGH_PASS_2_CUTOFF, sample_rate),
+        FirstOrderFilter::new(FilterKind::LowPass, LOW_PASS_CUTOFF, sample_rate),
+      ]
+    }
+  }
+
+  pub fn process(&mut self, sample: f32) -> f32 {
+    let mut result = sample;
+    for filter in self.filters.iter_mut() {
+      result = filter.process(result);
+    }
+    return result;
+  }
+}
+
+
+#[cfg(test)]
+mod audio_filter_tests {
+  use super::{FirstOrderFilter, FilterKind, OutputFilterChain};
+
+  #[test]
+  fn test_high_pass_step_response_decays() {
+    let sample_rate = 44100.0;
+    let mut filter = FirstOrderFilter::new(FilterKind::HighPass, 90.0, sample_rate);
+    let step_response: Vec<f32> = (0..2000).map(|_| filter.process(1.0)).collect();
+
+    // The step goes straight through, and then decays exponentially with a time constant of RC = 1 / (2 * PI * 90Hz)
+    assert!(step_response[0] > 0.98);
+    let samples_per_time_constant = (sample_rate / (2.0 * std::f32::consts::PI * 90.0)).round() as usize;
+    assert!((step_response[samples_per_time_constant] - (-1.0f32).exp()).abs() < 0.01);
+    assert!(step_response.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert!(step_response[2000 - 1] < 1e-4);
+  }
+
+  #[test]
+  fn test_low_pass_settles_on_dc() {
+    let mut filter = FirstOrderFilter::new(FilterKind::LowPass, 14000.0, 44100.0);
+    let step_response: Vec<f32> = (0..100).map(|_| filter.process(1.0)).collect();
+    assert!(step_response[0] < 1.0);
+    assert!((step_response[99] - 1.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn test_output_chain_removes_dc_offset() {
+    let mut filter_chain = OutputFilterChain::new(44100);
+    let mut last_output = 0.0;
+    // 100ms of a constant mixer output
+    for _ in 0..4410 {
+      last_output = filter_chain.process(0.5);
+    }
+    assert!(last_output.abs() < 1e-3);
+  }
+}
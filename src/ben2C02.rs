@@ -1,10 +1,27 @@
-use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex, MutexGuard, LockResult};
 
-  use crate::{graphics::Color, device::Device, utils::bitwise_utils, cartridge::{Cartridge, MirroringMode}};
+  use crate::{graphics::Color, device::{Device, DeviceError}, utils::bitwise_utils, cartridge::{Cartridge, MirroringMode, Region}};
   use rand::Rng;
+  #[cfg(feature = "serde")]
+  use serde::{Serialize, Deserialize};
 
   pub const PPU_MEMORY_BOUNDS: (u16, u16) = (0x2000, 0x3FFF);
 
+  // The PPU addresses four name tables at $2000-$2FFF (mirrored at $3000-$3EFF), but the console only has memory for two.
+  // The cartridge decides which of the two each one of the four ends up in, unless it brings memory for the other two
+  // (four-screen mode), which lives in tables 2 and 3.
+  fn name_table_index(addr: u16, mirroring_mode: MirroringMode) -> usize {
+    let logical_index = ((addr >> 10) & 0x03) as usize;
+    return match mirroring_mode {
+      MirroringMode::Vertical => logical_index & 0x01,
+      MirroringMode::Horizontal => logical_index >> 1,
+      MirroringMode::OnscreenLo => 0,
+      MirroringMode::OnscreenHi => 1,
+      MirroringMode::FourScreen => logical_index,
+      MirroringMode::PerTable(tables) => tables[logical_index] as usize,
+    };
+  }
+
   fn create_palette_vis_buffer() -> [Color; 64]{
     let mut buffer= [Color::new(0, 0, 0);64];
 
@@ -81,6 +98,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     return buffer;
   }
 
+  #[derive(Clone)]
   pub struct StatusRegister {
     flags: u8
   }
@@ -118,6 +136,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     }
   }
 
+  #[derive(Clone)]
   pub struct MaskRegister {
     flags: u8
   }
@@ -195,6 +214,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     }
   }
 
+  #[derive(Clone)]
   pub struct ControllerRegister {
     flags: u8
   }
@@ -273,6 +293,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
   }
 
   #[derive(Clone, Copy)]
+  #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
   pub struct VramRegister {
     flags: u16
   }
@@ -335,7 +356,47 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     }
   }
 
+  // Address of the background data for the tile the VRAM register points at
+  // See: https://www.nesdev.org/wiki/PPU_scrolling#Tile_and_attribute_fetching
+  #[derive(Clone, Copy, Debug, PartialEq)]
+  pub struct NametableAddress(u16);
+
+  impl NametableAddress {
+    // 0x2000 | (v & 0x0FFF): name table select, coarse Y and coarse X
+    pub fn tile_address(v: &VramRegister) -> NametableAddress {
+      return NametableAddress(0x2000 | (v.flags & 0x0FFF));
+    }
+
+    // 0x23C0 | NN | YYY | XXX: each attribute byte covers 4x4 tiles, so only the high 3 bits of coarse Y and X are used
+    pub fn attribute_address(v: &VramRegister) -> NametableAddress {
+      return NametableAddress(
+        0x23C0 |
+        ((v.get_nametable_y() as u16) << 11) |
+        ((v.get_nametable_x() as u16) << 10) |
+        (((v.get_coarse_y() as u16) >> 2) << 3) |
+        ((v.get_coarse_x() as u16) >> 2)
+      );
+    }
+
+    pub fn addr(&self) -> u16 {
+      return self.0;
+    }
+  }
+
+  // Snapshot of the PPU's internal scroll/timing state, used by the debug panel
+  #[derive(Clone, Copy, Debug)]
+  pub struct PpuDebugState {
+    pub scan_line: i16,
+    pub cycle: i16,
+    pub fine_x: u8,
+    pub vram_addr: u16,
+    pub temp_vram_addr: u16,
+    pub vertical_blank: u8,
+    pub sprite_zero_hit: u8,
+  }
+
 #[derive(Default, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
   pub struct SpriteObj {
     y: u8,
     tile_id: u8,
@@ -343,16 +404,47 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     x: u8
   }
 
+  // A buffer the PPU draws into while the UI reads from it. Cloning it copies the contents instead of sharing them,
+  // so that a copy of the PPU (e.g. in a save state) doesn't draw over the original's screen.
+  // The contents are boxed so they're only copied through the stack once, which is too small for them in test threads.
+  pub struct VisBuffer<T>(SharedVisBuffer<T>);
+
+  // What the visualizers hold on to
+  pub type SharedVisBuffer<T> = Arc<Mutex<Box<T>>>;
+
+  impl<T> VisBuffer<T> {
+    fn new(contents: T) -> VisBuffer<T> {
+      return VisBuffer(Arc::new(Mutex::new(Box::new(contents))));
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, Box<T>>> {
+      return self.0.lock();
+    }
+
+    // The same buffer, for the visualizers to draw from
+    pub fn share(&self) -> SharedVisBuffer<T> {
+      return self.0.clone();
+    }
+  }
+
+  impl<T: Copy> Clone for VisBuffer<T> {
+    fn clone(&self) -> VisBuffer<T> {
+      let contents: T = **self.0.lock().unwrap();
+      return VisBuffer::new(contents);
+    }
+  }
+
+  #[derive(Clone)]
   pub struct Ben2C02 {
     memory_bounds: (u16, u16),
 
-    cartridge: Rc<RefCell<Cartridge>>,
-
     scan_line: i16,
     cycle: i16,
     pub frame_render_complete: bool,
     odd_frame: bool,
     pub trigger_cpu_nmi: bool,
+    // Sets the amount of scanlines per frame, and whether odd frames are a cycle shorter
+    region: Region,
 
     controller_reg: ControllerRegister,
     mask_reg: MaskRegister,
@@ -360,6 +452,10 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     writing_high_byte_of_addr: bool,
     ppu_data_read_buffer: u8,
     oam_data_addr: u8,
+    // Last value driven onto the data bus between the CPU and the PPU registers. Reading a write-only
+    // register (or the unused bits of the status register) returns it ("open bus").
+    // Reference: https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+    ppu_bus_latch: u8,
 
     vram_reg: VramRegister,
     temp_vram_reg: VramRegister,
@@ -388,7 +484,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
     pattern_tables: [[u8; 4096]; 2],
     pattern_tables_mem_bounds: (u16, u16),
-    name_tables: [[u8; 1024]; 2],
+    name_tables: [[u8; 1024]; 4],
     name_tables_mem_bounds: (u16, u16),
     pub palette: [u8; 32],
     palette_mem_bounds: (u16, u16),
@@ -396,29 +492,32 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
     
     // These arrays are used for emulator visualization, thus the higher level Color structure
-    pub screen_vis_buffer: [[Color; 256]; 240],
-    pub pattern_tables_vis_buffer: [[[Color; 128]; 128]; 2],
-    name_tables_vis_buffer: [[[Color; 256]; 240]; 2],
+    // Shared with the UI visualizers, so that they can draw straight from the PPU output without copying it every frame
+    pub screen_vis_buffer: VisBuffer<[[Color; 256]; 240]>,
+    pub pattern_tables_vis_buffer: VisBuffer<[[[Color; 128]; 128]; 2]>,
+    pub name_tables_vis_buffer: VisBuffer<[[[Color; 256]; 240]; 2]>,
     pub palette_vis_bufer: [Color; 64],
   }
 
   impl Ben2C02 {
-    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Ben2C02 {
+    // The PPU doesn't own the cartridge, since the CPU reaches it too. It's lent to the PPU on every access to the PPU bus.
+    pub fn new() -> Ben2C02 {
       return Ben2C02 {
         memory_bounds: PPU_MEMORY_BOUNDS,
-        cartridge: cartridge,
-        
+
         scan_line: 0,
         cycle: 0,
         frame_render_complete: false,
         odd_frame: false,
         trigger_cpu_nmi: false,
+        region: Region::Ntsc,
 
         controller_reg: ControllerRegister::new(),
         mask_reg: MaskRegister::new(),
         status_reg: StatusRegister::new(),
         writing_high_byte_of_addr: true,
         ppu_data_read_buffer: 0,
+        ppu_bus_latch: 0,
         oam_data_addr: 0,
 
         vram_reg: VramRegister::new(),
@@ -444,7 +543,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
         pattern_tables: [[0; 4096]; 2],
         pattern_tables_mem_bounds: (0x0000, 0x1FFF),
-        name_tables: [[0; 1024]; 2],
+        name_tables: [[0; 1024]; 4],
         name_tables_mem_bounds: (0x2000, 0x3EFF),
         palette: [0; 32],
         palette_mem_bounds: (0x3F00, 0x3FFF),
@@ -452,12 +551,68 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
 
         palette_vis_bufer: create_palette_vis_buffer(),
-        screen_vis_buffer: [[Color::new(0, 0, 0); 256]; 240],
-        name_tables_vis_buffer: [[[Color::new(0, 0, 0); 256]; 240]; 2],
-        pattern_tables_vis_buffer: [[[Color::new(0, 0, 0); 128]; 128]; 2],
+        screen_vis_buffer: VisBuffer::new([[Color::new(0, 0, 0); 256]; 240]),
+        name_tables_vis_buffer: VisBuffer::new([[[Color::new(0, 0, 0); 256]; 240]; 2]),
+        pattern_tables_vis_buffer: VisBuffer::new([[[Color::new(0, 0, 0); 128]; 128]; 2]),
       }
     }
 
+    // Puts the rendering state back to the start of a frame, while keeping the contents of VRAM, palette and OAM
+    pub fn reset(&mut self) {
+      self.scan_line = -1;
+      self.cycle = 0;
+      self.frame_render_complete = false;
+      self.odd_frame = false;
+      self.trigger_cpu_nmi = false;
+
+      self.controller_reg = ControllerRegister::new();
+      self.mask_reg = MaskRegister::new();
+      self.status_reg = StatusRegister::new();
+      self.writing_high_byte_of_addr = true;
+      self.ppu_data_read_buffer = 0;
+
+      self.vram_reg = VramRegister::new();
+      self.temp_vram_reg = VramRegister::new();
+      self.fine_x = 0;
+
+      self.bg_next_tile_id = 0;
+      self.bg_next_tile_attribute = 0;
+      self.bg_next_tile_lsb = 0;
+      self.bg_next_tile_msb = 0;
+
+      self.bg_shifter_pattern_lo = 0;
+      self.bg_shifter_pattern_hi = 0;
+      self.bg_shifter_attrib_lo = 0;
+      self.bg_shifter_attrib_hi = 0;
+
+      self.sprites_on_curr_scanline.clear();
+      self.sprites_on_curr_scanline_pattern_lsb.clear();
+      self.sprites_on_curr_scanline_pattern_msb.clear();
+      self.sprite_zero_hit_possible = false;
+      self.sprite_zero_being_rendered = false;
+    }
+
+    pub fn region(&self) -> Region {
+      return self.region;
+    }
+
+    // Kept on reset, since it's the console that has a region
+    pub fn set_region(&mut self, region: Region) {
+      self.region = region;
+    }
+
+    pub fn get_debug_state(&self) -> PpuDebugState {
+      return PpuDebugState {
+        scan_line: self.scan_line,
+        cycle: self.cycle,
+        fine_x: self.fine_x,
+        vram_addr: self.vram_reg.flags,
+        temp_vram_addr: self.temp_vram_reg.flags,
+        vertical_blank: self.status_reg.get_vertical_blank(),
+        sprite_zero_hit: self.status_reg.get_sprite_zero_hit(),
+      };
+    }
+
     fn in_pattern_table_memory_bounds(&self, addr: u16) -> bool {
       return addr >= self.pattern_tables_mem_bounds.0 && addr <= self.pattern_tables_mem_bounds.1;
     }
@@ -470,12 +625,23 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
       return addr >= self.palette_mem_bounds.0 && addr <= self.palette_mem_bounds.1;
     }
 
-    pub fn clock_cycle(&mut self) {
+    // Runs the PPU on its own until the current frame is done, and returns a copy of it. Meant for headless rendering,
+    // where nothing else (the CPU, for example) needs to run in between.
+    // The copy is boxed, since a whole screen is too big for the stack of a test thread in debug builds.
+    pub fn render_frame_to_buffer(&mut self, cartridge: &mut Cartridge) -> Box<[[Color; 256]; 240]> {
+      while (!self.frame_render_complete) {
+        self.clock_cycle(cartridge);
+      }
+      self.frame_render_complete = false;
+      return self.screen_vis_buffer.lock().unwrap().to_vec().into_boxed_slice().try_into().unwrap();
+    }
+
+    pub fn clock_cycle(&mut self, cartridge: &mut Cartridge) {
 
       // This cycle stravaganza is very concisely explained here: https://www.nesdev.org/w/images/default/4/4f/Ppu.svg
       if (self.scan_line >= -1 && self.scan_line < 240) {
 
-        if (self.scan_line == 0 && self.cycle == 0 && self.odd_frame && (self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0)) {
+        if (self.scan_line == 0 && self.cycle == 0 && self.odd_frame && self.region == Region::Ntsc && (self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0)) {
           // "Odd Frame" cycle skip
           self.cycle = 1;
         }
@@ -498,18 +664,13 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
           match ((self.cycle - 1) % 8) {
             0 => {
               self.load_background_shift_registers_with_next_tile();
-              self.bg_next_tile_id = self.read_from_ppu_bus(0x2000 | (self.vram_reg.flags & 0xFFF)).unwrap();
+              self.bg_next_tile_id = self.read_from_ppu_bus(NametableAddress::tile_address(&self.vram_reg).addr(), cartridge).unwrap();
             },
             1 => {
 
             },
             2 => {
-              self.bg_next_tile_attribute = self.read_from_ppu_bus(
-                                                  0x23C0 |
-                                                  ((self.vram_reg.get_nametable_y() as u16) << 11) |
-                                                  ((self.vram_reg.get_nametable_x() as u16) << 10) |
-                                                  (((self.vram_reg.get_coarse_y() as u16) >> 2) << 3) |
-                                                  ((self.vram_reg.get_coarse_x() as u16) >> 2)).unwrap();
+              self.bg_next_tile_attribute = self.read_from_ppu_bus(NametableAddress::attribute_address(&self.vram_reg).addr(), cartridge).unwrap();
               if ((self.vram_reg.get_coarse_y() & 0x02) != 0) {
                 self.bg_next_tile_attribute >>= 4;
               }
@@ -525,7 +686,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
               self.bg_next_tile_lsb = self.read_from_ppu_bus(
                                             ((self.controller_reg.get_pattern_background() as u16) << 12) +
                                                   ((self.bg_next_tile_id as u16) * 16) +
-                                                  (self.vram_reg.get_fine_y() as u16)).unwrap();
+                                                  (self.vram_reg.get_fine_y() as u16), cartridge).unwrap();
             },
             5 => {
 
@@ -534,7 +695,7 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
               self.bg_next_tile_msb = self.read_from_ppu_bus(
                                             ((self.controller_reg.get_pattern_background() as u16) << 12) +
                                                   ((self.bg_next_tile_id as u16) * 16) +
-                                                  (self.vram_reg.get_fine_y() as u16) + 8).unwrap();
+                                                  (self.vram_reg.get_fine_y() as u16) + 8, cartridge).unwrap();
             },
             7 => {
               if self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0 {
@@ -545,6 +706,12 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
           }
         }
 
+        // The name table byte of the next tile is fetched twice more and thrown away. Along with the first fetch of the
+        // next scanline, that's three reads of the same address in a row, which MMC5 uses to count scanlines.
+        if ((self.cycle == 337 || self.cycle == 339) && (self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0)) {
+          self.read_from_ppu_bus(NametableAddress::tile_address(&self.vram_reg).addr(), cartridge).unwrap();
+        }
+
         if (self.cycle == 256) {
           if self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0 {
             self.increment_scroll_y();
@@ -625,8 +792,8 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
             }
             sprite_color_value_msb_addr = sprite_color_value_lsb_addr + 8;
 
-            let mut sprite_color_value_lsb = self.read_from_ppu_bus(sprite_color_value_lsb_addr).unwrap();
-            let mut sprite_color_value_msb = self.read_from_ppu_bus(sprite_color_value_msb_addr).unwrap();
+            let mut sprite_color_value_lsb = self.read_from_ppu_bus(sprite_color_value_lsb_addr, cartridge).unwrap();
+            let mut sprite_color_value_msb = self.read_from_ppu_bus(sprite_color_value_msb_addr, cartridge).unwrap();
 
             if ((sprite.attributes & 0x40) != 0) { // Sprite is flipped horizontally
               sprite_color_value_lsb = sprite_color_value_lsb.reverse_bits();
@@ -635,6 +802,20 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
             self.sprites_on_curr_scanline_pattern_lsb.push(sprite_color_value_lsb);
             self.sprites_on_curr_scanline_pattern_msb.push(sprite_color_value_msb);
           }
+
+          // The PPU fetches 8 sprites per scanline no matter what, using tile $FF for the empty slots.
+          // The data is thrown away, but mappers that count scanlines through the pattern fetches (MMC3) rely on them.
+          if (self.mask_reg.get_render_background() != 0 || self.mask_reg.get_render_sprites() != 0) {
+            let dummy_tile_addr = if (self.controller_reg.get_sprite_size() == 0) {
+              (if (self.controller_reg.get_pattern_sprite() != 0) { 0x1000 } else { 0x0000 }) + 0xFF * 16
+            } else {
+              0x1000 + 0xFE * 16
+            };
+            for _ in self.sprites_on_curr_scanline.len()..8 {
+              self.read_from_ppu_bus(dummy_tile_addr, cartridge).unwrap();
+              self.read_from_ppu_bus(dummy_tile_addr + 8, cartridge).unwrap();
+            }
+          }
         }
         
       }
@@ -735,14 +916,16 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
       }
 
       if (self.cycle < 256 && self.scan_line < 240 && self.scan_line != -1) {
-        self.screen_vis_buffer[self.scan_line as usize][self.cycle as usize] = self.get_color_from_palette(result_pixel_value, result_palette_id);
+        let pixel_color = self.get_color_from_palette(result_pixel_value, result_palette_id);
+        self.screen_vis_buffer.lock().unwrap()[self.scan_line as usize][self.cycle as usize] = pixel_color;
       }
 
       self.cycle += 1;
       if self.cycle > 340 {
         self.cycle = 0;
         self.scan_line += 1;
-        if (self.scan_line > 260) {
+        // The last scanline is the pre-render one, which is numbered -1
+        if (self.scan_line > self.region.scanlines_per_frame() - 2) {
           self.scan_line = -1;
           self.frame_render_complete = true;
           self.odd_frame = !self.odd_frame;
@@ -830,21 +1013,23 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
     // Refer to https://www.nesdev.org/wiki/PPU_programmer_reference#Pattern_tables
     // for a clearer explanation :)
-    pub fn update_pattern_tables_vis_buffer(&mut self, palette_id: u8) {
+    pub fn update_pattern_tables_vis_buffer(&mut self, palette_id: u8, cartridge: &Cartridge) {
       const PATTERN_TABLE_SIZE: u16 = 4096;
+      let pattern_tables_vis_buffer = self.pattern_tables_vis_buffer.share();
+      let mut pattern_tables_vis_buffer = pattern_tables_vis_buffer.lock().unwrap();
       for pattern_table_id in 0..2 {
         let start_addr = PATTERN_TABLE_SIZE * pattern_table_id;
         for tileIndexRow in 0..16 {
           for tileIndexCol in 0..16 {
             for pixelRow in 0..8 {
-              let tile_lsb_data = self.read_from_ppu_bus(start_addr + tileIndexCol * 16 + tileIndexRow * 256 + pixelRow).unwrap();
-              let tile_msb_data = self.read_from_ppu_bus(start_addr + tileIndexCol * 16 + tileIndexRow * 256 + pixelRow + 8).unwrap();
+              let tile_lsb_data = self.read_debug_from_ppu_bus(start_addr + tileIndexCol * 16 + tileIndexRow * 256 + pixelRow, cartridge);
+              let tile_msb_data = self.read_debug_from_ppu_bus(start_addr + tileIndexCol * 16 + tileIndexRow * 256 + pixelRow + 8, cartridge);
               for pixelCol in 0..8 {
                 let pixel_value_lsb = bitwise_utils::get_bit(tile_lsb_data, 7 - pixelCol);
                 let pixel_value_msb = bitwise_utils::get_bit(tile_msb_data, 7 - pixelCol);
                 let pixel_value = (pixel_value_msb << 1) + pixel_value_lsb;
                 let pixel_color = self.get_color_from_palette(pixel_value, palette_id);
-                self.pattern_tables_vis_buffer[pattern_table_id as usize][(tileIndexCol as u8 * 8 + pixelCol) as usize][(tileIndexRow * 8 + pixelRow) as usize] = pixel_color;
+                pattern_tables_vis_buffer[pattern_table_id as usize][(tileIndexCol as u8 * 8 + pixelCol) as usize][(tileIndexRow * 8 + pixelRow) as usize] = pixel_color;
               }
             }
           }
@@ -853,6 +1038,77 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
       
     }
 
+    // Returns the 2 bit value of a pixel of the given tile, reading the pattern data through the cartridge like the renderer does
+    fn get_tile_pixel_value(&self, pattern_table_id: u16, tile_id: u8, pixel_row: u16, pixel_col: u8, cartridge: &Cartridge) -> u8 {
+      let tile_addr = pattern_table_id * 4096 + (tile_id as u16) * 16 + pixel_row;
+      let tile_lsb_data = self.read_debug_from_ppu_bus(tile_addr, cartridge);
+      let tile_msb_data = self.read_debug_from_ppu_bus(tile_addr + 8, cartridge);
+      return (bitwise_utils::get_bit(tile_msb_data, 7 - pixel_col) << 1) + bitwise_utils::get_bit(tile_lsb_data, 7 - pixel_col);
+    }
+
+    // Renders a whole pattern table (16x16 tiles) with the given palette, as a row-major 128x128 RGB buffer
+    pub fn get_pattern_table_as_rgb(&self, table_id: usize, palette_id: u8, cartridge: &Cartridge) -> Vec<u8> {
+      let mut result = Vec::with_capacity(128 * 128 * 3);
+      for y in 0..128u16 {
+        for x in 0..128u16 {
+          let tile_id = ((y / 8) * 16 + x / 8) as u8;
+          let pixel_value = self.get_tile_pixel_value(table_id as u16, tile_id, y % 8, (x % 8) as u8, cartridge);
+          let color = self.get_color_from_palette(pixel_value, palette_id);
+          result.extend_from_slice(&[color.red, color.green, color.blue]);
+        }
+      }
+      return result;
+    }
+
+    // Color of a pixel of the background described by one of the name tables (ignoring scroll and sprites)
+    // Reference: https://www.nesdev.org/wiki/PPU_attribute_tables
+    fn get_nametable_pixel_color(&self, table_id: usize, x: u16, y: u16, cartridge: &Cartridge) -> Color {
+      let name_table = &self.name_tables[table_id];
+      let pattern_table_id = self.controller_reg.get_pattern_background() as u16;
+      let tile_row = y / 8;
+      let tile_col = x / 8;
+      let tile_id = name_table[(tile_row * 32 + tile_col) as usize];
+
+      // Each attribute byte covers a 4x4 tile area, with 2 bits for each of its 2x2 tile quadrants
+      let attribute = name_table[(0x3C0 + (tile_row / 4) * 8 + tile_col / 4) as usize];
+      let attribute_shift = ((tile_row % 4) / 2) * 4 + ((tile_col % 4) / 2) * 2;
+      let palette_id = (attribute >> attribute_shift) & 0x03;
+
+      let pixel_value = self.get_tile_pixel_value(pattern_table_id, tile_id, y % 8, (x % 8) as u8, cartridge);
+      // Transparent pixels always show the universal background color
+      return self.get_color_from_palette(pixel_value, if (pixel_value == 0) { 0 } else { palette_id });
+    }
+
+    // Renders one of the name tables as a row-major 256x240 RGB buffer
+    pub fn get_nametable_as_rgb(&self, table_id: usize, cartridge: &Cartridge) -> Vec<u8> {
+      let mut result = Vec::with_capacity(256 * 240 * 3);
+      for y in 0..240u16 {
+        for x in 0..256u16 {
+          let color = self.get_nametable_pixel_color(table_id, x, y, cartridge);
+          result.extend_from_slice(&[color.red, color.green, color.blue]);
+        }
+      }
+      return result;
+    }
+
+    // Fills the name table viewer buffers, one 32x30 tile screen per name table
+    pub fn update_name_tables_vis_buffer(&mut self, cartridge: &Cartridge) {
+      let name_tables_vis_buffer = self.name_tables_vis_buffer.share();
+      let mut name_tables_vis_buffer = name_tables_vis_buffer.lock().unwrap();
+      for name_table_id in 0..2 {
+        for y in 0..240u16 {
+          for x in 0..256u16 {
+            name_tables_vis_buffer[name_table_id][y as usize][x as usize] = self.get_nametable_pixel_color(name_table_id, x, y, cartridge);
+          }
+        }
+      }
+    }
+
+    // Colors of the 4 pixel values of one of the palettes, as they're shown by the pattern table visualizer
+    pub fn get_palette_colors(&self, palette_id: u8) -> [Color; 4] {
+      return [0, 1, 2, 3].map(|pixel_value| self.get_color_from_palette(pixel_value, palette_id));
+    }
+
     fn get_color_from_palette(&self, pixel_value: u8, palette_id: u8) -> Color {
       let pixel_color_code = self.palette[(palette_id * 4 + pixel_value) as usize];
       return self.palette_vis_bufer[pixel_color_code as usize];
@@ -889,38 +1145,13 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
     }
 
     // Useful: https://www.nesdev.org/wiki/PPU_memory_map
-    fn write_to_ppu_memory(&mut self, addr: u16, data: u8) -> Result<(), String>{
+    fn write_to_ppu_memory(&mut self, addr: u16, data: u8, mirroring_mode: MirroringMode) -> Result<(), String>{
       if self.in_pattern_table_memory_bounds(addr) {
 		    self.pattern_tables[((addr & 0x1000) > 0) as usize][(addr & 0x0FFF) as usize] = data;
         return Ok(());
       }
       else if self.in_name_table_memory_bounds(addr) {
-        let mirroring_mode = self.cartridge.borrow_mut().mirroring_mode;
-
-        if addr <= 0x23FF {
-          self.name_tables[0][(addr & 0x3FF) as usize] = data;
-        } else if addr <= 0x27FF {
-          if (matches!(mirroring_mode, MirroringMode::Horizontal)) {
-            self.name_tables[0][(addr & 0x3FF) as usize] = data;
-          } else if (matches!(mirroring_mode, MirroringMode::Vertical)) {
-            self.name_tables[1][(addr & 0x3FF) as usize] = data;
-          } else {
-            todo!("Mirroring mode {:?} not implemented!", mirroring_mode);
-          }
-        } else if addr <= 0x2BFF {
-          if (matches!(mirroring_mode, MirroringMode::Horizontal)) {
-            self.name_tables[1][(addr & 0x3FF) as usize] = data;
-          } else if (matches!(mirroring_mode, MirroringMode::Vertical)) {
-            self.name_tables[0][(addr & 0x3FF) as usize] = data;
-          } else {
-            todo!("Mirroring mode {:?} not implemented!", mirroring_mode);
-          }
-        } else if addr <= 0x2FFF {
-          self.name_tables[1][(addr & 0x3FF) as usize] = data;
-        } else {
-          // Addresses 3000-3EFF mirror addresses 2000-2EFF
-          return self.write_to_ppu_memory(addr - 0x1000, data);
-        }
+        self.name_tables[name_table_index(addr, mirroring_mode)][(addr & 0x3FF) as usize] = data;
         return Ok(());
       }
       else if self.in_palette_memory_bounds(addr) {
@@ -934,37 +1165,13 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
       }
     }
 
-    fn read_from_ppu_memory(&self, addr: u16) -> Result<u8, String>{
+    fn read_from_ppu_memory(&self, addr: u16, mirroring_mode: MirroringMode) -> Result<u8, String>{
       if self.in_pattern_table_memory_bounds(addr) {
 		    let data = self.pattern_tables[((addr & 0x1000) > 0) as usize][(addr & 0x0FFF) as usize];
         return Ok(data);
       }
       else if self.in_name_table_memory_bounds(addr) {
-        let mirroring_mode = self.cartridge.borrow().mirroring_mode;
-        if addr <= 0x23FF {
-          return Ok(self.name_tables[0][(addr & 0x3FF) as usize]);
-        } else if addr <= 0x27FF {
-          if (matches!(mirroring_mode, MirroringMode::Horizontal)) {
-            return Ok(self.name_tables[0][(addr & 0x3FF) as usize]);
-          } else if (matches!(mirroring_mode, MirroringMode::Vertical)) {
-            return Ok(self.name_tables[1][(addr & 0x3FF) as usize]);
-          } else {
-            todo!("Mirroring mode {:?} not implemented!", mirroring_mode);
-          }
-        } else if addr <= 0x2BFF {
-          if (matches!(mirroring_mode, MirroringMode::Horizontal)) {
-            return Ok(self.name_tables[1][(addr & 0x3FF) as usize]);
-          } else if (matches!(mirroring_mode, MirroringMode::Vertical)) {
-            return Ok(self.name_tables[0][(addr & 0x3FF) as usize]);
-          } else {
-            todo!("Mirroring mode {:?} not implemented!", mirroring_mode);
-          }
-        } else if addr <= 0x2FFF {
-          return Ok(self.name_tables[1][(addr & 0x3FF) as usize]);
-        } else {
-          // Addresses 3000-3EFF mirror addresses 2000-2EFF
-          return self.read_from_ppu_memory(addr - 0x1000);
-        }
+        return Ok(self.name_tables[name_table_index(addr, mirroring_mode)][(addr & 0x3FF) as usize]);
       }
       else if self.in_palette_memory_bounds(addr) {
         let data = self.palette[self.address_to_palette_index(addr)];
@@ -976,33 +1183,72 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
       }
     }
 
-    fn read_from_ppu_bus(&self, addr: u16) -> Result<u8, String> {
-      let read_from_cartridge = self.cartridge.borrow_mut().read(addr);
+    fn read_from_ppu_bus(&self, addr: u16, cartridge: &mut Cartridge) -> Result<u8, String> {
+      let read_from_cartridge = cartridge.read(addr);
       match read_from_cartridge {
         Ok(retrieved_data) => {
           return Ok(retrieved_data);
         },
         Err(message) => {
-          // println!("Tried to read from cartridge, but failed with error: {}. Reading from PPU internal memory instead :)" , message);
-          return Ok(self.read_from_ppu_memory(addr).unwrap());
+          log::trace!("Tried to read from cartridge, but failed with error: {}. Reading from PPU internal memory instead :)" , message);
+          return Ok(self.read_from_ppu_memory(addr, cartridge.current_mirroring()).unwrap());
         }
       }
     }
 
-    fn write_to_ppu_bus(&mut self, addr: u16, data: u8) -> Result<(), String> {
-      let write_to_cartridge = self.cartridge.borrow_mut().write(addr, data);
+    // Used by the visualizers, so that they don't affect the state of mappers that react to PPU reads
+    fn read_debug_from_ppu_bus(&self, addr: u16, cartridge: &Cartridge) -> u8 {
+      return match cartridge.read_debug(addr) {
+        Ok(retrieved_data) => retrieved_data,
+        Err(_) => self.read_from_ppu_memory(addr, cartridge.current_mirroring()).unwrap(),
+      };
+    }
+
+    fn write_to_ppu_bus(&mut self, addr: u16, data: u8, cartridge: &mut Cartridge) -> Result<(), String> {
+      let write_to_cartridge = cartridge.write(addr, data);
       match write_to_cartridge {
         Ok(()) => {
           return Ok(());
         },
         Err(message) => {
-          // println!("Tried to write to cartridge, but failed with error: {}. Writing to PPU internal memory instead :)" , message);
-          return Ok(self.write_to_ppu_memory(addr, data).unwrap());
+          log::trace!("Tried to write to cartridge, but failed with error: {}. Writing to PPU internal memory instead :)" , message);
+          return Ok(self.write_to_ppu_memory(addr, data, cartridge.current_mirroring()).unwrap());
         }
       }
     }
 
-    fn read_from_oam_memory(&self, addr: u8) -> u8 {
+    // Top left corner and height of every sprite that's on screen, in OAM order
+    pub fn sprite_bounding_boxes(&self) -> Vec<(u8, u8, u8)> {
+      let sprite_height = if (self.controller_reg.get_sprite_size() != 0) { 16 } else { 8 };
+      return self.oam_memory.iter()
+        // Games hide the sprites they don't use below the screen
+        .filter(|sprite| sprite.y < 240)
+        .map(|sprite| (sprite.x, sprite.y, sprite_height))
+        .collect();
+    }
+
+    // One row per sprite, with the attribute byte split into its fields. Priority is 0 for sprites in front of the background.
+    // Reference: https://www.nesdev.org/wiki/PPU_OAM#Byte_2
+    pub fn dump_oam_as_csv(&self) -> String {
+      let mut result = String::from("index,x,y,tile_id,palette,priority,flip_h,flip_v\n");
+      for (index, sprite) in self.oam_memory.iter().enumerate() {
+        result.push_str(&format!("{},{},{},{},{},{},{},{}\n", index, sprite.x, sprite.y, sprite.tile_id, sprite.attributes & 0x03,
+          (sprite.attributes >> 5) & 0x01, (sprite.attributes >> 6) & 0x01, (sprite.attributes >> 7) & 0x01));
+      }
+      return result;
+    }
+
+    // One row per palette RAM entry, with the color it's shown as
+    pub fn dump_palette_as_csv(&self) -> String {
+      let mut result = String::from("index,value,red,green,blue\n");
+      for (index, value) in self.palette.iter().enumerate() {
+        let color = self.palette_vis_bufer[(value & 0x3F) as usize];
+        result.push_str(&format!("{},{},{},{},{}\n", index, value, color.red, color.green, color.blue));
+      }
+      return result;
+    }
+
+    pub fn read_from_oam_memory(&self, addr: u8) -> u8 {
       let index = (addr / 4) as usize;
       match (addr % 4) {
         0 => {
@@ -1046,18 +1292,22 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
   
   }
 
-  impl Device for Ben2C02 {
+  // Accesses from the CPU to the PPU registers ($2000-$3FFF). They work like the Device trait, but PPUDATA goes through
+  // the PPU bus, so the cartridge has to be passed in.
+  impl Ben2C02 {
 
-    fn in_memory_bounds(&self, addr: u16)-> bool {
+    pub fn in_memory_bounds(&self, addr: u16)-> bool {
       return  addr >= self.memory_bounds.0 && addr <= self.memory_bounds.1;
     }
 
-    fn write(&mut self, addr: u16, data: u8) -> Result<(), String> {
+    pub fn write_register(&mut self, addr: u16, data: u8, cartridge: &mut Cartridge) -> Result<(), DeviceError> {
       if self.in_memory_bounds(addr) {
+        self.ppu_bus_latch = data;
         let mirrored_addr = addr & 0x0007;
         match mirrored_addr {
           0x0 => { // Control
             self.controller_reg.flags = data;
+            cartridge.ppu_control_written(data);
             self.temp_vram_reg.set_nametable_x(self.controller_reg.get_nametable_x());
             self.temp_vram_reg.set_nametable_y(self.controller_reg.get_nametable_y());
           },
@@ -1097,58 +1347,41 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
             self.writing_high_byte_of_addr = !self.writing_high_byte_of_addr;
           },
           0x7 => { // PPU data
-            self.write_to_ppu_bus(self.vram_reg.flags, data).unwrap();
+            self.write_to_ppu_bus(self.vram_reg.flags, data, cartridge).unwrap();
             let increment_amount = if (self.controller_reg.get_increment_mode() != 0) { 32 } else { 1 };
             self.vram_reg.flags = (self.vram_reg.flags + increment_amount) & 0x3FFF;
             return Ok(());
           },
-          _ => return Err(String::from("Error while mirroring address in PPU write() function!"))
+          _ => return Err(DeviceError::OutOfBounds)
         }
         return Ok(());
       } else {
-        return Err(String::from("Tried to write outside PPU bounds!"));
+        return Err(DeviceError::OutOfBounds);
       }
     }
 
-    fn read(&mut self, addr: u16) -> Result<u8, String> {
+    pub fn read_register(&mut self, addr: u16, cartridge: &mut Cartridge) -> Result<u8, DeviceError> {
       if self.in_memory_bounds(addr) {
         let mirrored_addr = addr & 0x0007;
         match mirrored_addr {
-          0x0 => { // Control
-            return Ok(self.controller_reg.flags);
-            // panic!("Tried to read from PPU control register, which is not readable!");
-          },
-          0x1 => { // Mask
-            return Ok(self.mask_reg.flags);
-            // panic!("Tried to read from PPU mask register, which is not readable!");
+          // Control, mask, OAM address, scroll and PPU address are write only
+          0x0 | 0x1 | 0x3 | 0x5 | 0x6 => {
+            return Ok(self.ppu_bus_latch);
           },
           0x2 => { // Status
-            // We use the 3 most significant bits of the status register
-            // and the 5 least sifgnificant bits of the data buffer
-            let result = (self.status_reg.flags & 0xE0) + (self.ppu_data_read_buffer & 0x1F);
+            // Only the 3 most significant bits are driven by the status register, the rest is open bus
+            let result = (self.status_reg.flags & 0xE0) | (self.ppu_bus_latch & 0x1F);
             self.status_reg.set_vertical_blank(0);
             self.writing_high_byte_of_addr = true;
+            self.ppu_bus_latch = result;
             return Ok(result);
           },
-          0x3 => { // OAM Address
-            return Ok(self.oam_data_addr);
-            // return Err(String::from("CPU tried to read from OAM address register, which is undefined."));
-          },
           0x4 => { // OAM Data
-            return Ok(self.read_from_oam_memory(self.oam_data_addr));
-            // return Err(String::from("CPU tried to read from OAM data register, which is undefined."));
-            
-          },
-          0x5 => { // Scroll
-            panic!("Tried to read from PPU scroll register, which is not readable!");
-            // return Ok(0);
-          },
-          0x6 => { // PPU Address
-            panic!("Tried to read from PPU address register, which is not readable!");
-            // return Ok(0);
+            self.ppu_bus_latch = self.read_from_oam_memory(self.oam_data_addr);
+            return Ok(self.ppu_bus_latch);
           },
           0x7 => { // PPU data
-            let read_result = self.read_from_ppu_bus(self.vram_reg.flags).unwrap();
+            let read_result = self.read_from_ppu_bus(self.vram_reg.flags, cartridge).unwrap();
 
             let return_value : u8;
             // Unless reading from palette memory, we return the value that is currently 
@@ -1165,13 +1398,364 @@ use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
 
             let increment_amount = if (self.controller_reg.get_increment_mode() != 0) { 32 } else { 1 };
             self.vram_reg.flags = (self.vram_reg.flags + increment_amount) & 0x3FFF; // Are we clearing the fine_y information here? Should we restore it after the increment?
+            self.ppu_bus_latch = return_value;
             return Ok(return_value);
 
           },
-          _ => return Err(String::from("Error while mirroring address in PPU write() function!"))
+          _ => return Err(DeviceError::OutOfBounds)
         }
       } else {
-        return Err(String::from("Tried to read outside PPU bounds!"));
+        return Err(DeviceError::OutOfBounds);
+      }
+    }
+
+    // Returns what the registers hold, without clearing VBlank, resetting the address latch or touching the read buffer
+    pub fn read_register_debug(&self, addr: u16, cartridge: &Cartridge) -> Result<u8, DeviceError> {
+      if self.in_memory_bounds(addr) {
+        return match addr & 0x0007 {
+          0x0 | 0x1 | 0x3 | 0x5 | 0x6 => Ok(self.ppu_bus_latch),
+          0x2 => Ok((self.status_reg.flags & 0xE0) | (self.ppu_bus_latch & 0x1F)),
+          0x4 => Ok(self.read_from_oam_memory(self.oam_data_addr)),
+          _ => {
+            // Palette reads aren't buffered
+            if self.in_palette_memory_bounds(self.vram_reg.flags) {
+              return self.read_from_ppu_memory(self.vram_reg.flags, cartridge.current_mirroring()).map_err(|_| DeviceError::OutOfBounds);
+            }
+            Ok(self.ppu_data_read_buffer)
+          },
+        };
+      } else {
+        return Err(DeviceError::OutOfBounds);
+      }
+    }
+  }
+
+
+  #[cfg(test)]
+  mod ppu_tests {
+    use crate::{bus::{Bus16Bit, BusBuilder}, cartridge::{Cartridge, MirroringMode}, test_utils::NesRomBuilder};
+    use super::{Ben2C02, NametableAddress, VramRegister, name_table_index};
+
+    // Gives every palette entry a different color, so that we can tell which palette a pixel was drawn with
+    fn bus_with_distinct_palette() -> Bus16Bit {
+      let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+      for i in 0..32 {
+        bus.ppu_mut().palette[i] = (i as u8) + 0x10;
+      }
+      return bus;
+    }
+
+    fn pixel(buffer: &Vec<u8>, width: usize, x: usize, y: usize) -> [u8; 3] {
+      let index = (y * width + x) * 3;
+      return [buffer[index], buffer[index + 1], buffer[index + 2]];
+    }
+
+    const SOLID_TILE_VALUE_1: u8 = 1;
+    const SOLID_TILE_VALUE_2: u8 = 2;
+    const SPRITE_X: u8 = 100;
+    const SPRITE_Y: u8 = 50;
+    const SPRITE_PALETTE: u8 = 1;
+    const BEHIND_BACKGROUND: u8 = 0b0010_0000;
+
+    // Renders two frames with every background tile set to background_tile, and a single sprite drawn with tile 2.
+    // Tile 0 is transparent, tile 1 is filled with pixel value 1, and tile 2 with pixel value 2.
+    // Returns the color of a pixel inside the sprite.
+    fn render_sprite_over_background(background_tile: u8, sprite_attributes: u8) -> ([u8; 3], Bus16Bit) {
+      let mut bus = BusBuilder::new().build();
+      {
+        let (ppu, cartridge) = bus.ppu_and_cartridge();
+        for row in 0..8 {
+          ppu.write_to_ppu_bus(SOLID_TILE_VALUE_1 as u16 * 16 + row, 0xFF, cartridge).unwrap();
+          ppu.write_to_ppu_bus(SOLID_TILE_VALUE_2 as u16 * 16 + row + 8, 0xFF, cartridge).unwrap();
+        }
+        for i in 0..32 {
+          ppu.palette[i] = (i as u8) + 0x10;
+        }
+        for i in 0..0x3C0 {
+          ppu.name_tables[0][i] = background_tile;
+        }
+        // Move every other sprite off screen
+        for addr in 0..=0xFF {
+          ppu.write_to_oam_memory(addr, 0xFF);
+        }
+        for (offset, data) in [SPRITE_Y, SOLID_TILE_VALUE_2, sprite_attributes | SPRITE_PALETTE, SPRITE_X].iter().enumerate() {
+          ppu.write_to_oam_memory(offset as u8, *data);
+        }
+      }
+      // Show the background and sprites everywhere, both using pattern table 0
+      bus.write(0x2000, 0x00).unwrap();
+      bus.write(0x2001, 0x1E).unwrap();
+
+      let color = {
+        let (ppu, cartridge) = bus.ppu_and_cartridge();
+        ppu.render_frame_to_buffer(cartridge);
+        ppu.render_frame_to_buffer(cartridge)[SPRITE_Y as usize + 4][SPRITE_X as usize + 4]
+      };
+      return ([color.red, color.green, color.blue], bus);
+    }
+
+    fn expected_color(ppu: &Ben2C02, pixel_value: u8, palette_id: u8) -> [u8; 3] {
+      let color = ppu.get_color_from_palette(pixel_value, palette_id);
+      return [color.red, color.green, color.blue];
+    }
+
+    #[test]
+    fn test_render_frame_to_buffer() {
+      // Tile 1 is filled with pixel value 3, and only the top left tile of the screen uses it
+      let rom = NesRomBuilder::new().write_chr(16, &[0xFF; 16]).build();
+      let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+      let mut ppu = Ben2C02::new();
+      ppu.name_tables[0] = [0; 1024];
+      ppu.name_tables[0][0] = 1;
+      for i in 0..32 {
+        ppu.palette[i] = (i as u8) + 0x10;
+      }
+      ppu.write_register(0x2001, 0x0A, &mut cartridge).unwrap();
+
+      // The first frame is drawn before the pre-render line has loaded the scroll position, so only the second one counts
+      ppu.render_frame_to_buffer(&mut cartridge);
+      let screen = ppu.render_frame_to_buffer(&mut cartridge);
+      assert!(!ppu.frame_render_complete);
+      let tile_color = ppu.get_color_from_palette(3, 0);
+      let backdrop_color = ppu.get_color_from_palette(0, 0);
+      // Away from the tile edges, like the sprite tests below
+      for (x, y, expected) in [(4, 4, tile_color), (12, 4, backdrop_color), (4, 12, backdrop_color)] {
+        let color = screen[y][x];
+        assert_eq!((color.red, color.green, color.blue), (expected.red, expected.green, expected.blue), "Wrong color at ({}, {})", x, y);
+      }
+    }
+
+    #[test]
+    fn test_front_sprite_covers_background() {
+      let (color, bus) = render_sprite_over_background(SOLID_TILE_VALUE_1, 0);
+      assert_eq!(color, expected_color(&bus.ppu(), 2, 4 + SPRITE_PALETTE));
+    }
+
+    #[test]
+    fn test_back_sprite_is_hidden_by_background() {
+      let (color, bus) = render_sprite_over_background(SOLID_TILE_VALUE_1, BEHIND_BACKGROUND);
+      assert_eq!(color, expected_color(&bus.ppu(), 1, 0));
+    }
+
+    #[test]
+    fn test_back_sprite_shows_through_transparent_background() {
+      let (color, bus) = render_sprite_over_background(0, BEHIND_BACKGROUND);
+      assert_eq!(color, expected_color(&bus.ppu(), 2, 4 + SPRITE_PALETTE));
+      // Outside the sprite, only the backdrop color is left
+      let backdrop = bus.ppu().screen_vis_buffer.lock().unwrap()[SPRITE_Y as usize + 4][SPRITE_X as usize + 20];
+      assert_eq!([backdrop.red, backdrop.green, backdrop.blue], expected_color(&bus.ppu(), 0, 0));
+    }
+
+    #[test]
+    fn test_name_table_mirroring() {
+      let logical_tables = [0x2000, 0x2400, 0x2800, 0x2C00];
+      let expected_tables = [
+        (MirroringMode::Vertical, [0, 1, 0, 1]),
+        (MirroringMode::Horizontal, [0, 0, 1, 1]),
+        (MirroringMode::OnscreenLo, [0, 0, 0, 0]),
+        (MirroringMode::OnscreenHi, [1, 1, 1, 1]),
+        (MirroringMode::FourScreen, [0, 1, 2, 3]),
+        (MirroringMode::PerTable([1, 0, 0, 1]), [1, 0, 0, 1]),
+      ];
+      for (mirroring_mode, expected) in expected_tables {
+        for (addr, expected_index) in logical_tables.iter().zip(expected) {
+          assert_eq!(name_table_index(*addr + 0x3FF, mirroring_mode), expected_index);
+          // $3000-$3EFF mirrors $2000-$2EFF
+          assert_eq!(name_table_index(*addr + 0x1000, mirroring_mode), expected_index);
+        }
+      }
+    }
+
+    #[test]
+    fn test_four_screen_name_tables_are_independent() {
+      let mut rom = NesRomBuilder::new().build();
+      rom[6] |= 0x08;
+      let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+      assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::FourScreen);
+
+      let (ppu, cartridge) = bus.ppu_and_cartridge();
+      for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+        ppu.write_to_ppu_bus(*addr + 0x10, 0x40 + i as u8, cartridge).unwrap();
+      }
+      for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+        assert_eq!(ppu.read_from_ppu_bus(*addr + 0x10, cartridge).unwrap(), 0x40 + i as u8);
+        // $3000-$3EFF mirrors them too
+        assert_eq!(ppu.read_from_ppu_bus(*addr + 0x1010, cartridge).unwrap(), 0x40 + i as u8);
+        assert_eq!(ppu.name_tables[i][0x10], 0x40 + i as u8);
+      }
+    }
+
+    #[test]
+    fn test_read_debug_has_no_side_effects() {
+      let mut bus = bus_with_distinct_palette();
+      bus.ppu_mut().status_reg.set_vertical_blank(1);
+      bus.ppu_mut().writing_high_byte_of_addr = false;
+
+      let status = bus.read_debug(0x2002);
+      assert_eq!(status & 0x80, 0x80);
+      assert_eq!(bus.read_debug(0x3FFA), status);
+      assert_eq!(bus.ppu().status_reg.get_vertical_blank(), 1);
+      assert_eq!(bus.ppu().writing_high_byte_of_addr, false);
+    }
+
+    #[test]
+    fn test_write_only_registers_return_open_bus() {
+      let mut bus = bus_with_distinct_palette();
+      bus.write(0x2000, 0x80).unwrap();
+      bus.write(0x2005, 0x5A).unwrap();
+      for addr in [0x2000, 0x2001, 0x2003, 0x2005, 0x2006, 0x3FFD] {
+        assert_eq!(bus.read(addr, false).unwrap(), 0x5A);
+      }
+
+      // The unused bits of the status register also come from the latch, and reading it refreshes the latch
+      bus.ppu_mut().status_reg.set_vertical_blank(1);
+      assert_eq!(bus.read(0x2002, false).unwrap(), 0x80 | 0x1A);
+      assert_eq!(bus.read(0x2000, false).unwrap(), 0x80 | 0x1A);
+    }
+
+    #[test]
+    fn test_sprite_bounding_boxes() {
+      let mut bus = BusBuilder::new().build();
+      for addr in 0..=0xFF {
+        bus.ppu_mut().write_to_oam_memory(addr, 0xFF);
+      }
+      for (offset, data) in [0x20, 0x01, 0x00, 0x10].iter().enumerate() {
+        bus.ppu_mut().write_to_oam_memory(8 + offset as u8, *data);
+      }
+      assert_eq!(bus.ppu().sprite_bounding_boxes(), vec![(0x10, 0x20, 8)]);
+      // 8x16 sprites
+      bus.write(0x2000, 0x20).unwrap();
+      assert_eq!(bus.ppu().sprite_bounding_boxes(), vec![(0x10, 0x20, 16)]);
+    }
+
+    #[test]
+    fn test_oam_and_palette_csv_dumps() {
+      let mut bus = bus_with_distinct_palette();
+      for (offset, data) in [0x30, 0x05, 0b1010_0010, 0x40].iter().enumerate() {
+        bus.ppu_mut().write_to_oam_memory(4 + offset as u8, *data);
+      }
+      let oam_csv = bus.ppu().dump_oam_as_csv();
+      let oam_lines: Vec<&str> = oam_csv.lines().collect();
+      assert_eq!(oam_lines.len(), 65);
+      assert_eq!(oam_lines[0], "index,x,y,tile_id,palette,priority,flip_h,flip_v");
+      assert_eq!(oam_lines[2], "1,64,48,5,2,1,0,1");
+
+      let palette_csv = bus.ppu().dump_palette_as_csv();
+      let palette_lines: Vec<&str> = palette_csv.lines().collect();
+      assert_eq!(palette_lines.len(), 33);
+      let color = bus.ppu().palette_vis_bufer[0x13];
+      assert_eq!(palette_lines[4], format!("3,19,{},{},{}", color.red, color.green, color.blue));
+    }
+
+    #[test]
+    fn test_buffer_sizes() {
+      let bus = bus_with_distinct_palette();
+      let ppu = bus.ppu();
+      assert_eq!(ppu.get_pattern_table_as_rgb(0, 0, bus.cartridge()).len(), 128 * 128 * 3);
+      assert_eq!(ppu.get_nametable_as_rgb(1, bus.cartridge()).len(), 256 * 240 * 3);
+    }
+
+    #[test]
+    fn test_nametable_uses_pattern_and_attribute_data() {
+      let mut bus = bus_with_distinct_palette();
+      let tile_id: u8 = 0x41;
+      {
+        let ppu = bus.ppu_mut();
+        // Tile at (0, 0) uses palette 0, tile at (2, 2) falls in the bottom right quadrant of the first attribute byte
+        ppu.name_tables[0][0] = tile_id;
+        ppu.name_tables[0][2 * 32 + 2] = tile_id;
+        ppu.name_tables[0][0x3C0] = 0b11_00_00_00;
+      }
+      let ppu = bus.ppu();
+      let cartridge = bus.cartridge();
+      let name_table = ppu.get_nametable_as_rgb(0, cartridge);
+
+      let expected_pixel = |pixel_value: u8, palette_id: u8| {
+        let color = ppu.palette_vis_bufer[ppu.palette[if (pixel_value == 0) { 0 } else { (palette_id * 4 + pixel_value) as usize }] as usize];
+        return [color.red, color.green, color.blue];
+      };
+      let mut tile_has_detail = false;
+      for y in 0..8 {
+        for x in 0..8 {
+          let pixel_value = ppu.get_tile_pixel_value(0, tile_id, y as u16, x as u8, cartridge);
+          assert_eq!(pixel(&name_table, 256, x, y), expected_pixel(pixel_value, 0));
+          assert_eq!(pixel(&name_table, 256, 16 + x, 16 + y), expected_pixel(pixel_value, 3));
+          tile_has_detail |= pixel_value != 0;
+        }
+      }
+      assert!(tile_has_detail);
+    }
+
+    #[test]
+    fn test_name_tables_vis_buffer_matches_rgb_export() {
+      let mut bus = bus_with_distinct_palette();
+      {
+        let (ppu, cartridge) = bus.ppu_and_cartridge();
+        ppu.name_tables[0][5 * 32 + 7] = 0x41;
+        ppu.name_tables[1][0] = 0x42;
+        ppu.name_tables[1][0x3C0] = 0b00_00_00_10;
+        ppu.update_name_tables_vis_buffer(cartridge);
+      }
+      let ppu = bus.ppu();
+      let name_tables_vis_buffer = ppu.name_tables_vis_buffer.lock().unwrap();
+      for table_id in 0..2 {
+        let name_table = ppu.get_nametable_as_rgb(table_id, bus.cartridge());
+        for y in 0..240 {
+          for x in 0..256 {
+            let color = name_tables_vis_buffer[table_id][y][x];
+            assert_eq!(pixel(&name_table, 256, x, y), [color.red, color.green, color.blue]);
+          }
+        }
+      }
+    }
+
+    fn vram_register(nametable_x: u8, nametable_y: u8, coarse_x: u8, coarse_y: u8, fine_y: u8) -> VramRegister {
+      let mut v = VramRegister::new();
+      v.set_nametable_x(nametable_x);
+      v.set_nametable_y(nametable_y);
+      v.set_coarse_x(coarse_x);
+      v.set_coarse_y(coarse_y);
+      v.set_fine_y(fine_y);
+      return v;
+    }
+
+    #[test]
+    fn test_tile_address() {
+      // (nametable x, nametable y, coarse x, coarse y, expected address)
+      let cases = [
+        (0, 0, 0, 0, 0x2000),
+        (0, 0, 31, 0, 0x201F),
+        (0, 0, 0, 29, 0x23A0),
+        (1, 0, 5, 3, 0x2465),
+        (0, 1, 10, 20, 0x2A8A),
+        (1, 1, 31, 29, 0x2FBF),
+      ];
+      for (nametable_x, nametable_y, coarse_x, coarse_y, expected) in cases {
+        // Fine Y selects the row inside the tile, so it doesn't change which tile is fetched
+        let v = vram_register(nametable_x, nametable_y, coarse_x, coarse_y, 7);
+        assert_eq!(NametableAddress::tile_address(&v).addr(), expected);
+        let nametable = 0x2000 + ((nametable_y as u16) * 2 + (nametable_x as u16)) * 0x400;
+        assert_eq!(expected, nametable + (coarse_y as u16) * 32 + (coarse_x as u16));
       }
     }
-  }
\ No newline at end of file
+
+    #[test]
+    fn test_attribute_address() {
+      let cases = [
+        (0, 0, 0, 0, 0x23C0),
+        (0, 0, 3, 3, 0x23C0),
+        (0, 0, 4, 0, 0x23C1),
+        (0, 0, 31, 29, 0x23FF),
+        (1, 0, 8, 12, 0x27DA),
+        (0, 1, 16, 4, 0x2BCC),
+        (1, 1, 31, 29, 0x2FFF),
+      ];
+      for (nametable_x, nametable_y, coarse_x, coarse_y, expected) in cases {
+        let v = vram_register(nametable_x, nametable_y, coarse_x, coarse_y, 3);
+        assert_eq!(NametableAddress::attribute_address(&v).addr(), expected);
+        // The attribute table takes the last 64 bytes of the name table, one byte per 4x4 tile block
+        let nametable = 0x2000 + ((nametable_y as u16) * 2 + (nametable_x as u16)) * 0x400;
+        assert_eq!(expected, nametable + 0x3C0 + ((coarse_y as u16) / 4) * 8 + (coarse_x as u16) / 4);
+      }
+    }
+  }
@@ -1,5 +1,9 @@
-use crate::{utils::bitwise_utils, bus::Bus16Bit};
+use crate::{utils::bitwise_utils, bus::{Bus16Bit, BusError}, call_stack::{CallStack, CallFrame}};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Registers {
   pub a: u8,
   pub x: u8,
@@ -8,6 +12,8 @@ pub struct Registers {
   pub pc: u16
 }
 
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Status {
   flags: u8
 }
@@ -91,7 +97,7 @@ impl Status {
 
 #[cfg(test)]
 mod status_tests {
-    use crate::Status;
+    use super::Status;
 
   #[test]
   fn test_create_status() {
@@ -116,11 +122,22 @@ mod status_tests {
     assert_eq!(status.get_carry(), 0);
   }
 
+  #[test]
+  #[cfg(feature = "serde")]
+  fn test_status_serialization_round_trip() {
+    let mut status = Status::new();
+    status.set_carry(1);
+    status.set_negative(1);
+    let json = serde_json::to_string(&status).unwrap();
+    let deserialized: Status = serde_json::from_str(&json).unwrap();
+    assert!(deserialized == status);
+  }
+
 }
 
 
-#[derive(Debug)]
-enum AddressingMode {
+#[derive(Debug, PartialEq)]
+pub(crate) enum AddressingMode {
   ACC, // Accum
   IMM, // Immediate
   ABS, // Absolute
@@ -137,7 +154,7 @@ enum AddressingMode {
 }
 
 #[derive(Debug)]
-enum Instruction {
+pub(crate) enum Instruction {
   ADC,
   AND,
   ASL,
@@ -197,15 +214,15 @@ enum Instruction {
   XXX // Illegal instruction
 }
 
-struct InstructionData {
-  instruction: Instruction, 
-  addressing_mode: AddressingMode,
-  cycles: u8,
+pub(crate) struct InstructionData {
+  pub(crate) instruction: Instruction, 
+  pub(crate) addressing_mode: AddressingMode,
+  pub(crate) cycles: u8,
 }
 
 // Original table was taken from https://github.com/OneLoneCoder/olcNES/blob/master/Part%232%20-%20CPU/olc6502.cpp
 // Author: David Barr, aka javidx9 or OneLoneCoder
-const INSTRUCTION_TABLE: [InstructionData; 256] = 
+pub(crate) const INSTRUCTION_TABLE: [InstructionData; 256] = 
 [
   InstructionData{instruction: Instruction::BRK, addressing_mode: AddressingMode::IMP, cycles: 7 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::INX, cycles: 6 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 2 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 8 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 3 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::ZP0, cycles: 3 },InstructionData{instruction: Instruction::ASL, addressing_mode: AddressingMode::ZP0, cycles: 5 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 5 },InstructionData{instruction: Instruction::PHP, addressing_mode: AddressingMode::IMP, cycles: 3 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::IMM, cycles: 2 },InstructionData{instruction: Instruction::ASL, addressing_mode: AddressingMode::ACC, cycles: 2 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 2 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 4 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::ABS, cycles: 4 },InstructionData{instruction: Instruction::ASL, addressing_mode: AddressingMode::ABS, cycles: 6 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 6 },
   InstructionData{instruction: Instruction::BPL, addressing_mode: AddressingMode::REL, cycles: 2 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::INY, cycles: 5 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 2 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 8 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 4 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::ZPX, cycles: 4 },InstructionData{instruction: Instruction::ASL, addressing_mode: AddressingMode::ZPX, cycles: 6 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 6 },InstructionData{instruction: Instruction::CLC, addressing_mode: AddressingMode::IMP, cycles: 2 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::ABY, cycles: 4 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 2 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 7 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 4 },InstructionData{instruction: Instruction::ORA, addressing_mode: AddressingMode::ABX, cycles: 4 },InstructionData{instruction: Instruction::ASL, addressing_mode: AddressingMode::ABX, cycles: 7 },InstructionData{instruction: Instruction::XXX, addressing_mode: AddressingMode::IMP, cycles: 7 },
@@ -235,6 +252,8 @@ const INTERRUPT_START_POINTER_ADDR: u16 = 0xFFFE;
 
 const NMI_START_POINTER_ADDR: u16 = 0xFFFA;
 
+// Cloning copies the whole console (see Bus16Bit's Clone), so a clone can be used as a snapshot
+#[derive(Clone)]
 pub struct Ben6502 {
   pub bus: Bus16Bit,
 
@@ -257,6 +276,21 @@ pub struct Ben6502 {
   */
   relative_mem_address: i8,
 
+  // Total amount of cycles the CPU was clocked for
+  pub total_cycles: u64,
+  // Value of total_cycles when the CPU was last reset
+  reset_cycle: u64,
+  // Subroutine calls, for debugging
+  pub call_stack: CallStack,
+  // Amount of NMIs serviced, so that debuggers can tell when the CPU enters the NMI handler
+  pub nmi_count: u64,
+  // Interrupt disable flag as it was before the current instruction ran. The CPU polls for IRQs before CLI, SEI
+  // and PLP change the flag, so their effect on IRQs is delayed by one instruction (RTI's isn't).
+  irq_disable_prev: u8,
+
+  // Address of the instruction being run, and the first bus error it ran into
+  instruction_pc: u16,
+  bus_error: Option<BusError>,
 }
 
 impl Ben6502 {
@@ -269,7 +303,14 @@ impl Ben6502 {
       addr_mode_requires_additional_cycle: false,
       instruction_requires_additional_cycle: false,
       absolute_mem_address: 0,
-      relative_mem_address: 0
+      relative_mem_address: 0,
+      total_cycles: 0,
+      reset_cycle: 0,
+      call_stack: CallStack::new(),
+      nmi_count: 0,
+      irq_disable_prev: 0,
+      instruction_pc: 0,
+      bus_error: None,
     };
     result.reset();
     return result;
@@ -285,27 +326,27 @@ impl Ben6502 {
         self.registers.pc += 1;
       },
       AddressingMode::ABS => {
-        self.absolute_mem_address = self.bus.read_word_little_endian(self.registers.pc, false).unwrap();
+        self.absolute_mem_address = self.read_bus_word(self.registers.pc);
         self.registers.pc += 2;
       },
       AddressingMode::ZP0 => {
-        let addr_low = self.bus.read(self.registers.pc, false).unwrap();
+        let addr_low = self.read_bus(self.registers.pc);
         self.registers.pc += 1;
         let addr_high = 0;
         self.absolute_mem_address = addr_low as u16;
       },
       AddressingMode::ZPX => {
-        let instruction_addr = self.bus.read(self.registers.pc, false).unwrap();
+        let instruction_addr = self.read_bus(self.registers.pc);
         self.registers.pc += 1;
         self.absolute_mem_address = (instruction_addr as u16 + self.registers.x as u16) & 0x00FF;
       },
       AddressingMode::ZPY => {
-        let instruction_addr = self.bus.read(self.registers.pc, false).unwrap();
+        let instruction_addr = self.read_bus(self.registers.pc);
         self.registers.pc += 1;
         self.absolute_mem_address = (instruction_addr as u16 + self.registers.y as u16) & 0x00FF;
       },
       AddressingMode::ABX => {
-        let mem_addr = self.bus.read_word_little_endian(self.registers.pc, false).unwrap();
+        let mem_addr = self.read_bus_word(self.registers.pc);
         self.registers.pc += 2;
 
         self.absolute_mem_address = mem_addr.wrapping_add(self.registers.x as u16);
@@ -315,7 +356,7 @@ impl Ben6502 {
         }
       },
       AddressingMode::ABY => {
-        let mem_addr = self.bus.read_word_little_endian(self.registers.pc, false).unwrap();
+        let mem_addr = self.read_bus_word(self.registers.pc);
         self.registers.pc += 2;
         self.absolute_mem_address = mem_addr.wrapping_add(self.registers.y as u16);
 
@@ -327,26 +368,26 @@ impl Ben6502 {
         // Implied addressing means that no address is required to execute the instruction
       },
       AddressingMode::REL => {
-        self.relative_mem_address = self.bus.read(self.registers.pc, false).unwrap() as i8;
+        self.relative_mem_address = self.read_bus(self.registers.pc) as i8;
         self.registers.pc += 1;
       },
       AddressingMode::INX => {
-        let instruction_addr = self.bus.read(self.registers.pc, false).unwrap();
+        let instruction_addr = self.read_bus(self.registers.pc);
         self.registers.pc += 1;
 
         let pointer_to_addr = (instruction_addr as u16 + self.registers.x as u16) & 0x00FF;
 
-        let abs_address_low = self.bus.read(pointer_to_addr as u16, false).unwrap();
-        let abs_address_high = self.bus.read((pointer_to_addr as u8).wrapping_add(1) as u16, false).unwrap();
+        let abs_address_low = self.read_bus(pointer_to_addr as u16);
+        let abs_address_high = self.read_bus((pointer_to_addr as u8).wrapping_add(1) as u16);
 
         self.absolute_mem_address = ((abs_address_high as u16) << 8) + (abs_address_low as u16);
       }
       AddressingMode::INY => {
-        let base_pointer_loc = self.bus.read(self.registers.pc, false).unwrap();
+        let base_pointer_loc = self.read_bus(self.registers.pc);
         self.registers.pc += 1;
 
-        let base_pointer_low = self.bus.read(base_pointer_loc as u16, false).unwrap();
-        let base_pointer_high = self.bus.read(base_pointer_loc.wrapping_add(1) as u16 , false).unwrap();
+        let base_pointer_low = self.read_bus(base_pointer_loc as u16);
+        let base_pointer_high = self.read_bus(base_pointer_loc.wrapping_add(1) as u16);
         let address_at_operand_location = ((base_pointer_high as u16) << 8) + base_pointer_low as u16;
 
         self.absolute_mem_address = (self.registers.y as u16).wrapping_add(address_at_operand_location as u16);
@@ -356,16 +397,16 @@ impl Ben6502 {
         }
       },
       AddressingMode::IND => {
-        let abs_address_of_low_byte = self.bus.read_word_little_endian(self.registers.pc, false).unwrap();
+        let abs_address_of_low_byte = self.read_bus_word(self.registers.pc);
         self.registers.pc += 2;
         
-        let low_byte = self.bus.read(abs_address_of_low_byte, false).unwrap();
+        let low_byte = self.read_bus(abs_address_of_low_byte);
         let high_byte: u8;
 
         if ((abs_address_of_low_byte & 0xFF) == 0x00FF) { // We must do this weird thing to simulate a hardware bug in the CPU with page boundaries. https://www.nesdev.org/6502bugs.txt
-          high_byte = self.bus.read(abs_address_of_low_byte & 0xFF00, false).unwrap();
+          high_byte = self.read_bus(abs_address_of_low_byte & 0xFF00);
         } else {
-          high_byte = self.bus.read(abs_address_of_low_byte + 1, false).unwrap();
+          high_byte = self.read_bus(abs_address_of_low_byte + 1);
         }
 
         self.absolute_mem_address = ((high_byte as u16) << 8) + (low_byte as u16);
@@ -380,7 +421,7 @@ impl Ben6502 {
 
     match instruction {
         Instruction::ADC => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = self.registers.a as u16 + operand as u16 + self.status.get_carry() as u16;
           self.status.set_carry( (result > 0x00FF) as u8);
           self.status.set_zero( (result & 0xFF == 0) as u8);
@@ -392,7 +433,7 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::AND => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.a = self.registers.a & operand;
           self.status.set_zero((self.registers.a == 0) as u8);
           self.status.set_negative(((self.registers.a & 0b10000000) != 0) as u8);
@@ -405,7 +446,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             operand = self.registers.a;
           } else {
-            operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+            operand = self.read_bus(self.absolute_mem_address);
           }
           let result: u16 = (operand as u16) << 1;
           self.status.set_carry((result & 0xFF00 != 0) as u8);
@@ -414,7 +455,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             self.registers.a = (result & 0xFF) as u8;
           } else {
-            self.bus.write(self.absolute_mem_address, (result & 0xFF) as u8).unwrap();
+            self.write_bus(self.absolute_mem_address, (result & 0xFF) as u8);
           }
         },
         Instruction::BCC => {
@@ -451,7 +492,7 @@ impl Ben6502 {
           }
         },
         Instruction::BIT => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = self.registers.a & operand;
 
           self.status.set_zero(( (result & 0xFF) == 0) as u8);
@@ -496,19 +537,16 @@ impl Ben6502 {
 
           self.status.set_irq_disable(1);
 
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ((self.registers.pc >> 8) & 0xFF) as u8).unwrap();
-          self.registers.sp -= 1;
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ( self.registers.pc       & 0xFF) as u8).unwrap();
-          self.registers.sp -= 1;
+          self.push_word(self.registers.pc);
 
           self.status.set_brk_command(1);
 
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, self.status.flags).unwrap();
+          self.write_bus(STACK_START_ADDR + self.registers.sp as u16, self.status.flags);
           self.registers.sp -= 1;
 
           self.status.set_brk_command(0);
 
-          self.registers.pc = self.bus.read_word_little_endian(INTERRUPT_START_POINTER_ADDR, false).unwrap();
+          self.registers.pc = self.read_bus_word(INTERRUPT_START_POINTER_ADDR);
           
         },
         Instruction::BVC => {
@@ -544,7 +582,7 @@ impl Ben6502 {
           self.status.set_overflow(0);
         },
         Instruction::CMP => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = (self.registers.a as u16).wrapping_sub(operand as u16);
           self.status.set_carry((self.registers.a >= operand) as u8);
           self.status.set_zero(( (result & 0x00FF) == 0x0000 ) as u8);
@@ -553,23 +591,23 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::CPX => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = (self.registers.x as u16).wrapping_sub(operand as u16);
           self.status.set_carry((self.registers.x >= operand) as u8);
           self.status.set_zero(( (result & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((result & 0b10000000 != 0) as u8);
         },
         Instruction::CPY => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = (self.registers.y as u16).wrapping_sub(operand as u16);
           self.status.set_carry((self.registers.y >= operand) as u8);
           self.status.set_zero(( (result & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((result & 0b10000000 != 0) as u8);
         },
         Instruction::DEC => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = operand.wrapping_sub(1);
-          self.bus.write(self.absolute_mem_address, result).unwrap();
+          self.write_bus(self.absolute_mem_address, result);
 
           self.status.set_zero(( (result & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((result & 0b10000000 != 0) as u8);
@@ -587,7 +625,7 @@ impl Ben6502 {
           self.status.set_negative((self.registers.y & 0b10000000 != 0) as u8);
         },
         Instruction::EOR => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.a ^= operand;
           self.status.set_zero(( (self.registers.a & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((self.registers.a & 0b10000000 != 0) as u8);
@@ -595,9 +633,9 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::INC => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           let result = operand.wrapping_add(1);
-          self.bus.write(self.absolute_mem_address, result).unwrap();
+          self.write_bus(self.absolute_mem_address, result);
 
           self.status.set_zero(( (result & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((result & 0b10000000 != 0) as u8);
@@ -624,16 +662,18 @@ impl Ben6502 {
           // More info: https://www.masswerk.at/6502/6502_instruction_set.html#stack
           self.registers.pc -= 1;
 
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, (self.registers.pc >> 8) as u8).unwrap();
-          self.registers.sp -= 1;
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, (self.registers.pc & 0xFF) as u8).unwrap();
-          self.registers.sp -= 1;
+          self.push_word(self.registers.pc);
 
+          self.call_stack.push(CallFrame {
+            call_site_pc: self.registers.pc.wrapping_sub(2),
+            target_pc: self.absolute_mem_address,
+            cycle: self.total_cycles,
+          });
           self.registers.pc = self.absolute_mem_address;
 
         },
         Instruction::LDA => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.a = operand;
 
           self.status.set_zero(( (self.registers.a & 0x00FF) == 0x0000 ) as u8);
@@ -642,7 +682,7 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::LDX => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.x = operand;
 
           self.status.set_zero(( (self.registers.x & 0x00FF) == 0x0000 ) as u8);
@@ -651,7 +691,7 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::LDY => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.y = operand;
 
           self.status.set_zero(( (self.registers.y & 0x00FF) == 0x0000 ) as u8);
@@ -665,7 +705,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             operand = self.registers.a;
           } else {
-            operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+            operand = self.read_bus(self.absolute_mem_address);
           }
           self.status.set_carry((operand & 0x0001 != 0) as u8);
 
@@ -676,7 +716,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             self.registers.a = (result & 0xFF) as u8;
           } else {
-            self.bus.write(self.absolute_mem_address, (result & 0xFF) as u8).unwrap();
+            self.write_bus(self.absolute_mem_address, (result & 0xFF) as u8);
           }
         },
         Instruction::NOP => {
@@ -695,7 +735,7 @@ impl Ben6502 {
           */
         },
         Instruction::ORA => { // OR with accum
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
           self.registers.a |= operand;
           self.status.set_zero(( (self.registers.a & 0x00FF) == 0x0000 ) as u8);
           self.status.set_negative((self.registers.a & 0b10000000 != 0) as u8);
@@ -703,26 +743,26 @@ impl Ben6502 {
           self.instruction_requires_additional_cycle = true;
         },
         Instruction::PHA => {
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, self.registers.a).unwrap();
+          self.write_bus(STACK_START_ADDR + self.registers.sp as u16, self.registers.a);
           self.registers.sp -= 1;
         },
         Instruction::PHP => {
           self.status.set_brk_command(1);
           self.status.set_unused_bit(1);
-          self.bus.write(STACK_START_ADDR + self.registers.sp as u16, self.status.flags).unwrap();
+          self.write_bus(STACK_START_ADDR + self.registers.sp as u16, self.status.flags);
           self.status.set_brk_command(0);
           self.status.set_unused_bit(0);
           self.registers.sp -= 1;
         },
         Instruction::PLA => {
           self.registers.sp += 1;
-          self.registers.a = self.bus.read(STACK_START_ADDR + self.registers.sp as u16, false).unwrap();
+          self.registers.a = self.read_bus(STACK_START_ADDR + self.registers.sp as u16);
           self.status.set_zero((self.registers.a == 0) as u8);
           self.status.set_negative((self.registers.a & 0b10000000 != 0) as u8);
         },
         Instruction::PLP => {
           self.registers.sp += 1;
-          self.status.flags = self.bus.read(STACK_START_ADDR + self.registers.sp as u16, false).unwrap();
+          self.status.flags = self.read_bus(STACK_START_ADDR + self.registers.sp as u16);
           self.status.set_unused_bit(1);
         },
         Instruction::ROL => {
@@ -731,7 +771,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             operand = self.registers.a;
           } else {
-            operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+            operand = self.read_bus(self.absolute_mem_address);
           }
 
           let result = ((operand as u16) << 1) | (self.status.get_carry() as u16);
@@ -743,7 +783,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             self.registers.a = (result & 0xFF) as u8;
           } else {
-            self.bus.write(self.absolute_mem_address, (result & 0xFF) as u8).unwrap();
+            self.write_bus(self.absolute_mem_address, (result & 0xFF) as u8);
           }
         },
         Instruction::ROR => {
@@ -752,7 +792,7 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             operand = self.registers.a;
           } else {
-            operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+            operand = self.read_bus(self.absolute_mem_address);
           }
 
           let result = ((self.status.get_carry() as u16) << 7) | ((operand as u16) >> 1);
@@ -764,28 +804,33 @@ impl Ben6502 {
           if matches!(addr_mode, AddressingMode::IMP) || matches!(addr_mode, AddressingMode::ACC) {
             self.registers.a = (result & 0xFF) as u8;
           } else {
-            self.bus.write(self.absolute_mem_address, (result & 0xFF) as u8).unwrap();
+            self.write_bus(self.absolute_mem_address, (result & 0xFF) as u8);
           }
         },
         Instruction::RTI => {
-          self.registers.sp += 1;
-          self.status.flags = self.bus.read(STACK_START_ADDR + self.registers.sp as u16, false).unwrap();
+          // Pulls P, PCL and PCH, in that order. Each pull wraps around within the stack page,
+          // so the PC can't be read as a single word (its high byte could be at $0100).
+          self.registers.sp = self.registers.sp.wrapping_add(1);
+          self.status.flags = self.read_bus(STACK_START_ADDR + self.registers.sp as u16);
 
           self.status.set_brk_command(0);
           self.status.set_unused_bit(0);
 
-          self.registers.sp += 1;
-          self.registers.pc = self.bus.read_word_little_endian(STACK_START_ADDR + self.registers.sp as u16, false).unwrap();
-          self.registers.sp += 1;
+          self.registers.sp = self.registers.sp.wrapping_add(1);
+          let pc_low = self.read_bus(STACK_START_ADDR + self.registers.sp as u16);
+          self.registers.sp = self.registers.sp.wrapping_add(1);
+          let pc_high = self.read_bus(STACK_START_ADDR + self.registers.sp as u16);
+          self.registers.pc = ((pc_high as u16) << 8) | (pc_low as u16);
         },
         Instruction::RTS => {
           self.registers.sp += 1;
-          self.registers.pc = self.bus.read_word_little_endian(STACK_START_ADDR + self.registers.sp as u16, false).unwrap();
+          self.registers.pc = self.read_bus_word(STACK_START_ADDR + self.registers.sp as u16);
           self.registers.sp += 1;
           self.registers.pc += 1;
+          self.call_stack.pop(self.registers.pc);
         },
         Instruction::SBC => {
-          let operand = self.bus.read(self.absolute_mem_address, false).unwrap();
+          let operand = self.read_bus(self.absolute_mem_address);
 
           let inverted_value = operand as u16 ^ 0xFF;
 
@@ -811,13 +856,13 @@ impl Ben6502 {
           self.status.set_irq_disable(1);
         },
         Instruction::STA => {
-          self.bus.write(self.absolute_mem_address, self.registers.a).unwrap();
+          self.write_bus(self.absolute_mem_address, self.registers.a);
         },
         Instruction::STX => {
-          self.bus.write(self.absolute_mem_address, self.registers.x).unwrap();
+          self.write_bus(self.absolute_mem_address, self.registers.x);
         },
         Instruction::STY => {
-          self.bus.write(self.absolute_mem_address, self.registers.y).unwrap();
+          self.write_bus(self.absolute_mem_address, self.registers.y);
         },
         Instruction::TAX => {
           self.registers.x = self.registers.a;
@@ -900,38 +945,90 @@ impl Ben6502 {
     self.registers.sp = SP_RESET_ADDR;
     
     self.status.reset();
+    self.irq_disable_prev = self.status.get_irq_disable();
 
     // On reset, the cpu goes to a hard-wired address, takes a pointer
     // from that address (2 bytes), and sets the PC to the address specified
-    self.registers.pc = self.bus.read_word_little_endian(PROGRAM_START_POINTER_ADDR, false).unwrap();
+    self.registers.pc = self.read_bus_word(PROGRAM_START_POINTER_ADDR);
     // self.registers.pc = 0xC000; // THIS LINE USED ONLY FOR NESTEST, TODO: Remove after testing is done :)
 
     self.absolute_mem_address = 0x0;
     self.relative_mem_address = 0x0;
     self.current_instruction_remaining_cycles = 8;
+    self.call_stack.clear();
+    self.reset_cycle = self.total_cycles;
+  }
+
+  // Includes the 8 cycles of the reset sequence itself. Same as total_cycles until the first soft reset.
+  pub fn cycles_since_reset(&self) -> u64 {
+    return self.total_cycles - self.reset_cycle;
+  }
+
+  // Outermost call first
+  pub fn get_call_stack(&self) -> &[CallFrame] {
+    return self.call_stack.frames();
+  }
+
+  // Describes every difference with another snapshot, in terms of what PartialEq compares. Meant for test failure messages.
+  pub fn diff(&self, other: &Ben6502) -> Vec<String> {
+    let mut differences = vec![];
+    let byte_registers = [
+      ("A", self.registers.a, other.registers.a),
+      ("X", self.registers.x, other.registers.x),
+      ("Y", self.registers.y, other.registers.y),
+      ("SP", self.registers.sp, other.registers.sp),
+      ("Status", self.status.flags, other.status.flags),
+    ];
+    for (name, value, other_value) in byte_registers {
+      if value != other_value {
+        differences.push(format!("{}: ${:02X} != ${:02X}", name, value, other_value));
+      }
+    }
+    if self.registers.pc != other.registers.pc {
+      differences.push(format!("PC: ${:04X} != ${:04X}", self.registers.pc, other.registers.pc));
+    }
+    if self.total_cycles != other.total_cycles {
+      differences.push(format!("Total cycles: {} != {}", self.total_cycles, other.total_cycles));
+    }
+    if self.current_instruction_remaining_cycles != other.current_instruction_remaining_cycles {
+      differences.push(format!("Remaining instruction cycles: {} != {}",
+        self.current_instruction_remaining_cycles, other.current_instruction_remaining_cycles));
+    }
+    let ram = self.bus.ram();
+    let other_ram = other.bus.ram();
+    for (addr, (value, other_value)) in ram.raw().iter().zip(other_ram.raw().iter()).enumerate() {
+      if value != other_value {
+        differences.push(format!("RAM ${:04X}: ${:02X} != ${:02X}", addr, value, other_value));
+      }
+    }
+    return differences;
+  }
+
+  // Disassembles the instruction at addr, returning it along with the address of the next instruction
+  pub fn disassemble_at(&self, addr: u16) -> (String, u16) {
+    return disassemble_instruction(&self.bus, addr);
   }
 
   pub fn irq(&mut self) {
 
-    if self.status.get_irq_disable() == 1 {
+    if self.irq_disable_prev == 1 {
       return;
     }
   
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ((self.registers.pc >> 8) & 0xFF) as u8).unwrap();
-    self.registers.sp -= 1;
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ( self.registers.pc       & 0xFF) as u8).unwrap();
-    self.registers.sp -= 1;
+    self.push_word(self.registers.pc);
 
     self.status.set_brk_command(0);
     self.status.set_unused_bit(1);
-    self.status.set_irq_disable(1);
-
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, self.status.flags).unwrap();
+    // The flags are pushed before interrupts get disabled, so that RTI enables them again
+    self.write_bus(STACK_START_ADDR + self.registers.sp as u16, self.status.flags);
     self.registers.sp -= 1;
+    self.status.set_irq_disable(1);
+    // The first instruction of the handler always runs before another IRQ can be taken
+    self.irq_disable_prev = 1;
 
     // Like on reset, the cpu goes to a hard-wired address, takes a pointer
     // from that address (2 bytes), and sets the PC to the address specified
-    self.registers.pc = self.bus.read_word_little_endian(INTERRUPT_START_POINTER_ADDR, false).unwrap();
+    self.registers.pc = self.read_bus_word(INTERRUPT_START_POINTER_ADDR);
 
     self.current_instruction_remaining_cycles = 7;
 
@@ -939,29 +1036,75 @@ impl Ben6502 {
 
   pub fn nmi(&mut self) {
 
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ((self.registers.pc >> 8) & 0xFF) as u8).unwrap();
-    self.registers.sp -= 1;
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, ( self.registers.pc       & 0xFF) as u8).unwrap();
-    self.registers.sp -= 1;
+    self.push_word(self.registers.pc);
 
     self.status.set_brk_command(0);
     self.status.set_unused_bit(1);
-    self.status.set_irq_disable(1);
-
-    self.bus.write(STACK_START_ADDR + self.registers.sp as u16, self.status.flags).unwrap();
+    self.write_bus(STACK_START_ADDR + self.registers.sp as u16, self.status.flags);
     self.registers.sp -= 1;
+    self.status.set_irq_disable(1);
+    self.irq_disable_prev = 1;
 
     // Like on reset, the cpu goes to a hard-wired address, takes a pointer
     // from that address (2 bytes), and sets the PC to the address specified
-    self.registers.pc = self.bus.read_word_little_endian(NMI_START_POINTER_ADDR, false).unwrap();
+    self.registers.pc = self.read_bus_word(NMI_START_POINTER_ADDR);
 
     self.current_instruction_remaining_cycles = 8;
+    self.nmi_count += 1;
+
+  }
+
+  // Instructions keep running when a bus access fails (failed reads return 0), and the first error is returned
+  // once the cycle is done, with the address of the instruction that caused it.
+  // Errors from interrupts and resets are returned on the next cycle.
+  fn read_bus(&mut self, addr: u16) -> u8 {
+    return match self.bus.read(addr, false) {
+      Ok(data) => data,
+      Err(error) => {
+        self.record_bus_error(error);
+        0
+      }
+    };
+  }
+
+  fn read_bus_word(&mut self, addr: u16) -> u16 {
+    return match self.bus.read_word_little_endian(addr, false) {
+      Ok(data) => data,
+      Err(error) => {
+        self.record_bus_error(error);
+        0
+      }
+    };
+  }
 
+  fn write_bus(&mut self, addr: u16, data: u8) {
+    if let Err(error) = self.bus.write(addr, data) {
+      self.record_bus_error(error);
+    }
   }
 
-  pub fn clock_cycle(&mut self) {
+  // Pushes the high byte first and then the low one, which leaves the word in little endian order right below the
+  // previous top of the stack. The stack wraps around within page 1, so a word that would straddle it is split.
+  fn push_word(&mut self, data: u16) {
+    if (self.registers.sp == 0x00) {
+      self.write_bus(STACK_START_ADDR, (data >> 8) as u8);
+      self.write_bus(STACK_START_ADDR + 0xFF, (data & 0xFF) as u8);
+    } else if let Err(error) = self.bus.write_word_little_endian(STACK_START_ADDR + self.registers.sp as u16 - 1, data) {
+      self.record_bus_error(error);
+    }
+    self.registers.sp = self.registers.sp.wrapping_sub(2);
+  }
+
+  fn record_bus_error(&mut self, error: BusError) {
+    if self.bus_error.is_none() {
+      self.bus_error = Some(BusError::AtPc { pc: self.instruction_pc, source: Box::new(error) });
+    }
+  }
+
+  pub fn clock_cycle(&mut self) -> Result<(), BusError> {
     if self.current_instruction_remaining_cycles == 0 {
-      let next_instruction_code = self.bus.read(self.registers.pc, false).unwrap();
+      self.instruction_pc = self.registers.pc;
+      let next_instruction_code = self.read_bus(self.registers.pc);
       self.status.set_unused_bit(1);
       self.registers.pc += 1;
       let next_instruction_data: &InstructionData = &INSTRUCTION_TABLE[next_instruction_code as usize];
@@ -969,9 +1112,13 @@ impl Ben6502 {
       
       self.addr_mode_requires_additional_cycle = false;
       self.instruction_requires_additional_cycle = false;
+      self.irq_disable_prev = self.status.get_irq_disable();
 
       self.set_addressing_mode(&next_instruction_data.addressing_mode);
       self.execute_instruction(&next_instruction_data.instruction, &next_instruction_data.addressing_mode, next_instruction_code);
+      if let Instruction::RTI = next_instruction_data.instruction {
+        self.irq_disable_prev = self.status.get_irq_disable();
+      }
 
       if self.addr_mode_requires_additional_cycle && self.instruction_requires_additional_cycle {
         self.current_instruction_remaining_cycles += 1;
@@ -979,12 +1126,28 @@ impl Ben6502 {
       self.status.set_unused_bit(1);
     }
     self.current_instruction_remaining_cycles -= 1;
+    self.total_cycles += 1;
+    return match self.bus_error.take() {
+      Some(error) => Err(error),
+      None => Ok(()),
+    };
   }
   
 
 }
 
-fn bytes_required_for_address(addressing_mode: &AddressingMode) -> u8 {
+// Compares the CPU state and the internal RAM. The rest of the console (PPU, APU, cartridge) isn't compared.
+impl PartialEq for Ben6502 {
+  fn eq(&self, other: &Ben6502) -> bool {
+    return self.registers == other.registers &&
+      self.status == other.status &&
+      self.total_cycles == other.total_cycles &&
+      self.current_instruction_remaining_cycles == other.current_instruction_remaining_cycles &&
+      self.bus.ram().raw() == other.bus.ram().raw();
+  }
+}
+
+pub(crate) fn bytes_required_for_address(addressing_mode: &AddressingMode) -> u8 {
   match (addressing_mode) {
     AddressingMode::ACC => {
       0
@@ -1029,22 +1192,347 @@ fn bytes_required_for_address(addressing_mode: &AddressingMode) -> u8 {
 }
 
 
-pub fn disassemble(program: &Vec<u8>) -> String {
-  let mut result = String::new();
-  let mut i = 0;
-  while i < program.len() {
-    let instruction_opcode = *program.get(i).unwrap();
-    let instruction_data = &INSTRUCTION_TABLE[instruction_opcode as usize];
-    result.push_str(&format!("{:?}", instruction_data.instruction));
+// Disassembles the instruction at the given address, reading it straight from the bus (without side effects,
+// so it's safe to call at any point of the emulation).
+// Returns a line like "$8001: A9 42    LDA #$42", and the address of the next instruction.
+// Illegal opcodes are shown as "???".
+pub fn disassemble_instruction(bus: &Bus16Bit, instruction_addr: u16) -> (String, u16) {
+  let instruction_opcode = bus.read_debug(instruction_addr);
+  let instruction_data = &INSTRUCTION_TABLE[instruction_opcode as usize];
+  let operation_bytes = bytes_required_for_address(&instruction_data.addressing_mode) as u16;
+
+  let mut instruction_bytes = vec![instruction_opcode];
+  for i in 1..=operation_bytes {
+    instruction_bytes.push(bus.read_debug(instruction_addr.wrapping_add(i)));
+  }
+  let raw_bytes: Vec<String> = instruction_bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+  let low = *instruction_bytes.get(1).unwrap_or(&0);
+  let word = ((*instruction_bytes.get(2).unwrap_or(&0) as u16) << 8) | (low as u16);
+  let operand = match instruction_data.addressing_mode {
+    AddressingMode::ACC => String::from(" A"),
+    AddressingMode::IMP => String::new(),
+    AddressingMode::IMM => format!(" #${:02X}", low),
+    AddressingMode::ZP0 => format!(" ${:02X}", low),
+    AddressingMode::ZPX => format!(" ${:02X},X", low),
+    AddressingMode::ZPY => format!(" ${:02X},Y", low),
+    AddressingMode::ABS => format!(" ${:04X}", word),
+    AddressingMode::ABX => format!(" ${:04X},X", word),
+    AddressingMode::ABY => format!(" ${:04X},Y", word),
+    AddressingMode::IND => format!(" (${:04X})", word),
+    AddressingMode::INX => format!(" (${:02X},X)", low),
+    AddressingMode::INY => format!(" (${:02X}),Y", low),
+    // Branch offsets are relative to the address of the next instruction, so we show the target address instead
+    AddressingMode::REL => format!(" ${:04X}", instruction_addr.wrapping_add(2).wrapping_add(low as i8 as u16)),
+  };
+
+  let mnemonic = match instruction_data.instruction {
+    Instruction::XXX => String::from("???"),
+    _ => format!("{:?}{}", instruction_data.instruction, operand),
+  };
+  let line = format!("${:04X}: {:<9}{}", instruction_addr, raw_bytes.join(" "), mnemonic);
+  return (line, instruction_addr.wrapping_add(1 + operation_bytes));
+}
+
+// Disassembles the instructions starting in the range [start_addr, end_addr).
+// Returns one (address, line) pair per instruction.
+pub fn disassemble_from_bus(bus: &Bus16Bit, start_addr: u16, end_addr: u16) -> Vec<(u16, String)> {
+  let mut result = vec![];
+  let mut addr = start_addr as u32;
+  while addr < end_addr as u32 {
+    let (line, next_addr) = disassemble_instruction(bus, addr as u16);
+    result.push((addr as u16, line));
+    // next_addr wraps around at the end of memory, but the instruction length is still right
+    addr += next_addr.wrapping_sub(addr as u16) as u32;
+  }
+  return result;
+}
+
+
+#[cfg(test)]
+mod disassembler_tests {
+  use crate::bus::Bus16Bit;
+  use super::{disassemble_from_bus, Ben6502};
+
+  #[test]
+  fn test_disassemble_nestest_entry_points() {
+    let bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    let lines = disassemble_from_bus(&bus, 0xC000, 0xC001);
+    assert_eq!(lines, vec![(0xC000, String::from("$C000: 4C F5 C5 JMP $C5F5"))]);
+
+    let lines = disassemble_from_bus(&bus, 0xC5F5, 0xC5F9);
+    assert_eq!(lines, vec![
+      (0xC5F5, String::from("$C5F5: A2 00    LDX #$00")),
+      (0xC5F7, String::from("$C5F7: 86 00    STX $00")),
+    ]);
+  }
+
+  #[test]
+  fn test_disassemble_at_walks_forward() {
+    let mut cpu = Ben6502::new(Bus16Bit::new("test_roms/nestest.nes").unwrap());
+    assert_eq!(cpu.disassemble_at(0xC000), (String::from("$C000: 4C F5 C5 JMP $C5F5"), 0xC003));
+    assert_eq!(cpu.disassemble_at(0xC5F5), (String::from("$C5F5: A2 00    LDX #$00"), 0xC5F7));
+
+    // Illegal opcodes still show their raw bytes
+    cpu.bus.write(0x0300, 0x02).unwrap();
+    assert_eq!(cpu.disassemble_at(0x0300), (String::from("$0300: 02       ???"), 0x0301));
+    // And the address wraps around at the end of memory
+    assert!(cpu.disassemble_at(0xFFFF).1 <= 0x0002);
+  }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+  use crate::{bus::BusBuilder, cartridge::Cartridge, test_utils::{assemble, NesRomBuilder}};
+  use super::Ben6502;
+
+  fn run_instructions(cpu: &mut Ben6502, instruction_count: usize) {
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+    for _ in 0..instruction_count {
+      cpu.clock_cycle().unwrap();
+      while (cpu.current_instruction_remaining_cycles > 0) {
+        cpu.clock_cycle().unwrap();
+      }
+    }
+  }
+
+  fn cpu_with_program() -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    let program = assemble(&[
+      ("LDA #", vec![0x42]),
+      ("STA zp", vec![0x10]),
+      ("INX", vec![]),
+      ("JMP abs", vec![0x05, 0xC0]),
+    ]);
+    bus.load_bytes_at(0xC000, &program).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0xC000).unwrap();
+    return Ben6502::new(bus);
+  }
+
+  #[test]
+  fn test_clone_is_equal_and_independent() {
+    let mut cpu = cpu_with_program();
+    let snapshot = cpu.clone();
+    assert!(snapshot == cpu);
+    assert_eq!(snapshot.diff(&cpu), Vec::<String>::new());
+
+    run_instructions(&mut cpu, 3);
+    assert!(snapshot != cpu);
+    assert_eq!(snapshot.bus.read_debug(0x0010), 0x00);
+    assert_eq!(cpu.bus.read_debug(0x0010), 0x42);
+
+    // Running the snapshot catches up with the original
+    let mut snapshot = snapshot;
+    run_instructions(&mut snapshot, 3);
+    assert_eq!(snapshot.diff(&cpu), Vec::<String>::new());
+  }
+
+  #[test]
+  fn test_diff_describes_each_difference() {
+    let cpu = cpu_with_program();
+    let mut other = cpu.clone();
+    other.registers.a = 0x42;
+    other.registers.pc = 0xC002;
+    other.bus.write(0x0010, 0x42).unwrap();
+    assert_eq!(cpu.diff(&other), vec![
+      String::from("A: $00 != $42"),
+      String::from("PC: $C000 != $C002"),
+      String::from("RAM $0010: $00 != $42"),
+    ]);
+  }
+
+  #[test]
+  fn test_clone_copies_the_cartridge() {
+    let rom = NesRomBuilder::new().prg_size(2).build();
+    let cpu = Ben6502::new(BusBuilder::new().cartridge(Cartridge::from_bytes(&rom).unwrap()).build());
+    let mut snapshot = cpu.clone();
+    snapshot.bus.write(0x6000, 0x99).unwrap();
+    assert_eq!(snapshot.bus.read_debug(0x6000), 0x99);
+    assert_eq!(cpu.bus.read_debug(0x6000), 0x00);
+  }
+}
+
+#[cfg(test)]
+mod instruction_tests {
+  use crate::{bus::BusBuilder, test_utils::assemble};
+  use super::Ben6502;
+
+  // Runs an RTI with the given stack pointer, after pushing PCH, PCL and P the way an interrupt does
+  fn run_rti(sp: u8, pc: u16, flags: u8) -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0x8000, &assemble(&[("RTI", vec![])])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    let mut cpu = Ben6502::new(bus);
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+
+    cpu.registers.sp = sp;
+    for byte in [(pc >> 8) as u8, (pc & 0xFF) as u8, flags] {
+      cpu.bus.write(0x0100 + cpu.registers.sp as u16, byte).unwrap();
+      cpu.registers.sp = cpu.registers.sp.wrapping_sub(1);
+    }
+    cpu.clock_cycle().unwrap();
+    return cpu;
+  }
+
+  #[test]
+  fn test_rti_pulls_status_and_pc() {
+    let cpu = run_rti(0xFD, 0xC123, 0b1100_0011);
+    assert_eq!(cpu.registers.pc, 0xC123);
+    assert_eq!(cpu.registers.sp, 0xFD);
+    // The break flag isn't restored, and the unused one always reads as set
+    assert_eq!(cpu.status.flags, 0b1110_0011);
+    assert_eq!(cpu.current_instruction_remaining_cycles, 5);
+  }
+
+  #[test]
+  fn test_rti_wraps_around_the_stack_page() {
+    // PCH is pulled from $0100, after P and PCL were pulled from $01FE and $01FF
+    let cpu = run_rti(0x00, 0xC456, 0b0001_0000);
+    assert_eq!(cpu.registers.pc, 0xC456);
+    assert_eq!(cpu.registers.sp, 0x00);
+    assert_eq!(cpu.status.get_brk_command(), 0);
+
+    // Here it's PCL and PCH, at $0100 and $0101
+    let cpu = run_rti(0x01, 0xC789, 0x00);
+    assert_eq!(cpu.registers.pc, 0xC789);
+    assert_eq!(cpu.registers.sp, 0x01);
+  }
+
+  fn run_until_instruction_ends(cpu: &mut Ben6502) {
+    cpu.clock_cycle().unwrap();
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+  }
+
+  // Runs the reset sequence of a program that starts at $8000, with the IRQ handler at $9000
+  fn cpu_with_irq_handler(program: &[(&str, Vec<u8>)]) -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0x8000, &assemble(program)).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    bus.write_word_little_endian(0xFFFE, 0x9000).unwrap();
+    let mut cpu = Ben6502::new(bus);
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+    return cpu;
+  }
+
+  #[test]
+  fn test_cli_enables_irqs_after_the_next_instruction() {
+    let mut cpu = cpu_with_irq_handler(&[("SEI", vec![]), ("CLI", vec![]), ("NOP", vec![]), ("NOP", vec![])]);
+    run_until_instruction_ends(&mut cpu);
+    run_until_instruction_ends(&mut cpu);
+    cpu.irq();
+    assert_eq!(cpu.registers.pc, 0x8002);
+
+    run_until_instruction_ends(&mut cpu);
+    cpu.irq();
+    assert_eq!(cpu.registers.pc, 0x9000);
+  }
+
+  #[test]
+  fn test_sei_lets_one_more_irq_through() {
+    let mut cpu = cpu_with_irq_handler(&[("SEI", vec![]), ("NOP", vec![])]);
+    run_until_instruction_ends(&mut cpu);
+    cpu.irq();
+    assert_eq!(cpu.registers.pc, 0x9000);
+
+    let mut cpu = cpu_with_irq_handler(&[("SEI", vec![]), ("NOP", vec![])]);
+    run_until_instruction_ends(&mut cpu);
+    run_until_instruction_ends(&mut cpu);
+    cpu.irq();
+    assert_eq!(cpu.registers.pc, 0x8002);
+  }
+
+  #[test]
+  fn test_rti_enables_irqs_right_away() {
+    // Pulls P with the interrupt disable flag clear, after SEI set it
+    let mut cpu = cpu_with_irq_handler(&[("SEI", vec![]), ("RTI", vec![])]);
+    run_until_instruction_ends(&mut cpu);
+    for byte in [0x80, 0x05, 0x00] {
+      cpu.bus.write(0x0100 + cpu.registers.sp as u16, byte).unwrap();
+      cpu.registers.sp = cpu.registers.sp.wrapping_sub(1);
+    }
+    run_until_instruction_ends(&mut cpu);
+    cpu.irq();
+    assert_eq!(cpu.registers.pc, 0x9000);
+  }
 
-    let operation_bytes = bytes_required_for_address(&instruction_data.addressing_mode) as usize;
-    for j in 0..operation_bytes {
-      if program.get(i+(j+1)).is_some() {
-        result.push_str(&format!(",{:02X}", program.get(i+(j+1)).unwrap()));
+  // Runs a JMP through the given pointer, after writing the given bytes
+  fn run_indirect_jmp(pointer: u16, bytes: &[(u16, u8)]) -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0x8000, &assemble(&[("JMP (abs)", vec![(pointer & 0xFF) as u8, (pointer >> 8) as u8])])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    for (addr, byte) in bytes {
+      bus.write(*addr, *byte).unwrap();
+    }
+    let mut cpu = Ben6502::new(bus);
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+    run_until_instruction_ends(&mut cpu);
+    return cpu;
+  }
+
+  #[test]
+  fn test_jsr_pushes_the_return_address() {
+    for (sp, high_byte_addr, low_byte_addr) in [(0xFD, 0x01FD, 0x01FC), (0x00, 0x0100, 0x01FF)] {
+      let mut bus = BusBuilder::new().build();
+      bus.load_bytes_at(0x8000, &assemble(&[("JSR abs", vec![0x00, 0x90])])).unwrap();
+      bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+      let mut cpu = Ben6502::new(bus);
+      while (cpu.current_instruction_remaining_cycles > 0) {
+        cpu.clock_cycle().unwrap();
       }
+      cpu.registers.sp = sp;
+      run_until_instruction_ends(&mut cpu);
+
+      // JSR pushes the address of its last byte
+      assert_eq!(cpu.registers.pc, 0x9000);
+      assert_eq!(cpu.registers.sp, sp.wrapping_sub(2));
+      assert_eq!(cpu.bus.read_debug(high_byte_addr), 0x80);
+      assert_eq!(cpu.bus.read_debug(low_byte_addr), 0x02);
     }
-    result.push_str(" - ");
-    i += 1 + operation_bytes;
   }
-  return result;
+
+  #[test]
+  fn test_indirect_jmp_page_boundary_bug() {
+    // The high byte of the target comes from the start of the pointer's page ($0100), not from the next page ($0200).
+    // This is how the real 6502 behaves, so it must not be "fixed".
+    let cpu = run_indirect_jmp(0x01FF, &[(0x01FF, 0x34), (0x0100, 0x12), (0x0200, 0x56)]);
+    assert_eq!(cpu.registers.pc, 0x1234);
+  }
+
+  #[test]
+  fn test_indirect_jmp() {
+    let cpu = run_indirect_jmp(0x0100, &[(0x0100, 0x34), (0x0101, 0x12)]);
+    assert_eq!(cpu.registers.pc, 0x1234);
+  }
+
+  #[test]
+  fn test_cycle_counts() {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0x8000, &assemble(&[("BRK", vec![]), ("NOP", vec![])])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    bus.write_word_little_endian(0xFFFE, 0x8001).unwrap();
+    let mut cpu = Ben6502::new(bus);
+    assert_eq!(cpu.total_cycles, 0);
+
+    // The reset sequence takes 8 cycles, BRK 7 and NOP 2
+    for expected_cycles in [8, 15, 17] {
+      run_until_instruction_ends(&mut cpu);
+      assert_eq!(cpu.total_cycles, expected_cycles);
+    }
+
+    // A soft reset starts counting again, but the total keeps going
+    cpu.reset();
+    run_until_instruction_ends(&mut cpu);
+    assert_eq!(cpu.cycles_since_reset(), 8);
+    assert_eq!(cpu.total_cycles, 25);
+  }
 }
@@ -0,0 +1,243 @@
+/*
+
+Runner for blargg's test ROMs, which check the accuracy of the emulation from inside the console itself.
+
+The ROMs report their progress through the cartridge's work RAM:
+- $6001-$6003 hold the signature $DE $B0 $61 once the status is valid.
+- $6000 holds the status: $80 while the test is running, $81 if the console needs to be reset,
+and the result code once it's done.
+- $6004 onwards holds the result as a null terminated string.
+
+The ROMs aren't checked in, so the tests that use them are ignored by default. To run them, copy the ROMs
+into test_roms/blargg/ and run `cargo test -- --ignored`.
+
+Reference: https://github.com/christopherpow/nes-test-roms/blob/master/readme.txt
+
+*/
+
+use crate::{apu::CPU_CLOCK_RATE, ben6502::Ben6502, bus::Bus16Bit, console, test_utils::{assemble, NesRomBuilder}};
+
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const RESULT_TEXT_ADDR: u16 = 0x6004;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUIRED: u8 = 0x81;
+
+// The PPU runs 3 cycles per CPU cycle
+const PPU_CYCLES_PER_SECOND: u64 = (CPU_CLOCK_RATE * 3.0) as u64;
+const TIMEOUT_SECONDS: f64 = 5.0;
+// The ROMs ask for the reset button to be pressed after at least 100ms
+const RESET_DELAY_PPU_CYCLES: u64 = PPU_CYCLES_PER_SECOND / 10;
+
+fn read_status(bus: &Bus16Bit) -> Option<u8> {
+  let signature = [bus.read_debug(SIGNATURE_ADDR), bus.read_debug(SIGNATURE_ADDR + 1), bus.read_debug(SIGNATURE_ADDR + 2)];
+  if signature != SIGNATURE {
+    return None;
+  }
+  return Some(bus.read_debug(STATUS_ADDR));
+}
+
+fn read_result_text(bus: &Bus16Bit) -> String {
+  let mut result = String::new();
+  let mut addr = RESULT_TEXT_ADDR;
+  while addr < 0x8000 {
+    let character = bus.read_debug(addr);
+    if character == 0 {
+      break;
+    }
+    result.push(character as char);
+    addr += 1;
+  }
+  return result;
+}
+
+// Runs the ROM until it reports a result (or 5 seconds of emulated time pass), and returns the text it printed
+pub fn run_blargg_rom(path: &str) -> String {
+  return run_blargg_rom_with_timeout(path, TIMEOUT_SECONDS);
+}
+
+fn run_blargg_rom_with_timeout(path: &str, timeout_seconds: f64) -> String {
+  let timeout_ppu_cycles = (timeout_seconds * PPU_CYCLES_PER_SECOND as f64) as u64;
+  let mut cpu = Ben6502::new(Bus16Bit::new(path).unwrap());
+  let mut current_cycle: u64 = 0;
+  let mut test_started = false;
+  let mut reset_at_cycle: Option<u64> = None;
+
+  while current_cycle < timeout_ppu_cycles {
+    console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    if let Some(reset_cycle) = reset_at_cycle {
+      if current_cycle >= reset_cycle {
+        console::soft_reset(&mut cpu);
+        reset_at_cycle = None;
+      }
+      continue;
+    }
+    // The status can only change after a CPU cycle
+    if current_cycle % 3 != 1 {
+      continue;
+    }
+    match read_status(&cpu.bus) {
+      Some(STATUS_RUNNING) => test_started = true,
+      Some(STATUS_RESET_REQUIRED) => reset_at_cycle = Some(current_cycle + RESET_DELAY_PPU_CYCLES),
+      Some(_) if test_started => return read_result_text(&cpu.bus),
+      _ => {}
+    }
+  }
+  return format!("Timed out, last output: {}", read_result_text(&cpu.bus));
+}
+
+// Builds a mapper 0 ROM that runs the given code from $8000, and then loops forever
+fn create_test_rom(file_name: &str, code: &[u8]) -> String {
+  return create_test_rom_with_chr(file_name, code, &[]);
+}
+
+// Same as create_test_rom, with the given tiles at the start of CHR ROM
+fn create_test_rom_with_chr(file_name: &str, code: &[u8], chr_rom: &[u8]) -> String {
+  let mut prg_rom = vec![0xEA; 0x4000];
+  prg_rom[..code.len()].copy_from_slice(code);
+  let loop_addr = 0x8000 + code.len() as u16;
+  prg_rom[code.len()..code.len() + 3].copy_from_slice(&[0x4C, (loop_addr & 0xFF) as u8, (loop_addr >> 8) as u8]);
+  // NMI, reset and IRQ vectors
+  for vector in 0..3 {
+    let target = if vector == 1 { 0x8000 } else { loop_addr };
+    prg_rom[0x3FFA + vector * 2] = (target & 0xFF) as u8;
+    prg_rom[0x3FFB + vector * 2] = (target >> 8) as u8;
+  }
+
+  let contents = NesRomBuilder::new().write_prg(0, &prg_rom).write_chr(0, chr_rom).build();
+  let path = std::env::temp_dir().join(file_name);
+  std::fs::write(&path, contents).unwrap();
+  return path.to_string_lossy().into_owned();
+}
+
+// LDA #value, STA addr
+fn store(addr: u16, value: u8) -> Vec<u8> {
+  return vec![0xA9, value, 0x8D, (addr & 0xFF) as u8, (addr >> 8) as u8];
+}
+
+#[test]
+fn test_runner_reads_result_text() {
+  let mut code = vec![];
+  for (i, byte) in SIGNATURE.iter().enumerate() {
+    code.extend(store(SIGNATURE_ADDR + i as u16, *byte));
+  }
+  code.extend(store(STATUS_ADDR, STATUS_RUNNING));
+  for (i, character) in "Passed\n".bytes().chain([0]).enumerate() {
+    code.extend(store(RESULT_TEXT_ADDR + i as u16, character));
+  }
+  code.extend(store(STATUS_ADDR, 0x00));
+  let rom_path = create_test_rom("rustness_blargg_runner_test.nes", &code);
+
+  assert_eq!(run_blargg_rom(&rom_path), "Passed\n");
+  std::fs::remove_file(rom_path).unwrap();
+}
+
+#[test]
+fn test_runner_times_out_without_result() {
+  let rom_path = create_test_rom("rustness_blargg_timeout_test.nes", &[]);
+  assert!(run_blargg_rom_with_timeout(&rom_path, 0.1).starts_with("Timed out"));
+  std::fs::remove_file(rom_path).unwrap();
+}
+
+// Draws a solid background tile and a solid sprite 0 over it at (128, 120), with both layers enabled, and then
+// polls $2002 until the hit flag comes up. $10 is set once the CPU sees it.
+#[test]
+fn test_sprite_zero_hit_timing() {
+  let code = assemble(&[
+    // The frame counter IRQ would send the CPU to the endless loop
+    ("SEI", vec![]),
+    // Wait for the PPU to warm up
+    ("BIT abs", vec![0x02, 0x20]),
+    ("BPL rel", vec![0xFB]),
+    ("BIT abs", vec![0x02, 0x20]),
+    ("BPL rel", vec![0xFB]),
+    // Tile 1 at row 15, column 16 of the first nametable
+    ("LDA #", vec![0x21]),
+    ("STA abs", vec![0x06, 0x20]),
+    ("LDA #", vec![0xF0]),
+    ("STA abs", vec![0x06, 0x20]),
+    ("LDA #", vec![0x01]),
+    ("STA abs", vec![0x07, 0x20]),
+    // Sprite 0: Y (minus one), tile, attributes, X
+    ("LDA #", vec![0x00]),
+    ("STA abs", vec![0x03, 0x20]),
+    ("LDA #", vec![119]),
+    ("STA abs", vec![0x04, 0x20]),
+    ("LDA #", vec![0x01]),
+    ("STA abs", vec![0x04, 0x20]),
+    ("LDA #", vec![0x00]),
+    ("STA abs", vec![0x04, 0x20]),
+    ("LDA #", vec![128]),
+    ("STA abs", vec![0x04, 0x20]),
+    // No scrolling, both layers from pattern table 0
+    ("LDA #", vec![0x00]),
+    ("STA abs", vec![0x05, 0x20]),
+    ("STA abs", vec![0x05, 0x20]),
+    ("STA abs", vec![0x00, 0x20]),
+    // Show the background and sprites, including the leftmost 8 pixels
+    ("LDA #", vec![0x1E]),
+    ("STA abs", vec![0x01, 0x20]),
+    ("BIT abs", vec![0x02, 0x20]),
+    ("BVC rel", vec![0xFB]),
+    ("INC zp", vec![0x10]),
+  ]);
+  // Tile 1 uses color 1 for all of its pixels
+  let mut chr_rom = vec![0; 0x20];
+  chr_rom[0x10..0x18].fill(0xFF);
+  let rom_path = create_test_rom_with_chr("rustness_sprite_zero_hit_test.nes", &code, &chr_rom);
+  let mut cpu = Ben6502::new(Bus16Bit::new(&rom_path).unwrap());
+  std::fs::remove_file(rom_path).unwrap();
+
+  let mut current_cycle: u64 = 0;
+  let mut hit_position = None;
+  while (current_cycle < PPU_CYCLES_PER_SECOND / 10 && hit_position.is_none()) {
+    console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    let ppu_state = cpu.bus.ppu().get_debug_state();
+    if (ppu_state.sprite_zero_hit != 0) {
+      hit_position = Some((ppu_state.scan_line, ppu_state.cycle));
+    }
+  }
+  // The hit happens on the first pixel where both layers are opaque, at the top left corner of the sprite. Pixel 128
+  // is drawn on cycle 129, and the state is read once the PPU has moved on to the next one.
+  assert_eq!(hit_position, Some((120, 130)));
+
+  for _ in 0..100 {
+    console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+  }
+  assert_eq!(cpu.bus.read_debug(0x0010), 1);
+}
+
+#[test]
+#[ignore = "needs test_roms/blargg/ppu_sprite_hit/rom_singles/01-basics.nes"]
+fn test_ppu_sprite_hit_basics() {
+  assert!(run_blargg_rom("test_roms/blargg/ppu_sprite_hit/rom_singles/01-basics.nes").starts_with("Passed"));
+}
+
+#[test]
+#[ignore = "needs the ROMs in test_roms/blargg/mmc3_test/rom_singles/"]
+fn test_mmc3_irq() {
+  for rom_name in ["1-clocking", "2-details", "3-A12_clocking", "4-scanline_timing", "5-MMC3"] {
+    let result = run_blargg_rom(&format!("test_roms/blargg/mmc3_test/rom_singles/{}.nes", rom_name));
+    assert!(result.starts_with("Passed"), "{}: {}", rom_name, result);
+  }
+}
+
+#[test]
+#[ignore = "needs test_roms/blargg/cpu_dummy_reads.nes"]
+fn test_cpu_dummy_reads() {
+  assert!(run_blargg_rom("test_roms/blargg/cpu_dummy_reads.nes").starts_with("Passed"));
+}
+
+#[test]
+#[ignore = "needs test_roms/blargg/cpu_timing_test6.nes"]
+fn test_cpu_timing() {
+  assert!(run_blargg_rom("test_roms/blargg/cpu_timing_test6.nes").starts_with("Passed"));
+}
+
+#[test]
+#[ignore = "needs test_roms/blargg/ppu_vbl_nmi/rom_singles/01-vbl_basics.nes"]
+fn test_ppu_vbl_basics() {
+  assert!(run_blargg_rom("test_roms/blargg/ppu_vbl_nmi/rom_singles/01-vbl_basics.nes").starts_with("Passed"));
+}
@@ -1,122 +1,1026 @@
-use std::{sync::{Arc, Mutex}, cell::RefCell, rc::Rc};
+use std::{fmt, ops::RangeInclusive, cmp::{min, max}, sync::Arc};
 
-use crate::{device::Device, ben2C02::Ben2C02, hex_utils, cartridge::create_cartridge_from_ines_file, ram::Ram2K, controller::Controller};
+use crate::{device::{Device, DeviceError}, ben2C02::Ben2C02, hex_utils, cartridge::{Cartridge, Region, create_cartridge_from_ines_file}, ram::{Ram2K, FlatRam, RamInitMode}, controller::Controller, apu::{Apu, DMC_DMA_STALL_CYCLES}, apu_io_region::{ApuIoRegion, IO_REGION_BOUNDS}, irq::{IrqLine, IrqSource}};
 
 pub struct Bus16Bit {
-  pub devices: Vec<Rc<RefCell<dyn Device>>>,
-  pub PPU: Rc<RefCell<Ben2C02>>,
-  pub controller: Rc<RefCell<Controller>>,
+  // Sorted by priority, so the first device that responds to an address is the one accessed
+  devices: Vec<RegisteredDevice>,
+  // The console's own components are owned by the bus, and reached through the accessors below
+  ram: Ram2K,
+  PPU: Ben2C02,
+  APU: Apu,
+  controller: Controller,
+  io_region: ApuIoRegion,
+  // The cartridge the PPU reads from. Without one it's a blank cartridge, and the CPU sees cartridge_space_ram instead.
+  cartridge: Cartridge,
+  cartridge_space_ram: Option<FlatRam>,
 
-  // Direct Memory Access variables
-  pub dma_transfer_active: bool,
-  pub waiting_for_cycle_alignment: bool,
-  pub dma_page: u8,
-  pub dma_curr_data: u8,
-  pub dma_curr_addr: u16,
+  // OAM DMA transfer requested through $4014. The CPU is halted until it finishes.
+  pub pending_dma: Option<DmaState>,
+  // Copies the whole page on the first transfer cycle instead of one byte every two cycles. The CPU is halted for just as long.
+  pub fast_oam_dma: bool,
+
+  // Remaining CPU cycles the CPU must stay halted for due to DMC sample fetches
+  pub dmc_stall_cycles: u8,
+
+  pub irq_line: IrqLine,
+
+  // Last value read from or written to the bus. Reading an address no device responds to returns it, since
+  // nothing drives the data lines and they keep their previous value ("open bus").
+  // Reference: https://www.nesdev.org/wiki/Open_bus_behavior
+  last_bus_value: u8,
+  // Makes accesses to unmapped addresses fail instead, which helps finding emulation bugs
+  pub strict_mode: bool,
+
+  // Called on every access a device handled, for debugging register interactions. Only addresses in
+  // access_log_ranges are reported, or all of them if there are none. Copies of the bus report to the same logger.
+  access_logger: Option<Arc<dyn Fn(BusAccess) + Send + Sync>>,
+  pub access_log_ranges: Vec<RangeInclusive<u16>>,
+  // Kept up to date by the console so accesses can be told apart in the logs. The CPU runs each instruction on its
+  // first cycle, so all of an instruction's accesses are reported on that cycle.
+  pub cpu_cycle: u64,
+
+  // How many times each address was read and written, for the memory heat map. Side effect free reads aren't counted.
+  pub read_counts: Box<[u32; 65536]>,
+  pub write_counts: Box<[u32; 65536]>,
+}
+
+// Built on the heap, since 256KB arrays don't fit in the stack of the test threads
+fn new_access_counts() -> Box<[u32; 65536]> {
+  return vec![0; 65536].into_boxed_slice().try_into().unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusAccess {
+  pub addr: u16,
+  pub value: u8,
+  pub kind: AccessType,
+  pub cpu_cycle: u64,
+}
+
+// OAM DMA takes 513 CPU cycles: one while the CPU finishes its write, then 256 read/write pairs.
+// An extra alignment cycle is needed if the $4014 write landed on an odd CPU cycle, since reads must happen on even ones.
+// Reference: https://www.nesdev.org/wiki/PPU_registers#OAMDMA
+pub const OAM_DMA_CYCLES: u16 = 513;
+const OAM_DMA_TRANSFER_CYCLES: u16 = 512;
+
+#[derive(Clone)]
+pub struct DmaState {
+  pub page: u8,
+  // CPU cycles spent on the transfer so far
+  pub cycles_elapsed: u16,
+  // 513 or 514, decided on the transfer's first cycle
+  pub total_cycles: u16,
+  data: u8,
+}
+
+impl DmaState {
+  pub fn new(page: u8) -> DmaState {
+    return DmaState {
+      page,
+      cycles_elapsed: 0,
+      total_cycles: 0,
+      data: 0,
+    };
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessType {
+  Read,
+  Write,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusError {
+  // No device responds at the address (only reported in strict mode, otherwise it's open bus)
+  Unmapped { addr: u16, access: AccessType },
+  // The device at the address couldn't handle the access
+  DeviceError { addr: u16, access: AccessType, source: DeviceError },
+  // Added by the CPU, with the address of the instruction that made the access
+  AtPc { pc: u16, source: Box<BusError> },
+}
+
+impl fmt::Display for BusError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    return match self {
+      BusError::Unmapped { addr, access: AccessType::Read } => write!(f, "Unmapped read from ${:04X}", addr),
+      BusError::Unmapped { addr, access: AccessType::Write } => write!(f, "Unmapped write to ${:04X}", addr),
+      BusError::DeviceError { addr, access: AccessType::Read, source } => write!(f, "Failed read from ${:04X} ({})", addr, source),
+      BusError::DeviceError { addr, access: AccessType::Write, source } => write!(f, "Failed write to ${:04X} ({})", addr, source),
+      BusError::AtPc { pc, source } => write!(f, "{} at PC ${:04X}", source, pc),
+    };
+  }
+}
+
+impl std::error::Error for BusError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    return match self {
+      BusError::Unmapped { .. } => None,
+      BusError::DeviceError { source, .. } => Some(source),
+      BusError::AtPc { source, .. } => Some(source.as_ref()),
+    };
+  }
+}
+
+// A device along with the addresses it was registered for. Accesses only reach it inside that range, and only
+// where the device itself responds (e.g. the cartridge claims all of $4020-$FFFF, but most mappers ignore $4020-$5FFF).
+#[derive(Clone)]
+struct RegisteredDevice {
+  name: &'static str,
+  range: RangeInclusive<u16>,
+  priority: i8,
+  slot: DeviceSlot,
+}
+
+// Which device answers for a registered range. The built in ones point at the bus' own fields, so they're accessed
+// without any dynamic dispatch.
+#[derive(Clone)]
+enum DeviceSlot {
+  Ram,
+  Ppu,
+  IoRegion,
+  Cartridge,
+  CartridgeSpaceRam,
+  // Only tests register devices of their own (e.g. mocks made with new_device)
+  #[cfg(test)]
+  External(Box<dyn Device + Send>),
+}
+
+// Two devices registered for the same addresses with the same priority, so it isn't clear which one should be accessed
+#[derive(Debug, PartialEq)]
+pub struct AddressConflict {
+  pub name: &'static str,
+  pub other_name: &'static str,
+  pub overlap: RangeInclusive<u16>,
+}
+
+impl fmt::Display for AddressConflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    return write!(f, "{} and {} both claim ${:04X}-${:04X}", self.name, self.other_name, self.overlap.start(), self.overlap.end());
+  }
+}
+
+// Builds a bus piece by piece. Without a cartridge, $8000-$FFFF is plain RAM, which lets tests write
+// programs (and the reset vector) straight into memory without a ROM.
+pub struct BusBuilder {
+  ram_init_mode: RamInitMode,
+  cartridge: Option<Cartridge>,
 }
 
-const DMA_ADDR: u16 = 0x4014;
+impl BusBuilder {
+  pub fn new() -> BusBuilder {
+    return BusBuilder {
+      ram_init_mode: RamInitMode::Zero,
+      cartridge: None,
+    };
+  }
+
+  pub fn ram_init_mode(mut self, ram_init_mode: RamInitMode) -> BusBuilder {
+    self.ram_init_mode = ram_init_mode;
+    return self;
+  }
+
+  pub fn cartridge(mut self, cartridge: Cartridge) -> BusBuilder {
+    self.cartridge = Some(cartridge);
+    return self;
+  }
+
+  pub fn build(self) -> Bus16Bit {
+    return Bus16Bit::from_devices(self.ram_init_mode, self.cartridge);
+  }
+}
 
 // Assumed to be a 16-bit bus
 impl Bus16Bit {
 
-  pub fn new(rom_file_path: &str) -> Bus16Bit {
-    let ram = Rc::new(RefCell::new(Ram2K::new((0x0000, 0x1FFF))));
-    let apu_mock = Rc::new(RefCell::new(Ram2K::new((0x4000, 0x4015))));
-    let cartridge = Rc::new(RefCell::new(create_cartridge_from_ines_file(rom_file_path).unwrap()));
-    let PPU = Rc::new(RefCell::new(Ben2C02::new(cartridge.clone())));
-    let controller = Rc::new(RefCell::new(Controller::new()));
-
-    let mut devices: Vec<Rc<RefCell<dyn Device>>> = vec![];
-    devices.push(ram);
-    devices.push(apu_mock);
-    devices.push(PPU.clone());
-    devices.push(controller.clone());
-    devices.push(cartridge);
-    return Bus16Bit {
-      devices,
+  // Fails if the ROM file can't be read or isn't supported, instead of panicking
+  pub fn new(rom_file_path: &str) -> Result<Bus16Bit, String> {
+    return Bus16Bit::from_rom_file(rom_file_path, RamInitMode::Zero);
+  }
+
+  pub fn from_rom_file(rom_file_path: &str, ram_init_mode: RamInitMode) -> Result<Bus16Bit, String> {
+    let cartridge = create_cartridge_from_ines_file(rom_file_path)?;
+    return Ok(BusBuilder::new().ram_init_mode(ram_init_mode).cartridge(cartridge).build());
+  }
+
+  pub fn with_cartridge(cartridge: Cartridge) -> Bus16Bit {
+    return BusBuilder::new().cartridge(cartridge).build();
+  }
+
+  fn from_devices(ram_init_mode: RamInitMode, cartridge: Option<Cartridge>) -> Bus16Bit {
+    let ram = Ram2K::with_init_mode((0x0000, 0x1FFF), ram_init_mode);
+    let (cartridge, cartridge_space_ram) = match cartridge {
+      Some(mut cartridge) => {
+        cartridge.load_trainer_into_prg_ram();
+        (cartridge, None)
+      },
+      // The PPU still needs somewhere to read patterns from, but the CPU sees plain RAM in the cartridge space
+      None => (Cartridge::blank(), Some(FlatRam::unmirrored((0x8000, 0xFFFF)))),
+    };
+    return Bus16Bit::connect(ram, Ben2C02::new(), Apu::new(), Controller::new(), cartridge, cartridge_space_ram);
+  }
+
+  // Puts the components on the bus
+  fn connect(ram: Ram2K, PPU: Ben2C02, APU: Apu, controller: Controller, cartridge: Cartridge, cartridge_space_ram: Option<FlatRam>) -> Bus16Bit {
+    let has_cartridge = cartridge_space_ram.is_none();
+    let mut bus = Bus16Bit {
+      devices: vec![],
+      ram,
       PPU,
+      APU,
       controller,
-      dma_transfer_active: false,
-      waiting_for_cycle_alignment: true,
-      dma_page: 0x0,
-      dma_curr_data: 0x0,
-      dma_curr_addr: 0x0,
+      io_region: ApuIoRegion::new(),
+      cartridge,
+      cartridge_space_ram,
+      pending_dma: None,
+      fast_oam_dma: false,
+      dmc_stall_cycles: 0,
+      irq_line: IrqLine::new(),
+      last_bus_value: 0,
+      strict_mode: false,
+      access_logger: None,
+      access_log_ranges: vec![],
+      cpu_cycle: 0,
+      read_counts: new_access_counts(),
+      write_counts: new_access_counts(),
+    };
+    bus.register_slot("Internal RAM", 0x0000..=0x1FFF, 0, DeviceSlot::Ram).unwrap();
+    bus.register_slot("PPU registers", 0x2000..=0x3FFF, 0, DeviceSlot::Ppu).unwrap();
+    bus.register_slot("APU and I/O registers", IO_REGION_BOUNDS.0..=IO_REGION_BOUNDS.1, 0, DeviceSlot::IoRegion).unwrap();
+    if (has_cartridge) {
+      bus.register_slot("Cartridge", 0x4020..=0xFFFF, 0, DeviceSlot::Cartridge).unwrap();
+    } else {
+      bus.register_slot("RAM", 0x8000..=0xFFFF, 0, DeviceSlot::CartridgeSpaceRam).unwrap();
     }
+    let region = bus.cartridge.region();
+    bus.set_region(region);
+    return bus;
   }
 
-  pub fn read(&mut self, addr: u16, readOnly: bool) -> Result<u8, String> {
-    for device in self.devices.iter() {
-      if device.borrow().in_memory_bounds(addr) {
-        return device.borrow_mut().read(addr);
+  // Comes from the cartridge's header, but can be changed for ROMs with a wrong one
+  pub fn set_region(&mut self, region: Region) {
+    self.PPU.set_region(region);
+    self.APU.set_cpu_clock_rate(region.cpu_clock_rate());
+  }
+
+  // Fails if the range overlaps a device that's already registered.
+  // Use register_device_with_priority to shadow part of another device on purpose.
+  #[cfg(test)]
+  pub fn register_device(&mut self, name: &'static str, range: RangeInclusive<u16>, device: Box<dyn Device + Send>) -> Result<(), AddressConflict> {
+    return self.register_device_with_priority(name, range, 0, device);
+  }
+
+  // Devices with a higher priority take the addresses they share with lower priority ones (the default priority is 0).
+  // Overlaps between devices with the same priority are still rejected.
+  #[cfg(test)]
+  pub fn register_device_with_priority(&mut self, name: &'static str, range: RangeInclusive<u16>, priority: i8, device: Box<dyn Device + Send>) -> Result<(), AddressConflict> {
+    return self.register_slot(name, range, priority, DeviceSlot::External(device));
+  }
+
+  fn register_slot(&mut self, name: &'static str, range: RangeInclusive<u16>, priority: i8, slot: DeviceSlot) -> Result<(), AddressConflict> {
+    for registered in self.devices.iter().filter(|registered| registered.priority == priority) {
+      let overlap_start = max(*range.start(), *registered.range.start());
+      let overlap_end = min(*range.end(), *registered.range.end());
+      if (overlap_start <= overlap_end) {
+        return Err(AddressConflict { name, other_name: registered.name, overlap: overlap_start..=overlap_end });
       }
     }
-    return Ok(0);
-    return Err(String::from(format!("Error reading from memory bus (No device found in given address: 0x{:x}).", addr)));
+    let index = self.devices.iter().position(|registered| registered.priority < priority).unwrap_or(self.devices.len());
+    self.devices.insert(index, RegisteredDevice { name, range, priority, slot });
+    return Ok(());
   }
 
-  pub fn read_word_little_endian(&mut self, addr: u16, readOnly: bool) -> Result<u16, String> {
-    let low = self.read(addr, false);
-    let high = self.read(addr + 1, false);
+  // Registered ranges in address order, for showing the memory map.
+  // The ranges are inclusive, since the last one has to end at $FFFF.
+  pub fn address_map(&self) -> Vec<(RangeInclusive<u16>, &'static str)> {
+    let mut address_map: Vec<(RangeInclusive<u16>, &'static str)> = self.devices.iter()
+      .map(|registered| (registered.range.clone(), registered.name))
+      .collect();
+    address_map.sort_by_key(|(range, _)| *range.start());
+    return address_map;
+  }
 
-    if (low.is_ok() && high.is_ok()) {
-      let result = ((high.unwrap() as u16) << 8) + (low.unwrap() as u16);
-      return Ok(result);
-    } else {
-      return Err(String::from(format!("Error reading word from memory bus (No device found in given address: 0x{:x}).", addr)));
+  pub fn ram(&self) -> &Ram2K {
+    return &self.ram;
+  }
+
+  pub fn ppu(&self) -> &Ben2C02 {
+    return &self.PPU;
+  }
+
+  pub fn ppu_mut(&mut self) -> &mut Ben2C02 {
+    return &mut self.PPU;
+  }
+
+  // For the PPU methods that read through the PPU bus, which need the cartridge lent to them
+  pub fn ppu_and_cartridge(&mut self) -> (&mut Ben2C02, &mut Cartridge) {
+    return (&mut self.PPU, &mut self.cartridge);
+  }
+
+  pub fn apu(&self) -> &Apu {
+    return &self.APU;
+  }
+
+  pub fn apu_mut(&mut self) -> &mut Apu {
+    return &mut self.APU;
+  }
+
+  pub fn controller(&self) -> &Controller {
+    return &self.controller;
+  }
+
+  pub fn controller_mut(&mut self) -> &mut Controller {
+    return &mut self.controller;
+  }
+
+  pub fn cartridge(&self) -> &Cartridge {
+    return &self.cartridge;
+  }
+
+  pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+    return &mut self.cartridge;
+  }
+
+  // Clocks the PPU once (it runs at 3 times the CPU rate)
+  pub fn clock_ppu(&mut self) {
+    self.PPU.clock_cycle(&mut self.cartridge);
+  }
+
+  // Clocks the APU once (it runs at the CPU rate), and performs the DMC sample fetch if it requested one.
+  // Fails if the sample couldn't be read, in which case the DMC doesn't get it.
+  pub fn clock_apu(&mut self) -> Result<(), BusError> {
+    self.APU.clock_cycle();
+    let dmc_fetch_address = self.APU.dmc_fetch_address();
+    if let Some(addr) = dmc_fetch_address {
+      let sample = self.read(addr, false)?;
+      self.APU.dmc_load_sample(sample);
+      self.dmc_stall_cycles += DMC_DMA_STALL_CYCLES;
     }
+    return Ok(());
   }
 
-  pub fn write(&mut self, addr: u16, content: u8) -> Result<(), String>{
-    if (addr == DMA_ADDR) {
-      self.dma_page = content;
-      self.dma_curr_addr = (self.dma_page as u16) << 8;
-      self.dma_transfer_active = true;
-      self.waiting_for_cycle_alignment = true;
-      self.dma_curr_data = 0;
+  // Runs one CPU cycle of the pending OAM DMA transfer, in place of the CPU. cpu_cycle is the number of cycles the CPU
+  // had run before this one. Instructions run all at once on their first cycle, so the $4014 write is counted as
+  // happening on the cycle right before the transfer starts.
+  // Fails if a byte of the page couldn't be read, though the transfer goes on.
+  pub fn clock_oam_dma(&mut self, cpu_cycle: u64) -> Result<(), BusError> {
+    let Some(mut dma) = self.pending_dma.take() else {
       return Ok(());
+    };
+    let mut result = Ok(());
+    if (dma.cycles_elapsed == 0) {
+      let write_cycle = cpu_cycle.wrapping_sub(1);
+      dma.total_cycles = OAM_DMA_CYCLES + (write_cycle % 2) as u16;
     }
-    for device in self.devices.iter_mut() {
-      if device.borrow().in_memory_bounds(addr) {
-        return device.borrow_mut().write(addr, content);
+
+    let alignment_cycles = dma.total_cycles - OAM_DMA_TRANSFER_CYCLES;
+    if (dma.cycles_elapsed >= alignment_cycles) {
+      let transfer_cycle = dma.cycles_elapsed - alignment_cycles;
+      let offset = (transfer_cycle / 2) as u8;
+      let page_addr = (dma.page as u16) << 8;
+      if (self.fast_oam_dma) {
+        if (transfer_cycle == 0) {
+          for offset in 0..=0xFF {
+            match self.read(page_addr | offset, false) {
+              Ok(data) => self.PPU.write_to_oam_memory(offset as u8, data),
+              Err(error) => result = Err(error),
+            }
+          }
+        }
+      } else if (transfer_cycle % 2 == 0) {
+        match self.read(page_addr | offset as u16, false) {
+          Ok(data) => dma.data = data,
+          Err(error) => result = Err(error),
+        }
+      } else {
+        self.PPU.write_to_oam_memory(offset, dma.data);
       }
     }
-    return Err(format!("Error writing to memory bus (No device found in given address: 0x{:X}", addr));
+
+    dma.cycles_elapsed += 1;
+    if (dma.cycles_elapsed < dma.total_cycles) {
+      self.pending_dma = Some(dma);
+    }
+    return result;
+  }
+
+  // Samples every IRQ source into the IRQ line and returns whether the CPU should be interrupted.
+  // Sources are acknowledged through register accesses, so this has to be polled after the CPU runs, not only after clocking.
+  pub fn poll_irq(&mut self) -> bool {
+    self.irq_line.set(IrqSource::ApuFrameCounter, self.APU.frame_counter.irq_pending);
+    self.irq_line.set(IrqSource::Dmc, self.APU.dmc.irq_pending);
+    self.irq_line.set(IrqSource::Mapper, self.cartridge.irq_pending());
+    return self.irq_line.is_asserted();
+  }
+
+  pub fn set_access_logger(&mut self, access_logger: Option<Box<dyn Fn(BusAccess) + Send + Sync>>) {
+    self.access_logger = access_logger.map(Arc::from);
+  }
+
+  fn log_access(&mut self, addr: u16, value: u8, kind: AccessType) {
+    if let Some(access_logger) = &self.access_logger {
+      if (self.access_log_ranges.is_empty() || self.access_log_ranges.iter().any(|range| range.contains(&addr))) {
+        access_logger(BusAccess { addr, value, kind, cpu_cycle: self.cpu_cycle });
+      }
+    }
+  }
+
+  fn find_device(&self, addr: u16) -> Option<usize> {
+    return self.devices.iter().position(|registered| registered.range.contains(&addr) && self.slot_in_memory_bounds(&registered.slot, addr));
+  }
+
+  fn slot_in_memory_bounds(&self, slot: &DeviceSlot, addr: u16) -> bool {
+    return match slot {
+      DeviceSlot::Ram => self.ram.in_memory_bounds(addr),
+      DeviceSlot::Ppu => self.PPU.in_memory_bounds(addr),
+      DeviceSlot::IoRegion => self.io_region.in_memory_bounds(addr),
+      DeviceSlot::Cartridge => self.cartridge.in_memory_bounds(addr),
+      DeviceSlot::CartridgeSpaceRam => self.cartridge_space_ram.as_ref().map_or(false, |flat_ram| flat_ram.in_memory_bounds(addr)),
+      #[cfg(test)]
+      DeviceSlot::External(device) => device.in_memory_bounds(addr),
+    };
+  }
+
+  fn read_device(&mut self, device_index: usize, addr: u16) -> Result<u8, DeviceError> {
+    return match &mut self.devices[device_index].slot {
+      DeviceSlot::Ram => self.ram.read(addr),
+      DeviceSlot::Ppu => self.PPU.read_register(addr, &mut self.cartridge),
+      DeviceSlot::IoRegion => self.io_region.read(addr, &mut self.APU, &mut self.controller),
+      DeviceSlot::Cartridge => self.cartridge.read(addr),
+      DeviceSlot::CartridgeSpaceRam => self.cartridge_space_ram.as_mut().ok_or(DeviceError::OutOfBounds)?.read(addr),
+      #[cfg(test)]
+      DeviceSlot::External(device) => device.read(addr),
+    };
+  }
+
+  fn read_device_debug(&self, device_index: usize, addr: u16) -> Result<u8, DeviceError> {
+    return match &self.devices[device_index].slot {
+      DeviceSlot::Ram => self.ram.read_debug(addr),
+      DeviceSlot::Ppu => self.PPU.read_register_debug(addr, &self.cartridge),
+      DeviceSlot::IoRegion => self.io_region.read_debug(addr, &self.APU, &self.controller),
+      DeviceSlot::Cartridge => self.cartridge.read_debug(addr),
+      DeviceSlot::CartridgeSpaceRam => self.cartridge_space_ram.as_ref().ok_or(DeviceError::OutOfBounds)?.read_debug(addr),
+      #[cfg(test)]
+      DeviceSlot::External(device) => device.read_debug(addr),
+    };
+  }
+
+  fn write_device(&mut self, device_index: usize, addr: u16, data: u8) -> Result<(), DeviceError> {
+    return match &mut self.devices[device_index].slot {
+      DeviceSlot::Ram => self.ram.write(addr, data),
+      DeviceSlot::Ppu => self.PPU.write_register(addr, data, &mut self.cartridge),
+      DeviceSlot::IoRegion => self.io_region.write(addr, data, &mut self.APU, &mut self.controller),
+      DeviceSlot::Cartridge => self.cartridge.write(addr, data),
+      DeviceSlot::CartridgeSpaceRam => self.cartridge_space_ram.as_mut().ok_or(DeviceError::OutOfBounds)?.write(addr, data),
+      #[cfg(test)]
+      DeviceSlot::External(device) => device.write(addr, data),
+    };
+  }
+
+  #[must_use = "reads can have side effects, use peek to just look at memory"]
+  pub fn read(&mut self, addr: u16, readOnly: bool) -> Result<u8, BusError> {
+    self.read_counts[addr as usize] = self.read_counts[addr as usize].saturating_add(1);
+    if let Some(device_index) = self.find_device(addr) {
+      let data = self.read_device(device_index, addr)
+        .map_err(|source| BusError::DeviceError { addr, access: AccessType::Read, source })?;
+      self.last_bus_value = data;
+      self.log_access(addr, data, AccessType::Read);
+      return Ok(data);
+    }
+    if (self.strict_mode) {
+      return Err(BusError::Unmapped { addr, access: AccessType::Read });
+    }
+    return Ok(self.last_bus_value);
+  }
+
+  pub fn read_word_little_endian(&mut self, addr: u16, readOnly: bool) -> Result<u16, BusError> {
+    let low = self.read(addr, false)?;
+    let high = self.read(addr.wrapping_add(1), false)?;
+    return Ok(((high as u16) << 8) + (low as u16));
   }
 
-  pub fn get_memory_content_as_string(&mut self, start_addr: u16, end_addr: u16) -> String {
+  pub fn write_word_little_endian(&mut self, addr: u16, value: u16) -> Result<(), BusError> {
+    self.write(addr, (value & 0x00FF) as u8)?;
+    self.write(addr.wrapping_add(1), (value >> 8) as u8)?;
+    return Ok(());
+  }
+
+  // Writes the bytes one at a time, wrapping around at $FFFF, so the range can span any number of devices.
+  // Stops at the first address that fails, and everything before it stays written.
+  pub fn load_bytes_at(&mut self, addr: u16, bytes: &[u8]) -> Result<(), BusError> {
+    for (i, byte) in bytes.iter().enumerate() {
+      self.write(addr.wrapping_add(i as u16), *byte)?;
+    }
+    return Ok(());
+  }
+
+  // Side effect free, like peek, so it's safe to use over registers.
+  // Fails on the first address that is unmapped or can't be peeked, since there's no open bus value to return for it.
+  pub fn read_bytes(&self, addr: u16, len: usize) -> Result<Vec<u8>, BusError> {
+    let mut result = Vec::with_capacity(len);
+    for i in 0..len {
+      let curr_addr = addr.wrapping_add(i as u16);
+      let device_index = self.find_device(curr_addr)
+        .ok_or(BusError::Unmapped { addr: curr_addr, access: AccessType::Read })?;
+      let data = self.read_device_debug(device_index, curr_addr)
+        .map_err(|source| BusError::DeviceError { addr: curr_addr, access: AccessType::Read, source })?;
+      result.push(data);
+    }
+    return Ok(result);
+  }
+
+  #[must_use = "writes to unmapped addresses fail in strict mode"]
+  pub fn write(&mut self, addr: u16, content: u8) -> Result<(), BusError>{
+    self.write_counts[addr as usize] = self.write_counts[addr as usize].saturating_add(1);
+    self.last_bus_value = content;
+    if let Some(device_index) = self.find_device(addr) {
+      self.write_device(device_index, addr, content)
+        .map_err(|source| BusError::DeviceError { addr, access: AccessType::Write, source })?;
+      // Writes to $4014 are just requests, the transfer itself is clocked from here
+      let oam_dma_request = self.io_region.take_oam_dma_request();
+      if let Some(dma_page) = oam_dma_request {
+        self.pending_dma = Some(DmaState::new(dma_page));
+      }
+      self.log_access(addr, content, AccessType::Write);
+      return Ok(());
+    }
+    if (self.strict_mode) {
+      return Err(BusError::Unmapped { addr, access: AccessType::Write });
+    }
+    // Nothing is listening, so the write is lost
+    return Ok(());
+  }
+
+  // Starts the heat map over
+  pub fn clear_access_counts(&mut self) {
+    self.read_counts.fill(0);
+    self.write_counts.fill(0);
+  }
+
+  // The cartridge's PRG ROM layout, for debugging. Empty when there's no cartridge.
+  pub fn cartridge_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    if (self.cartridge_space_ram.is_some()) {
+      return vec![];
+    }
+    return self.cartridge.current_prg_banks();
+  }
+
+  // Side effect free read, for debuggers and visualizers.
+  // Returns None if nothing is mapped at the address, or if the device there can't be read without side effects.
+  pub fn peek(&self, addr: u16) -> Option<u8> {
+    let device_index = self.find_device(addr)?;
+    return self.read_device_debug(device_index, addr).ok();
+  }
+
+  // Same as peek, but addresses that can't be peeked read as 0xFF
+  pub fn read_debug(&self, addr: u16) -> u8 {
+    return self.peek(addr).unwrap_or(0xFF);
+  }
+
+  // Addresses that can't be peeked are shown as "??"
+  pub fn get_memory_content_as_string(&self, start_addr: u16, end_addr: u16) -> String {
     let mut result = String::new();
     for curr_addr in start_addr..end_addr {
-      let memory_content = self.read(curr_addr, false).unwrap();
-      result.push_str(&hex_utils::decimal_byte_to_hex_str(memory_content));
+      match self.peek(curr_addr) {
+        Some(memory_content) => result.push_str(&hex_utils::decimal_byte_to_hex_str(memory_content)),
+        None => result.push_str("??"),
+      }
       result.push_str(" ");
     }
     return result;
   }
 
-  pub fn get_memory_content_as_vec(&mut self, start_addr: u16, end_addr: u16) -> Vec<u8> {
-    let mut result = vec![];
-    for curr_addr in start_addr..end_addr {
-      let memory_content = self.read(curr_addr, false).unwrap();
-      result.push(memory_content);
-    }
-    return result;
+  pub fn get_memory_content_as_vec(&self, start_addr: u16, end_addr: u16) -> Result<Vec<u8>, BusError> {
+    return self.read_bytes(start_addr, end_addr.saturating_sub(start_addr) as usize);
   }
+}
 
-  // pub fn get_PPU(&mut self) -> Rc<RefCell<Ben2C02>> {
-  //   return self.PPU;
-  // }
+// Deep copy, for save states and comparing snapshots. Every component is copied, so the copy doesn't
+// share state with the original, except for the PPU's visualization buffers, which belong to the UI.
+// Devices registered after construction and the access logger aren't carried over.
+impl Clone for Bus16Bit {
+  fn clone(&self) -> Bus16Bit {
+    let mut bus = Bus16Bit::connect(self.ram.clone(), self.PPU.clone(), self.APU.clone(), self.controller.clone(),
+      self.cartridge.clone(), self.cartridge_space_ram.clone());
+    // Also copies the devices registered from outside, which connect() doesn't know about
+    bus.devices = self.devices.clone();
+    bus.io_region = self.io_region.clone();
+    bus.pending_dma = self.pending_dma.clone();
+    bus.fast_oam_dma = self.fast_oam_dma;
+    bus.dmc_stall_cycles = self.dmc_stall_cycles;
+    bus.irq_line = self.irq_line;
+    bus.last_bus_value = self.last_bus_value;
+    bus.strict_mode = self.strict_mode;
+    bus.access_logger = self.access_logger.clone();
+    bus.access_log_ranges = self.access_log_ranges.clone();
+    bus.cpu_cycle = self.cpu_cycle;
+    bus.read_counts = self.read_counts.clone();
+    bus.write_counts = self.write_counts.clone();
+    return bus;
+  }
 }
 
 
 #[cfg(test)]
 mod bus_tests {
-  use crate::Bus16Bit;
+  use std::sync::{Arc, Mutex};
+  use crate::{bus::{Bus16Bit, BusBuilder, AddressConflict, AccessType, BusAccess, BusError}, device::{Device, DeviceError, new_device}, ben6502::Ben6502, cartridge::Cartridge, console, ram::{Ram, Ram2K}, test_utils::{assemble, NesRomBuilder}};
+
+  fn countdown_program() -> Vec<u8> {
+    return assemble(&[
+      ("LDX #", vec![0x05]),
+      // loop:
+      ("DEX", vec![]),
+      ("STX zp", vec![0x10]),
+      ("BNE rel", vec![0xFB]),
+      ("BRK", vec![]),
+    ]);
+  }
+
+  fn run_instructions(cpu: &mut Ben6502, instruction_count: usize) {
+    for _ in 0..instruction_count {
+      // Finish the previous instruction (or the reset sequence), the next cycle runs a new one
+      while (cpu.current_instruction_remaining_cycles > 0) {
+        cpu.clock_cycle().unwrap();
+      }
+      cpu.clock_cycle().unwrap();
+    }
+  }
+
+  #[test]
+  fn test_default_address_map() {
+    assert_eq!(Bus16Bit::new("test_roms/nestest.nes").unwrap().address_map(), vec![
+      (0x0000..=0x1FFF, "Internal RAM"),
+      (0x2000..=0x3FFF, "PPU registers"),
+      (0x4000..=0x401F, "APU and I/O registers"),
+      (0x4020..=0xFFFF, "Cartridge"),
+    ]);
+    assert_eq!(BusBuilder::new().build().address_map()[3], (0x8000..=0xFFFF, "RAM"));
+  }
+
+  #[test]
+  fn test_overlapping_devices_are_rejected() {
+    let mut bus = BusBuilder::new().build();
+    // Overlaps the end of the internal RAM and the start of the PPU registers
+    let conflict = bus.register_device("Test RAM", 0x1FFE..=0x2001, Box::new(Ram2K::new((0x1FFE, 0x2001)))).unwrap_err();
+    assert_eq!(conflict, AddressConflict { name: "Test RAM", other_name: "Internal RAM", overlap: 0x1FFE..=0x1FFF });
+    assert_eq!(conflict.to_string(), "Test RAM and Internal RAM both claim $1FFE-$1FFF");
+    assert_eq!(bus.address_map().len(), 4);
+
+    // Fits in the unused space after the I/O registers
+    bus.register_device("Test RAM", 0x4020..=0x5FFF, Box::new(Ram2K::new((0x4020, 0x5FFF)))).unwrap();
+    bus.write(0x4020, 0x42).unwrap();
+    assert_eq!(bus.read(0x4820, false).unwrap(), 0x42);
+  }
+
+  #[test]
+  fn test_higher_priority_devices_shadow_others() {
+    let mut bus = BusBuilder::new().build();
+    bus.register_device_with_priority("Test RAM", 0x2000..=0x2003, 1, Box::new(Ram::<4>::new((0x2000, 0x2003)))).unwrap();
+    // The same priority still conflicts
+    assert!(bus.register_device_with_priority("Other RAM", 0x2002..=0x2005, 1, Box::new(Ram::<4>::new((0x2002, 0x2005)))).is_err());
+
+    // PPUCTRL writes select the name table in the temporary VRAM address
+    bus.write(0x2000, 0x03).unwrap();
+    assert_eq!(bus.read(0x2000, false).unwrap(), 0x03);
+    assert_eq!(bus.ppu().get_debug_state().temp_vram_addr & 0x0C00, 0x0000);
+    // Past the shadowed range, accesses reach the PPU again
+    bus.write(0x2008, 0x03).unwrap();
+    assert_eq!(bus.ppu().get_debug_state().temp_vram_addr & 0x0C00, 0x0C00);
+
+    // Lower priority devices only see what nothing else claims
+    bus.register_device_with_priority("Fallback RAM", 0x0000..=0x0003, -1, Box::new(Ram::<4>::new((0x0000, 0x0003)))).unwrap();
+    bus.write(0x0000, 0x24).unwrap();
+    assert_eq!(bus.ram().raw()[0], 0x24);
+    assert!(bus.address_map().contains(&(0x0000..=0x0003, "Fallback RAM")));
+  }
+
+  #[test]
+  fn test_custom_devices() {
+    let writes = Arc::new(Mutex::new(vec![]));
+    let device_writes = writes.clone();
+    let device = new_device(0x5000..=0x5003, |addr| (addr & 0xFF) as u8, move |addr, data| device_writes.lock().unwrap().push((addr, data)));
+    let mut bus = BusBuilder::new().build();
+    bus.register_device("Mock device", 0x5000..=0x5003, device).unwrap();
+
+    assert_eq!(bus.read(0x5002, false).unwrap(), 0x02);
+    bus.write(0x5003, 0x42).unwrap();
+    assert_eq!(*writes.lock().unwrap(), vec![(0x5003, 0x42)]);
+    // They have no side effect free reads
+    assert_eq!(bus.peek(0x5002), None);
+  }
+
+  #[test]
+  fn test_clones_have_their_own_devices_and_screen() {
+    let mut bus = BusBuilder::new().build();
+    bus.register_device("Test RAM", 0x5000..=0x5003, Box::new(Ram::<4>::new((0x5000, 0x5003)))).unwrap();
+    bus.write(0x5000, 0x11).unwrap();
+    bus.ppu_mut().screen_vis_buffer.lock().unwrap()[0][0].red = 0x42;
+
+    let mut copy = bus.clone();
+    assert_eq!(copy.read(0x5000, false).unwrap(), 0x11);
+    copy.write(0x5000, 0x22).unwrap();
+    assert_eq!(bus.read(0x5000, false).unwrap(), 0x11);
+
+    // Running the copy draws over its own screen, not over the original's
+    assert_eq!(copy.ppu().screen_vis_buffer.lock().unwrap()[0][0].red, 0x42);
+    let (ppu, cartridge) = copy.ppu_and_cartridge();
+    ppu.render_frame_to_buffer(cartridge);
+    assert_ne!(copy.ppu().screen_vis_buffer.lock().unwrap()[0][0].red, 0x42);
+    assert_eq!(bus.ppu().screen_vis_buffer.lock().unwrap()[0][0].red, 0x42);
+  }
+
+  #[test]
+  fn test_bus_with_in_memory_cartridge() {
+    // Mapper 0 with a single PRG bank, so $8000 and $C000 see the same data
+    let rom = NesRomBuilder::new().set_reset_vector(0x8000).write_prg(0, &countdown_program()).build();
+
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap()));
+    assert_eq!(cpu.registers.pc, 0x8000);
+    assert_eq!(cpu.bus.read_debug(0xC000), 0xA2);
+    // LDX, and then two rounds of the loop
+    run_instructions(&mut cpu, 1 + 3 * 2);
+    assert_eq!(cpu.registers.x, 3);
+    assert_eq!(cpu.bus.read_debug(0x0010), 3);
+  }
+
+  #[test]
+  fn test_invalid_rom_bytes_fail() {
+    assert!(Cartridge::from_bytes(&[b'N', b'E', b'S']).is_err());
+    assert!(Cartridge::from_bytes(&[b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    assert!(Bus16Bit::new("test_roms/missing.nes").is_err());
+  }
+
+  #[test]
+  fn test_builder_without_cartridge_maps_ram() {
+    let mut bus = BusBuilder::new().build();
+    for (i, byte) in countdown_program().iter().enumerate() {
+      bus.write(0x9000 + i as u16, *byte).unwrap();
+    }
+    bus.write(0xFFFC, 0x00).unwrap();
+    bus.write(0xFFFD, 0x90).unwrap();
+
+    let mut cpu = Ben6502::new(bus);
+    assert_eq!(cpu.registers.pc, 0x9000);
+    run_instructions(&mut cpu, 1 + 3 * 5);
+    assert_eq!(cpu.registers.x, 0);
+    assert_eq!(cpu.bus.read_debug(0x0010), 0);
+    assert_eq!(cpu.registers.pc, 0x9007);
+  }
 
   // #[test]
   // fn test_get_memory_content_as_string() {
-  //   let bus = Bus16Bit::new("hey_mona.nes");
+  //   let bus = Bus16Bit::new("hey_mona.nes").unwrap();
   //   println!("{}", bus.get_memory_content_as_string(0, 100));
   // }
 
+  #[test]
+  fn test_peek_leaves_ppu_state_alone() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    while (bus.ppu().status_reg.get_vertical_blank() == 0) {
+      bus.clock_ppu();
+    }
+    // Point the PPU address at the start of the name tables, and fill the read buffer with something
+    bus.write(0x2006, 0x20).unwrap();
+    bus.write(0x2006, 0x00).unwrap();
+    bus.write(0x2007, 0xAB).unwrap();
+    bus.write(0x2006, 0x20).unwrap();
+    bus.write(0x2006, 0x00).unwrap();
+
+    let status_peek = bus.peek(0x2002);
+    let data_peek = bus.peek(0x2007);
+    for _ in 0..10 {
+      assert_eq!(bus.peek(0x2002), status_peek);
+      assert_eq!(bus.peek(0x2007), data_peek);
+    }
+
+    // VBlank wasn't cleared by the peeks
+    assert_eq!(bus.read(0x2002, false).unwrap() & 0x80, 0x80);
+    // Neither was the address incremented, nor the read buffer refilled
+    bus.read(0x2007, false).unwrap();
+    assert_eq!(bus.read(0x2007, false).unwrap(), 0xAB);
+  }
+
+  #[test]
+  fn test_peek_leaves_controller_state_alone() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.controller_mut().emulator_input[0] = 0b1010_0000;
+    bus.write(0x4016, 0x01).unwrap();
+    bus.write(0x4016, 0x00).unwrap();
+
+    for _ in 0..10 {
+      assert_eq!(bus.peek(0x4016).map(|data| data & 0x01), Some(1));
+    }
+    let buttons: Vec<u8> = (0..3).map(|_| bus.read(0x4016, false).unwrap() & 0x01).collect();
+    assert_eq!(buttons, vec![1, 0, 1]);
+  }
+
+  #[test]
+  fn test_unmapped_reads_return_open_bus() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.write(0x0010, 0x42).unwrap();
+    assert_eq!(bus.read(0x5000, false).unwrap(), 0x42);
+
+    bus.read(0x8000, false).unwrap();
+    let rom_byte = bus.read_debug(0x8000);
+    assert_eq!(bus.read(0x5000, false).unwrap(), rom_byte);
+
+    // Writes to unmapped addresses are lost
+    bus.write(0x5000, 0x99).unwrap();
+    bus.write(0x0010, 0x42).unwrap();
+    assert_eq!(bus.read(0x5000, false).unwrap(), 0x42);
+  }
+
+  #[test]
+  fn test_strict_mode_fails_on_unmapped_accesses() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.strict_mode = true;
+    assert!(bus.read(0x5000, false).is_err());
+    assert!(bus.write(0x5000, 0x00).is_err());
+    assert!(bus.read(0x0000, false).is_ok());
+  }
+
+  #[test]
+  fn test_bus_errors_are_typed() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.strict_mode = true;
+    assert_eq!(bus.read(0x5000, false), Err(BusError::Unmapped { addr: 0x5000, access: AccessType::Read }));
+    assert_eq!(bus.write(0x5123, 0x00), Err(BusError::Unmapped { addr: 0x5123, access: AccessType::Write }));
+    // Only $4016 strobes the controllers, and $4017 writes are taken by the frame counter before reaching them
+    let controller_error = bus.controller_mut().write(0x4017, 0x00).unwrap_err();
+    assert_eq!(controller_error, DeviceError::Unsupported);
+  }
+
+  #[test]
+  fn test_cpu_reports_bus_errors_with_pc() {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0xC4F2, &assemble(&[("STA abs", vec![0x23, 0x51])])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0xC4F2).unwrap();
+    bus.strict_mode = true;
+
+    let mut cpu = Ben6502::new(bus);
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      cpu.clock_cycle().unwrap();
+    }
+    let error = cpu.clock_cycle().unwrap_err();
+    assert_eq!(error, BusError::AtPc { pc: 0xC4F2, source: Box::new(BusError::Unmapped { addr: 0x5123, access: AccessType::Write }) });
+    assert_eq!(error.to_string(), "Unmapped write to $5123 at PC $C4F2");
+    // The instruction still finished, and the error isn't reported twice
+    assert_eq!(cpu.registers.pc, 0xC4F5);
+    assert!(cpu.clock_cycle().is_ok());
+  }
+
+  #[test]
+  fn test_cpu_survives_running_through_unmapped_memory() {
+    let mut cpu = Ben6502::new(Bus16Bit::new("test_roms/nestest.nes").unwrap());
+    cpu.registers.pc = 0x5000;
+    cpu.current_instruction_remaining_cycles = 0;
+    for _ in 0..1000 {
+      cpu.clock_cycle().unwrap();
+    }
+  }
+
+  #[test]
+  fn test_memory_content_string_marks_unmapped_addresses() {
+    let bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    assert_eq!(bus.peek(0x5000), None);
+    assert_eq!(bus.get_memory_content_as_string(0x5000, 0x5002), "?? ?? ");
+    assert_eq!(bus.get_memory_content_as_vec(0x5000, 0x5002), Err(BusError::Unmapped { addr: 0x5000, access: AccessType::Read }));
+  }
+
+  #[test]
+  fn test_bulk_accesses_across_ram_and_ppu() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.strict_mode = true;
+    // The last two bytes of mirrored RAM, then PPUCTRL and PPUMASK
+    bus.load_bytes_at(0x1FFE, &[0x11, 0x22, 0x80, 0x1E]).unwrap();
+    assert_eq!(bus.read_bytes(0x07FE, 2).unwrap(), vec![0x11, 0x22]);
+    // PPUCTRL and PPUMASK are write-only, so they read back the last value written to the PPU
+    assert_eq!(bus.read_bytes(0x1FFF, 3).unwrap(), vec![0x22, 0x1E, 0x1E]);
+    assert_eq!(bus.get_memory_content_as_vec(0x1FFE, 0x2000).unwrap(), vec![0x11, 0x22]);
+  }
+
+  #[test]
+  fn test_bulk_accesses_report_the_failing_address() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    bus.strict_mode = true;
+    // $401E and $401F are in the I/O region, $4020 isn't mapped without a mapper that uses it
+    let error = bus.load_bytes_at(0x401E, &[0x01, 0x02, 0x03, 0x04]).unwrap_err();
+    assert_eq!(error, BusError::Unmapped { addr: 0x4020, access: AccessType::Write });
+    let error = bus.read_bytes(0x401F, 2).unwrap_err();
+    assert_eq!(error, BusError::Unmapped { addr: 0x4020, access: AccessType::Read });
+  }
+
+  #[test]
+  fn test_word_round_trip() {
+    let mut bus = BusBuilder::new().build();
+    bus.write_word_little_endian(0x0100, 0xABCD).unwrap();
+    assert_eq!(bus.read_debug(0x0100), 0xCD);
+    assert_eq!(bus.read_debug(0x0101), 0xAB);
+    assert_eq!(bus.read_word_little_endian(0x0100, false).unwrap(), 0xABCD);
+  }
+
+  #[test]
+  fn test_bulk_accesses_wrap_around_the_address_space() {
+    let mut bus = BusBuilder::new().build();
+    bus.write_word_little_endian(0xFFFF, 0x1234).unwrap();
+    assert_eq!(bus.read_debug(0xFFFF), 0x34);
+    assert_eq!(bus.read_debug(0x0000), 0x12);
+    assert_eq!(bus.read_word_little_endian(0xFFFF, false).unwrap(), 0x1234);
+
+    bus.load_bytes_at(0xFFFE, &[0xAA, 0xBB, 0xCC, 0xDD]).unwrap();
+    assert_eq!(bus.read_bytes(0xFFFE, 4).unwrap(), vec![0xAA, 0xBB, 0xCC, 0xDD]);
+    assert_eq!(bus.read_bytes(0x0000, 2).unwrap(), vec![0xCC, 0xDD]);
+  }
+
+  #[test]
+  fn test_access_logger_reports_filtered_accesses() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    let accesses = Arc::new(Mutex::new(vec![]));
+    let logged_accesses = accesses.clone();
+    bus.set_access_logger(Some(Box::new(move |access| logged_accesses.lock().unwrap().push(access))));
+    bus.access_log_ranges = vec![0x2000..=0x2007];
+
+    bus.cpu_cycle = 10;
+    bus.write(0x0010, 0x42).unwrap();
+    bus.write(0x2006, 0x21).unwrap();
+    bus.cpu_cycle = 11;
+    bus.read(0x0010, false).unwrap();
+    let status = bus.read(0x2002, false).unwrap();
+    // Mirrors of the PPU registers are outside of the range
+    bus.write(0x2008, 0x00).unwrap();
+    // Debug reads aren't accesses
+    bus.peek(0x2002);
+
+    assert_eq!(*accesses.lock().unwrap(), vec![
+      BusAccess { addr: 0x2006, value: 0x21, kind: AccessType::Write, cpu_cycle: 10 },
+      BusAccess { addr: 0x2002, value: status, kind: AccessType::Read, cpu_cycle: 11 },
+    ]);
+
+    // Without ranges everything is logged, until the logger is removed
+    bus.access_log_ranges.clear();
+    bus.read(0x0010, false).unwrap();
+    bus.set_access_logger(None);
+    bus.read(0x0010, false).unwrap();
+    assert_eq!(accesses.lock().unwrap().len(), 3);
+    assert_eq!(accesses.lock().unwrap()[2], BusAccess { addr: 0x0010, value: 0x42, kind: AccessType::Read, cpu_cycle: 11 });
+  }
+
+  #[test]
+  fn test_access_logger_sees_cpu_cycles() {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0x8000, &assemble(&[
+      ("STA zp", vec![0x10]),
+      ("STA zp", vec![0x11]),
+    ])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    let accesses = Arc::new(Mutex::new(vec![]));
+    let logged_accesses = accesses.clone();
+    bus.set_access_logger(Some(Box::new(move |access| logged_accesses.lock().unwrap().push(access))));
+    bus.access_log_ranges = vec![0x0010..=0x0011];
+
+    let mut cpu = Ben6502::new(bus);
+    let mut current_cycle = 0;
+    while (accesses.lock().unwrap().len() < 2) {
+      console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    }
+    let accesses = accesses.lock().unwrap();
+    // STA zp takes 3 cycles
+    assert_eq!(accesses[1].cpu_cycle - accesses[0].cpu_cycle, 3);
+    assert_eq!(accesses[0].addr, 0x0010);
+    assert_eq!(accesses[0].kind, AccessType::Write);
+  }
+
+  #[test]
+  fn test_access_counts() {
+    let mut bus = BusBuilder::new().build();
+    bus.write(0x0010, 0x42).unwrap();
+    bus.write(0x0010, 0x43).unwrap();
+    bus.read(0x0010, false).unwrap();
+    // Mirrors are counted at the address the CPU used
+    bus.read(0x0810, false).unwrap();
+    // Unmapped addresses are accessed all the same
+    bus.read(0x5000, false).unwrap();
+    // Side effect free reads don't count
+    bus.peek(0x0010);
+    bus.read_bytes(0x0010, 4).unwrap();
+    assert_eq!((bus.read_counts[0x0010], bus.write_counts[0x0010]), (1, 2));
+    assert_eq!((bus.read_counts[0x0810], bus.read_counts[0x5000]), (1, 1));
+    assert_eq!(bus.read_counts[0x0011], 0);
+
+    bus.clear_access_counts();
+    assert!(bus.read_counts.iter().chain(bus.write_counts.iter()).all(|count| *count == 0));
+  }
+
+  #[test]
+  fn test_write_word_sets_the_reset_vector() {
+    let mut bus = BusBuilder::new().build();
+    bus.load_bytes_at(0xC000, &assemble(&[("LDX #", vec![0x05])])).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0xC000).unwrap();
+    let mut cpu = Ben6502::new(bus);
+    assert_eq!(cpu.registers.pc, 0xC000);
+    run_instructions(&mut cpu, 1);
+    assert_eq!(cpu.registers.x, 0x05);
+  }
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+/*
+
+Logical call stack of the CPU, for the debugger. It's built by watching JSR and RTS instructions, so it
+doesn't depend on what's actually in the stack page, which games freely reuse for other data.
+
+Games don't always return from a subroutine with an RTS to the instruction after the JSR (jump tables that push
+their target and RTS into it are common, for example), so returns are matched against the frames on the stack:
+- If the return address matches the top frame, it's popped.
+- If it matches a deeper frame, everything up to it is unwound.
+- Otherwise, the RTS isn't paired with any JSR, and the stack is left alone.
+
+*/
+
+// Deeply nested calls are unusual, so when the limit is reached it's most likely due to a mismatch, and the oldest frames are dropped
+pub const MAX_CALL_STACK_DEPTH: usize = 64;
+
+// Size of the JSR instruction, the return address is right after it
+const JSR_INSTRUCTION_SIZE: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+  // Address of the JSR instruction
+  pub call_site_pc: u16,
+  // Address of the subroutine
+  pub target_pc: u16,
+  // CPU cycle in which the subroutine was called
+  pub cycle: u64,
+}
+
+impl CallFrame {
+  pub fn return_pc(&self) -> u16 {
+    return self.call_site_pc.wrapping_add(JSR_INSTRUCTION_SIZE);
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+  frames: Vec<CallFrame>,
+  // Amount of RTS instructions that didn't return to the top frame
+  pub mismatched_returns: u64,
+}
+
+impl CallStack {
+  pub fn new() -> CallStack {
+    return CallStack {
+      frames: vec![],
+      mismatched_returns: 0,
+    };
+  }
+
+  pub fn push(&mut self, frame: CallFrame) {
+    if self.frames.len() >= MAX_CALL_STACK_DEPTH {
+      self.frames.remove(0);
+    }
+    self.frames.push(frame);
+  }
+
+  // Called on RTS with the address it returned to. Returns the popped frame, if any frame matched.
+  pub fn pop(&mut self, return_pc: u16) -> Option<CallFrame> {
+    let matching_frame_index = self.frames.iter().rposition(|frame| frame.return_pc() == return_pc);
+    match matching_frame_index {
+      Some(index) => {
+        if index != self.frames.len() - 1 {
+          self.mismatched_returns += 1;
+        }
+        let frame = self.frames[index];
+        self.frames.truncate(index);
+        return Some(frame);
+      },
+      None => {
+        self.mismatched_returns += 1;
+        return None;
+      }
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.frames.clear();
+    self.mismatched_returns = 0;
+  }
+
+  // Outermost call first
+  pub fn frames(&self) -> &[CallFrame] {
+    return &self.frames;
+  }
+}
+
+
+#[cfg(test)]
+mod call_stack_tests {
+  use super::{CallFrame, CallStack, MAX_CALL_STACK_DEPTH};
+  use crate::{ben6502::Ben6502, bus::Bus16Bit};
+
+  fn frame(call_site_pc: u16, target_pc: u16) -> CallFrame {
+    return CallFrame { call_site_pc, target_pc, cycle: 0 };
+  }
+
+  #[test]
+  fn test_returns_that_skip_frames_unwind_them() {
+    let mut call_stack = CallStack::new();
+    call_stack.push(frame(0x8000, 0x9000));
+    call_stack.push(frame(0x9010, 0xA000));
+
+    // Returning straight to the outer caller
+    assert_eq!(call_stack.pop(0x8003), Some(frame(0x8000, 0x9000)));
+    assert!(call_stack.frames().is_empty());
+    assert_eq!(call_stack.mismatched_returns, 1);
+
+    // An RTS used as a jump doesn't touch the stack
+    call_stack.push(frame(0x8000, 0x9000));
+    assert_eq!(call_stack.pop(0xC123), None);
+    assert_eq!(call_stack.frames().len(), 1);
+    assert_eq!(call_stack.mismatched_returns, 2);
+  }
+
+  #[test]
+  fn test_depth_is_capped() {
+    let mut call_stack = CallStack::new();
+    for i in 0..(MAX_CALL_STACK_DEPTH as u16 + 10) {
+      call_stack.push(frame(i, 0x9000));
+    }
+    assert_eq!(call_stack.frames().len(), MAX_CALL_STACK_DEPTH);
+    assert_eq!(call_stack.frames()[0].call_site_pc, 10);
+  }
+
+  #[test]
+  fn test_cpu_tracks_jsr_and_rts() {
+    let mut cpu = Ben6502::new(Bus16Bit::new("test_roms/nestest.nes").unwrap());
+    // $0200: JSR $0210, $0210: JSR $0220, $0220: RTS
+    let program: [(u16, &[u8]); 3] = [(0x0200, &[0x20, 0x10, 0x02]), (0x0210, &[0x20, 0x20, 0x02, 0x60]), (0x0220, &[0x60])];
+    for (addr, code) in program {
+      for (i, byte) in code.iter().enumerate() {
+        cpu.bus.write(addr + i as u16, *byte).unwrap();
+      }
+    }
+    cpu.registers.pc = 0x0200;
+    cpu.current_instruction_remaining_cycles = 0;
+    let mut run_instruction = |cpu: &mut Ben6502| {
+      cpu.clock_cycle().unwrap();
+      while (cpu.current_instruction_remaining_cycles > 0) {
+        cpu.clock_cycle().unwrap();
+      }
+    };
+
+    run_instruction(&mut cpu);
+    run_instruction(&mut cpu);
+    let frames = cpu.get_call_stack();
+    assert_eq!(frames.len(), 2);
+    assert_eq!((frames[0].call_site_pc, frames[0].target_pc), (0x0200, 0x0210));
+    assert_eq!((frames[1].call_site_pc, frames[1].target_pc), (0x0210, 0x0220));
+    assert!(frames[1].cycle > frames[0].cycle);
+
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.registers.pc, 0x0213);
+    assert_eq!(cpu.get_call_stack().len(), 1);
+    run_instruction(&mut cpu);
+    assert_eq!(cpu.registers.pc, 0x0203);
+    assert!(cpu.get_call_stack().is_empty());
+    assert_eq!(cpu.call_stack.mismatched_returns, 0);
+  }
+}
@@ -1,29 +1,48 @@
-use std::{fs, rc::Rc, sync::{Mutex, Arc}};
+use std::{fmt, fs, path::Path, rc::Rc, sync::{Mutex, Arc}};
 
-use crate::{mapper::{Mapper, Mapper000}, device::Device};
+use crate::{mapper::{Mapper, Mapper000, Mapper001, Mapper002, Mapper004, Mapper005, Mapper007, Mapper009, Mapper011, Mapper034, Mapper066, Mapper071, Mapper206, PRG_RAM_START_ADDR, PRG_RAM_END_ADDR}, device::{Device, DeviceError, private::Sealed}, crc32::{crc32, crc32_update}};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MirroringMode {
   Vertical,
   Horizontal,
   OnscreenLo,
-  OnscreenHi
+  OnscreenHi,
+  // The cartridge has memory for the other two name tables, so all four are separate
+  FourScreen,
+  // Each of the four name tables picks one of the console's two, for mappers that set them one by one (MMC5)
+  PerTable([u8; 4])
 }
 
-fn verify_nes_header (file_contents: &Vec<u8>) -> bool{
+fn verify_nes_header (file_contents: &[u8]) -> bool{
   return file_contents[0] == ('N' as u8) &&
         file_contents[1] == ('E' as u8) &&
-        file_contents[2] == ('S' as u8);
+        file_contents[2] == ('S' as u8) &&
+        file_contents[3] == 0x1A;
 }
 
 fn get_mapper1_from_flags6(flags6: u8) -> u8 {
   return (flags6 >> 4) & 0b1111;
 }
 
+fn get_battery_from_flags6(flags6: u8) -> bool {
+  return (flags6 & 0b10) != 0;
+}
+
 fn get_mapper2_from_flags7(flags7: u8) -> u8 {
   return (flags7 >> 4) & 0b1111;
 }
 
+// NES 2.0 headers are marked with 0b10 in bits 2-3 of flags 7
+fn is_nes_2_0_from_flags7(flags7: u8) -> bool {
+  return (flags7 & 0b1100) == 0b1000;
+}
+
+// In NES 2.0 headers, byte 8 holds the submapper number in its upper nibble (it's the PRG RAM size in iNES ones)
+fn get_submapper_from_byte8(byte8: u8) -> u8 {
+  return (byte8 >> 4) & 0b1111;
+}
+
 fn get_tv_system_1_from_flags9(flags9: u8) -> u8 {
   return flags9 & 0b1;
 }
@@ -32,21 +51,158 @@ fn get_tv_system_2_from_flags10(flags10: u8) -> u8 {
   return flags10 & 0b11;
 }
 
-fn create_mapper_from_number(mapper_num: u8, num_prg_banks: u8, num_chr_banks: u8) -> Result<Box<dyn Mapper>, String> {
+// In NES 2.0 headers, byte 12 holds the CPU/PPU timing: 0 is NTSC, 1 PAL, 2 multiple regions and 3 Dendy
+fn get_timing_from_byte12(byte12: u8) -> u8 {
+  return byte12 & 0b11;
+}
+
+// Name of the board (or chip) behind each supported mapper number
+fn mapper_name(mapper_num: u8) -> &'static str {
+  return match mapper_num {
+    0 => "NROM",
+    1 => "MMC1",
+    2 => "UxROM",
+    4 => "MMC3",
+    5 => "MMC5",
+    7 => "AxROM",
+    9 => "MMC2",
+    11 => "Color Dreams",
+    34 => "BNROM/NINA-001",
+    66 => "GxROM",
+    71 => "Camerica",
+    206 => "Namco 108",
+    _ => "Unknown",
+  };
+}
+
+fn create_mapper_from_number(mapper_num: u8, submapper_num: u8, num_prg_banks: u8, num_chr_banks: u8) -> Result<Box<dyn Mapper>, CartridgeError> {
   match mapper_num {
     0 => {
       let result = Mapper000::new(num_prg_banks, num_chr_banks);
       return Ok(Box::new(result));
     },
-    _ => Err(String::from(format!("Tried to create a mapper using mapper number {}", mapper_num)))
+    1 => {
+      let result = Mapper001::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    2 => {
+      let result = Mapper002::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    4 => {
+      let result = Mapper004::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    5 => {
+      let result = Mapper005::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    7 => {
+      let result = Mapper007::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    9 => {
+      // The last three 8KB PRG banks are fixed, and MMC2 boards have no CHR RAM
+      if (num_prg_banks < 2 || num_chr_banks == 0) {
+        return Err(CartridgeError::UnsupportedBankCount { mapper_num, num_prg_banks, num_chr_banks });
+      }
+      let result = Mapper009::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    11 => {
+      let result = Mapper011::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    34 => {
+      let result = Mapper034::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    66 => {
+      let result = Mapper066::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    71 => {
+      let mut result = Mapper071::new(num_prg_banks, num_chr_banks);
+      // Submapper 1 is Fire Hawk's board, the only one with mirroring control
+      result.mirroring_control = submapper_num == 1;
+      return Ok(Box::new(result));
+    },
+    206 => {
+      let result = Mapper206::new(num_prg_banks, num_chr_banks);
+      return Ok(Box::new(result));
+    },
+    _ => Err(CartridgeError::UnsupportedMapper(mapper_num))
+  }
+}
+
+const PRG_RAM_BANK_SIZE: usize = 8 * 1024;
+const EXPANSION_AREA_START_ADDR: u16 = 0x4020;
+// Some ROMs (mostly conversions of Famicom Disk System games) have a trainer between the header and the PRG data,
+// which has to be loaded into PRG RAM at $7000-$71FF
+const TRAINER_SIZE: usize = 512;
+const TRAINER_ADDR: u16 = 0x7000;
+const HEADER_SIZE: usize = 16;
+const PRG_ROM_BANK_SIZE: usize = 16384;
+const CHR_ROM_BANK_SIZE: usize = 8192;
+
+// Why a ROM couldn't be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+  ReadFailed { file_path: String, reason: String },
+  // Not even the 16 byte header fits
+  HeaderTooShort { file_size: usize },
+  // The file doesn't start with "NES" and $1A, so it's probably not a ROM at all
+  InvalidHeader,
+  NoPrgRom,
+  // The file ends before the section (trainer, PRG ROM or CHR ROM) the header says is there
+  Truncated { section: &'static str, expected_size: usize, file_size: usize },
+  UnsupportedMapper(u8),
+  // The mapper can't work with this much PRG or CHR ROM (in 16KB and 8KB banks, like in the header)
+  UnsupportedBankCount { mapper_num: u8, num_prg_banks: u8, num_chr_banks: u8 },
+}
+
+impl fmt::Display for CartridgeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Error while loading ROM file: ")?;
+    return match self {
+      CartridgeError::ReadFailed { file_path, reason } => write!(f, "could not read {} ({}).", file_path, reason),
+      CartridgeError::HeaderTooShort { file_size } => write!(f, "the file has {} bytes, which is too short for an NES header.", file_size),
+      CartridgeError::InvalidHeader => write!(f, "invalid NES header."),
+      CartridgeError::NoPrgRom => write!(f, "the header says there's no PRG ROM."),
+      CartridgeError::Truncated { section, expected_size, file_size } =>
+        write!(f, "the file is smaller than its header says (it has {} bytes, and the {} ends at byte {}).", file_size, section, expected_size),
+      CartridgeError::UnsupportedMapper(mapper_num) =>
+        write!(f, "mapper {} isn't supported (only mappers 0, 1, 2, 4, 5, 7, 9, 11, 34, 66, 71 and 206 are).", mapper_num),
+      CartridgeError::UnsupportedBankCount { mapper_num, num_prg_banks, num_chr_banks } =>
+        write!(f, "mapper {} can't be used with {}KB of PRG ROM and {}KB of CHR ROM.", mapper_num, *num_prg_banks as usize * 16, *num_chr_banks as usize * 8),
+    };
+  }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// The bus and the UI report loading errors as plain messages
+impl From<CartridgeError> for String {
+  fn from(error: CartridgeError) -> String {
+    return error.to_string();
   }
 }
 
 // Reference: https://www.nesdev.org/wiki/INES
-pub fn create_cartridge_from_ines_file(file_path: &str) -> Result<Cartridge, String> {
-  let file_contents = fs::read(file_path).unwrap();
-  if !verify_nes_header(&file_contents){
-    return Err(String::from("Error while loading ROM file: invalid NES header."));
+#[must_use = "the ROM may not exist or not be supported"]
+pub fn create_cartridge_from_ines_file(file_path: &str) -> Result<Cartridge, CartridgeError> {
+  let file_contents = fs::read(file_path)
+    .map_err(|e| CartridgeError::ReadFailed { file_path: String::from(file_path), reason: e.to_string() })?;
+  return Cartridge::from_bytes(&file_contents);
+}
+
+// Reads the 16 byte header at the start of an iNES file
+fn parse_ines_header(file_contents: &[u8]) -> Result<RomHeader, CartridgeError> {
+  if file_contents.len() < HEADER_SIZE {
+    return Err(CartridgeError::HeaderTooShort { file_size: file_contents.len() });
+  }
+  if !verify_nes_header(file_contents) {
+    return Err(CartridgeError::InvalidHeader);
   }
 
   let nes_name = &file_contents[0..4];
@@ -54,96 +210,355 @@ pub fn create_cartridge_from_ines_file(file_path: &str) -> Result<Cartridge, Str
   let chr_chunks = file_contents[5];
   let flags6 = file_contents[6];
   let flags7 = file_contents[7];
-  let prg_ram_size = file_contents[8];
+  // NES 2.0 headers describe PRG RAM differently, so those get the default 8KB
+  let is_nes_2_0 = is_nes_2_0_from_flags7(flags7);
+  let prg_ram_size = if is_nes_2_0 { 0 } else { file_contents[8] };
+  let submapper = if is_nes_2_0 { get_submapper_from_byte8(file_contents[8]) } else { 0 };
   let flags9 = file_contents[9];
   let flags10 = file_contents[10];
+  let nes_2_0_timing = if is_nes_2_0 { Some(get_timing_from_byte12(file_contents[12])) } else { None };
 
-  let header = RomHeader{
+  return Ok(RomHeader{
     name: nes_name.try_into().unwrap(),
     prg_chunks,
     chr_chunks,
     mapper1: get_mapper1_from_flags6(flags6),
     mapper2: get_mapper2_from_flags7(flags7),
+    has_battery: get_battery_from_flags6(flags6),
+    submapper,
     prg_ram_size,
     tv_system_1: get_tv_system_1_from_flags9(flags9),
     tv_system_2: get_tv_system_2_from_flags10(flags10),
-  };
-
-  let mirroring_mode = if (flags6 & 0x01) != 0 { MirroringMode::Vertical } else { MirroringMode::Horizontal };
-
-  let mapper = create_mapper_from_number((header.mapper2 << 4) & header.mapper1, prg_chunks, chr_chunks).unwrap();
-
-  let mut cartridge = Cartridge::new(header, mapper, mirroring_mode);
+    nes_2_0_timing,
+  });
+}
 
-  let prg_data_start_index: usize= if ((flags6 & 0x04 != 0) as bool) { 16 + 512 } else { 16 }; 
-  
+// The part of the file the header says a section takes, if the file is long enough to have it
+fn get_section<'a>(file_contents: &'a [u8], section: &'static str, start_index: usize, size: usize) -> Result<&'a [u8], CartridgeError> {
+  return file_contents.get(start_index..start_index + size)
+    .ok_or(CartridgeError::Truncated { section, expected_size: start_index + size, file_size: file_contents.len() });
+}
 
-  // "Discover" File Format
-  let n_file_type = 1;
+// Same as above, for ROMs that don't come from a file (or tests that build their own)
+fn create_cartridge_from_ines_bytes(file_contents: &[u8]) -> Result<Cartridge, CartridgeError> {
+  let header = parse_ines_header(file_contents)?;
+  let prg_chunks = header.prg_chunks;
+  let chr_chunks = header.chr_chunks;
+  let flags6 = file_contents[6];
+  // Every mapper needs somewhere to read the reset vector from
+  if (prg_chunks == 0) {
+    return Err(CartridgeError::NoPrgRom);
+  }
 
-  match n_file_type {
-    0 => {
+  let mirroring_mode = if (flags6 & 0x08) != 0 {
+    MirroringMode::FourScreen
+  } else if (flags6 & 0x01) != 0 {
+    MirroringMode::Vertical
+  } else {
+    MirroringMode::Horizontal
+  };
 
-    },
-    1 => {
+  let mapper = create_mapper_from_number(header.mapper_number(), header.submapper, prg_chunks, chr_chunks)?;
 
-      let prg_data_end_index= prg_data_start_index + (prg_chunks as usize) * 16384;
-      for i in prg_data_start_index..prg_data_end_index {
-        cartridge.PRG_data.push(file_contents[i as usize]);
-      }
-      
-      let chr_data_start_index= prg_data_end_index;
-      let chr_data_end_index= chr_data_start_index + (chr_chunks as usize) * 8192;
-      
-      for i in chr_data_start_index..chr_data_end_index {
-        cartridge.CHR_data.push(file_contents[i as usize]);
-      }
-    },
-    2 => {
+  let mut cartridge = Cartridge::new(header, mapper, mirroring_mode);
 
-    },
-    _ => {
-      return Err(String::from(format!("File type {} hasn't been implemented", n_file_type)));
-    }
+  let has_trainer = (flags6 & 0x04) != 0;
+  let prg_data_start_index: usize = if (has_trainer) { HEADER_SIZE + TRAINER_SIZE } else { HEADER_SIZE };
+  if (has_trainer) {
+    let trainer = get_section(file_contents, "trainer", HEADER_SIZE, TRAINER_SIZE)?;
+    cartridge.trainer = Some(trainer.try_into().unwrap());
   }
+
+  let prg_size = (prg_chunks as usize) * PRG_ROM_BANK_SIZE;
+  cartridge.PRG_data = get_section(file_contents, "PRG ROM", prg_data_start_index, prg_size)?.to_vec();
+  let chr_size = (chr_chunks as usize) * CHR_ROM_BANK_SIZE;
+  cartridge.CHR_data = get_section(file_contents, "CHR ROM", prg_data_start_index + prg_size, chr_size)?.to_vec();
+  cartridge.crc32 = crc32_update(crc32(&cartridge.PRG_data), &cartridge.CHR_data);
   return Ok(cartridge);
 
 }
 
 
 
+#[derive(Clone)]
 struct RomHeader {
   name: [u8; 4],
   prg_chunks: u8,
   chr_chunks: u8,
   mapper1: u8,
   mapper2: u8,
+  // PRG RAM is battery backed, so it has to be kept between runs
+  has_battery: bool,
+  // Tells apart boards that share a mapper number. Always 0 for iNES headers.
+  submapper: u8,
   prg_ram_size: u8,
   tv_system_1: u8,
-  tv_system_2: u8
+  tv_system_2: u8,
+  // Only NES 2.0 headers have it, and it takes precedence over the TV system flags
+  nes_2_0_timing: Option<u8>,
   // unused: char[]
 }
 
+impl RomHeader {
+  // The low nibble comes from flags 6, and the high one from flags 7
+  fn mapper_number(&self) -> u8 {
+    return (self.mapper2 << 4) | self.mapper1;
+  }
+
+  // Games that run on multiple regions get NTSC. Dendy consoles (Famicom clones) have PAL's frame rate and scanline
+  // count, which is what games notice the most, so those get PAL too.
+  fn region(&self) -> Region {
+    return match self.nes_2_0_timing {
+      Some(1) | Some(3) => Region::Pal,
+      Some(_) => Region::Ntsc,
+      None => if self.tv_system_1 == 1 { Region::Pal } else { Region::Ntsc },
+    };
+  }
+}
+
+// Decides the console's timing. PAL consoles have a slower CPU, 50 extra scanlines per frame and a lower frame rate.
+// Reference: https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+  Ntsc,
+  Pal,
+}
+
+impl Region {
+  pub const ALL: [Region; 2] = [Region::Ntsc, Region::Pal];
+
+  // Both chips are clocked by dividing the same master clock, by 4 and 12 on NTSC, and by 5 and 16 on PAL.
+  // So the CPU runs once every 3 PPU cycles on NTSC, and once every 3.2 on PAL.
+  pub fn master_clocks_per_ppu_cycle(&self) -> u64 {
+    return match self { Region::Ntsc => 4, Region::Pal => 5 };
+  }
+
+  pub fn master_clocks_per_cpu_cycle(&self) -> u64 {
+    return match self { Region::Ntsc => 12, Region::Pal => 16 };
+  }
+
+  // Including the pre-render scanline
+  pub fn scanlines_per_frame(&self) -> i16 {
+    return match self { Region::Ntsc => 262, Region::Pal => 312 };
+  }
+
+  pub fn frames_per_second(&self) -> f64 {
+    return match self { Region::Ntsc => 60.0988, Region::Pal => 50.0070 };
+  }
+
+  pub fn cpu_clock_rate(&self) -> f64 {
+    return match self { Region::Ntsc => 1789773.0, Region::Pal => 1662607.0 };
+  }
+}
+
+impl std::fmt::Display for Region {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    return write!(f, "{}", match self { Region::Ntsc => "NTSC", Region::Pal => "PAL" });
+  }
+}
+
+// What the UI shows about the loaded ROM. The header itself stays private, since its fields depend on its format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+  pub mapper_number: u8,
+  pub mapper_name: &'static str,
+  // In bytes. A CHR size of 0 means the board has CHR RAM instead
+  pub prg_rom_size: usize,
+  pub chr_rom_size: usize,
+  // As the header says, the mapper may change it later
+  pub mirroring: MirroringMode,
+  pub has_battery: bool,
+  pub has_trainer: bool,
+  pub region: Region,
+  // See Cartridge::crc32. ROM databases also list SHA-1 hashes, but only the CRC32 is computed.
+  pub crc32: u32,
+}
+
+impl RomInfo {
+  // e.g. "Mapper 0 (NROM), 32KB PRG, 8KB CHR, horizontal mirroring, NTSC"
+  pub fn summary(&self) -> String {
+    let chr_description = if (self.chr_rom_size == 0) { String::from("CHR RAM") } else { format!("{}KB CHR", self.chr_rom_size / 1024) };
+    let mirroring_description = match self.mirroring {
+      MirroringMode::Vertical => "vertical",
+      MirroringMode::Horizontal => "horizontal",
+      MirroringMode::FourScreen => "four-screen",
+      _ => "one-screen",
+    };
+    return format!("Mapper {} ({}), {}KB PRG, {}, {} mirroring, {}", self.mapper_number, self.mapper_name, self.prg_rom_size / 1024,
+      chr_description, mirroring_description, self.region);
+  }
+}
+
+#[derive(Clone)]
 pub struct Cartridge {
   cpu_memory_bounds: (u16, u16),
   ppu_memory_bounds: (u16, u16),
+  prg_ram_memory_bounds: (u16, u16),
+  // Work RAM at $6000-$7FFF. Not every board has it, but games without it never touch that range anyway
+  pub prg_ram: Vec<u8>,
+  // Set by writes to PRG RAM, and cleared when it's loaded from or saved to a file
+  prg_ram_dirty: bool,
   rom_header: RomHeader,
+  pub trainer: Option<[u8; TRAINER_SIZE]>,
   PRG_data: Vec<u8>,
   CHR_data: Vec<u8>,
+  // Of the PRG and CHR data, without the header
+  crc32: u32,
   mapper: Box<dyn Mapper>,
-  pub mirroring_mode: MirroringMode
+  // Only used when the mapper has no mirroring control, see current_mirroring
+  header_mirroring_mode: MirroringMode
 }
 
 impl Cartridge {
-  fn new(rom_header: RomHeader, mapper: Box<dyn Mapper>, mirroring_mode: MirroringMode) -> Cartridge {
+  // Parses the contents of an iNES file
+  pub fn from_bytes(file_contents: &[u8]) -> Result<Cartridge, CartridgeError> {
+    return create_cartridge_from_ines_bytes(file_contents);
+  }
+
+  // A mapper 0 cartridge with 32KB of PRG and 8KB of CHR, all zeroes
+  pub fn blank() -> Cartridge {
+    let mut file_contents = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    file_contents.extend(vec![0; 2 * 16384 + 8192]);
+    return Cartridge::from_bytes(&file_contents).unwrap();
+  }
+
+  pub fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return self.mapper.current_prg_banks();
+  }
+
+  // Called when the cartridge is plugged into the bus, since PRG RAM is only visible through the mapper
+  pub fn load_trainer_into_prg_ram(&mut self) {
+    if let Some(trainer) = self.trainer {
+      for (i, byte) in trainer.iter().enumerate() {
+        // PRG RAM writes never fail, a mapper that disabled it just ignores them
+        let _ = self.write(TRAINER_ADDR + i as u16, *byte);
+      }
+    }
+  }
+
+  pub fn has_battery(&self) -> bool {
+    return self.rom_header.has_battery;
+  }
+
+  // A missing file just means the game hasn't saved yet. Files of the wrong size are loaded as far as they fit,
+  // and the rest of PRG RAM is cleared.
+  pub fn load_battery_ram(&mut self, file_path: &Path) -> Result<(), String> {
+    let contents = match fs::read(file_path) {
+      Ok(contents) => contents,
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+      Err(error) => return Err(format!("Error while loading save file {}: {}", file_path.display(), error)),
+    };
+    if (contents.len() != self.prg_ram.len()) {
+      log::warn!("Save file {} has {} bytes, but PRG RAM has {}", file_path.display(), contents.len(), self.prg_ram.len());
+    }
+    let loaded_len = contents.len().min(self.prg_ram.len());
+    self.prg_ram.fill(0);
+    self.prg_ram[..loaded_len].copy_from_slice(&contents[..loaded_len]);
+    self.prg_ram_dirty = false;
+    return Ok(());
+  }
+
+  // Only writes the file if PRG RAM changed since it was last loaded or saved. Returns whether it was written.
+  pub fn save_battery_ram(&mut self, file_path: &Path) -> Result<bool, String> {
+    if (!self.prg_ram_dirty) {
+      return Ok(false);
+    }
+    if let Some(directory) = file_path.parent() {
+      fs::create_dir_all(directory).map_err(|e| format!("Error while creating the saves directory {}: {}", directory.display(), e))?;
+    }
+    fs::write(file_path, &self.prg_ram).map_err(|e| format!("Error while writing save file {}: {}", file_path.display(), e))?;
+    self.prg_ram_dirty = false;
+    return Ok(true);
+  }
+
+  pub fn irq_pending(&self) -> bool {
+    return self.mapper.irq_pending();
+  }
+
+  // Called by the PPU when the CPU writes to PPUCTRL, for mappers that keep track of it
+  pub fn ppu_control_written(&mut self, data: u8) {
+    self.mapper.ppu_control_written(data);
+  }
+
+  // Called by the console once per CPU cycle, even while the CPU is halted by DMA
+  pub fn cpu_clock(&mut self) {
+    self.mapper.cpu_clock();
+  }
+
+  pub fn mapper_number(&self) -> u8 {
+    return self.rom_header.mapper_number();
+  }
+
+  // In 16KB units
+  pub fn prg_bank_count(&self) -> u8 {
+    return self.rom_header.prg_chunks;
+  }
+
+  // In 8KB units. 0 means the board has CHR RAM instead
+  pub fn chr_bank_count(&self) -> u8 {
+    return self.rom_header.chr_chunks;
+  }
+
+  // Mappers with mirroring control can change it at any time, so the PPU asks for it on every name table access.
+  // The rest keep the one from the header, and boards with four name tables ignore the mapper.
+  pub fn current_mirroring(&self) -> MirroringMode {
+    if (self.header_mirroring_mode == MirroringMode::FourScreen) {
+      return MirroringMode::FourScreen;
+    }
+    return self.mapper.mirroring_mode().unwrap_or(self.header_mirroring_mode);
+  }
+
+  // As the header says, mis-headered ROMs can be overridden with Bus16Bit::set_region
+  pub fn region(&self) -> Region {
+    return self.rom_header.region();
+  }
+
+  // Identifies the game regardless of the file name and header, the same way ROM databases (like No-Intro) do
+  pub fn crc32(&self) -> u32 {
+    return self.crc32;
+  }
+
+  pub fn info(&self) -> RomInfo {
+    return RomInfo {
+      mapper_number: self.mapper_number(),
+      mapper_name: mapper_name(self.mapper_number()),
+      prg_rom_size: self.prg_bank_count() as usize * 16384,
+      chr_rom_size: self.chr_bank_count() as usize * 8192,
+      mirroring: self.header_mirroring_mode,
+      has_battery: self.has_battery(),
+      has_trainer: self.trainer.is_some(),
+      region: self.region(),
+      crc32: self.crc32(),
+    };
+  }
+
+  // One line summary of the ROM, e.g. "Mapper 0, 32KB PRG ROM, 8KB CHR ROM, Vertical mirroring"
+  pub fn describe(&self) -> String {
+    // Boards without CHR ROM have 8KB of CHR RAM instead
+    let chr_description = if (self.chr_bank_count() == 0) {
+      String::from("8KB CHR RAM")
+    } else {
+      format!("{}KB CHR ROM", self.chr_bank_count() as usize * 8)
+    };
+    return format!("Mapper {}, {}KB PRG ROM, {}, {:?} mirroring", self.mapper_number(), self.prg_bank_count() as usize * 16,
+      chr_description, self.header_mirroring_mode);
+  }
+
+  fn new(rom_header: RomHeader, mapper: Box<dyn Mapper>, header_mirroring_mode: MirroringMode) -> Cartridge {
     return Cartridge {
       cpu_memory_bounds: (0x8000, 0xFFFF),
       ppu_memory_bounds: (0x0000, 0x1FFF),
+      prg_ram_memory_bounds: (PRG_RAM_START_ADDR, PRG_RAM_END_ADDR),
+      // The header gives the size in 8KB units, and 0 means 8KB for compatibility with older ROM files
+      prg_ram: vec![0; (rom_header.prg_ram_size.max(1) as usize) * PRG_RAM_BANK_SIZE],
+      prg_ram_dirty: false,
       rom_header,
+      trainer: None,
       PRG_data: vec![],
       CHR_data: vec![],
+      crc32: 0,
       mapper,
-      mirroring_mode
+      header_mirroring_mode
     };
   }
 
@@ -155,29 +570,71 @@ impl Cartridge {
     return addr >= self.cpu_memory_bounds.0 && addr <= self.cpu_memory_bounds.1;
   }
 
+  fn in_prg_ram_memory_bounds(&self, addr:u16) -> bool {
+    return addr >= self.prg_ram_memory_bounds.0 && addr <= self.prg_ram_memory_bounds.1;
+  }
+
+  fn in_expansion_area_bounds(&self, addr:u16) -> bool {
+    return addr >= EXPANSION_AREA_START_ADDR && addr < PRG_RAM_START_ADDR && self.mapper.in_expansion_area_bounds(addr);
+  }
+
+  // The PPU sees the name tables at $2000-$2FFF, mirrored at $3000-$3EFF
+  fn in_name_table_memory_bounds(&self, addr:u16) -> bool {
+    return addr >= 0x2000 && addr <= 0x3EFF;
+  }
+
+  // Disabled PRG RAM reads as 0, since open bus isn't visible from here
+  fn read_prg_ram(&self, addr: u16) -> u8 {
+    return match self.mapper.mapReadAddressFromPrgRam(addr) {
+      Some(mapped_addr) => self.prg_ram[mapped_addr as usize % self.prg_ram.len()],
+      None => 0,
+    };
+  }
+
 }
 
+impl Sealed for Cartridge {}
+
 impl Device for Cartridge {
 
+  fn clone_device(&self) -> Box<dyn Device + Send> {
+    return Box::new(self.clone());
+  }
+
   fn in_memory_bounds(&self, addr: u16)-> bool {
-    if self.in_cpu_memory_bounds(addr) {
+    if self.in_cpu_memory_bounds(addr) || self.in_prg_ram_memory_bounds(addr) || self.in_expansion_area_bounds(addr) {
       return true;
     } else {
       return false;
     }
   }
 
-  fn write(&mut self, addr: u16, content: u8) -> Result<(), String> {
-    if self.in_cpu_memory_bounds(addr) {
+  fn write(&mut self, addr: u16, content: u8) -> Result<(), DeviceError> {
+    if self.in_prg_ram_memory_bounds(addr) {
+      if let Some(mapped_addr) = self.mapper.mapWriteAddressFromPrgRam(addr, content) {
+        let prg_ram_size = self.prg_ram.len();
+        self.prg_ram[mapped_addr as usize % prg_ram_size] = content;
+        self.prg_ram_dirty = true;
+      }
+      return Ok(());
+    } else if self.in_expansion_area_bounds(addr) {
+      self.mapper.write_expansion_area(addr, content);
+      return Ok(());
+    } else if self.in_cpu_memory_bounds(addr) {
       // Write operation from CPU
-      let mapped_addr_res = self.mapper.mapWriteAddressFromCPU(addr);
+      let content = if self.mapper.has_bus_conflicts() { content & self.read_debug(addr)? } else { content };
+      let mapped_addr_res = self.mapper.mapWriteAddressFromCPU(addr, content);
       match mapped_addr_res {
-        Ok(mapped_addr) => {
+        Ok(Some(mapped_addr)) => {
           self.PRG_data[mapped_addr as usize] = content;
           return Ok(());
         },
+        Ok(None) => {
+          return Ok(());
+        },
         Err(message) => {
-          return Err(message);
+          log::warn!("Mapper could not handle CPU write of 0x{:X} to 0x{:X}: {}", content, addr, message);
+          return Err(DeviceError::Mapper(message));
         }
       }
     } else if self.in_ppu_memory_bounds(addr) {
@@ -192,16 +649,26 @@ impl Device for Cartridge {
           return Ok(());
         },
         Err(message) => {
-          return Err(message);
+          return Err(DeviceError::Mapper(message));
         }
       }        
+    } else if self.in_name_table_memory_bounds(addr) {
+      // Name tables the mapper doesn't take are in the PPU
+      if self.mapper.write_name_table(0x2000 | (addr & 0x0FFF), content) {
+        return Ok(());
+      }
+      return Err(DeviceError::OutOfBounds);
     } else {
-      return Err(format!("Tried to write outside Cartridge bounds! Address: 0x{:X}", addr));
+      return Err(DeviceError::OutOfBounds);
     }
   }
 
-  fn read(&mut self, addr: u16) -> Result<u8, String> {
-    if self.in_cpu_memory_bounds(addr) {
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError> {
+    if self.in_prg_ram_memory_bounds(addr) {
+      return Ok(self.read_prg_ram(addr));
+    } else if self.in_expansion_area_bounds(addr) {
+      return Ok(self.mapper.read_expansion_area(addr));
+    } else if self.in_cpu_memory_bounds(addr) {
       // Read operation from CPU
       let mapped_addr_res = self.mapper.mapReadAddressFromCPU(addr);
       match mapped_addr_res {
@@ -210,7 +677,7 @@ impl Device for Cartridge {
           return Ok(*data);
         },
         Err(message) => {
-          return Err(message);
+          return Err(DeviceError::Mapper(message));
         }
       }
     } else if self.in_ppu_memory_bounds(addr) {
@@ -218,16 +685,677 @@ impl Device for Cartridge {
       let mapped_addr_res = self.mapper.mapReadAddressFromPPU(addr);
       match mapped_addr_res {
         Ok(mapped_addr) => {
-          // println!("{}", mapped_addr);
+          log::trace!("PPU read from 0x{:X} mapped to CHR address 0x{:X}", addr, mapped_addr);
           let data = self.CHR_data.get(mapped_addr as usize).unwrap_or(&0);
           return Ok(*data);
         },
         Err(message) => {
-          return Err(message);
+          return Err(DeviceError::Mapper(message));
         }
       }
+    } else if self.in_name_table_memory_bounds(addr) {
+      return self.mapper.read_name_table(0x2000 | (addr & 0x0FFF)).ok_or(DeviceError::OutOfBounds);
     } else {
-      return Err(format!("Tried to read outside Cartridge bounds! Address: 0x{:X}", addr));
+      return Err(DeviceError::OutOfBounds);
     }
   }
-}
\ No newline at end of file
+
+  fn read_debug(&self, addr: u16) -> Result<u8, DeviceError> {
+    if self.in_prg_ram_memory_bounds(addr) {
+      return Ok(self.read_prg_ram(addr));
+    } else if self.in_expansion_area_bounds(addr) {
+      return Ok(self.mapper.read_expansion_area_debug(addr));
+    } else if self.in_cpu_memory_bounds(addr) {
+      let mapped_addr = self.mapper.mapDebugReadAddressFromCPU(addr).map_err(DeviceError::Mapper)?;
+      return Ok(*self.PRG_data.get(mapped_addr as usize).unwrap_or(&0));
+    } else if self.in_ppu_memory_bounds(addr) {
+      let mapped_addr = self.mapper.mapDebugReadAddressFromPPU(addr).map_err(DeviceError::Mapper)?;
+      return Ok(*self.CHR_data.get(mapped_addr as usize).unwrap_or(&0));
+    } else if self.in_name_table_memory_bounds(addr) {
+      return self.mapper.read_name_table_debug(0x2000 | (addr & 0x0FFF)).ok_or(DeviceError::OutOfBounds);
+    } else {
+      return Err(DeviceError::OutOfBounds);
+    }
+  }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+  use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+  use super::{Cartridge, CartridgeError, MirroringMode, Region, RomInfo, parse_ines_header};
+  use crate::{mapper::{Mapper, Mapper000, Mapper002, Mapper007, Mapper011}, test_utils::NesRomBuilder};
+  use crate::{bus::Bus16Bit, device::Device, ben6502::Ben6502, console};
+
+  // Mapper 0 ROM with a single PRG bank, and the given PRG RAM size (in 8KB units) in the header
+  fn create_rom(prg_ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, prg_ram_size, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(vec![0; 0x4000 + 0x2000]);
+    return rom;
+  }
+
+  #[test]
+  fn test_prg_ram_reads_and_writes() {
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&create_rom(0)).unwrap());
+    bus.strict_mode = true;
+    bus.write(0x6000, 0x11).unwrap();
+    bus.write(0x7FFF, 0x22).unwrap();
+    assert_eq!(bus.read(0x6000, false).unwrap(), 0x11);
+    assert_eq!(bus.read(0x7FFF, false).unwrap(), 0x22);
+    assert_eq!(bus.peek(0x6000), Some(0x11));
+  }
+
+  #[test]
+  fn test_prg_ram_size_comes_from_header() {
+    // 0 means 8KB
+    let mut cartridge = Cartridge::from_bytes(&create_rom(0)).unwrap();
+    assert_eq!(cartridge.prg_ram.len(), 0x2000);
+    // The whole 8KB is addressable without mirroring
+    for addr in 0x6000..=0x7FFF {
+      cartridge.write(addr, (addr >> 8) as u8 ^ addr as u8).unwrap();
+    }
+    for addr in 0x6000..=0x7FFF {
+      assert_eq!(cartridge.read(addr).unwrap(), (addr >> 8) as u8 ^ addr as u8);
+    }
+
+    // Without a mapper that banks it, only the first 8KB of a bigger RAM is visible
+    let mut cartridge = Cartridge::from_bytes(&create_rom(2)).unwrap();
+    assert_eq!(cartridge.prg_ram.len(), 0x4000);
+    cartridge.write(0x6000, 0x33).unwrap();
+    assert_eq!(cartridge.prg_ram[0x0000], 0x33);
+    assert_eq!(cartridge.prg_ram[0x2000], 0x00);
+  }
+
+  #[test]
+  fn test_mapper_number_comes_from_both_flags() {
+    // Mapper 4 has its number in the high nibble of flags 6, and nothing in flags 7
+    let mapper_4_header = [b'N', b'E', b'S', 0x1A, 2, 1, 0x40, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(parse_ines_header(&mapper_4_header).unwrap().mapper_number(), 4);
+    let mapper_73_header = [b'N', b'E', b'S', 0x1A, 2, 1, 0x90, 0x40, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(parse_ines_header(&mapper_73_header).unwrap().mapper_number(), 73);
+
+    // Unsupported mappers are reported by number
+    let mut rom = mapper_73_header.to_vec();
+    rom.extend(vec![0; 2 * 0x4000 + 0x2000]);
+    let error = Cartridge::from_bytes(&rom).err().unwrap();
+    assert_eq!(error, CartridgeError::UnsupportedMapper(73));
+    assert!(error.to_string().contains("mapper 73 isn't supported"), "{}", error);
+  }
+
+  #[test]
+  fn test_mmc2_needs_three_fixed_prg_banks_and_chr_rom() {
+    // A single 16KB PRG bank is only two 8KB banks, one short of the fixed ones at $A000-$FFFF
+    let error = Cartridge::from_bytes(&NesRomBuilder::new().mapper(9).prg_size(1).chr_size(2).build()).err().unwrap();
+    assert_eq!(error, CartridgeError::UnsupportedBankCount { mapper_num: 9, num_prg_banks: 1, num_chr_banks: 2 });
+    assert!(error.to_string().contains("16KB of PRG ROM"), "{}", error);
+
+    // Without CHR ROM the cartridge would have CHR RAM, which MMC2 can't bank
+    let error = Cartridge::from_bytes(&NesRomBuilder::new().mapper(9).prg_size(2).chr_size(0).build()).err().unwrap();
+    assert_eq!(error, CartridgeError::UnsupportedBankCount { mapper_num: 9, num_prg_banks: 2, num_chr_banks: 0 });
+
+    assert!(Cartridge::from_bytes(&NesRomBuilder::new().mapper(9).prg_size(2).chr_size(1).build()).is_ok());
+  }
+
+  #[test]
+  fn test_invalid_and_truncated_roms() {
+    let error = |file_contents: &[u8]| Cartridge::from_bytes(file_contents).err().unwrap();
+    assert_eq!(error(&[b'N', b'E', b'S']), CartridgeError::HeaderTooShort { file_size: 3 });
+    assert_eq!(error(b"NES\n this is a text file"), CartridgeError::InvalidHeader);
+    assert_eq!(error(&[b'N', b'E', b'S', 0x1A, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), CartridgeError::NoPrgRom);
+
+    // One PRG and one CHR bank, with a trainer
+    let rom = create_rom_with_trainer(Some(0x11));
+    assert_eq!(error(&rom[..100]), CartridgeError::Truncated { section: "trainer", expected_size: 528, file_size: 100 });
+    assert_eq!(error(&rom[..10000]), CartridgeError::Truncated { section: "PRG ROM", expected_size: 528 + 0x4000, file_size: 10000 });
+    assert_eq!(error(&rom[..rom.len() - 1]),
+      CartridgeError::Truncated { section: "CHR ROM", expected_size: rom.len(), file_size: rom.len() - 1 });
+    assert!(Cartridge::from_bytes(&rom).is_ok());
+  }
+
+  #[test]
+  fn test_random_and_truncated_roms_never_panic() {
+    let supported_mappers = [0, 1, 2, 4, 5, 7, 9, 11, 34, 66, 71, 206];
+    let mut rng = StdRng::seed_from_u64(0x6502);
+    for _ in 0..200 {
+      let mut file_contents = vec![0; rng.gen_range(0..0x10000)];
+      rng.fill_bytes(&mut file_contents);
+      // Half of them get a valid looking header for one of the supported mappers, so that the checks after it run too
+      if (file_contents.len() >= 8 && rng.gen_bool(0.5)) {
+        let mapper = supported_mappers[rng.gen_range(0..supported_mappers.len())];
+        file_contents[0..4].copy_from_slice(&[b'N', b'E', b'S', 0x1A]);
+        file_contents[4] %= 4;
+        file_contents[5] %= 4;
+        file_contents[6] = (file_contents[6] & 0x0F) | (mapper << 4);
+        file_contents[7] = (file_contents[7] & 0x0F) | (mapper & 0xF0);
+      }
+      // Whatever the mapper does with the banks it was given, the whole cartridge can be read, from the CPU and the PPU
+      if let Ok(mut cartridge) = Cartridge::from_bytes(&file_contents) {
+        for addr in (0x8000..=0xFFFF).chain(0x0000..=0x1FFF) {
+          let _ = cartridge.read_debug(addr);
+          let _ = cartridge.read(addr);
+        }
+      }
+    }
+
+    let rom = NesRomBuilder::new().mapper(4).prg_size(2).chr_size(1).build();
+    for length in (0..rom.len()).step_by(997) {
+      assert!(Cartridge::from_bytes(&rom[..length]).is_err());
+    }
+  }
+
+  #[test]
+  fn test_info() {
+    // Mapper 4 with battery backed PRG RAM and vertical mirroring, for PAL consoles
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0x43, 0x00, 0, 0x01, 0, 0, 0, 0, 0, 0];
+    rom.extend(vec![0; 2 * 0x4000 + 0x2000]);
+    let info = Cartridge::from_bytes(&rom).unwrap().info();
+    assert_eq!(info, RomInfo {
+      mapper_number: 4,
+      mapper_name: "MMC3",
+      prg_rom_size: 0x8000,
+      chr_rom_size: 0x2000,
+      mirroring: MirroringMode::Vertical,
+      has_battery: true,
+      has_trainer: false,
+      region: Region::Pal,
+      crc32: 0x2C2BB90A,
+    });
+    assert_eq!(info.summary(), "Mapper 4 (MMC3), 32KB PRG, 8KB CHR, vertical mirroring, PAL");
+
+    let info = Cartridge::from_bytes(&NesRomBuilder::new().mapper(2).chr_size(0).build()).unwrap().info();
+    assert_eq!(info.summary(), "Mapper 2 (UxROM), 16KB PRG, CHR RAM, horizontal mirroring, NTSC");
+  }
+
+  #[test]
+  fn test_region_comes_from_the_header() {
+    let region = |flags7: u8, flags9: u8, byte12: u8| {
+      let header = [b'N', b'E', b'S', 0x1A, 1, 1, 0x00, flags7, 0, flags9, 0, 0, byte12, 0, 0, 0];
+      return parse_ines_header(&header).unwrap().region();
+    };
+    assert_eq!(region(0x00, 0x00, 0), Region::Ntsc);
+    assert_eq!(region(0x00, 0x01, 0), Region::Pal);
+    // iNES headers don't have a timing byte
+    assert_eq!(region(0x00, 0x00, 1), Region::Ntsc);
+
+    // NES 2.0 ones ignore the TV system flag
+    assert_eq!(region(0x08, 0x01, 0), Region::Ntsc);
+    assert_eq!(region(0x08, 0x00, 1), Region::Pal);
+    assert_eq!(region(0x08, 0x00, 2), Region::Ntsc);
+    assert_eq!(region(0x08, 0x00, 3), Region::Pal);
+  }
+
+  #[test]
+  fn test_crc32_only_covers_prg_and_chr() {
+    // 16KB of PRG and 8KB of CHR, all zeroes except for the first two bytes
+    let rom = NesRomBuilder::new().write_prg(0, &[0xA9, 0x42]).build();
+    assert_eq!(Cartridge::from_bytes(&rom).unwrap().crc32(), 0x6E3820AB);
+
+    // Same data, different header
+    let mut rom = rom;
+    rom[9] = 0x01;
+    assert_eq!(Cartridge::from_bytes(&rom).unwrap().crc32(), 0x6E3820AB);
+  }
+
+  #[test]
+  fn test_bank_counts() {
+    let cartridge = Cartridge::from_bytes(&NesRomBuilder::new().mapper(2).prg_size(8).chr_size(0).build()).unwrap();
+    assert_eq!(cartridge.mapper_number(), 2);
+    assert_eq!(cartridge.prg_bank_count(), 8);
+    assert_eq!(cartridge.chr_bank_count(), 0);
+  }
+
+  // Mapper 0 ROM with one PRG and one CHR bank, each filled with a different value
+  fn create_rom_with_trainer(trainer: Option<u8>) -> Vec<u8> {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, if trainer.is_some() { 0x04 } else { 0x00 }, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    if let Some(trainer) = trainer {
+      rom.extend(vec![trainer; 512]);
+    }
+    rom.extend(vec![0xAA; 0x4000]);
+    rom.extend(vec![0xBB; 0x2000]);
+    return rom;
+  }
+
+  #[test]
+  fn test_trainer_is_loaded_at_7000() {
+    for trainer in [None, Some(0x5C)] {
+      let mut cartridge = Cartridge::from_bytes(&create_rom_with_trainer(trainer)).unwrap();
+      assert_eq!(cartridge.trainer.map(|trainer| trainer[0]), trainer);
+      // The PRG and CHR data start after the trainer
+      assert_eq!(cartridge.read(0x8000).unwrap(), 0xAA);
+      assert_eq!(cartridge.read(0xFFFF).unwrap(), 0xAA);
+      assert_eq!(cartridge.read(0x0000).unwrap(), 0xBB);
+      assert_eq!(cartridge.read(0x1FFF).unwrap(), 0xBB);
+
+      let bus = Bus16Bit::with_cartridge(cartridge);
+      let expected_trainer_byte = trainer.unwrap_or(0x00);
+      assert_eq!(bus.peek(0x7000), Some(expected_trainer_byte));
+      assert_eq!(bus.peek(0x71FF), Some(expected_trainer_byte));
+      assert_eq!(bus.peek(0x6FFF), Some(0x00));
+      assert_eq!(bus.peek(0x7200), Some(0x00));
+    }
+  }
+
+  #[test]
+  fn test_battery_ram_round_trips_through_save_file() {
+    let file_path = std::env::temp_dir().join("rustness_battery_ram_test.sav");
+    let _ = std::fs::remove_file(&file_path);
+    let mut rom = create_rom(0);
+    rom[6] = 0x02;
+
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert!(bus.cartridge().has_battery());
+    // Nothing saved yet
+    bus.cartridge_mut().load_battery_ram(&file_path).unwrap();
+    assert!(!bus.cartridge_mut().save_battery_ram(&file_path).unwrap());
+
+    bus.write(0x6000, 0x12).unwrap();
+    bus.write(0x7FFF, 0x34).unwrap();
+    assert!(bus.cartridge_mut().save_battery_ram(&file_path).unwrap());
+    // Unchanged since the last save
+    assert!(!bus.cartridge_mut().save_battery_ram(&file_path).unwrap());
+    drop(bus);
+
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    bus.cartridge_mut().load_battery_ram(&file_path).unwrap();
+    assert_eq!(bus.peek(0x6000), Some(0x12));
+    assert_eq!(bus.peek(0x7FFF), Some(0x34));
+    std::fs::remove_file(file_path).unwrap();
+  }
+
+  #[test]
+  fn test_battery_ram_files_of_the_wrong_size() {
+    let file_path = std::env::temp_dir().join("rustness_battery_ram_size_test.sav");
+    let mut cartridge = Cartridge::from_bytes(&create_rom(0)).unwrap();
+
+    std::fs::write(&file_path, [0xAB; 0x10]).unwrap();
+    cartridge.load_battery_ram(&file_path).unwrap();
+    assert_eq!(cartridge.prg_ram[0x0F], 0xAB);
+    assert_eq!(cartridge.prg_ram[0x10], 0x00);
+
+    std::fs::write(&file_path, vec![0xCD; 0x3000]).unwrap();
+    cartridge.load_battery_ram(&file_path).unwrap();
+    assert_eq!(cartridge.prg_ram.len(), 0x2000);
+    assert!(cartridge.prg_ram.iter().all(|byte| *byte == 0xCD));
+    std::fs::remove_file(file_path).unwrap();
+  }
+
+  #[test]
+  fn test_mmc1_mirroring_reaches_the_ppu() {
+    // Mapper 1, 128KB of PRG ROM and CHR RAM
+    let rom = NesRomBuilder::new().mapper(1).prg_size(8).chr_size(0).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 1);
+    // MMC1 ignores the header, and starts with one-screen mirroring until the game writes the control register
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::OnscreenLo);
+
+    // Vertical mirroring, written to the control register one bit at a time
+    for bit in [0, 1, 0, 0, 0] {
+      bus.write(0x8000, bit).unwrap();
+    }
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::Vertical);
+  }
+
+  // NROM, plus whatever a test turns on: with a mirroring mode, any write to $8000-$FFFF switches between vertical (0)
+  // and horizontal (anything else) mirroring, and with an IRQ countdown, the IRQ fires after that many CPU cycles, like
+  // the cycle counters on VRC boards
+  #[derive(Clone)]
+  struct TestMapper {
+    nrom: Mapper000,
+    mirroring_mode: Option<MirroringMode>,
+    irq_countdown: Option<u32>,
+  }
+
+  impl TestMapper {
+    fn new() -> TestMapper {
+      return TestMapper { nrom: Mapper000::new(1, 1), mirroring_mode: None, irq_countdown: None };
+    }
+  }
+
+  impl Mapper for TestMapper {
+    fn cloneMapper(&self) -> Box<dyn Mapper> {
+      return Box::new(self.clone());
+    }
+
+    fn in_cpu_address_bounds(&self, addr: u16) -> bool {
+      return self.nrom.in_cpu_address_bounds(addr);
+    }
+
+    fn in_ppu_address_bounds(&self, addr: u16) -> bool {
+      return self.nrom.in_ppu_address_bounds(addr);
+    }
+
+    fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+      return self.nrom.current_prg_banks();
+    }
+
+    fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+      return self.nrom.mapReadAddressFromCPU(addr);
+    }
+
+    fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+      return self.nrom.mapDebugReadAddressFromCPU(addr);
+    }
+
+    fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+      if (self.mirroring_mode.is_none()) {
+        return self.nrom.mapWriteAddressFromCPU(addr, data);
+      }
+      self.mirroring_mode = Some(if (data == 0) { MirroringMode::Vertical } else { MirroringMode::Horizontal });
+      return Ok(None);
+    }
+
+    fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+      return self.nrom.mapReadAddressFromPPU(addr);
+    }
+
+    fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+      return self.nrom.mapDebugReadAddressFromPPU(addr);
+    }
+
+    fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+      return self.nrom.mapWriteAddressFromPPU(addr);
+    }
+
+    fn mirroring_mode(&self) -> Option<MirroringMode> {
+      return self.mirroring_mode;
+    }
+
+    fn irq_pending(&self) -> bool {
+      return self.irq_countdown == Some(0);
+    }
+
+    fn cpu_clock(&mut self) {
+      if let Some(cycles_left) = self.irq_countdown.as_mut() {
+        *cycles_left = cycles_left.saturating_sub(1);
+      }
+    }
+  }
+
+  // Goes through PPUADDR and PPUDATA, like a game would. Reads are buffered, so the first one is thrown away.
+  fn read_vram(bus: &mut Bus16Bit, addr: u16) -> u8 {
+    bus.write(0x2006, (addr >> 8) as u8).unwrap();
+    bus.write(0x2006, addr as u8).unwrap();
+    bus.read(0x2007, false).unwrap();
+    return bus.read(0x2007, false).unwrap();
+  }
+
+  fn write_vram(bus: &mut Bus16Bit, addr: u16, data: u8) {
+    bus.write(0x2006, (addr >> 8) as u8).unwrap();
+    bus.write(0x2006, addr as u8).unwrap();
+    bus.write(0x2007, data).unwrap();
+  }
+
+  #[test]
+  fn test_mirroring_changes_mid_frame_reach_the_ppu() {
+    let mut cartridge = Cartridge::from_bytes(&NesRomBuilder::new().build()).unwrap();
+    cartridge.mapper = Box::new(TestMapper { mirroring_mode: Some(MirroringMode::Vertical), ..TestMapper::new() });
+    let mut bus = Bus16Bit::with_cartridge(cartridge);
+    // The header says horizontal, but the mapper decides
+    assert_eq!(bus.cartridge().describe(), "Mapper 0, 16KB PRG ROM, 8KB CHR ROM, Horizontal mirroring");
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::Vertical);
+    write_vram(&mut bus, 0x2000, 0x11);
+    write_vram(&mut bus, 0x2400, 0x22);
+
+    // Halfway through the frame
+    for _ in 0..(120 * 341) {
+      bus.clock_ppu();
+    }
+    assert_eq!(read_vram(&mut bus, 0x2400), 0x22);
+    assert_eq!(read_vram(&mut bus, 0x2800), 0x11);
+
+    bus.write(0x8000, 1).unwrap();
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::Horizontal);
+    assert_eq!(read_vram(&mut bus, 0x2400), 0x11);
+    assert_eq!(read_vram(&mut bus, 0x2800), 0x22);
+
+    // And back
+    bus.write(0x8000, 0).unwrap();
+    assert_eq!(read_vram(&mut bus, 0x2400), 0x22);
+  }
+
+  #[test]
+  fn test_four_screen_ignores_mapper_mirroring() {
+    // An MMC3 board with four name tables, like Gauntlet's
+    let mut rom = NesRomBuilder::new().mapper(4).prg_size(2).build();
+    rom[6] |= 0x08;
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::FourScreen);
+    bus.write(0xA000, 0x01).unwrap();
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::FourScreen);
+  }
+
+  #[test]
+  fn test_camerica_bank_switching_and_mirroring() {
+    let mut rom = NesRomBuilder::new().mapper(71).prg_size(8).chr_size(0).numbered_prg_banks(0x4000, 8).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 71);
+    assert_eq!(bus.peek(0xFFFF), Some(7));
+    for bank in [3, 6, 1] {
+      bus.write(0xC000, bank).unwrap();
+      assert_eq!(bus.peek(0x8000), Some(bank));
+      assert_eq!(bus.peek(0xC000), Some(7));
+    }
+    // Only Fire Hawk's board has mirroring control, and the rest ignore writes below $C000
+    bus.write(0x9000, 0x10).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(1));
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::Horizontal);
+
+    // NES 2.0 header, with Fire Hawk's submapper in byte 8
+    rom[7] |= 0x08;
+    rom[8] = 1 << 4;
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().prg_ram.len(), 0x2000);
+    bus.write(0x9000, 0x10).unwrap();
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::OnscreenHi);
+    bus.write(0x9000, 0x00).unwrap();
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::OnscreenLo);
+  }
+
+  #[test]
+  fn test_uxrom_bank_switching() {
+    let rom = NesRomBuilder::new().mapper(2).prg_size(8).chr_size(0).numbered_prg_banks(0x4000, 8).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 2);
+    assert_eq!(bus.peek(0x8000), Some(0));
+    assert_eq!(bus.peek(0xFFFF), Some(7));
+
+    for bank in [3, 6, 1] {
+      bus.write(0x8000, bank).unwrap();
+      assert_eq!(bus.peek(0x8000), Some(bank));
+      assert_eq!(bus.peek(0xBFFF), Some(bank));
+      // The upper bank stays on the last one
+      assert_eq!(bus.peek(0xC000), Some(7));
+    }
+  }
+
+  #[test]
+  fn test_uxrom_bus_conflicts() {
+    let rom = NesRomBuilder::new().mapper(2).prg_size(8).chr_size(0).numbered_prg_banks(0x4000, 8).build();
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    let mut mapper = Mapper002::new(8, 0);
+    mapper.bus_conflicts = true;
+    cartridge.mapper = Box::new(mapper);
+    let mut bus = Bus16Bit::with_cartridge(cartridge);
+
+    // $C000-$FFFF holds 7, so all the bits of the bank number make it through
+    bus.write(0xC000, 0x05).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(5));
+    // $8000-$BFFF now holds 5, which clears bit 1 of the written value
+    bus.write(0x8000, 0x06).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(4));
+    bus.write(0xC000, 0x0B).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(3));
+  }
+
+  #[test]
+  fn test_axrom_bank_switching_and_mirroring() {
+    let rom = NesRomBuilder::new().mapper(7).prg_size(8).chr_size(0).numbered_prg_banks(0x8000, 4).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 7);
+    assert_eq!(bus.peek(0x8000), Some(0));
+    assert_eq!(bus.peek(0xFFFF), Some(0));
+
+    for (data, bank, mirroring_mode) in [(0x12, 2, MirroringMode::OnscreenHi), (0x03, 3, MirroringMode::OnscreenLo), (0x11, 1, MirroringMode::OnscreenHi)] {
+      bus.write(0x8000, data).unwrap();
+      assert_eq!(bus.peek(0x8000), Some(bank));
+      assert_eq!(bus.peek(0xFFFF), Some(bank));
+      assert_eq!(bus.cartridge().current_mirroring(), mirroring_mode);
+    }
+
+    // With bus conflicts, the written value is ANDed with the ROM byte, which here is the current bank (0)
+    let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+    let mut mapper = Mapper007::new(8, 0);
+    mapper.bus_conflicts = true;
+    cartridge.mapper = Box::new(mapper);
+    let mut bus = Bus16Bit::with_cartridge(cartridge);
+    bus.write(0x8000, 0x13).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(0));
+    assert_eq!(bus.cartridge().current_mirroring(), MirroringMode::OnscreenLo);
+  }
+
+  // Color Dreams ROM with numbered banks of 32KB PRG and 8KB CHR, except for $F000 in every PRG bank, which holds $FF
+  // so that writes there don't conflict
+  fn create_color_dreams_rom() -> Vec<u8> {
+    let mut rom_builder = NesRomBuilder::new().mapper(11).prg_size(8).chr_size(16).numbered_prg_banks(0x8000, 4).numbered_chr_banks(0x2000, 16);
+    for bank in 0..4 {
+      rom_builder = rom_builder.write_prg(bank * 0x8000 + 0x7000, &[0xFF]);
+    }
+    return rom_builder.build();
+  }
+
+  #[test]
+  fn test_color_dreams_bank_switching() {
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&create_color_dreams_rom()).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 11);
+
+    for (data, prg_bank, chr_bank) in [(0x00, 0, 0), (0x53, 3, 5), (0xF1, 1, 15), (0x22, 2, 2)] {
+      bus.write(0xF000, data).unwrap();
+      assert_eq!(bus.peek(0x8000), Some(prg_bank));
+      assert_eq!(bus.peek(0xFFFF), Some(prg_bank));
+      assert_eq!(bus.cartridge().read_debug(0x0000).unwrap(), chr_bank);
+      assert_eq!(bus.cartridge().read_debug(0x1FFF).unwrap(), chr_bank);
+    }
+  }
+
+  #[test]
+  fn test_color_dreams_bus_conflicts() {
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&create_color_dreams_rom()).unwrap());
+    bus.write(0xF000, 0x03).unwrap();
+    // $8000 holds 3, so the CHR bank bits get cleared
+    bus.write(0x8000, 0x72).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(2));
+    assert_eq!(bus.cartridge().read_debug(0x0000).unwrap(), 0x00);
+
+    let mut cartridge = Cartridge::from_bytes(&create_color_dreams_rom()).unwrap();
+    let mut mapper = Mapper011::new(8, 16);
+    mapper.bus_conflicts = false;
+    cartridge.mapper = Box::new(mapper);
+    let mut bus = Bus16Bit::with_cartridge(cartridge);
+    bus.write(0x8000, 0x72).unwrap();
+    assert_eq!(bus.peek(0x8000), Some(2));
+    assert_eq!(bus.cartridge().read_debug(0x0000).unwrap(), 0x07);
+  }
+
+  #[test]
+  fn test_gxrom_bank_switching() {
+    let rom = NesRomBuilder::new().mapper(66).prg_size(8).chr_size(4).numbered_prg_banks(0x8000, 4).numbered_chr_banks(0x2000, 4).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    assert_eq!(bus.cartridge().mapper_number(), 66);
+
+    for (data, prg_bank, chr_bank) in [(0x00, 0, 0), (0x31, 3, 1), (0x12, 1, 2), (0x23, 2, 3)] {
+      bus.write(0x8000, data).unwrap();
+      assert_eq!(bus.peek(0x8000), Some(prg_bank));
+      assert_eq!(bus.peek(0xFFFF), Some(prg_bank));
+      assert_eq!(bus.cartridge().read_debug(0x0000).unwrap(), chr_bank);
+      assert_eq!(bus.cartridge().read_debug(0x1FFF).unwrap(), chr_bank);
+    }
+  }
+
+  #[test]
+  fn test_describe() {
+    let mut rom = create_rom(0);
+    rom[6] = 0x01;
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.mapper_number(), 0);
+    assert_eq!(cartridge.describe(), "Mapper 0, 16KB PRG ROM, 8KB CHR ROM, Vertical mirroring");
+  }
+
+  fn create_mmc5_bus() -> Bus16Bit {
+    let rom = NesRomBuilder::new().mapper(5).prg_size(16).chr_size(16).build();
+    let mut bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    bus.strict_mode = true;
+    return bus;
+  }
+
+  #[test]
+  fn test_mmc5_ex_ram() {
+    let mut bus = create_mmc5_bus();
+    // Mode 2: plain RAM for the CPU
+    bus.write(0x5104, 2).unwrap();
+    bus.write(0x5C00, 0x42).unwrap();
+    bus.write(0x5FFF, 0x43).unwrap();
+    assert_eq!(bus.read(0x5C00, false).unwrap(), 0x42);
+    assert_eq!(bus.read(0x5FFF, false).unwrap(), 0x43);
+    // Mode 3: read only
+    bus.write(0x5104, 3).unwrap();
+    bus.write(0x5C00, 0x99).unwrap();
+    assert_eq!(bus.read(0x5C00, false).unwrap(), 0x42);
+
+    // Mode 0: a name table for the PPU, which the CPU can't read
+    bus.write(0x5104, 0).unwrap();
+    assert_eq!(bus.read(0x5C00, false).unwrap(), 0x00);
+    // $2400 comes from ExRAM, and $2C00 from the fill mode
+    bus.write(0x5105, 0b11_00_10_00).unwrap();
+    bus.write(0x5106, 0x33).unwrap();
+    bus.write(0x5107, 0x02).unwrap();
+    let cartridge = bus.cartridge_mut();
+    assert_eq!(cartridge.read(0x2400).unwrap(), 0x42);
+    assert_eq!(cartridge.read(0x37FF).unwrap(), 0x43);
+    cartridge.write(0x2401, 0x44).unwrap();
+    assert_eq!(cartridge.read_debug(0x2401).unwrap(), 0x44);
+    assert_eq!(cartridge.read(0x2C05).unwrap(), 0x33);
+    assert_eq!(cartridge.read(0x2FC0).unwrap(), 0xAA);
+    // The other two are left to the PPU
+    assert!(cartridge.read(0x2000).is_err());
+    assert!(cartridge.write(0x2800, 0x00).is_err());
+    assert_eq!(cartridge.current_mirroring(), MirroringMode::OnscreenLo);
+  }
+
+  #[test]
+  fn test_mapper_is_clocked_every_cpu_cycle() {
+    let mut cartridge = Cartridge::from_bytes(&NesRomBuilder::new().build()).unwrap();
+    cartridge.mapper = Box::new(TestMapper { irq_countdown: Some(100), ..TestMapper::new() });
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(cartridge));
+    let mut current_cycle = 0;
+    // 3 PPU cycles per CPU cycle
+    for _ in 0..(99 * 3) {
+      console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    }
+    assert!(!cpu.bus.cartridge().irq_pending());
+    for _ in 0..3 {
+      console::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    }
+    assert!(cpu.bus.cartridge().irq_pending());
+  }
+
+  #[test]
+  fn test_mmc5_name_table_mapping_and_prg_ram() {
+    let mut bus = create_mmc5_bus();
+    for (mapping, mirroring_mode) in [(0x44, MirroringMode::Vertical), (0x50, MirroringMode::Horizontal),
+                                      (0x55, MirroringMode::OnscreenHi), (0x14, MirroringMode::PerTable([0, 1, 1, 0]))] {
+      bus.write(0x5105, mapping).unwrap();
+      assert_eq!(bus.cartridge().current_mirroring(), mirroring_mode);
+    }
+
+    // PRG RAM is write protected until $5102 and $5103 hold 2 and 1
+    bus.write(0x6000, 0x11).unwrap();
+    assert_eq!(bus.read(0x6000, false).unwrap(), 0x00);
+    bus.write(0x5102, 0x02).unwrap();
+    bus.write(0x5103, 0x01).unwrap();
+    bus.write(0x6000, 0x11).unwrap();
+    assert_eq!(bus.read(0x6000, false).unwrap(), 0x11);
+
+    // Banks past the end of PRG RAM mirror it
+    bus.write(0x5113, 1).unwrap();
+    assert_eq!(bus.read(0x6000, false).unwrap(), 0x11);
+  }
+}
@@ -0,0 +1,182 @@
+/*
+
+Emulator settings that persist between runs, stored as JSON next to the executable's working directory.
+
+Missing fields fall back to their defaults, so config files written by older versions keep loading.
+Builds without the serde feature can't read or write the file, and always start with the default settings.
+
+*/
+
+use std::{fs, path::{Path, PathBuf}};
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use crate::{apu::MixerSettings, ram::RamInitMode};
+
+pub const CONFIG_FILE_PATH: &str = "config.json";
+// Where battery saves go unless the config says otherwise
+pub const DEFAULT_SAVES_DIRECTORY: &str = "saves";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct EmulatorConfig {
+  pub mixer: MixerSettings,
+  // Where battery saves go. Without one, they're kept in DEFAULT_SAVES_DIRECTORY.
+  pub saves_directory: Option<PathBuf>,
+  // What the console's RAM holds when a ROM is loaded
+  pub ram_init_mode: RamInitMode,
+}
+
+impl EmulatorConfig {
+  // A missing or unreadable config file just means we start with the default settings
+  #[cfg(feature = "serde")]
+  pub fn load(file_path: &Path) -> EmulatorConfig {
+    return match fs::read_to_string(file_path) {
+      Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+      Err(_) => EmulatorConfig::default(),
+    };
+  }
+
+  #[cfg(not(feature = "serde"))]
+  pub fn load(_file_path: &Path) -> EmulatorConfig {
+    return EmulatorConfig::default();
+  }
+
+  #[cfg(feature = "serde")]
+  pub fn save(&self, file_path: &Path) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+    return fs::write(file_path, contents).map_err(|e| format!("Error writing config file: {}", e));
+  }
+
+  #[cfg(not(feature = "serde"))]
+  pub fn save(&self, _file_path: &Path) -> Result<(), String> {
+    return Err(String::from("Settings can't be saved, the emulator was built without the serde feature"));
+  }
+
+  pub fn default_path() -> PathBuf {
+    return PathBuf::from(CONFIG_FILE_PATH);
+  }
+
+  pub fn saves_directory(&self) -> PathBuf {
+    return self.saves_directory.clone().unwrap_or(PathBuf::from(DEFAULT_SAVES_DIRECTORY));
+  }
+
+  // "<rom name> (<CRC32 of the ROM>).sav", for games with battery backed PRG RAM. The name is only there to tell the
+  // files apart, saves are found by the CRC32 (see find_previous_save_file), so renaming or moving the ROM keeps them.
+  pub fn save_file_path(&self, rom_path: &Path, rom_crc32: u32) -> PathBuf {
+    let rom_name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    return self.saves_directory().join(format!("{} ({:08X}).sav", rom_name, rom_crc32));
+  }
+
+  // A save for the ROM that isn't where save_file_path says, because it was written when the ROM had another name, or
+  // by an older version, which kept "<rom name>.sav" next to the ROM (or in the configured saves directory)
+  pub fn find_previous_save_file(&self, rom_path: &Path, rom_crc32: u32) -> Option<PathBuf> {
+    if (self.save_file_path(rom_path, rom_crc32).exists()) {
+      return None;
+    }
+    let crc32_suffix = format!("({:08X}).sav", rom_crc32);
+    if let Ok(entries) = fs::read_dir(self.saves_directory()) {
+      let renamed_rom_save = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path())
+        .find(|path| path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(&crc32_suffix)));
+      if (renamed_rom_save.is_some()) {
+        return renamed_rom_save;
+      }
+    }
+    let legacy_save_file_path = self.legacy_save_file_path(rom_path);
+    if (legacy_save_file_path.exists()) {
+      return Some(legacy_save_file_path);
+    }
+    return None;
+  }
+
+  fn legacy_save_file_path(&self, rom_path: &Path) -> PathBuf {
+    let save_file_path = rom_path.with_extension("sav");
+    return match (&self.saves_directory, save_file_path.file_name()) {
+      (Some(saves_directory), Some(file_name)) => saves_directory.join(file_name),
+      _ => save_file_path,
+    };
+  }
+}
+
+// Moves a save file to where it's expected now. Falls back to copying when the new place is on another file system.
+pub fn move_save_file(from: &Path, to: &Path) -> Result<(), String> {
+  if let Some(directory) = to.parent() {
+    fs::create_dir_all(directory).map_err(|e| format!("Error creating {}: {}", directory.display(), e))?;
+  }
+  if (fs::rename(from, to).is_ok()) {
+    return Ok(());
+  }
+  fs::copy(from, to).map_err(|e| format!("Error moving save file {} to {}: {}", from.display(), to.display(), e))?;
+  return fs::remove_file(from).map_err(|e| format!("Error removing old save file {}: {}", from.display(), e));
+}
+
+
+#[cfg(test)]
+mod config_tests {
+  use std::path::{Path, PathBuf};
+  use super::{EmulatorConfig, move_save_file};
+  #[cfg(feature = "serde")]
+  use crate::{apu::Channel, ram::RamInitMode};
+
+  #[test]
+  #[cfg(feature = "serde")]
+  fn test_save_and_load_round_trip() {
+    let file_path = std::env::temp_dir().join("rustness_config_test.json");
+    let mut config = EmulatorConfig::default();
+    config.mixer.master_volume = 0.25;
+    config.mixer.channel_muted[Channel::Noise as usize] = true;
+    config.ram_init_mode = RamInitMode::NesPattern;
+    config.save(&file_path).unwrap();
+
+    assert_eq!(EmulatorConfig::load(&file_path), config);
+    std::fs::remove_file(file_path).unwrap();
+  }
+
+  #[test]
+  fn test_save_file_path() {
+    let mut config = EmulatorConfig::default();
+    assert_eq!(config.save_file_path(Path::new("roms/Zelda.nes"), 0x3FE272FB), Path::new("saves/Zelda (3FE272FB).sav"));
+    assert_eq!(config.legacy_save_file_path(Path::new("roms/Zelda.nes")), Path::new("roms/Zelda.sav"));
+    config.saves_directory = Some(PathBuf::from("my_saves"));
+    assert_eq!(config.save_file_path(Path::new("roms/Zelda.nes"), 0x3FE272FB), Path::new("my_saves/Zelda (3FE272FB).sav"));
+    assert_eq!(config.legacy_save_file_path(Path::new("roms/Zelda.nes")), Path::new("my_saves/Zelda.sav"));
+  }
+
+  #[test]
+  fn test_find_and_move_previous_save_files() {
+    let test_directory = std::env::temp_dir().join("rustness_saves_test");
+    let _ = std::fs::remove_dir_all(&test_directory);
+    let rom_path = test_directory.join("roms").join("Zelda.nes");
+    let config = EmulatorConfig { saves_directory: Some(test_directory.join("saves")), ..EmulatorConfig::default() };
+    std::fs::create_dir_all(config.saves_directory()).unwrap();
+    let save_file_path = config.save_file_path(&rom_path, 0x3FE272FB);
+    assert_eq!(config.find_previous_save_file(&rom_path, 0x3FE272FB), None);
+
+    // Written by an older version, named after the ROM file
+    let legacy_save_file_path = config.legacy_save_file_path(&rom_path);
+    std::fs::write(&legacy_save_file_path, [0x11]).unwrap();
+    assert_eq!(config.find_previous_save_file(&rom_path, 0x3FE272FB), Some(legacy_save_file_path.clone()));
+    move_save_file(&legacy_save_file_path, &save_file_path).unwrap();
+    assert!(!legacy_save_file_path.exists());
+    assert_eq!(std::fs::read(&save_file_path).unwrap(), [0x11]);
+    assert_eq!(config.find_previous_save_file(&rom_path, 0x3FE272FB), None);
+
+    // The ROM was renamed, but it's still the same game
+    let renamed_rom_path = test_directory.join("roms").join("The Legend of Zelda.nes");
+    assert_eq!(config.find_previous_save_file(&renamed_rom_path, 0x3FE272FB), Some(save_file_path.clone()));
+    assert_eq!(config.find_previous_save_file(&renamed_rom_path, 0x12345678), None);
+    std::fs::remove_dir_all(test_directory).unwrap();
+  }
+
+  #[test]
+  #[cfg(feature = "serde")]
+  fn test_missing_fields_use_defaults() {
+    let config: EmulatorConfig = serde_json::from_str(r#"{"mixer": {"muted": true}}"#).unwrap();
+    assert!(config.mixer.muted);
+    assert_eq!(config.mixer.master_volume, 1.0);
+    assert_eq!(config.mixer.channel_muted, [false; 5]);
+    assert_eq!(config.ram_init_mode, RamInitMode::Zero);
+  }
+}
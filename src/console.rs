@@ -0,0 +1,327 @@
+/*
+
+Ties the components of the console together, clocking them at their relative rates and wiring the interrupt
+lines and DMA transfers between them. It's kept separate from the UI so that the emulation can also run headless
+(in tests, for example).
+
+*/
+
+use crate::{ben6502::Ben6502, bus::BusError, cartridge::Region};
+
+// Whether the CPU and APU run on the given PPU cycle, which is every 3 PPU cycles on NTSC consoles and every 3.2 on PAL ones
+fn is_cpu_cycle(ppu_cycle: u64, region: Region) -> bool {
+  let master_clock = ppu_cycle * region.master_clocks_per_ppu_cycle();
+  return master_clock % region.master_clocks_per_cpu_cycle() < region.master_clocks_per_ppu_cycle();
+}
+
+// Advances the whole console by one PPU cycle (the fastest clock in the system). The CPU and APU run every 3 PPU cycles
+// (3.2 on PAL consoles).
+// Fails if the CPU, or a DMA transfer, ran into a bus error, though the cycle is still completed.
+pub fn clock_cycle(cpu: &mut Ben6502, current_cycle: &mut u64) -> Result<(), BusError> {
+  let mut result = Ok(());
+  cpu.bus.clock_ppu();
+  if is_cpu_cycle(*current_cycle, cpu.bus.ppu().region()) {
+    cpu.bus.cpu_cycle = cpu.total_cycles;
+    result = cpu.bus.clock_apu();
+    cpu.bus.cartridge_mut().cpu_clock();
+    if (cpu.bus.dmc_stall_cycles > 0) {
+      // The CPU is halted while the DMC fetches a sample byte
+      cpu.bus.dmc_stall_cycles -= 1;
+    } else if (cpu.bus.pending_dma.is_some() && cpu.current_instruction_remaining_cycles == 0) {
+      // The transfer starts once the instruction that wrote to $4014 is done, and the CPU doesn't fetch anything until it ends
+      result = result.and(cpu.bus.clock_oam_dma(cpu.total_cycles));
+      cpu.total_cycles += 1;
+    } else {
+      result = result.and(cpu.clock_cycle());
+      // The IRQ line is level triggered, so it is polled on every instruction boundary while any source holds it
+      if (cpu.current_instruction_remaining_cycles == 0 && cpu.bus.poll_irq()) {
+        cpu.irq();
+      }
+    }
+  }
+  if (cpu.bus.ppu().trigger_cpu_nmi) {
+    cpu.bus.ppu_mut().trigger_cpu_nmi = false;
+    cpu.nmi();
+  }
+  *current_cycle += 1;
+  return result;
+}
+
+pub fn frames_per_second(cpu: &Ben6502) -> f64 {
+  return cpu.bus.ppu().region().frames_per_second();
+}
+
+// Pressing the console's reset button resets the CPU, PPU and APU, the cartridge and RAM are left as they are
+pub fn soft_reset(cpu: &mut Ben6502) {
+  cpu.reset();
+  cpu.bus.ppu_mut().reset();
+  cpu.bus.apu_mut().reset();
+}
+
+
+#[cfg(test)]
+mod console_tests {
+  use crate::{ben6502::Ben6502, bus::{Bus16Bit, BusBuilder, OAM_DMA_CYCLES}, cartridge::{Cartridge, Region}, test_utils::{assemble, NesRomBuilder}};
+
+  // Runs the console for a whole CPU cycle
+  fn clock_cpu_cycle(cpu: &mut Ben6502, current_cycle: &mut u64) {
+    for _ in 0..3 {
+      super::clock_cycle(cpu, current_cycle).unwrap();
+    }
+  }
+
+  // Fills page $02 with sprite data and runs the given code, followed by an OAM DMA of that page and an endless loop of INC $10
+  fn create_dma_test_cpu(code_before_dma: &[u8]) -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    for i in 0..=0xFF {
+      bus.write(0x0200 + i, (i as u8) ^ 0x5A).unwrap();
+    }
+    let mut program = code_before_dma.to_vec();
+    program.extend(assemble(&[("LDA #", vec![0x02]), ("STA abs", vec![0x14, 0x40])]));
+    let loop_addr = 0x8000 + program.len() as u16;
+    program.extend(assemble(&[("INC zp", vec![0x10]), ("JMP abs", vec![(loop_addr & 0xFF) as u8, (loop_addr >> 8) as u8])]));
+    for (i, byte) in program.iter().enumerate() {
+      bus.write(0x8000 + i as u16, *byte).unwrap();
+    }
+    bus.write(0xFFFC, 0x00).unwrap();
+    bus.write(0xFFFD, 0x80).unwrap();
+    return Ben6502::new(bus);
+  }
+
+  // Returns the length of the CPU stall, and the cycle the $4014 write happened on
+  fn measure_dma_stall(cpu: &mut Ben6502) -> (u16, u64) {
+    let mut current_cycle = 0;
+    while (cpu.bus.pending_dma.is_none()) {
+      clock_cpu_cycle(cpu, &mut current_cycle);
+    }
+    // STA absolute takes 4 cycles, and writes on the last one
+    let write_cycle = cpu.total_cycles - 1 + 3;
+    while (cpu.current_instruction_remaining_cycles > 0) {
+      clock_cpu_cycle(cpu, &mut current_cycle);
+    }
+
+    let mut stall_cycles = 0;
+    while (cpu.bus.pending_dma.is_some()) {
+      clock_cpu_cycle(cpu, &mut current_cycle);
+      stall_cycles += 1;
+      // No instructions ran during the transfer
+      assert_eq!(cpu.bus.read_debug(0x0010), 0);
+    }
+    return (stall_cycles, write_cycle);
+  }
+
+  fn assert_oam_holds_page(cpu: &Ben6502) {
+    let ppu = cpu.bus.ppu();
+    for addr in 0..=0xFF {
+      assert_eq!(ppu.read_from_oam_memory(addr), addr ^ 0x5A);
+    }
+  }
+
+  #[test]
+  fn test_oam_dma_stall_depends_on_write_cycle_parity() {
+    // LDA $00 takes 3 cycles, which moves the $4014 write to a cycle of the other parity
+    let mut stall_lengths = vec![];
+    for code_before_dma in [vec![], assemble(&[("LDA zp", vec![0x00])])] {
+      let mut cpu = create_dma_test_cpu(&code_before_dma);
+      let (stall_cycles, write_cycle) = measure_dma_stall(&mut cpu);
+      let expected_stall_cycles = if write_cycle % 2 == 1 { OAM_DMA_CYCLES + 1 } else { OAM_DMA_CYCLES };
+      assert_eq!(stall_cycles, expected_stall_cycles);
+      stall_lengths.push(stall_cycles);
+    }
+    stall_lengths.sort();
+    assert_eq!(stall_lengths, vec![513, 514]);
+  }
+
+  #[test]
+  fn test_oam_is_populated_before_cpu_resumes() {
+    for fast_oam_dma in [false, true] {
+      let mut cpu = create_dma_test_cpu(&[]);
+      cpu.bus.fast_oam_dma = fast_oam_dma;
+      measure_dma_stall(&mut cpu);
+      assert_oam_holds_page(&cpu);
+
+      let mut current_cycle = 0;
+      for _ in 0..10 {
+        clock_cpu_cycle(&mut cpu, &mut current_cycle);
+      }
+      assert!(cpu.bus.read_debug(0x0010) > 0);
+    }
+  }
+
+  // Enables interrupts and loops forever. The IRQ handler counts interrupts in $10 and acknowledges them through $4015.
+  fn create_irq_test_cpu(frame_counter_setting: u8) -> Ben6502 {
+    let mut bus = BusBuilder::new().build();
+    let program = assemble(&[
+      ("LDA #", vec![frame_counter_setting]),
+      ("STA abs", vec![0x17, 0x40]),
+      ("CLI", vec![]),
+      ("JMP abs", vec![0x06, 0x80]),
+    ]);
+    let irq_handler = assemble(&[
+      ("INC zp", vec![0x10]),
+      ("LDA abs", vec![0x15, 0x40]),
+      ("RTI", vec![]),
+    ]);
+    bus.load_bytes_at(0x8000, &program).unwrap();
+    bus.load_bytes_at(0x9000, &irq_handler).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    bus.write_word_little_endian(0xFFFE, 0x9000).unwrap();
+    return Ben6502::new(bus);
+  }
+
+  #[test]
+  fn test_frame_counter_irq_reaches_the_cpu() {
+    // A 4-step sequence takes 29830 CPU cycles, so three of them fit in 100000 cycles
+    let mut cpu = create_irq_test_cpu(0x00);
+    let mut current_cycle = 0;
+    for _ in 0..100000 {
+      clock_cpu_cycle(&mut cpu, &mut current_cycle);
+    }
+    assert_eq!(cpu.bus.read_debug(0x0010), 3);
+
+    // Neither the IRQ inhibit flag nor the 5-step mode raise it
+    for frame_counter_setting in [0x40, 0x80] {
+      let mut cpu = create_irq_test_cpu(frame_counter_setting);
+      let mut current_cycle = 0;
+      for _ in 0..100000 {
+        clock_cpu_cycle(&mut cpu, &mut current_cycle);
+      }
+      assert_eq!(cpu.bus.read_debug(0x0010), 0);
+    }
+  }
+
+  #[test]
+  fn test_mmc3_irq_reaches_the_cpu() {
+    // Runs from the fixed bank at $E000. Once in vertical blank, it sets up an IRQ every 10 scanlines, with the sprites
+    // in the other pattern table so that A12 rises once per scanline, and turns rendering on.
+    let program = assemble(&[
+      ("BIT abs", vec![0x02, 0x20]),
+      ("BPL rel", vec![0xFB]),
+      // No frame counter IRQs
+      ("LDA #", vec![0x40]),
+      ("STA abs", vec![0x17, 0x40]),
+      ("LDA #", vec![9]),
+      ("STA abs", vec![0x00, 0xC0]),
+      ("STA abs", vec![0x01, 0xC0]),
+      ("STA abs", vec![0x01, 0xE0]),
+      ("LDA #", vec![0x08]),
+      ("STA abs", vec![0x00, 0x20]),
+      ("LDA #", vec![0x18]),
+      ("STA abs", vec![0x01, 0x20]),
+      ("CLI", vec![]),
+      ("JMP abs", vec![0x20, 0xE0]),
+    ]);
+    // Counts the IRQs in $10, and acknowledges them
+    let irq_handler = assemble(&[
+      ("INC zp", vec![0x10]),
+      ("STA abs", vec![0x00, 0xE0]),
+      ("STA abs", vec![0x01, 0xE0]),
+      ("RTI", vec![]),
+    ]);
+    let rom = NesRomBuilder::new()
+      .mapper(4)
+      .prg_size(2)
+      .write_prg(0x6000, &program)
+      .write_prg(0x6100, &irq_handler)
+      .write_prg(0x7FFE, &[0x00, 0xE1])
+      .set_reset_vector(0xE000)
+      .build();
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap()));
+
+    let mut current_cycle = 0;
+    let mut irq_scanlines = vec![];
+    while (irq_scanlines.len() < 3) {
+      let irq_count = cpu.bus.read_debug(0x0010);
+      clock_cpu_cycle(&mut cpu, &mut current_cycle);
+      if (cpu.bus.read_debug(0x0010) != irq_count) {
+        irq_scanlines.push(cpu.bus.ppu().get_debug_state().scan_line);
+      }
+    }
+    // The counter is reloaded on the pre-render scanline, and counts down at the end of every scanline after that
+    assert_eq!(irq_scanlines, vec![9, 19, 29]);
+  }
+
+  #[test]
+  fn test_nmi_is_counted_once_per_frame() {
+    // Enables the NMI on vertical blank and loops, with an NMI handler that only returns
+    let mut bus = BusBuilder::new().build();
+    let program = assemble(&[
+      ("SEI", vec![]),
+      ("LDA #", vec![0x80]),
+      ("STA abs", vec![0x00, 0x20]),
+      ("JMP abs", vec![0x06, 0x80]),
+    ]);
+    bus.load_bytes_at(0x8000, &program).unwrap();
+    bus.load_bytes_at(0x9000, &assemble(&[("RTI", vec![])])).unwrap();
+    bus.write_word_little_endian(0xFFFA, 0x9000).unwrap();
+    bus.write_word_little_endian(0xFFFC, 0x8000).unwrap();
+    let mut cpu = Ben6502::new(bus);
+
+    let mut current_cycle = 0;
+    while (cpu.nmi_count == 0) {
+      super::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    }
+    // The CPU is stopped right at the entry point of the handler
+    assert_eq!(cpu.registers.pc, 0x9000);
+
+    // A frame is 341 * 262 PPU cycles long
+    for _ in 0..341 * 262 {
+      super::clock_cycle(&mut cpu, &mut current_cycle).unwrap();
+    }
+    assert_eq!(cpu.nmi_count, 2);
+  }
+
+  // Runs a whole frame, starting right after the previous one, and returns how many PPU and CPU cycles it took
+  fn measure_frame(cpu: &mut Ben6502, current_cycle: &mut u64) -> (u64, u64) {
+    while (!cpu.bus.ppu().frame_render_complete) {
+      super::clock_cycle(cpu, current_cycle).unwrap();
+    }
+    cpu.bus.ppu_mut().frame_render_complete = false;
+    let (start_ppu_cycle, start_cpu_cycle) = (*current_cycle, cpu.total_cycles);
+    while (!cpu.bus.ppu().frame_render_complete) {
+      super::clock_cycle(cpu, current_cycle).unwrap();
+    }
+    return (*current_cycle - start_ppu_cycle, cpu.total_cycles - start_cpu_cycle);
+  }
+
+  #[test]
+  fn test_console_timing_depends_on_the_region() {
+    // Loops forever with interrupts disabled, and rendering off so that no frame is a cycle shorter
+    let program = assemble(&[("SEI", vec![]), ("JMP abs", vec![0x01, 0x80])]);
+    let mut rom = NesRomBuilder::new().set_reset_vector(0x8000).write_prg(0, &program).build();
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap()));
+    let mut current_cycle = 0;
+    assert_eq!(super::frames_per_second(&cpu), 60.0988);
+    let (ppu_cycles, cpu_cycles) = measure_frame(&mut cpu, &mut current_cycle);
+    assert_eq!(ppu_cycles, 341 * 262);
+    assert!((29780..=29781).contains(&cpu_cycles), "{}", cpu_cycles);
+
+    // The TV system flag in flags 9 marks PAL ROMs
+    rom[9] = 0x01;
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap()));
+    let mut current_cycle = 0;
+    assert_eq!(super::frames_per_second(&cpu), 50.007);
+    let (ppu_cycles, cpu_cycles) = measure_frame(&mut cpu, &mut current_cycle);
+    assert_eq!(ppu_cycles, 341 * 312);
+    assert!((33247..=33248).contains(&cpu_cycles), "{}", cpu_cycles);
+
+    // Mis-headered ROMs can be switched back
+    cpu.bus.set_region(Region::Ntsc);
+    assert_eq!(super::frames_per_second(&cpu), 60.0988);
+    let (ppu_cycles, _) = measure_frame(&mut cpu, &mut current_cycle);
+    assert_eq!(ppu_cycles, 341 * 262);
+  }
+
+  #[test]
+  fn test_console_runs_on_another_thread() {
+    let mut cpu = create_irq_test_cpu(0x00);
+    let cpu = std::thread::spawn(move || {
+      let mut current_cycle = 0;
+      for _ in 0..30000 {
+        clock_cpu_cycle(&mut cpu, &mut current_cycle);
+      }
+      return cpu;
+    }).join().unwrap();
+    assert_eq!(cpu.bus.read_debug(0x0010), 1);
+  }
+}
@@ -1,20 +1,25 @@
 /*
 
 Input is processed in the following way:
-- When the game needs controller input, it writes to one of the addresses (0x4016 ot 0x4017)
-- The controller then gathers all the pressed/unpressed buttons and stores them into a byte
+- When the game needs controller input, it writes 1 and then 0 to bit 0 of address 0x4016 (the strobe, shared by both controllers)
+- While the strobe is high, the controllers keep reloading their shift registers with the buttons being pressed.
+When it goes low, the shift registers keep the last state.
 - The CPU can now read from the register 8 times to get the pressd/unpressed value of each button.
+After that, standard controllers return 1 on every read. While the strobe is high, every read returns the A button.
 
 - In this implementation, the emulator_input array is updated by the emulator UI program,
-and whenever the game writes to location 0x4016 or 0x4017, the data is moved to the data variable that
-will be used to return adecuate read values.
+and it is copied to the data variable whenever the controllers reload.
+
+Reference: https://www.nesdev.org/wiki/Standard_controller
 
 */
 
-use crate::device::Device;
+use crate::device::{Device, DeviceError, private::Sealed};
 
+#[derive(Clone)]
 pub struct Controller {
   data: [u8; 2],
+  strobe: bool,
   pub emulator_input: [u8; 2]
 }
 
@@ -22,37 +27,121 @@ impl Controller {
   pub fn new() -> Self {
     return Controller {
       data: [0; 2],
+      strobe: false,
       emulator_input: [0; 2],
     }
   }
+
+  fn read_port(&mut self, port: usize) -> u8 {
+    if self.strobe {
+      self.data = self.emulator_input;
+    }
+    let return_value = (self.data[port] & 0x80 > 0) as u8;
+    if !self.strobe {
+      // Once the 8 buttons are shifted out, the shift register is filled with 1s
+      self.data[port] = (self.data[port] << 1) | 0x01;
+    }
+    return return_value;
+  }
 }
 
+impl Sealed for Controller {}
+
 impl Device for Controller {
+  fn clone_device(&self) -> Box<dyn Device + Send> {
+    return Box::new(self.clone());
+  }
+
   fn in_memory_bounds(&self, addr: u16)-> bool {
     return addr == 0x4016 || addr == 0x4017;
   }
 
-  fn write(&mut self, addr: u16, data: u8) -> Result<(), String> {
+  // Only $4016 is writable, and the strobe reaches both controller ports ($4017 writes belong to the APU)
+  fn write(&mut self, addr: u16, data: u8) -> Result<(), DeviceError> {
     if addr == 0x4016 {
-      self.data[0] = self.emulator_input[0];
-      return Ok(());
-    } else if addr == 0x4017 {
-      self.data[1] = self.emulator_input[1];
+      let was_strobing = self.strobe;
+      self.strobe = data & 0x01 != 0;
+      // Lowering the strobe also reloads, since the buttons were being reloaded continuously until this point
+      if self.strobe || was_strobing {
+        self.data = self.emulator_input;
+      }
       return Ok(());
     }
-    return Err(String::from("Read from controller but not from addresses 0x4016 or 0x4017"));
+    return Err(DeviceError::Unsupported);
   }
 
-  fn read(&mut self, addr: u16) -> Result<u8, String> {
-    if addr == 0x4016 {
-      let return_value = (self.data[0] & 0x80 > 0) as u8;
-      self.data[0] <<= 1;
-      return Ok(return_value);
-    } else if addr == 0x4017 {
-      let return_value = (self.data[1] & 0x80 > 0) as u8;
-      self.data[1] <<= 1;
-      return Ok(return_value);
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError> {
+    if addr == 0x4016 || addr == 0x4017 {
+      return Ok(self.read_port((addr - 0x4016) as usize));
     }
-    return Err(String::from("Read from controller but not from addresses 0x4016 or 0x4017"));
+    return Err(DeviceError::OutOfBounds);
+  }
+
+  fn read_debug(&self, addr: u16) -> Result<u8, DeviceError> {
+    if addr == 0x4016 || addr == 0x4017 {
+      let data = if self.strobe { self.emulator_input } else { self.data };
+      return Ok((data[(addr - 0x4016) as usize] & 0x80 > 0) as u8);
+    }
+    return Err(DeviceError::OutOfBounds);
+  }
+}
+
+
+#[cfg(test)]
+mod controller_tests {
+  use super::Controller;
+  use crate::device::Device;
+
+  fn read_buttons(controller: &mut Controller, addr: u16, count: usize) -> Vec<u8> {
+    return (0..count).map(|_| controller.read(addr).unwrap()).collect();
+  }
+
+  #[test]
+  fn test_strobe_high_reloads_on_every_read() {
+    let mut controller = Controller::new();
+    controller.emulator_input = [0b1000_0000, 0];
+    controller.write(0x4016, 0x01).unwrap();
+    // Always the A button, no matter how many times it's read
+    assert_eq!(read_buttons(&mut controller, 0x4016, 10), vec![1; 10]);
+
+    // Releasing A shows up right away while strobing
+    controller.emulator_input[0] = 0b0100_0000;
+    assert_eq!(controller.read(0x4016).unwrap(), 0);
+
+    controller.write(0x4016, 0x00).unwrap();
+    assert_eq!(read_buttons(&mut controller, 0x4016, 2), vec![0, 1]);
+  }
+
+  #[test]
+  fn test_strobe_low_freezes_buttons() {
+    let mut controller = Controller::new();
+    controller.emulator_input = [0b1010_0000, 0];
+    controller.write(0x4016, 0x01).unwrap();
+    controller.write(0x4016, 0x00).unwrap();
+    controller.emulator_input = [0, 0];
+    assert_eq!(read_buttons(&mut controller, 0x4016, 3), vec![1, 0, 1]);
+    // Writing 0 again doesn't reload
+    controller.write(0x4016, 0x00).unwrap();
+    assert_eq!(read_buttons(&mut controller, 0x4016, 5), vec![0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_reads_return_1_after_8_buttons() {
+    let mut controller = Controller::new();
+    controller.emulator_input = [0b0000_0001, 0];
+    controller.write(0x4016, 0x01).unwrap();
+    controller.write(0x4016, 0x00).unwrap();
+    assert_eq!(read_buttons(&mut controller, 0x4016, 8), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(read_buttons(&mut controller, 0x4016, 4), vec![1, 1, 1, 1]);
+    // The second port is shifted separately
+    assert_eq!(read_buttons(&mut controller, 0x4017, 9), vec![0, 0, 0, 0, 0, 0, 0, 0, 1]);
+  }
+
+  #[test]
+  fn test_4017_writes_do_not_strobe() {
+    let mut controller = Controller::new();
+    controller.emulator_input = [0xFF, 0xFF];
+    assert!(controller.write(0x4017, 0x01).is_err());
+    assert_eq!(controller.read(0x4016).unwrap(), 0);
   }
 }
\ No newline at end of file
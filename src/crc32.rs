@@ -0,0 +1,45 @@
+/*
+
+CRC32 checksums, used to identify ROMs regardless of their file name.
+
+It's the common CRC-32 (the one zip files and PNG images use), which is also what ROM databases like No-Intro list,
+computed over the PRG and CHR data without the iNES header.
+
+*/
+
+// Reversed representation of the CRC-32 polynomial
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+pub fn crc32(data: &[u8]) -> u32 {
+  return crc32_update(0, data);
+}
+
+// Continues the checksum of the data before this one, for data that isn't contiguous
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+  let mut crc = !crc;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if (crc & 1) != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+    }
+  }
+  return !crc;
+}
+
+
+#[cfg(test)]
+mod crc32_tests {
+  use super::{crc32, crc32_update};
+
+  #[test]
+  fn test_known_answers() {
+    assert_eq!(crc32(b""), 0x00000000);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414FA339);
+  }
+
+  #[test]
+  fn test_update_continues_the_checksum() {
+    assert_eq!(crc32_update(crc32(b"12345"), b"6789"), crc32(b"123456789"));
+  }
+}
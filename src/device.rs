@@ -1,5 +1,105 @@
-pub trait Device {
+use std::fmt;
+#[cfg(test)]
+use std::{ops::RangeInclusive, sync::Arc};
+
+// Why a device couldn't handle an access. The bus adds the address and access type when it reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceError {
+  // The address isn't handled by the device
+  OutOfBounds,
+  // The device can't be accessed that way at that address (e.g. writing to a read-only register, or a read_debug
+  // that would have side effects)
+  Unsupported,
+  // The cartridge's mapper rejected the access
+  Mapper(String),
+}
+
+impl fmt::Display for DeviceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    return match self {
+      DeviceError::OutOfBounds => write!(f, "address outside of the device's bounds"),
+      DeviceError::Unsupported => write!(f, "access not supported by the device"),
+      DeviceError::Mapper(message) => write!(f, "mapper error: {}", message),
+    };
+  }
+}
+
+impl std::error::Error for DeviceError {}
+
+// Device is sealed: the bus relies on the devices it knows about (e.g. the cartridge and RAM mirroring their ranges,
+// or the PPU and APU registers having side effects on reads), so only this crate implements it.
+// Mocks that tests need to put on the bus can be made with new_device.
+pub(crate) mod private {
+  pub trait Sealed {}
+}
+
+pub trait Device: private::Sealed {
   fn in_memory_bounds(&self, addr: u16)-> bool;
-  fn write(&mut self, addr: u16, data: u8) -> Result<(), String>;
-  fn read(&mut self, addr: u16) -> Result<u8, String>;
-}
\ No newline at end of file
+  #[must_use = "the device may have rejected the write"]
+  fn write(&mut self, addr: u16, data: u8) -> Result<(), DeviceError>;
+  #[must_use = "reads can have side effects, use read_debug to just look at the device"]
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError>;
+
+  // Reads without any of the side effects a real read might have (clearing flags, advancing buffers, switching banks...),
+  // for debuggers and visualizers. Devices that can't guarantee that just don't support it.
+  fn read_debug(&self, addr: u16) -> Result<u8, DeviceError> {
+    return Err(DeviceError::Unsupported);
+  }
+
+  // The bus keeps the devices registered on it as trait objects, so they can't be cloned through a derive
+  fn clone_device(&self) -> Box<dyn Device + Send>;
+}
+
+impl Clone for Box<dyn Device + Send> {
+  fn clone(&self) -> Box<dyn Device + Send> {
+    return self.clone_device();
+  }
+}
+
+// A device made out of a read and a write function, which get the full CPU address. Copies share the functions.
+#[cfg(test)]
+#[derive(Clone)]
+struct CustomDevice {
+  memory_bounds: RangeInclusive<u16>,
+  read: Arc<dyn Fn(u16) -> u8 + Send + Sync>,
+  write: Arc<dyn Fn(u16, u8) + Send + Sync>,
+}
+
+#[cfg(test)]
+impl private::Sealed for CustomDevice {}
+
+#[cfg(test)]
+impl Device for CustomDevice {
+  fn clone_device(&self) -> Box<dyn Device + Send> {
+    return Box::new(self.clone());
+  }
+
+  fn in_memory_bounds(&self, addr: u16) -> bool {
+    return self.memory_bounds.contains(&addr);
+  }
+
+  fn write(&mut self, addr: u16, data: u8) -> Result<(), DeviceError> {
+    if !self.in_memory_bounds(addr) {
+      return Err(DeviceError::OutOfBounds);
+    }
+    (self.write)(addr, data);
+    return Ok(());
+  }
+
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError> {
+    if !self.in_memory_bounds(addr) {
+      return Err(DeviceError::OutOfBounds);
+    }
+    return Ok((self.read)(addr));
+  }
+}
+
+// For registering mocks on the bus in tests
+#[cfg(test)]
+pub fn new_device(
+  memory_bounds: RangeInclusive<u16>,
+  read: impl Fn(u16) -> u8 + Send + Sync + 'static,
+  write: impl Fn(u16, u8) + Send + Sync + 'static,
+) -> Box<dyn Device + Send> {
+  return Box::new(CustomDevice { memory_bounds, read: Arc::new(read), write: Arc::new(write) });
+}
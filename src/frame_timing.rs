@@ -0,0 +1,130 @@
+/*
+
+Keeps the time between the last displayed frames, to show how fast the emulation is actually running.
+
+The UI timer that paces the emulation is not exact, and emulating a frame can take longer than a frame on slow
+machines, so the real frame rate can drift away from the NES one (60.0988 Hz for NTSC).
+
+*/
+
+use std::time::Instant;
+
+// One second worth of frames
+pub const FRAME_TIMING_HISTORY_LENGTH: usize = 60;
+
+pub struct FrameTimingHistory {
+  // Ring buffer with the time between frames, in milliseconds
+  frame_times_ms: [f64; FRAME_TIMING_HISTORY_LENGTH],
+  next_index: usize,
+  len: usize,
+  last_frame_instant: Option<Instant>,
+}
+
+impl FrameTimingHistory {
+  pub fn new() -> FrameTimingHistory {
+    return FrameTimingHistory {
+      frame_times_ms: [0.0; FRAME_TIMING_HISTORY_LENGTH],
+      next_index: 0,
+      len: 0,
+      last_frame_instant: None,
+    }
+  }
+
+  // Called every time a frame is shown. The first frame only starts the clock.
+  pub fn record_frame(&mut self, now: Instant) {
+    if let Some(last_frame_instant) = self.last_frame_instant {
+      self.push(now.duration_since(last_frame_instant).as_secs_f64() * 1000.0);
+    }
+    self.last_frame_instant = Some(now);
+  }
+
+  pub fn push(&mut self, frame_time_ms: f64) {
+    self.frame_times_ms[self.next_index] = frame_time_ms;
+    self.next_index = (self.next_index + 1) % FRAME_TIMING_HISTORY_LENGTH;
+    self.len = (self.len + 1).min(FRAME_TIMING_HISTORY_LENGTH);
+  }
+
+  // Forgets every frame, so that the time spent paused doesn't count
+  pub fn clear(&mut self) {
+    *self = FrameTimingHistory::new();
+  }
+
+  pub fn last_frame_time_ms(&self) -> Option<f64> {
+    if (self.len == 0) {
+      return None;
+    }
+    let last_index = (self.next_index + FRAME_TIMING_HISTORY_LENGTH - 1) % FRAME_TIMING_HISTORY_LENGTH;
+    return Some(self.frame_times_ms[last_index]);
+  }
+
+  pub fn rolling_average_ms(&self) -> Option<f64> {
+    if (self.len == 0) {
+      return None;
+    }
+    // Until the buffer fills up, the entries after len are still zero
+    return Some(self.frame_times_ms.iter().sum::<f64>() / self.len as f64);
+  }
+
+  // Instantaneous frame rate and frame time, followed by the average frame rate of the last 60 frames
+  pub fn label(&self) -> String {
+    return match (self.last_frame_time_ms(), self.rolling_average_ms()) {
+      (Some(last_frame_time_ms), Some(rolling_average_ms)) => format!(
+        "{:.2} fps ({:.2} ms)\navg {:.2} fps",
+        1000.0 / last_frame_time_ms,
+        last_frame_time_ms,
+        1000.0 / rolling_average_ms,
+      ),
+      _ => String::from("-- fps"),
+    };
+  }
+}
+
+
+#[cfg(test)]
+mod frame_timing_tests {
+  use std::time::{Duration, Instant};
+  use super::{FrameTimingHistory, FRAME_TIMING_HISTORY_LENGTH};
+
+  const NTSC_FRAME_TIME_MS: f64 = 1000.0 / 60.0988;
+
+  #[test]
+  fn test_label_at_ntsc_frame_rate() {
+    let mut frame_timing = FrameTimingHistory::new();
+    assert_eq!(frame_timing.label(), "-- fps");
+    for _ in 0..FRAME_TIMING_HISTORY_LENGTH {
+      frame_timing.push(NTSC_FRAME_TIME_MS);
+    }
+    assert_eq!(frame_timing.label(), "60.10 fps (16.64 ms)\navg 60.10 fps");
+  }
+
+  #[test]
+  fn test_average_only_covers_the_last_frames() {
+    let mut frame_timing = FrameTimingHistory::new();
+    frame_timing.push(20.0);
+    frame_timing.push(10.0);
+    assert_eq!(frame_timing.rolling_average_ms(), Some(15.0));
+
+    // The slow frame falls out of the history after 60 more
+    frame_timing.push(100.0);
+    for _ in 0..FRAME_TIMING_HISTORY_LENGTH {
+      frame_timing.push(10.0);
+    }
+    assert_eq!(frame_timing.last_frame_time_ms(), Some(10.0));
+    assert_eq!(frame_timing.rolling_average_ms(), Some(10.0));
+  }
+
+  #[test]
+  fn test_record_frame_measures_time_between_frames() {
+    let mut frame_timing = FrameTimingHistory::new();
+    let start = Instant::now();
+    frame_timing.record_frame(start);
+    assert_eq!(frame_timing.last_frame_time_ms(), None);
+    frame_timing.record_frame(start + Duration::from_millis(16));
+    frame_timing.record_frame(start + Duration::from_millis(34));
+    assert_eq!(frame_timing.last_frame_time_ms(), Some(18.0));
+    assert_eq!(frame_timing.rolling_average_ms(), Some(17.0));
+
+    frame_timing.clear();
+    assert_eq!(frame_timing.rolling_average_ms(), None);
+  }
+}
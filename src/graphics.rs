@@ -1,4 +1,8 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
 #[derive(Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Color {
   pub red: u8,
   pub green: u8,
@@ -10,8 +14,9 @@ impl Color {
     return Color { red, green, blue };
   }
 
+  #[deprecated(note = "use iced::Color::from(color) or color.into() instead")]
   pub fn to_iced_color(&self) -> iced::Color {
-    return iced::Color::new((self.red as f32) / 255.0, (self.green as f32) / 255.0, (self.blue as f32) / 255.0, 1.0);
+    return iced::Color::from(*self);
   }
 }
 
@@ -19,4 +24,34 @@ impl Clone for Color {
   fn clone(&self) -> Self {
       Self { red: self.red.clone(), green: self.green.clone(), blue: self.blue.clone() }
   }
-}
\ No newline at end of file
+}
+
+impl From<Color> for iced::Color {
+  fn from(color: Color) -> iced::Color {
+    return iced::Color::new((color.red as f32) / 255.0, (color.green as f32) / 255.0, (color.blue as f32) / 255.0, 1.0);
+  }
+}
+
+// The alpha channel is dropped, since the NES can't display transparent colors
+impl From<iced::Color> for Color {
+  fn from(color: iced::Color) -> Color {
+    let to_u8 = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+    return Color::new(to_u8(color.r), to_u8(color.g), to_u8(color.b));
+  }
+}
+
+
+#[cfg(test)]
+mod graphics_tests {
+  use super::Color;
+
+  #[test]
+  fn test_iced_color_conversion_round_trip() {
+    let color = Color::new(0x00, 0x7F, 0xFF);
+    let iced_color: iced::Color = color.into();
+    assert_eq!((iced_color.r, iced_color.g, iced_color.b, iced_color.a), (0.0, 127.0 / 255.0, 1.0, 1.0));
+
+    let round_trip = Color::from(iced_color);
+    assert_eq!((round_trip.red, round_trip.green, round_trip.blue), (0x00, 0x7F, 0xFF));
+  }
+}
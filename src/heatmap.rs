@@ -0,0 +1,85 @@
+/*
+
+Colors for the memory heat map debug panel, which shows how often the CPU side of the bus accessed each address.
+
+The 64KB address space is drawn as a 256x256 grid, one row per page, so the hot loops, the variables a game keeps
+polling and the I/O registers it hammers stand out.
+
+*/
+
+use crate::graphics::Color;
+
+pub const HEATMAP_SIZE: usize = 256;
+
+// Black for a heat of 0, then red, yellow and white for the hottest addresses
+pub fn heat_color(heat: f32) -> Color {
+  let heat = heat.clamp(0.0, 1.0) * 3.0;
+  let to_u8 = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+  return Color::new(to_u8(heat), to_u8(heat - 1.0), to_u8(heat - 2.0));
+}
+
+// One color per address, in address order. The counts go through a log scale, since a hot loop is easily accessed
+// millions of times more than everything else, which would leave the rest of the map black.
+pub fn heatmap_colors(read_counts: &[u32; 65536], write_counts: &[u32; 65536]) -> Vec<Color> {
+  let access_counts: Vec<u64> = read_counts.iter().zip(write_counts.iter())
+    .map(|(read_count, write_count)| *read_count as u64 + *write_count as u64)
+    .collect();
+  let max_heat = (*access_counts.iter().max().unwrap() as f64).ln_1p();
+  return access_counts.iter()
+    .map(|access_count| {
+      if (*access_count == 0) {
+        return Color::new(0, 0, 0);
+      }
+      return heat_color(((*access_count as f64).ln_1p() / max_heat) as f32);
+    })
+    .collect();
+}
+
+
+#[cfg(test)]
+mod heatmap_tests {
+  use super::{heat_color, heatmap_colors};
+
+  fn rgb(color: crate::graphics::Color) -> [u8; 3] {
+    return [color.red, color.green, color.blue];
+  }
+
+  #[test]
+  fn test_heat_color_gradient() {
+    assert_eq!(rgb(heat_color(0.0)), [0, 0, 0]);
+    assert_eq!(rgb(heat_color(1.0 / 3.0)), [255, 0, 0]);
+    assert_eq!(rgb(heat_color(2.0 / 3.0)), [255, 255, 0]);
+    assert_eq!(rgb(heat_color(1.0)), [255, 255, 255]);
+    assert_eq!(rgb(heat_color(2.0)), [255, 255, 255]);
+  }
+
+  #[test]
+  fn test_heatmap_colors() {
+    let mut read_counts = Box::new([0; 65536]);
+    let mut write_counts = Box::new([0; 65536]);
+    read_counts[0x8000] = 1_000_000;
+    write_counts[0x0200] = 1000;
+    read_counts[0x4016] = 10;
+    write_counts[0x4016] = 10;
+
+    let colors = heatmap_colors(&read_counts, &write_counts);
+    assert_eq!(colors.len(), 65536);
+    // Never accessed
+    assert_eq!(rgb(colors[0x0000]), [0, 0, 0]);
+    // The hottest address is white, and the rest get colder with fewer accesses, but are still visible
+    assert_eq!(rgb(colors[0x8000]), [255, 255, 255]);
+    let heat = |color: crate::graphics::Color| color.red as u32 + color.green as u32 + color.blue as u32;
+    assert!(heat(colors[0x0200]) > heat(colors[0x4016]));
+    assert!(heat(colors[0x4016]) > 0);
+
+    // Reads and writes count the same
+    read_counts[0x0201] = 1000;
+    assert_eq!(rgb(heatmap_colors(&read_counts, &write_counts)[0x0201]), rgb(colors[0x0200]));
+  }
+
+  #[test]
+  fn test_heatmap_colors_without_accesses() {
+    let colors = heatmap_colors(&Box::new([0; 65536]), &Box::new([0; 65536]));
+    assert!(colors.iter().all(|color| [color.red, color.green, color.blue] == [0, 0, 0]));
+  }
+}
@@ -0,0 +1,101 @@
+/*
+
+The CPU has a single, level triggered IRQ line shared by every component that can request an interrupt.
+Each source drives its own bit, and the line stays asserted as long as any of them is set, so acknowledging
+one source doesn't hide another one that fired at the same time.
+
+How a source gets acknowledged depends on the source itself:
+- APU frame counter: reading $4015, or setting the IRQ inhibit flag through $4017.
+- DMC: writing $4015, or clearing the DMC IRQ enable flag through $4010.
+- Mappers: through mapper-specific registers.
+
+Reference: https://www.nesdev.org/wiki/IRQ
+
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+  ApuFrameCounter,
+  Dmc,
+  Mapper,
+}
+
+impl IrqSource {
+  fn mask(&self) -> u8 {
+    return match self {
+      IrqSource::ApuFrameCounter => 0x01,
+      IrqSource::Dmc => 0x02,
+      IrqSource::Mapper => 0x04,
+    };
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IrqLine {
+  sources: u8,
+}
+
+impl IrqLine {
+  pub fn new() -> IrqLine {
+    return IrqLine { sources: 0 };
+  }
+
+  pub fn set(&mut self, source: IrqSource, asserted: bool) {
+    if asserted {
+      self.sources |= source.mask();
+    } else {
+      self.sources &= !source.mask();
+    }
+  }
+
+  pub fn is_source_asserted(&self, source: IrqSource) -> bool {
+    return (self.sources & source.mask()) != 0;
+  }
+
+  // State of the CPU's IRQ input
+  pub fn is_asserted(&self) -> bool {
+    return self.sources != 0;
+  }
+}
+
+
+#[cfg(test)]
+mod irq_tests {
+  use super::{IrqLine, IrqSource};
+  use crate::bus::Bus16Bit;
+
+  #[test]
+  fn test_line_stays_asserted_until_every_source_is_acknowledged() {
+    let mut irq_line = IrqLine::new();
+    assert!(!irq_line.is_asserted());
+    irq_line.set(IrqSource::ApuFrameCounter, true);
+    irq_line.set(IrqSource::Mapper, true);
+
+    irq_line.set(IrqSource::ApuFrameCounter, false);
+    assert!(irq_line.is_asserted());
+    assert!(irq_line.is_source_asserted(IrqSource::Mapper));
+    irq_line.set(IrqSource::Mapper, false);
+    assert!(!irq_line.is_asserted());
+  }
+
+  #[test]
+  fn test_apu_frame_and_dmc_irqs_are_acknowledged_separately() {
+    let mut bus = Bus16Bit::new("test_roms/nestest.nes").unwrap();
+    {
+      let apu = bus.apu_mut();
+      apu.frame_counter.irq_pending = true;
+      apu.dmc.irq_pending = true;
+    }
+    assert!(bus.poll_irq());
+
+    // Reading $4015 only acknowledges the frame interrupt
+    bus.read(0x4015, false).unwrap();
+    assert!(bus.poll_irq());
+    assert!(bus.irq_line.is_source_asserted(IrqSource::Dmc));
+    assert!(!bus.irq_line.is_source_asserted(IrqSource::ApuFrameCounter));
+
+    // And writing it acknowledges the DMC one
+    bus.write(0x4015, 0x00).unwrap();
+    assert!(!bus.poll_irq());
+  }
+}
@@ -1,30 +1,56 @@
 #![allow(unused_parens)]
+mod apu;
+mod apu_io_region;
+mod audio;
+mod audio_filter;
 mod ben2C02;
+#[cfg(test)]
+mod blargg_tests;
 mod ben6502;
 mod bus;
+mod call_stack;
 mod cartridge;
+mod config;
+mod console;
 mod controller;
+mod crc32;
 mod device;
+mod frame_timing;
 mod graphics;
+mod heatmap;
+mod irq;
 mod mapper;
 mod ram;
+mod rate_control;
+mod recent_roms;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod watch;
+mod wav_writer;
 
-use std::cell::RefCell;
 use std::env;
-use std::rc::Rc;
-use std::sync::{Mutex, Arc, MutexGuard};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use bus::Bus16Bit;
+use bus::{Bus16Bit, BusError};
 use ben6502::Ben6502;
 use utils::hex_utils;
-use ben2C02::Ben2C02;
+use ben2C02::{Ben2C02, SharedVisBuffer};
 use ram::Ram2K;
-use cartridge::Cartridge;
+use cartridge::{Cartridge, Region};
+use irq::IrqSource;
 use device::Device;
+use recent_roms::RecentRoms;
+use config::{EmulatorConfig, move_save_file};
+use apu::{Channel, ApuDebugState, PulseDebugState};
+use audio::AudioOutput;
+use rate_control::DynamicRateControl;
+use frame_timing::FrameTimingHistory;
+use watch::{DisplayFormat, WatchExpression};
 
 
-use iced::widget::{button, column, row, text};
+use iced::widget::{button, checkbox, column, container, row, text, text_input, pick_list, slider, scrollable};
 use iced::{Alignment, Element, Sandbox, Settings, Renderer, event, Application, Subscription, executor, Theme, Command, Rectangle, time, Point, Size};
 
 use iced::keyboard::{self, KeyCode, Modifiers};
@@ -33,71 +59,530 @@ use iced_native::{Event, Program};
 use iced_native::Length;
 use iced_native::Color;
 
-use std::time::{Duration, Instant};
 
 use iced::widget::canvas;
 use iced::widget::canvas::{
-  Cache, Canvas, Cursor, Frame, Geometry, Path, Text,
+  Cache, Canvas, Cursor, Frame, Geometry, Text, Stroke,
 };
+use iced::mouse;
+use iced::window;
 
 
-fn main() {
-  env::set_var("RUST_BACKTRACE", "1");
-  RustNESs::run(Settings::default());
+fn main() -> iced::Result {
+  env_logger::init();
+  // The ROM can be passed as the first argument, otherwise a file picker is shown on startup
+  let rom_path = env::args().nth(1).map(PathBuf::from);
+  // Closing the window is handled by the emulator, so battery saves are written first
+  return RustNESs::run(Settings { flags: rom_path, exit_on_close_request: false, ..Settings::default() });
+}
+
+// Missing save files are fine, it just means the game hasn't saved yet
+fn load_battery_ram(cpu_bus: &mut Bus16Bit, config: &EmulatorConfig, rom_path: &Path) {
+  if (!cpu_bus.cartridge().has_battery()) {
+    return;
+  }
+  let mut file_path = config.save_file_path(rom_path, cpu_bus.cartridge().crc32());
+  // Saves from older versions, or from when the ROM had another name, are moved to where the game saves now
+  if let Some(previous_file_path) = config.find_previous_save_file(rom_path, cpu_bus.cartridge().crc32()) {
+    match move_save_file(&previous_file_path, &file_path) {
+      Ok(()) => log::info!("Moved save file {} to {}", previous_file_path.display(), file_path.display()),
+      Err(error) => {
+        log::warn!("{}", error);
+        file_path = previous_file_path;
+      }
+    }
+  }
+  if let Err(error) = cpu_bus.cartridge_mut().load_battery_ram(&file_path) {
+    show_error_dialog(&error);
+  }
+}
+
+// Writes the battery backed PRG RAM of a game, if it has any and it changed
+fn save_battery_ram(cpu_bus: &mut Bus16Bit, config: &EmulatorConfig, rom_path: &Path) {
+  if (!cpu_bus.cartridge().has_battery()) {
+    return;
+  }
+  let file_path = config.save_file_path(rom_path, cpu_bus.cartridge().crc32());
+  match cpu_bus.cartridge_mut().save_battery_ram(&file_path) {
+    Ok(true) => log::info!("Saved battery RAM to {}", file_path.display()),
+    Ok(false) => {},
+    Err(error) => log::error!("{}", error),
+  }
+}
+
+// The old game is saved before the new one's save is read, otherwise reloading the same ROM would lose everything
+// since the last save
+fn switch_battery_ram(old_cpu_bus: &mut Bus16Bit, old_rom_path: &Path, new_cpu_bus: &mut Bus16Bit, new_rom_path: &Path, config: &EmulatorConfig) {
+  save_battery_ram(old_cpu_bus, config, old_rom_path);
+  load_battery_ram(new_cpu_bus, config, new_rom_path);
+}
+
+fn pick_rom_file() -> Option<PathBuf> {
+  return rfd::FileDialog::new().add_filter("NES ROM", &["nes"]).pick_file();
+}
+
+fn show_error_dialog(message: &str) {
+  log::error!("{}", message);
+  rfd::MessageDialog::new()
+    .set_level(rfd::MessageLevel::Error)
+    .set_title("RustNESs")
+    .set_description(message)
+    .show();
+}
+
+fn is_nes_file(path: &Path) -> bool {
+  return path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("nes"));
+}
+
+// Tints the whole window while a file is dragged over it, to show that it can be dropped anywhere
+struct DropZoneStyle;
+
+impl container::StyleSheet for DropZoneStyle {
+  type Style = Theme;
+
+  fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+    return container::Appearance {
+      background: Some(iced::Background::Color(Color::from_rgba(0.3, 0.5, 1.0, 0.25))),
+      border_width: 4.0,
+      border_color: Color::from_rgb(0.3, 0.5, 1.0),
+      ..container::Appearance::default()
+    };
+  }
 }
 
-const EMULATOR_FRAMES_PER_SECONDD: u64 = 52;
 const SCREEN_HEIGHT: u16 = 500;
 const PATTERN_TABLE_VIS_HEIGHT: u16 = 300;
 const PALETTE_VIS_HEIGHT: u16 = 30;
 const PALETTE_VIS_WIDTH: u16 = 240;
+const HEATMAP_VIS_SIZE: u16 = 256;
+const RESET_MESSAGE_FRAMES: u8 = 60;
+// About 10 seconds
+const BATTERY_SAVE_INTERVAL_FRAMES: u64 = 600;
 
 struct RustNESs {
   cpu: Ben6502,
   current_cycle: u64,
 
   paused: bool,
-  cycles_per_second: u64,
+  // Pauses emulation as soon as the CPU jumps to the NMI handler, to step through it
+  pause_on_nmi: bool,
+  // How often the UI timer asks for the next frame, the refresh rate of the console's region
+  frames_per_second: f64,
+  // Frames left to display the "RESET" message for
+  reset_message_frames_left: u8,
+  // Frames emulated since the ROM was loaded
+  frame_count: u64,
+  show_debug_panels: bool,
+  rom_path: PathBuf,
+  // Whether a file is being dragged over the window
+  file_hovered: bool,
+  show_menu: bool,
+  // Kept in memory only, so they're gone when the emulator is closed or another ROM is loaded
+  save_states: [Option<SaveState>; SAVE_STATE_SLOTS as usize],
 
   input_handler: NESInputHandler,
+  recent_roms: RecentRoms,
+  config: EmulatorConfig,
+  audio_output: Option<AudioOutput>,
+  rate_control: DynamicRateControl,
+  frame_timing: FrameTimingHistory,
 
   ppu_screen_buffer_visualizer: PPUScreenBufferVisualizer,
   ppu_pattern_tables_buffer_visualizer: PPUPatternTableBufferVisualizer,
   ppu_palette_visualizer: PPUPaletteVisualizer,
+  apu_debug_state: ApuDebugState,
+  prg_banks: Vec<(u16, u16, u32)>,
+  hovered_tile: Option<(u8, u8)>,
+  heatmap_visualizer: HeatmapVisualizer,
+  watch_panel: WatchPanel,
 
   mem_visualizer: MemoryVisualizer
 }
 
 impl RustNESs {
 
-  fn clock_cycle(&mut self) {
-    self.cpu.bus.PPU.borrow_mut().clock_cycle();
-    if self.current_cycle % 3 == 0 {
-      if (self.cpu.bus.dma_transfer_active) {
-        if (self.cpu.bus.waiting_for_cycle_alignment) {
-          if (self.current_cycle % 2 == 1) {
-            self.cpu.bus.waiting_for_cycle_alignment = false;
-          }
-        } else {
-          if (self.current_cycle % 2 == 0) {
-            self.cpu.bus.dma_curr_data = self.cpu.bus.read(self.cpu.bus.dma_curr_addr, false).unwrap();
-          } else {
-            self.cpu.bus.PPU.borrow_mut().write_to_oam_memory((self.cpu.bus.dma_curr_addr & 0xFF) as u8, self.cpu.bus.dma_curr_data);
-            self.cpu.bus.dma_curr_addr += 1;
-            if (self.cpu.bus.dma_curr_addr >> 8 != (self.cpu.bus.dma_page as u16)) {
-              self.cpu.bus.dma_transfer_active = false;
-            }
-          }
-        }
-      } else {
-        self.cpu.clock_cycle();
+  fn clock_cycle(&mut self) -> Result<(), BusError> {
+    let nmi_count = self.cpu.nmi_count;
+    let result = console::clock_cycle(&mut self.cpu, &mut self.current_cycle);
+    if (self.pause_on_nmi && self.cpu.nmi_count != nmi_count) {
+      self.paused = true;
+    }
+    return result;
+  }
+
+  fn step_instruction(&mut self) -> Result<(), BusError> {
+    self.clock_cycle()?;
+    while (self.cpu.current_instruction_remaining_cycles > 0){
+      self.clock_cycle()?;
+    }
+    return Ok(());
+  }
+
+  // Whether the CPU entered the NMI handler since nmi_count was taken, and the emulation should stop there
+  fn stopped_at_nmi(&self, nmi_count: u64) -> bool {
+    return self.pause_on_nmi && self.cpu.nmi_count != nmi_count;
+  }
+
+  // Pauses where the error happened, so the state that led to it can be looked at in the debug panels
+  fn stop_on_emulation_error(&mut self, error: BusError) {
+    self.paused = true;
+    show_error_dialog(&format!("Emulation stopped: {}", error));
+  }
+
+  fn save_battery_ram(&mut self) {
+    save_battery_ram(&mut self.cpu.bus, &self.config, &self.rom_path);
+  }
+
+  // Swaps the whole console for one with the new cartridge. If the ROM can't be loaded, the current one keeps running.
+  fn load_rom(&mut self, rom_path: &Path) -> Result<(), String> {
+    let mut cpu_bus = Bus16Bit::from_rom_file(&rom_path.to_string_lossy(), self.config.ram_init_mode)?;
+    log::info!("Loaded {}: {}", rom_path.display(), cpu_bus.cartridge().describe());
+    switch_battery_ram(&mut self.cpu.bus, &self.rom_path, &mut cpu_bus, rom_path, &self.config);
+
+    // Otherwise the recording would be dropped along with the old APU, without fixing up its header
+    if let Err(error) = self.cpu.bus.apu_mut().stop_wav_recording() {
+      log::error!("{}", error);
+    }
+    {
+      // The output sample rate and mixer settings carry over to the new APU
+      let old_apu = self.cpu.bus.apu();
+      let new_apu = cpu_bus.apu_mut();
+      new_apu.set_output_sample_rate(old_apu.output_sample_rate());
+      new_apu.mixer_settings = old_apu.mixer_settings.clone();
+    }
+    self.cpu = Ben6502::new(cpu_bus);
+    self.connect_visualizers();
+    self.current_cycle = 0;
+    self.frame_count = 0;
+    self.save_states = Default::default();
+    self.rom_path = rom_path.to_path_buf();
+    self.frames_per_second = console::frames_per_second(&self.cpu);
+
+    self.recent_roms.add(rom_path);
+    if let Err(error) = self.recent_roms.save() {
+      log::warn!("{}", error);
+    }
+    return Ok(());
+  }
+
+  // Innermost call first, like a debugger's backtrace
+  fn call_stack_view(&self) -> Element<'_, EmulatorMessage> {
+    let mut frames_column = column![];
+    for frame in self.cpu.get_call_stack().iter().rev() {
+      frames_column = frames_column.push(text(format!("${:04X} (returns to ${:04X})", frame.target_pc, frame.return_pc())).size(18));
+    }
+    column![
+      checkbox("Pause on NMI", self.pause_on_nmi, EmulatorMessage::SetPauseOnNmi),
+      text(format!("Call stack ({} mismatched returns):", self.cpu.call_stack.mismatched_returns)).size(20),
+      scrollable(frames_column).height(Length::Units(150)),
+    ].into()
+  }
+
+  fn status_view(&self) -> Element<'_, EmulatorMessage> {
+    let ppu_debug_state = self.cpu.bus.ppu().get_debug_state();
+    // As of the last time the CPU polled it
+    let irq_line = self.cpu.bus.irq_line;
+    column![
+      row![
+        text("Cpu registers:").size(20),
+        text(format!(" A: 0x{:X}", self.cpu.registers.a)),
+        text(format!(" X: 0x{:X}", self.cpu.registers.x)),
+        text(format!(" Y: 0x{:X}", self.cpu.registers.y)),
+        text(format!(" PC: 0x{:X}", self.cpu.registers.pc)),
+        text(format!("SP: 0x{:X}", self.cpu.registers.sp)),
+      ],
+      row![
+        text("Cpu flags:").size(20),
+        text("Carry: "),
+        text(self.cpu.status.get_carry().to_string()),
+        text("Zero: "),
+        text(self.cpu.status.get_zero().to_string()),
+        text("Negative: "),
+        text(self.cpu.status.get_negative().to_string()),
+        text("overflow: "),
+        text(self.cpu.status.get_overflow().to_string()),
+        text("Decimal mode: "),
+        text(self.cpu.status.get_decimal_mode().to_string()),
+        text("BRK command: "),
+        text(self.cpu.status.get_brk_command().to_string()),
+        text("IRQ Disable: "),
+        text(self.cpu.status.get_irq_disable().to_string())
+      ],
+      row![
+        text("Controller 1:").size(20),
+        text(format!(" {:08b} (A, B, Select, Start, Up, Down, Left, Right)", self.cpu.bus.controller().emulator_input[0])),
+      ],
+      row![
+        text("IRQ sources:").size(20),
+        text(format!(" Frame counter: {}", irq_line.is_source_asserted(IrqSource::ApuFrameCounter))),
+        text(format!(" DMC: {}", irq_line.is_source_asserted(IrqSource::Dmc))),
+        text(format!(" Mapper: {}", irq_line.is_source_asserted(IrqSource::Mapper))),
+      ],
+
+      row![
+        text("PPU flags:").size(20),
+        text("Vertical Blank: "),
+        text(ppu_debug_state.vertical_blank.to_string()),
+      ],
+      row![
+        text("PPU scroll:").size(20),
+        text(format!(" Fine X: {}", ppu_debug_state.fine_x)),
+        text(format!(" VRAM: 0x{:04X}", ppu_debug_state.vram_addr)),
+        text(format!(" Temp VRAM: 0x{:04X}", ppu_debug_state.temp_vram_addr)),
+      ],
+      row![
+        text("PPU timing:").size(20),
+        text(format!(" Scanline: {}", ppu_debug_state.scan_line)),
+        text(format!(" Cycle: {}", ppu_debug_state.cycle)),
+      ],
+    ]
+    .into()
+  }
+
+  fn apu_status_view(&self) -> Element<'_, EmulatorMessage> {
+    let state = &self.apu_debug_state;
+    let pulse_row = |name: &str, pulse: &PulseDebugState| row![
+      text(format!("{}:", name)).size(20),
+      text(format!(" Enabled: {}", pulse.enabled)),
+      text(format!(" Duty: {}", pulse.duty)),
+      text(format!(" Timer: {}/{}", pulse.timer, pulse.timer_period)),
+      text(format!(" Length: {}", pulse.length_counter)),
+      text(format!(" Volume: {}", pulse.envelope_volume)),
+      text(format!(" Sweep: {} (period {}, {}shift {})", if pulse.sweep_enabled { "on" } else { "off" }, pulse.sweep_period, if pulse.sweep_negate { "negate, " } else { "" }, pulse.sweep_shift)),
+    ];
+    column![
+      pulse_row("Pulse 1", &state.pulse_1),
+      pulse_row("Pulse 2", &state.pulse_2),
+      row![
+        text("Triangle:").size(20),
+        text(format!(" Enabled: {}", state.triangle.enabled)),
+        text(format!(" Timer: {}/{}", state.triangle.timer, state.triangle.timer_period)),
+        text(format!(" Length: {}", state.triangle.length_counter)),
+        text(format!(" Linear: {}", state.triangle.linear_counter)),
+      ],
+      row![
+        text("Noise:").size(20),
+        text(format!(" Enabled: {}", state.noise.enabled)),
+        text(format!(" Mode: {}", state.noise.mode_flag as u8)),
+        text(format!(" Timer: {}/{}", state.noise.timer, state.noise.timer_period)),
+        text(format!(" Length: {}", state.noise.length_counter)),
+        text(format!(" Volume: {}", state.noise.envelope_volume)),
+      ],
+      row![
+        text("DMC:").size(20),
+        text(format!(" Enabled: {}", state.dmc.enabled)),
+        text(format!(" Timer: {}/{}", state.dmc.timer, state.dmc.timer_period)),
+        text(format!(" Output: {}", state.dmc.output_level)),
+        text(format!(" Address: 0x{:04X}", state.dmc.current_address)),
+        text(format!(" Bytes remaining: {}", state.dmc.bytes_remaining)),
+      ],
+    ]
+    .into()
+  }
+
+  fn heatmap_view(&self) -> Element<'_, EmulatorMessage> {
+    column![
+      text("Memory accesses (one row per page):").size(20),
+      self.heatmap_visualizer.view(),
+      button("Clear").on_press(EmulatorMessage::ClearHeatmap),
+    ].into()
+  }
+
+  fn rom_info_view(&self) -> Element<'_, EmulatorMessage> {
+    let cartridge = self.cpu.bus.cartridge();
+    let info = cartridge.info();
+    column![
+      row![
+        text("ROM:").size(20),
+        text(format!(" Mapper: {} ({})", info.mapper_number, info.mapper_name)),
+        text(format!(" PRG banks: {} ({}KB)", cartridge.prg_bank_count(), info.prg_rom_size / 1024)),
+        text(format!(" CHR banks: {} ({}KB)", cartridge.chr_bank_count(), info.chr_rom_size / 1024)),
+      ],
+      row![
+        text(format!(" Mirroring: {:?}", info.mirroring)),
+        text(format!(" Battery: {}", info.has_battery)),
+        text(format!(" Trainer: {}", info.has_trainer)),
+        text(format!(" CRC32: {:08X}", info.crc32)),
+        text(format!(" Region (header says {}):", info.region)),
+        pick_list(&Region::ALL[..], Some(self.cpu.bus.ppu().region()), EmulatorMessage::SetRegion),
+      ],
+    ].into()
+  }
+
+  // One row per PRG bank mapped into the CPU address space
+  fn mapper_banks_view(&self) -> Element<'_, EmulatorMessage> {
+    let mut banks_column = column![text("Mapper banks:").size(20)];
+    for (start_addr, end_addr, prg_offset) in self.prg_banks.iter() {
+      banks_column = banks_column.push(text(format!("0x{:04X} - 0x{:04X}: PRG ROM 0x{:05X}", start_addr, end_addr, prg_offset)));
+    }
+    banks_column.into()
+  }
+
+  fn audio_channels_view(&self) -> Element<'_, EmulatorMessage> {
+    let apu = self.cpu.bus.apu();
+    let mut mute_row = row![text("Mute channels:").size(20)].spacing(10);
+    let mut solo_row = row![text("Solo channel:").size(20)].spacing(10);
+    for channel in Channel::ALL {
+      mute_row = mute_row.push(checkbox(channel.name(), apu.is_channel_muted(channel), move |muted| EmulatorMessage::SetChannelMuted(channel, muted)));
+      solo_row = solo_row.push(button(channel.name()).on_press(EmulatorMessage::SoloChannel(channel)));
+    }
+    let options_row = row![
+      checkbox("Linear mixing", apu.mixer_settings.linear_mixing, EmulatorMessage::SetLinearMixing),
+      checkbox("Output filters", apu.mixer_settings.output_filters_enabled, EmulatorMessage::SetOutputFiltersEnabled),
+    ].spacing(10);
+    column![mute_row, solo_row, options_row].into()
+  }
+
+  // Details of the tile under the mouse in the pattern table visualizer
+  fn hovered_tile_view(&self) -> Element<'_, EmulatorMessage> {
+    let (pattern_table_id, tile_index) = match self.hovered_tile {
+      Some(hovered_tile) => hovered_tile,
+      None => return column![].into(),
+    };
+    let palette_id = self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id;
+    let tile_addr = (pattern_table_id as u16) * 0x1000 + (tile_index as u16) * 16;
+
+    let mut swatches = row![text(format!("Palette {}:", palette_id))].spacing(5);
+    for color in self.cpu.bus.ppu().get_palette_colors(palette_id) {
+      swatches = swatches.push(text("\u{2588}\u{2588}").style(iced::Color::from(color)));
+    }
+    column![
+      text(format!("Pattern table {}, tile {} (${:02X})", pattern_table_id, tile_index, tile_index)),
+      text(format!("PPU address: ${:04X}-${:04X}", tile_addr, tile_addr + 15)),
+      swatches,
+    ].into()
+  }
+
+  // One line per sprite, in OAM order
+  fn oam_view(&self) -> Element<'_, EmulatorMessage> {
+    let ppu = self.cpu.bus.ppu();
+    let mut sprites_column = column![];
+    for index in 0..64u8 {
+      let [y, tile_id, attributes, x] = [0, 1, 2, 3].map(|offset| ppu.read_from_oam_memory(index * 4 + offset));
+      sprites_column = sprites_column.push(text(format!("#{:02}: ({}, {}) tile ${:02X} attributes ${:02X}", index, x, y, tile_id, attributes)).size(18));
+    }
+    column![
+      row![text("OAM:").size(20), button("Export CSV").on_press(EmulatorMessage::ExportOam)].spacing(10),
+      scrollable(sprites_column).height(Length::Units(150)),
+    ].into()
+  }
+
+  fn emulate_frame(&mut self) -> Result<(), BusError> {
+    if (self.reset_message_frames_left > 0) {
+      self.reset_message_frames_left -= 1;
+    }
+    let input_byte = self.input_handler.get_input_byte();
+    self.cpu.bus.controller_mut().emulator_input[0] = input_byte;
+
+    let nmi_count = self.cpu.nmi_count;
+    self.clock_cycle()?;
+    while (!self.cpu.bus.ppu().frame_render_complete){
+      // The rest of the frame runs once emulation is resumed
+      if (self.stopped_at_nmi(nmi_count)) {
+        return Ok(());
       }
+      self.clock_cycle()?;
     }
-    if (self.cpu.bus.PPU.borrow().trigger_cpu_nmi) {
-      self.cpu.bus.PPU.borrow_mut().trigger_cpu_nmi = false;
-      self.cpu.nmi();
+    self.cpu.bus.ppu_mut().frame_render_complete = false;
+    self.frame_count += 1;
+    return Ok(());
+  }
+
+  // Saves the sprite attributes as <rom name>_oam_<frame>.csv, in the working directory
+  fn export_oam(&self) {
+    let rom_name = self.rom_path.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_path = PathBuf::from(format!("{}_oam_{}.csv", rom_name, self.frame_count));
+    match std::fs::write(&file_path, self.cpu.bus.ppu().dump_oam_as_csv()) {
+      Ok(()) => log::info!("Saved OAM to {}", file_path.display()),
+      Err(error) => show_error_dialog(&format!("Could not save {}: {}", file_path.display(), error)),
+    }
+  }
+
+  // Points the visualizers at the current PPU's buffers, after the console was swapped for another one
+  fn connect_visualizers(&mut self) {
+    self.ppu_screen_buffer_visualizer.screen_vis_buffer = self.cpu.bus.ppu().screen_vis_buffer.share();
+    self.ppu_pattern_tables_buffer_visualizer.pattern_tables_vis_buffer = self.cpu.bus.ppu().pattern_tables_vis_buffer.share();
+    self.ppu_screen_buffer_visualizer.canvas_cache.clear();
+    self.ppu_pattern_tables_buffer_visualizer.canvas_cache.clear();
+  }
+
+  // Emulates n whole frames back to back (e.g. for benchmarks), only refreshing the visualizers once they are all done
+  pub fn run_frames(&mut self, n: u32) {
+    let start_render_time = Instant::now();
+    let nmi_count = self.cpu.nmi_count;
+    for _ in 0..n {
+      if let Err(error) = self.emulate_frame() {
+        self.stop_on_emulation_error(error);
+        break;
+      }
+      if (self.stopped_at_nmi(nmi_count)) {
+        break;
+      }
+    }
+    log::info!("Emulating {} frame(s) took {}ms", n, start_render_time.elapsed().as_millis());
+    // So that not much progress is lost if the emulator doesn't get to exit cleanly
+    if (self.frame_count % BATTERY_SAVE_INTERVAL_FRAMES < n as u64) {
+      self.save_battery_ram();
+    }
+    let palette_id = self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id;
+    let (ppu, cartridge) = self.cpu.bus.ppu_and_cartridge();
+    ppu.update_pattern_tables_vis_buffer(palette_id, cartridge);
+    ppu.update_name_tables_vis_buffer(cartridge);
+
+    // The visualizers draw straight from the PPU buffers, so we only need to invalidate their caches
+    self.ppu_screen_buffer_visualizer.canvas_cache.clear();
+    self.ppu_pattern_tables_buffer_visualizer.canvas_cache.clear();
+  }
+
+  fn select_pause_menu_item(&mut self, item: PauseMenuItem) -> Command<EmulatorMessage> {
+    self.show_menu = false;
+    match item {
+      PauseMenuItem::Resume => {
+        self.paused = false;
+      },
+      PauseMenuItem::SaveState(slot) => {
+        self.save_states[slot as usize] = Some(SaveState { cpu: self.cpu.clone(), current_cycle: self.current_cycle, frame_count: self.frame_count });
+        log::info!("Saved state to slot {}", slot + 1);
+        // So that other slots can be saved or loaded right away
+        self.show_menu = true;
+      },
+      PauseMenuItem::LoadState(slot) => {
+        let Some(save_state) = self.save_states[slot as usize].clone() else {
+          self.show_menu = true;
+          return Command::none();
+        };
+        self.cpu = save_state.cpu;
+        self.connect_visualizers();
+        self.current_cycle = save_state.current_cycle;
+        self.frame_count = save_state.frame_count;
+        self.paused = false;
+      },
+      PauseMenuItem::Reset => {
+        console::soft_reset(&mut self.cpu);
+        self.reset_message_frames_left = RESET_MESSAGE_FRAMES;
+        self.paused = false;
+      },
+      PauseMenuItem::OpenRom => {
+        return self.update(EmulatorMessage::OpenRomDialog);
+      },
+      // The audio settings are in the debug panels
+      PauseMenuItem::Settings => {
+        self.show_debug_panels = true;
+      },
+      PauseMenuItem::Quit => {
+        self.save_battery_ram();
+        return window::close();
+      },
+    }
+    return Command::none();
+  }
+
+  // The mixer settings live in the APU, so they're copied into the config before saving it
+  fn save_config(&mut self) {
+    self.config.mixer = self.cpu.bus.apu().mixer_settings.clone();
+    if let Err(error) = self.config.save(&EmulatorConfig::default_path()) {
+      log::warn!("{}", error);
     }
-    self.current_cycle += 1;
   }
 
 }
@@ -108,8 +593,40 @@ enum EmulatorMessage {
   NextCPUInstruction,
   NextFrame,
   Run50CPUInstructions,
+  SoftReset,
+  ToggleMute,
+  ToggleDebugPanels,
+  SetMasterVolume(f32),
+  ToggleWavRecording,
+  SetChannelMuted(Channel, bool),
+  SoloChannel(Channel),
+  SetLinearMixing(bool),
+  SetOutputFiltersEnabled(bool),
+  // Pattern table id and tile index under the mouse in the pattern table visualizer
+  HoverTile(u8, u8),
+  ClearTileHover,
+  ExportOam,
+  ToggleTileGrid,
+  ToggleSpriteOutlines,
+  ToggleScanlineIndicator,
+  ToggleFps,
+  SetPauseOnNmi(bool),
+  ClearHeatmap,
+  WatchInputChanged(String),
+  AddWatch,
+  RemoveWatch(usize),
+  SetWatchFormat(usize, DisplayFormat),
+  // Hex address typed into the memory visualizer's cursor input
+  MemoryCursorInputChanged(String),
+  MoveMemoryCursor(i16),
+  // Overrides the region from the header, for ROMs that have the wrong one
+  SetRegion(Region),
+  ShowMenu(bool),
+  PauseMenu(PauseMenuItem),
 
   PatternTablePaletteCycle,
+  OpenRomDialog,
+  LoadRom(PathBuf),
   EventOccurred(iced_native::Event),
 }
 
@@ -119,32 +636,91 @@ impl Application for RustNESs {
 
   type Theme = Theme;
   
-  type Flags = ();
+  // Path of the ROM to load on startup
+  type Flags = Option<PathBuf>;
 
   fn new(flags: Self::Flags) -> (RustNESs, iced::Command<EmulatorMessage>) {
-    let args: Vec<String> = env::args().collect();
-    let rom_file_path = args.get(1).unwrap();
+    // Without a ROM there's nothing to emulate, so we keep asking until we get a valid one or the user gives up
+    let mut rom_path = flags.or_else(pick_rom_file);
+    let config = EmulatorConfig::load(&EmulatorConfig::default_path());
+    let mut cpu_bus = loop {
+      let Some(path) = rom_path else {
+        std::process::exit(0);
+      };
+      match Bus16Bit::from_rom_file(&path.to_string_lossy(), config.ram_init_mode) {
+        Ok(cpu_bus) => {
+          rom_path = Some(path);
+          break cpu_bus;
+        },
+        Err(error) => {
+          show_error_dialog(&error);
+          rom_path = pick_rom_file();
+        }
+      }
+    };
+    let rom_path = rom_path.unwrap();
 
+    let mut recent_roms = RecentRoms::load(Path::new(recent_roms::RECENT_ROMS_FILE_PATH));
+    recent_roms.add(&rom_path);
+    if let Err(error) = recent_roms.save() {
+      log::warn!("{}", error);
+    }
 
-    let mut cpu_bus = Bus16Bit::new(rom_file_path);
+    cpu_bus.apu_mut().mixer_settings = config.mixer.clone();
+    load_battery_ram(&mut cpu_bus, &config, &rom_path);
 
-    // cpu_bus.write(PROGRAM_START_POINTER_ADDR, 0x00).unwrap();
-    // cpu_bus.write(PROGRAM_START_POINTER_ADDR + 1, 0x80).unwrap();
+    // cpu_bus.write_word_little_endian(PROGRAM_START_POINTER_ADDR, 0x8000).unwrap();
     
+    // The emulator keeps running without sound if there's no usable audio device
+    let audio_output = match AudioOutput::new() {
+      Ok(audio_output) => {
+        cpu_bus.apu_mut().set_output_sample_rate(audio_output.sample_rate);
+        Some(audio_output)
+      },
+      Err(error) => {
+        log::warn!("Could not start audio output: {}", error);
+        None
+      }
+    };
+
+    let screen_vis_buffer = cpu_bus.ppu().screen_vis_buffer.share();
+    let pattern_tables_vis_buffer = cpu_bus.ppu().pattern_tables_vis_buffer.share();
     let cpu: Ben6502 = Ben6502::new(cpu_bus);
+    let frames_per_second = console::frames_per_second(&cpu);
     return (Self { 
               cpu,
               current_cycle: 0,
+              frame_count: 0,
               paused: true,
-              cycles_per_second: EMULATOR_FRAMES_PER_SECONDD,
+              pause_on_nmi: false,
+              frames_per_second,
+              reset_message_frames_left: 0,
+              show_debug_panels: true,
+              rom_path,
+              file_hovered: false,
+              show_menu: false,
+              save_states: Default::default(),
               input_handler: NESInputHandler::new(),
+              recent_roms,
+              config,
+              audio_output,
+              rate_control: DynamicRateControl::new(),
+              frame_timing: FrameTimingHistory::new(),
               ppu_screen_buffer_visualizer: PPUScreenBufferVisualizer {
-                screen_vis_buffer: [[graphics::Color::new(0, 0, 0); 256]; 240],
+                screen_vis_buffer,
                 canvas_cache: Cache::default(),
-                pixel_height: f32::from(SCREEN_HEIGHT) / 240.0
+                pixel_height: f32::from(SCREEN_HEIGHT) / 240.0,
+                show_tile_grid: false,
+                show_sprite_outlines: false,
+                show_scanline_indicator: false,
+                show_fps: false,
+                sprite_boxes: vec![],
+                scan_line: 0,
+                fps_label: String::new(),
+                pause_menu: None,
               },
               ppu_pattern_tables_buffer_visualizer: PPUPatternTableBufferVisualizer {
-                pattern_tables_vis_buffer: [[[graphics::Color::new(0, 0, 0); 128]; 128]; 2],
+                pattern_tables_vis_buffer,
                 pattern_table_vis_palette_id: 0,
                 canvas_cache: Cache::default(),
                 pixel_height: f32::from(PATTERN_TABLE_VIS_HEIGHT) / 128.0
@@ -154,6 +730,20 @@ impl Application for RustNESs {
                 canvas_cache: Cache::default(),
                 pixel_height: f32::from(PALETTE_VIS_WIDTH) / 32.0
               },
+              apu_debug_state: ApuDebugState::default(),
+              prg_banks: vec![],
+              hovered_tile: None,
+              heatmap_visualizer: HeatmapVisualizer {
+                colors: vec![],
+                canvas_cache: Cache::default(),
+                pixel_height: f32::from(HEATMAP_VIS_SIZE) / heatmap::HEATMAP_SIZE as f32,
+              },
+              watch_panel: WatchPanel {
+                expressions: watch::default_watch_expressions(),
+                values: vec![],
+                input: String::new(),
+                input_error: None,
+              },
               mem_visualizer: MemoryVisualizer {
                 ram_start_addr: 0x00, //0xC0,
                 ram_end_addr: 0x100,
@@ -161,9 +751,12 @@ impl Application for RustNESs {
                 pc_end_addr: 0x8010,
                 stack_start_addr: 0x100 + ben6502::SP_RESET_ADDR as u16 - 100,
                 stack_end_addr: 0x100 + ben6502::SP_RESET_ADDR as u16,
+                cursor_addr: 0x0000,
+                cursor_input: String::new(),
+                cursor_status_str: String::new(),
 
                 ram_content_str: String::from(""),
-                program_content_str: String::from(""),
+                ppu_registers_str: String::from(""),
                 program_content: vec![],
                 stack_content_str: String::from(""),
               }
@@ -172,8 +765,14 @@ impl Application for RustNESs {
     );
   }
 
+  // e.g. "RustNESs NES Emulator of whimsy! - Super Mario Bros. — Mapper 0 (NROM), 32KB PRG, 8KB CHR, vertical mirroring, NTSC"
   fn title(&self) -> String {
-    return String::from("RustNESs NES Emulator of whimsy!");
+    let rom_name = self.rom_path.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let title = format!("RustNESs NES Emulator of whimsy! - {} \u{2014} {}", rom_name, self.cpu.bus.cartridge().info().summary());
+    if self.cpu.bus.apu().is_recording_wav() {
+      return format!("{} [Recording audio]", title);
+    }
+    return title;
   }
 
   fn update(&mut self, message: Self::Message) -> iced::Command<EmulatorMessage> {
@@ -181,67 +780,227 @@ impl Application for RustNESs {
     match message {
         EmulatorMessage::TogglePauseEmulation => {
           self.paused = !self.paused;
+          self.frame_timing.clear();
         },
         EmulatorMessage::NextCPUInstruction => {
-          self.clock_cycle();
-          while (self.cpu.current_instruction_remaining_cycles > 0){
-            self.clock_cycle();
+          if let Err(error) = self.step_instruction() {
+            self.stop_on_emulation_error(error);
           }
         },
 
         EmulatorMessage::Run50CPUInstructions => {
+          let nmi_count = self.cpu.nmi_count;
           for i in 0..500 {
-            self.clock_cycle();
-            while (self.cpu.current_instruction_remaining_cycles > 0){
-              self.clock_cycle();
+            if let Err(error) = self.step_instruction() {
+              self.stop_on_emulation_error(error);
+              break;
+            }
+            if (self.stopped_at_nmi(nmi_count)) {
+              break;
             }
           }
         },
-        EmulatorMessage::NextFrame => {
-          let input_byte = self.input_handler.get_input_byte();
-          self.cpu.bus.controller.borrow_mut().emulator_input[0] = input_byte;
-
-          let start_render_time = Instant::now();
-
-          self.clock_cycle();
-          while (!self.cpu.bus.PPU.borrow().frame_render_complete){
-            self.clock_cycle();
+        EmulatorMessage::ShowMenu(show_menu) => {
+          self.show_menu = show_menu;
+          if (show_menu) {
+            self.paused = true;
           }
-
-          // println!("Frame render took {}ms", start_render_time.elapsed().as_millis());
-          self.cpu.bus.PPU.borrow_mut().frame_render_complete = false;
-          self.cpu.bus.PPU.borrow_mut().update_pattern_tables_vis_buffer(self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id);
-
+        },
+        EmulatorMessage::PauseMenu(item) => {
+          return self.select_pause_menu_item(item);
+        },
+        EmulatorMessage::SoftReset => {
+          console::soft_reset(&mut self.cpu);
+          self.reset_message_frames_left = RESET_MESSAGE_FRAMES;
+        },
+        EmulatorMessage::ToggleMute => {
+          let muted = self.cpu.bus.apu().mixer_settings.muted;
+          self.cpu.bus.apu_mut().mixer_settings.muted = !muted;
+          self.save_config();
+        },
+        EmulatorMessage::ToggleDebugPanels => {
+          self.show_debug_panels = !self.show_debug_panels;
+        },
+        EmulatorMessage::SetLinearMixing(linear_mixing) => {
+          self.cpu.bus.apu_mut().mixer_settings.linear_mixing = linear_mixing;
+          self.save_config();
+        },
+        EmulatorMessage::SetOutputFiltersEnabled(enabled) => {
+          self.cpu.bus.apu_mut().mixer_settings.output_filters_enabled = enabled;
+          self.save_config();
+        },
+        EmulatorMessage::SetMasterVolume(volume) => {
+          self.cpu.bus.apu_mut().mixer_settings.master_volume = volume;
+          self.save_config();
+        },
+        EmulatorMessage::ToggleWavRecording => {
+          let apu = self.cpu.bus.apu_mut();
+          let result = if apu.is_recording_wav() {
+            apu.stop_wav_recording()
+          } else {
+            let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+            let file_path = PathBuf::from(format!("recording_{}.wav", timestamp));
+            log::info!("Recording audio to {}", file_path.display());
+            apu.start_wav_recording(&file_path)
+          };
+          if let Err(error) = result {
+            log::error!("{}", error);
+          }
+        },
+        EmulatorMessage::SetChannelMuted(channel, muted) => {
+          self.cpu.bus.apu_mut().set_channel_muted(channel, muted);
+          self.save_config();
+        },
+        EmulatorMessage::SoloChannel(channel) => {
+          self.cpu.bus.apu_mut().solo(channel);
+          self.save_config();
+        },
+        EmulatorMessage::NextFrame => {
+          self.run_frames(1);
+          self.frame_timing.record_frame(Instant::now());
         },
         EmulatorMessage::PatternTablePaletteCycle => {
           self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id += 1;
           if self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id > 7 {
             self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id = 0;
           }
+          self.ppu_pattern_tables_buffer_visualizer.canvas_cache.clear();
+        },
+        EmulatorMessage::HoverTile(pattern_table_id, tile_index) => {
+          self.hovered_tile = Some((pattern_table_id, tile_index));
+        },
+        EmulatorMessage::ClearTileHover => {
+          self.hovered_tile = None;
+        },
+        EmulatorMessage::ExportOam => {
+          self.export_oam();
+        },
+        EmulatorMessage::ToggleTileGrid => {
+          self.ppu_screen_buffer_visualizer.show_tile_grid = !self.ppu_screen_buffer_visualizer.show_tile_grid;
+        },
+        EmulatorMessage::ToggleSpriteOutlines => {
+          self.ppu_screen_buffer_visualizer.show_sprite_outlines = !self.ppu_screen_buffer_visualizer.show_sprite_outlines;
+        },
+        EmulatorMessage::SetPauseOnNmi(pause_on_nmi) => {
+          self.pause_on_nmi = pause_on_nmi;
+        },
+        EmulatorMessage::ClearHeatmap => {
+          self.cpu.bus.clear_access_counts();
+        },
+        EmulatorMessage::WatchInputChanged(input) => {
+          self.watch_panel.input = input;
+          self.watch_panel.input_error = None;
+        },
+        EmulatorMessage::AddWatch => {
+          self.watch_panel.add_expression_from_input();
+        },
+        EmulatorMessage::RemoveWatch(index) => {
+          self.watch_panel.expressions.remove(index);
+        },
+        EmulatorMessage::SetWatchFormat(index, format) => {
+          self.watch_panel.expressions[index].format = format;
+        },
+        EmulatorMessage::MemoryCursorInputChanged(input) => {
+          if let Ok(addr) = u16::from_str_radix(input.trim_start_matches('$'), 16) {
+            self.mem_visualizer.cursor_addr = addr;
+          }
+          self.mem_visualizer.cursor_input = input;
+        },
+        EmulatorMessage::SetRegion(region) => {
+          self.cpu.bus.set_region(region);
+          self.frames_per_second = console::frames_per_second(&self.cpu);
+        },
+        EmulatorMessage::MoveMemoryCursor(offset) => {
+          self.mem_visualizer.cursor_addr = self.mem_visualizer.cursor_addr.wrapping_add_signed(offset);
+          self.mem_visualizer.cursor_input = format!("{:04X}", self.mem_visualizer.cursor_addr);
+        },
+        EmulatorMessage::ToggleFps => {
+          self.ppu_screen_buffer_visualizer.show_fps = !self.ppu_screen_buffer_visualizer.show_fps;
+        },
+        EmulatorMessage::ToggleScanlineIndicator => {
+          self.ppu_screen_buffer_visualizer.show_scanline_indicator = !self.ppu_screen_buffer_visualizer.show_scanline_indicator;
+        },
+        EmulatorMessage::OpenRomDialog => {
+          if let Some(rom_path) = pick_rom_file() {
+            return self.update(EmulatorMessage::LoadRom(rom_path));
+          }
+        },
+        EmulatorMessage::LoadRom(rom_path) => {
+          if let Err(error) = self.load_rom(&rom_path) {
+            show_error_dialog(&error);
+          }
         },
 
         EmulatorMessage::EventOccurred(event) => {
           match event {
             Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::Space, modifiers }) => {
-              // println!("Spacebar (For run 1 cpu instruction) pressed!");
+              log::debug!("Spacebar (For run 1 cpu instruction) pressed!");
               self.update(EmulatorMessage::NextCPUInstruction);
             },
             Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::Key5, modifiers }) => {
-              println!("Key5(For run 50 cpu instructions) pressed!");
+              log::debug!("Key5(For run 50 cpu instructions) pressed!");
               self.update(EmulatorMessage::Run50CPUInstructions);
             },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::F, modifiers }) if modifiers.shift() => {
+              log::debug!("Shift+F(toggle fps counter) pressed!");
+              self.update(EmulatorMessage::ToggleFps);
+            },
             Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::F, modifiers }) => {
-              println!("F(For next Frame) pressed!");
+              log::debug!("F(For next Frame) pressed!");
               self.update(EmulatorMessage::NextFrame);
             },
             Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::P, modifiers }) => {
-              println!("P(cycle palette color) pressed!");
+              log::debug!("P(cycle palette color) pressed!");
               self.update(EmulatorMessage::PatternTablePaletteCycle);
             },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::G, modifiers }) => {
+              log::debug!("G(toggle tile grid) pressed!");
+              self.update(EmulatorMessage::ToggleTileGrid);
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::O, modifiers }) => {
+              log::debug!("O(toggle sprite outlines) pressed!");
+              self.update(EmulatorMessage::ToggleSpriteOutlines);
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::L, modifiers }) => {
+              log::debug!("L(toggle scanline indicator) pressed!");
+              self.update(EmulatorMessage::ToggleScanlineIndicator);
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::Tab, modifiers }) => {
+              self.update(EmulatorMessage::ToggleDebugPanels);
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::F9, modifiers }) => {
+              log::debug!("F9(toggle audio recording) pressed!");
+              self.update(EmulatorMessage::ToggleWavRecording);
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::R, modifiers }) => {
+              log::debug!("R(soft reset) pressed!");
+              self.update(EmulatorMessage::SoftReset);
+            },
             Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::Enter, modifiers }) => {
-              println!("Enter(play/pause emulation) pressed!");
+              log::debug!("Enter(play/pause emulation) pressed!");
               self.update(EmulatorMessage::TogglePauseEmulation);
             },
+            Event::Keyboard(keyboard::Event::KeyReleased { key_code: KeyCode::Escape, modifiers }) => {
+              log::debug!("Escape(pause menu) pressed!");
+              self.update(EmulatorMessage::ShowMenu(!self.show_menu));
+            },
+            Event::Window(window::Event::CloseRequested) => {
+              self.save_battery_ram();
+              return window::close();
+            },
+            Event::Window(window::Event::FileHovered(_)) => {
+              self.file_hovered = true;
+            },
+            Event::Window(window::Event::FilesHoveredLeft) => {
+              self.file_hovered = false;
+            },
+            Event::Window(window::Event::FileDropped(path)) => {
+              self.file_hovered = false;
+              if is_nes_file(&path) {
+                return self.update(EmulatorMessage::LoadRom(path));
+              }
+              show_error_dialog(&format!("Only .nes ROM files can be loaded ({} was dropped)", path.display()));
+            },
             _ => {
               self.input_handler.handle_keyboard_input(event);
             }
@@ -249,78 +1008,99 @@ impl Application for RustNESs {
       }
     }
     self.mem_visualizer.update(&mut self.cpu);
+    self.watch_panel.update(&self.cpu.bus);
 
-    self.cpu.bus.PPU.borrow_mut().update_pattern_tables_vis_buffer(self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id);
-    self.ppu_screen_buffer_visualizer.update_data(&self.cpu.bus.PPU.borrow_mut());
-    self.ppu_pattern_tables_buffer_visualizer.update_data(&self.cpu.bus.PPU.borrow_mut());
-    self.ppu_palette_visualizer.update_data(&self.cpu.bus.PPU.borrow_mut());
+    let audio_samples = self.cpu.bus.apu_mut().take_samples();
+    if let Some(audio_output) = self.audio_output.as_mut() {
+      audio_output.queue_samples(&audio_samples);
+      let rate_adjustment = self.rate_control.update(audio_output.fill_level());
+      self.cpu.bus.apu_mut().set_rate_adjustment(rate_adjustment);
+    }
+
+    let palette_id = self.ppu_pattern_tables_buffer_visualizer.pattern_table_vis_palette_id;
+    let (ppu, cartridge) = self.cpu.bus.ppu_and_cartridge();
+    ppu.update_pattern_tables_vis_buffer(palette_id, cartridge);
+    self.ppu_palette_visualizer.update_data(self.cpu.bus.ppu());
+    self.ppu_screen_buffer_visualizer.sprite_boxes = self.cpu.bus.ppu().sprite_bounding_boxes();
+    self.ppu_screen_buffer_visualizer.scan_line = self.cpu.bus.ppu().get_debug_state().scan_line;
+    self.ppu_screen_buffer_visualizer.fps_label = self.frame_timing.label();
+    self.ppu_screen_buffer_visualizer.pause_menu = if self.show_menu { Some(self.save_states.each_ref().map(Option::is_some)) } else { None };
+    self.apu_debug_state = self.cpu.bus.apu().debug_snapshot();
+    self.prg_banks = self.cpu.bus.cartridge_prg_banks();
+    // Going through the whole address space isn't free, so it's only done while the panel is visible
+    if (self.show_debug_panels) {
+      self.heatmap_visualizer.update_data(&self.cpu.bus);
+    }
     Command::none()
     
   }
 
   fn view(&self) -> Element<'_, Self::Message> {
-    column![
-      // Contains screen visualizer and PPU buffer visualizers
-      row![
-
+    // Toolbar
+    let toolbar = row![
+      pick_list(
+        self.recent_roms.entries(),
+        None,
+        |entry: recent_roms::RecentRomEntry| EmulatorMessage::LoadRom(entry.0)
+      ).placeholder("Recent ROMs"),
+      button("Open ROM...").on_press(EmulatorMessage::OpenRomDialog),
+      button(if self.cpu.bus.apu().mixer_settings.muted { "Unmute" } else { "Mute" }).on_press(EmulatorMessage::ToggleMute),
+      text(" Volume:"),
+      slider(0.0..=1.0, self.cpu.bus.apu().mixer_settings.master_volume, EmulatorMessage::SetMasterVolume).step(0.05).width(Length::Units(150)),
+      button(if self.show_debug_panels { "Hide debug panels" } else { "Show debug panels" }).on_press(EmulatorMessage::ToggleDebugPanels),
+      text(if self.reset_message_frames_left > 0 { " RESET" } else { "" }).size(20),
+    ];
+
+    // Contains screen visualizer and PPU buffer visualizers
+    let mut screen_row = row![
       self.ppu_screen_buffer_visualizer.view(),
-      self.ppu_pattern_tables_buffer_visualizer.view(),
-      self.ppu_palette_visualizer.view(),
-      ],
-
-      // Contains Memory visualizer and CPU+PPU status visualizers  
-      row![
+    ];
+    if (self.show_debug_panels) {
+      screen_row = screen_row
+        .push(column![self.ppu_pattern_tables_buffer_visualizer.view(), self.hovered_tile_view()])
+        .push(self.ppu_palette_visualizer.view());
+    }
 
+    let mut content = column![
+      toolbar,
+      screen_row,
+    ];
 
-        // MemoryVisualizer
+    // Contains Memory visualizer and CPU+PPU status visualizers
+    if (self.show_debug_panels) {
+      content = content.push(row![
         self.mem_visualizer.view(),
+        column![self.status_view(), self.call_stack_view(), self.rom_info_view(), self.mapper_banks_view(), self.oam_view()],
+        column![self.heatmap_view(), self.watch_panel.view()],
+      ]);
+      content = content
+        .push(self.apu_status_view())
+        .push(self.audio_channels_view());
+    }
 
-        // StatusVisualizer
-        column![
-          row![
-            text("Cpu registers:").size(20),
-            text(format!(" A: 0x{:X}", self.cpu.registers.a)),
-            text(format!(" X: 0x{:X}", self.cpu.registers.x)),
-            text(format!(" Y: 0x{:X}", self.cpu.registers.y)),
-            text(format!(" PC: 0x{:X}", self.cpu.registers.pc)),
-            text(format!("SP: 0x{:X}", self.cpu.registers.sp)),
-          ],
-          row![
-            text("Cpu flags:").size(20),
-            text("Carry: "),
-            text(self.cpu.status.get_carry().to_string()),
-            text("Zero: "),
-            text(self.cpu.status.get_zero().to_string()),
-            text("Negative: "),
-            text(self.cpu.status.get_negative().to_string()),
-            text("overflow: "),
-            text(self.cpu.status.get_overflow().to_string()),
-            text("Decimal mode: "),
-            text(self.cpu.status.get_decimal_mode().to_string()),
-            text("BRK command: "),
-            text(self.cpu.status.get_brk_command().to_string()),
-            text("IRQ Disable: "),
-            text(self.cpu.status.get_irq_disable().to_string())
-          ],
-
-          row![
-            text("PPU flags:").size(20),
-            text("Vertical Blank: "),
-            text(self.cpu.bus.PPU.borrow().status_reg.get_vertical_blank().to_string()),
-          ],
-        ]
-      ]
-    ]
-    .padding(20)
-    .align_items(Alignment::Center)
-    .into()
+    if (self.file_hovered) {
+      content = column![text("Drop the ROM to load it").size(30), content].align_items(Alignment::Center);
+    }
+
+    let content = content
+      .padding(20)
+      .align_items(Alignment::Center);
+    let mut window_container = container(content);
+    if (self.file_hovered) {
+      window_container = window_container.style(iced::theme::Container::Custom(Box::new(DropZoneStyle)));
+    }
+    window_container.into()
   }
 
   fn subscription(&self) -> Subscription<EmulatorMessage> {
     let mut subs = vec![];
-    subs.push(iced_native::subscription::events().map(EmulatorMessage::EventOccurred));
+    // Keys typed into the watch panel's input are neither shortcuts nor controller input
+    subs.push(iced_native::subscription::events_with(|event, status| match (&event, status) {
+      (Event::Keyboard(_), event::Status::Captured) => None,
+      _ => Some(EmulatorMessage::EventOccurred(event)),
+    }));
     if !self.paused {
-      subs.push(iced::time::every(time::Duration::from_millis(1000 / self.cycles_per_second)).map(|em| {EmulatorMessage::NextFrame}));
+      subs.push(iced::time::every(time::Duration::from_secs_f64(1.0 / self.frames_per_second)).map(|em| {EmulatorMessage::NextFrame}));
     }
     return Subscription::batch(subs);
   }
@@ -334,11 +1114,56 @@ struct MemoryVisualizer {
   pc_end_addr: u16,
   stack_start_addr: u16,
   stack_end_addr: u16,
+  // Address whose contents are shown in the status bar, along with its name if it has a well known one
+  cursor_addr: u16,
+  cursor_input: String,
 
   ram_content_str: String,
-  program_content_str: String,
-  program_content: Vec<u8>,
-  stack_content_str: String
+  ppu_registers_str: String,
+  // Disassembled instructions starting at PC, as (address, line) pairs
+  program_content: Vec<(u16, String)>,
+  stack_content_str: String,
+  cursor_status_str: String
+}
+
+// Names for the addresses that come up the most when looking at NES memory
+static KNOWN_NES_ADDRESSES: &[(u16, &str)] = &[
+  (0x0000, "ZP:0"),
+  (0x0100, "STACK"),
+  (0x2000, "PPU_CTRL"),
+  (0x2001, "PPU_MASK"),
+  (0x2002, "PPU_STATUS"),
+  (0x2003, "OAM_ADDR"),
+  (0x4000, "APU_SQ1"),
+  (0x4014, "OAMDMA"),
+  (0x4016, "JOY1"),
+  (0x8000, "PRG_START"),
+  (0xFFFA, "NMI_VEC"),
+  (0xFFFC, "RESET_VEC"),
+  (0xFFFE, "IRQ_VEC"),
+];
+
+fn known_address_name(addr: u16) -> Option<&'static str> {
+  return KNOWN_NES_ADDRESSES.iter().find(|(known_addr, _)| *known_addr == addr).map(|(_, name)| *name);
+}
+
+// e.g. "$FFFC (RESET_VEC): $00 $C0". Two bytes are shown, since vectors and pointers take two.
+fn memory_cursor_status(addr: u16, bytes: [u8; 2]) -> String {
+  let name = known_address_name(addr).map(|name| format!(" ({})", name)).unwrap_or_default();
+  return format!("${:04X}{}: ${:02X} ${:02X}", addr, name, bytes[0], bytes[1]);
+}
+
+const PPU_REGISTERS_START_ADDR: u16 = 0x2000;
+const STACK_WINDOW_BELOW_SP: u16 = 40;
+const STACK_WINDOW_ABOVE_SP: u16 = 4;
+
+// Range of addresses (end exclusive) around the stack pointer shown by the memory visualizer.
+// It's clamped to the stack page ($0100-$01FF), so that it never wanders into other memory when SP is near either end.
+fn stack_window_bounds(sp: u8) -> (u16, u16) {
+  let sp_addr = ben6502::STACK_START_ADDR + sp as u16;
+  let start_addr = std::cmp::max(ben6502::STACK_START_ADDR, sp_addr.saturating_sub(STACK_WINDOW_BELOW_SP));
+  let end_addr = std::cmp::min(ben6502::STACK_START_ADDR + 0x100, sp_addr + STACK_WINDOW_ABOVE_SP);
+  return (start_addr, end_addr);
 }
 
 impl MemoryVisualizer {
@@ -351,36 +1176,44 @@ impl MemoryVisualizer {
       self.pc_end_addr = self.pc_start_addr;
     }
 
-    self.stack_start_addr = ben6502::STACK_START_ADDR + cpu.registers.sp as u16 - 40;
-    self.stack_end_addr = ben6502::STACK_START_ADDR + cpu.registers.sp as u16 + 4;
-
-
-    if ((self.pc_start_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.pc_start_addr <= ben2C02::PPU_MEMORY_BOUNDS.1) ||
-        (self.pc_end_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.pc_end_addr <= ben2C02::PPU_MEMORY_BOUNDS.1) ||
-        (self.stack_start_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.stack_start_addr <= ben2C02::PPU_MEMORY_BOUNDS.1) ||
-        (self.stack_end_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.stack_end_addr <= ben2C02::PPU_MEMORY_BOUNDS.1) ||
-        (self.ram_start_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.ram_start_addr <= ben2C02::PPU_MEMORY_BOUNDS.1) ||
-        (self.ram_end_addr >= ben2C02::PPU_MEMORY_BOUNDS.0 && self.ram_end_addr <= ben2C02::PPU_MEMORY_BOUNDS.1)) {
-          panic!("Memory visualizer is reading from PPU memory bounds, which might alter the state of the emulation!");
-        }
+    (self.stack_start_addr, self.stack_end_addr) = stack_window_bounds(cpu.registers.sp);
 
+    // All of these use side effect free reads, so it's fine if the ranges include memory mapped registers
     self.ram_content_str = cpu.bus.get_memory_content_as_string(self.ram_start_addr, self.ram_end_addr);
-    self.program_content_str = cpu.bus.get_memory_content_as_string(self.pc_start_addr, self.pc_end_addr);
-    self.program_content = cpu.bus.get_memory_content_as_vec(self.pc_start_addr, self.pc_end_addr);
+    self.ppu_registers_str = cpu.bus.get_memory_content_as_string(PPU_REGISTERS_START_ADDR, PPU_REGISTERS_START_ADDR + 8);
+    self.program_content = ben6502::disassemble_from_bus(&cpu.bus, self.pc_start_addr, self.pc_end_addr);
     self.stack_content_str = cpu.bus.get_memory_content_as_string(self.stack_start_addr, self.stack_end_addr);    
+    self.cursor_status_str = memory_cursor_status(self.cursor_addr, [cpu.bus.read_debug(self.cursor_addr), cpu.bus.read_debug(self.cursor_addr.wrapping_add(1))]);
 
   }
 
   fn view<'a>(&self) -> Element<'a, EmulatorMessage> {
-  
+    // One row per instruction, with the one at PC highlighted
+    let mut program_column = column![];
+    for (addr, line) in self.program_content.iter() {
+      let mut line_text = text(line).size(18);
+      if (*addr == self.pc_start_addr) {
+        line_text = line_text.style(Color::from([0.0, 0.0, 1.0]));
+      }
+      program_column = program_column.push(line_text);
+    }
+
     column![
       text(format!("RAM contents (Addr 0x{:x} - 0x{:x}):", self.ram_start_addr, self.ram_end_addr-1)),
       text(&self.ram_content_str).size(20),
-      text(format!("RAM contents  at PC (Addr 0x{:x} - 0x{:x}):", self.pc_start_addr, self.pc_end_addr-1)),
-      text(&self.program_content_str).size(20),
-      text(ben6502::disassemble(&self.program_content)).size(18).style(Color::from([0.0, 0.0, 1.0])),
+      text(format!("PPU registers (Addr 0x{:x} - 0x{:x}):", PPU_REGISTERS_START_ADDR, PPU_REGISTERS_START_ADDR + 7)),
+      text(&self.ppu_registers_str).size(20),
+      text(format!("Program at PC (Addr 0x{:x} - 0x{:x}):", self.pc_start_addr, self.pc_end_addr-1)),
+      program_column,
       text(format!("Stack contents (Addr 0x{:x} - 0x{:x}):", self.stack_start_addr, self.stack_end_addr-1)),
-      text(&self.stack_content_str).size(20)
+      text(&self.stack_content_str).size(20),
+      row![
+        text("Cursor:"),
+        button("<").on_press(EmulatorMessage::MoveMemoryCursor(-1)),
+        text_input("Address", &self.cursor_input, EmulatorMessage::MemoryCursorInputChanged).width(Length::Units(80)),
+        button(">").on_press(EmulatorMessage::MoveMemoryCursor(1)),
+        text(&self.cursor_status_str).size(20),
+      ].spacing(5).align_items(Alignment::Center),
     ]
     .max_width(500)
     .into()
@@ -388,10 +1221,69 @@ impl MemoryVisualizer {
 }
 
 
+struct WatchPanel {
+  expressions: Vec<WatchExpression>,
+  // Formatted values of the expressions, in the same order, refreshed after every update
+  values: Vec<String>,
+  input: String,
+  // Why the last expression typed couldn't be added
+  input_error: Option<String>,
+}
+
+impl WatchPanel {
+  fn update(&mut self, bus: &Bus16Bit) {
+    self.values = self.expressions.iter()
+      .map(|expression| expression.format_value(bus.read_debug(expression.address)))
+      .collect();
+  }
+
+  fn add_expression_from_input(&mut self) {
+    match WatchExpression::parse(&self.input) {
+      Ok(expression) => {
+        self.expressions.push(expression);
+        self.input.clear();
+      },
+      Err(error) => self.input_error = Some(error),
+    }
+  }
+
+  fn view(&self) -> Element<'_, EmulatorMessage> {
+    let mut expressions_column = column![];
+    for (index, (expression, value)) in self.expressions.iter().zip(self.values.iter()).enumerate() {
+      expressions_column = expressions_column.push(row![
+        text(format!("{} (${:04X}): {}", expression.name, expression.address, value)).size(18).width(Length::Units(260)),
+        pick_list(&DisplayFormat::ALL[..], Some(expression.format), move |format| EmulatorMessage::SetWatchFormat(index, format)),
+        button("x").on_press(EmulatorMessage::RemoveWatch(index)),
+      ].spacing(5).align_items(Alignment::Center));
+    }
+    column![
+      text("Watch:").size(20),
+      scrollable(expressions_column).height(Length::Units(150)),
+      text_input("name $address hex/dec/bin/signed", &self.input, EmulatorMessage::WatchInputChanged)
+        .on_submit(EmulatorMessage::AddWatch)
+        .width(Length::Units(HEATMAP_VIS_SIZE)),
+      text(self.input_error.as_deref().unwrap_or("")).style(Color::from([1.0, 0.0, 0.0])),
+    ].into()
+  }
+}
+
+
 struct PPUScreenBufferVisualizer {
-  screen_vis_buffer: [[graphics::Color; 256]; 240],
+  screen_vis_buffer: SharedVisBuffer<[[graphics::Color; 256]; 240]>,
   canvas_cache: Cache,
-  pixel_height: f32
+  pixel_height: f32,
+  // Debug overlays, drawn on top of the cached screen
+  show_tile_grid: bool,
+  show_sprite_outlines: bool,
+  show_scanline_indicator: bool,
+  // (x, y, height) of the sprites on screen, refreshed every frame
+  sprite_boxes: Vec<(u8, u8, u8)>,
+  // Scanline the PPU is on, which is only interesting while stepping through a frame
+  scan_line: i16,
+  show_fps: bool,
+  fps_label: String,
+  // Which save state slots are filled, while the pause menu is shown on top of the screen
+  pause_menu: Option<[bool; SAVE_STATE_SLOTS as usize]>,
 }
 
 impl PPUScreenBufferVisualizer {
@@ -402,24 +1294,27 @@ impl PPUScreenBufferVisualizer {
         .into()
   }
 
-  pub fn update_data(&mut self, ppu: &Ben2C02) {
-    // Every time we update, I'm copying the contents of the PPU buffer
-    // onto the buffer of the Screen Visualizer. This is awful, but I can't 
-    // figure out lifetimes well enough to directly reference the PPU buffer :/
-    // TODO: Reference PPU buffer directly
-    for i in 0..ppu.screen_vis_buffer.len() {
-      for j in 0..ppu.screen_vis_buffer[0].len() {
-        self.screen_vis_buffer[i][j] = ppu.screen_vis_buffer[i][j];
-      }
-    }
-    self.canvas_cache.clear();
-  }
 }
 
 
 impl canvas::Program<EmulatorMessage> for PPUScreenBufferVisualizer {
   type State = ();
 
+  fn update(
+      &self,
+      _state: &mut Self::State,
+      event: canvas::Event,
+      bounds: Rectangle,
+      cursor: Cursor,
+  ) -> (event::Status, Option<EmulatorMessage>) {
+    if let (Some(_), canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left))) = (self.pause_menu, event) {
+      if let Some(item) = cursor.position_in(&bounds).and_then(pause_menu_item_at) {
+        return (event::Status::Captured, Some(EmulatorMessage::PauseMenu(item)));
+      }
+    }
+    return (event::Status::Ignored, None);
+  }
+
   fn draw(
       &self,
       _state: &Self::State,
@@ -429,18 +1324,199 @@ impl canvas::Program<EmulatorMessage> for PPUScreenBufferVisualizer {
   ) -> Vec<Geometry> {
 
     let pixel_grid = self.canvas_cache.draw(bounds.size(), |frame| {
-      for i in 0..self.screen_vis_buffer.len() {
-        for j in 0..self.screen_vis_buffer[0].len() {
-          let pixel_color = self.screen_vis_buffer[i][j];
+      let screen_vis_buffer = self.screen_vis_buffer.lock().unwrap();
+      for i in 0..screen_vis_buffer.len() {
+        for j in 0..screen_vis_buffer[0].len() {
+          let pixel_color = screen_vis_buffer[i][j];
 
           frame.fill_rectangle(
               Point::new( (j as f32) * self.pixel_height as f32, (i as f32) * self.pixel_height as f32),
               Size::new(self.pixel_height, self.pixel_height),
-              pixel_color.to_iced_color(),
+              iced::Color::from(pixel_color),
           );
         }
       }
     });
+
+    let mut overlay = Frame::new(bounds.size());
+    if (self.show_tile_grid) {
+      let grid_stroke = Stroke::default().with_color(Color::from_rgba(1.0, 1.0, 1.0, 0.3)).with_width(1.0);
+      for x in (0..256).step_by(8) {
+        let x = x as f32 * self.pixel_height;
+        overlay.stroke(&canvas::Path::line(Point::new(x, 0.0), Point::new(x, 240.0 * self.pixel_height)), grid_stroke.clone());
+      }
+      for y in (0..240).step_by(8) {
+        let y = y as f32 * self.pixel_height;
+        overlay.stroke(&canvas::Path::line(Point::new(0.0, y), Point::new(256.0 * self.pixel_height, y)), grid_stroke.clone());
+      }
+    }
+    if (self.show_sprite_outlines) {
+      let outline_stroke = Stroke::default().with_color(Color::from_rgb(1.0, 0.0, 0.0)).with_width(1.0);
+      for (x, y, height) in self.sprite_boxes.iter() {
+        let outline = canvas::Path::rectangle(
+          Point::new(*x as f32 * self.pixel_height, *y as f32 * self.pixel_height),
+          Size::new(8.0 * self.pixel_height, *height as f32 * self.pixel_height),
+        );
+        overlay.stroke(&outline, outline_stroke.clone());
+      }
+    }
+    // Nothing is drawn during the pre-render line and vertical blank, so there's no line to show then
+    if (self.show_scanline_indicator && self.scan_line >= 0 && self.scan_line < 240) {
+      overlay.fill_rectangle(
+        Point::new(0.0, self.scan_line as f32 * self.pixel_height),
+        Size::new(256.0 * self.pixel_height, self.pixel_height),
+        Color::from_rgba(1.0, 1.0, 0.0, 0.5),
+      );
+    }
+    if (self.show_fps) {
+      overlay.fill_text(Text {
+        content: self.fps_label.clone(),
+        position: Point::new(256.0 * self.pixel_height - 4.0, 4.0),
+        color: Color::from_rgba(1.0, 1.0, 1.0, 0.7),
+        size: 16.0,
+        horizontal_alignment: iced::alignment::Horizontal::Right,
+        ..Text::default()
+      });
+    }
+    if let Some(filled_slots) = self.pause_menu {
+      overlay.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgba(0.0, 0.0, 0.0, 0.7));
+      let hovered_item = cursor.position_in(&bounds).and_then(pause_menu_item_at);
+      for (item, rectangle) in pause_menu_layout() {
+        // Empty slots can't be loaded
+        let enabled = match item {
+          PauseMenuItem::LoadState(slot) => filled_slots[slot as usize],
+          _ => true,
+        };
+        let background_alpha = if (enabled && hovered_item == Some(item)) { 0.35 } else { 0.15 };
+        overlay.fill_rectangle(rectangle.position(), rectangle.size(), Color::from_rgba(1.0, 1.0, 1.0, background_alpha));
+        overlay.fill_text(Text {
+          content: item.label(),
+          position: rectangle.center(),
+          color: Color::from_rgba(1.0, 1.0, 1.0, if enabled { 1.0 } else { 0.4 }),
+          size: 18.0,
+          horizontal_alignment: iced::alignment::Horizontal::Center,
+          vertical_alignment: iced::alignment::Vertical::Center,
+          ..Text::default()
+        });
+      }
+    }
+    vec![pixel_grid, overlay.into_geometry()]
+  }
+}
+
+const SAVE_STATE_SLOTS: u8 = 4;
+
+// A copy of the whole console, along with the frontend's counters
+#[derive(Clone)]
+struct SaveState {
+  cpu: Ben6502,
+  current_cycle: u64,
+  frame_count: u64,
+}
+
+// Save and load states take the slot, from 0 to SAVE_STATE_SLOTS - 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseMenuItem {
+  Resume,
+  SaveState(u8),
+  LoadState(u8),
+  Reset,
+  OpenRom,
+  Settings,
+  Quit,
+}
+
+impl PauseMenuItem {
+  fn label(&self) -> String {
+    return match self {
+      PauseMenuItem::Resume => String::from("Resume"),
+      PauseMenuItem::SaveState(slot) => format!("Save {}", slot + 1),
+      PauseMenuItem::LoadState(slot) => format!("Load {}", slot + 1),
+      PauseMenuItem::Reset => String::from("Reset"),
+      PauseMenuItem::OpenRom => String::from("Open ROM..."),
+      PauseMenuItem::Settings => String::from("Settings"),
+      PauseMenuItem::Quit => String::from("Quit"),
+    };
+  }
+}
+
+const PAUSE_MENU_WIDTH: f32 = 300.0;
+const PAUSE_MENU_ROW_HEIGHT: f32 = 40.0;
+const PAUSE_MENU_SPACING: f32 = 10.0;
+
+// Where each item of the pause menu goes, centered on the screen. The save and load rows have one item per slot.
+fn pause_menu_layout() -> Vec<(PauseMenuItem, Rectangle)> {
+  let rows: Vec<Vec<PauseMenuItem>> = vec![
+    vec![PauseMenuItem::Resume],
+    (0..SAVE_STATE_SLOTS).map(PauseMenuItem::SaveState).collect(),
+    (0..SAVE_STATE_SLOTS).map(PauseMenuItem::LoadState).collect(),
+    vec![PauseMenuItem::Reset],
+    vec![PauseMenuItem::OpenRom],
+    vec![PauseMenuItem::Settings],
+    vec![PauseMenuItem::Quit],
+  ];
+  let screen_size = f32::from(SCREEN_HEIGHT);
+  let menu_height = rows.len() as f32 * (PAUSE_MENU_ROW_HEIGHT + PAUSE_MENU_SPACING) - PAUSE_MENU_SPACING;
+  let menu_x = (screen_size - PAUSE_MENU_WIDTH) / 2.0;
+  let mut row_y = (screen_size - menu_height) / 2.0;
+  let mut result = vec![];
+  for row in rows {
+    let item_width = (PAUSE_MENU_WIDTH - PAUSE_MENU_SPACING * (row.len() - 1) as f32) / row.len() as f32;
+    for (index, item) in row.into_iter().enumerate() {
+      let item_x = menu_x + index as f32 * (item_width + PAUSE_MENU_SPACING);
+      result.push((item, Rectangle { x: item_x, y: row_y, width: item_width, height: PAUSE_MENU_ROW_HEIGHT }));
+    }
+    row_y += PAUSE_MENU_ROW_HEIGHT + PAUSE_MENU_SPACING;
+  }
+  return result;
+}
+
+fn pause_menu_item_at(position: Point) -> Option<PauseMenuItem> {
+  return pause_menu_layout().into_iter().find(|(_, rectangle)| rectangle.contains(position)).map(|(item, _)| item);
+}
+
+struct HeatmapVisualizer {
+  // One color per address, refreshed every frame
+  colors: Vec<graphics::Color>,
+  canvas_cache: Cache,
+  pixel_height: f32,
+}
+
+impl HeatmapVisualizer {
+  pub fn view(&self) -> Element<EmulatorMessage> {
+    Canvas::new(self)
+        .width(Length::Units(HEATMAP_VIS_SIZE))
+        .height(Length::Units(HEATMAP_VIS_SIZE))
+        .into()
+  }
+
+  pub fn update_data(&mut self, bus: &Bus16Bit) {
+    self.colors = heatmap::heatmap_colors(&bus.read_counts, &bus.write_counts);
+    self.canvas_cache.clear();
+  }
+}
+
+impl canvas::Program<EmulatorMessage> for HeatmapVisualizer {
+  type State = ();
+
+  fn draw(
+      &self,
+      _state: &Self::State,
+      _theme: &Theme,
+      bounds: Rectangle,
+      cursor: Cursor,
+  ) -> Vec<Geometry> {
+
+    let pixel_grid = self.canvas_cache.draw(bounds.size(), |frame| {
+      for (addr, pixel_color) in self.colors.iter().enumerate() {
+        let (row, col) = (addr / heatmap::HEATMAP_SIZE, addr % heatmap::HEATMAP_SIZE);
+        frame.fill_rectangle(
+            Point::new((col as f32) * self.pixel_height, (row as f32) * self.pixel_height),
+            Size::new(self.pixel_height, self.pixel_height),
+            iced::Color::from(*pixel_color),
+        );
+      }
+    });
     vec![pixel_grid]
   }
 }
@@ -490,7 +1566,7 @@ impl canvas::Program<EmulatorMessage> for PPUPaletteVisualizer {
         frame.fill_rectangle(
             Point::new((i as f32) * self.pixel_height as f32, 0.0),
             Size::new(self.pixel_height, self.pixel_height),
-            pixel_color.to_iced_color(),
+            iced::Color::from(pixel_color),
         );
       }
     });
@@ -501,7 +1577,7 @@ impl canvas::Program<EmulatorMessage> for PPUPaletteVisualizer {
 
 
 struct PPUPatternTableBufferVisualizer {
-  pattern_tables_vis_buffer: [[[graphics::Color; 128]; 128]; 2],
+  pattern_tables_vis_buffer: SharedVisBuffer<[[[graphics::Color; 128]; 128]; 2]>,
   canvas_cache: Cache,
   pixel_height: f32,
   pattern_table_vis_palette_id: u8
@@ -515,24 +1591,45 @@ impl PPUPatternTableBufferVisualizer {
         .into()
   }
 
-  pub fn update_data(&mut self, ppu: &Ben2C02) {
-    // Every time we update, I'm copying the contents of the PPU buffer
-    // onto the buffer of the Visualizer. This is awful, but I can't 
-    // figure out lifetimes well enough to directly reference the PPU buffer :/
-    // TODO: Reference PPU buffer directly
-    for tableIndex in 0..2 {
-      for i in 0..ppu.pattern_tables_vis_buffer[0].len() {
-        for j in 0..ppu.pattern_tables_vis_buffer[0][0].len() {
-          self.pattern_tables_vis_buffer[tableIndex][i][j] = ppu.pattern_tables_vis_buffer[tableIndex][i][j];
-        }
-      }
-    }
-    self.canvas_cache.clear();
+}
+
+// Pattern table id and tile index of the tile drawn at the given position (relative to the visualizer)
+fn pattern_table_tile_at(position: Point, pixel_height: f32) -> Option<(u8, u8)> {
+  if (position.x < 0.0 || position.y < 0.0) {
+    return None;
+  }
+  let tile_col = (position.x / (pixel_height * 8.0)) as u32;
+  let tile_row = (position.y / (pixel_height * 8.0)) as u32;
+  if (tile_col >= 32 || tile_row >= 16) {
+    return None;
   }
+  return Some(((tile_col / 16) as u8, (tile_row * 16 + tile_col % 16) as u8));
 }
 
 impl canvas::Program<EmulatorMessage> for PPUPatternTableBufferVisualizer {
-  type State = ();
+  // Last hovered tile, so that we only send a message when it changes
+  type State = Option<(u8, u8)>;
+
+  fn update(
+      &self,
+      state: &mut Self::State,
+      event: canvas::Event,
+      bounds: Rectangle,
+      cursor: Cursor,
+  ) -> (event::Status, Option<EmulatorMessage>) {
+    if let canvas::Event::Mouse(mouse::Event::CursorMoved { .. }) = event {
+      let hovered_tile = cursor.position_in(&bounds).and_then(|position| pattern_table_tile_at(position, self.pixel_height));
+      if (hovered_tile != *state) {
+        *state = hovered_tile;
+        let message = match hovered_tile {
+          Some((pattern_table_id, tile_index)) => EmulatorMessage::HoverTile(pattern_table_id, tile_index),
+          None => EmulatorMessage::ClearTileHover,
+        };
+        return (event::Status::Ignored, Some(message));
+      }
+    }
+    return (event::Status::Ignored, None);
+  }
 
   fn draw(
       &self,
@@ -543,18 +1640,19 @@ impl canvas::Program<EmulatorMessage> for PPUPatternTableBufferVisualizer {
   ) -> Vec<Geometry> {
 
     let pixel_grid = self.canvas_cache.draw(bounds.size(), |frame| {
+      let pattern_tables_vis_buffer = self.pattern_tables_vis_buffer.lock().unwrap();
       for tableIndex in 0..2 {
-        for i in 0..self.pattern_tables_vis_buffer[0].len() {
-          for j in 0..self.pattern_tables_vis_buffer[0][0].len() {
-            let pixel_color = self.pattern_tables_vis_buffer[tableIndex][i][j];
+        for i in 0..pattern_tables_vis_buffer[0].len() {
+          for j in 0..pattern_tables_vis_buffer[0][0].len() {
+            let pixel_color = pattern_tables_vis_buffer[tableIndex][i][j];
   
             frame.fill_rectangle(
                 Point::new(
-                          (tableIndex as f32) * self.pixel_height * (self.pattern_tables_vis_buffer[0].len() as f32)  + (i as f32) * self.pixel_height as f32,
+                          (tableIndex as f32) * self.pixel_height * (pattern_tables_vis_buffer[0].len() as f32)  + (i as f32) * self.pixel_height as f32,
                           (j as f32) * self.pixel_height as f32
                 ),
                 Size::new(self.pixel_height, self.pixel_height),
-                pixel_color.to_iced_color(),
+                iced::Color::from(pixel_color),
             );
           }
         }
@@ -673,4 +1771,85 @@ impl NESInputHandler {
     }
     return result;
   }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod memory_visualizer_tests {
+  use super::{stack_window_bounds, pattern_table_tile_at, is_nes_file, memory_cursor_status, pause_menu_item_at, PauseMenuItem};
+  use iced::Point;
+  use std::path::Path;
+
+  #[test]
+  fn test_stack_window_with_empty_stack() {
+    assert_eq!(stack_window_bounds(0xFF), (0x1FF - 40, 0x200));
+  }
+
+  #[test]
+  fn test_stack_window_with_full_stack() {
+    assert_eq!(stack_window_bounds(0x00), (0x100, 0x104));
+    assert_eq!(stack_window_bounds(0x10), (0x100, 0x114));
+  }
+
+  #[test]
+  fn test_pattern_table_tile_at() {
+    // Each pattern table is 16x16 tiles of 8x8 pixels, drawn side by side
+    assert_eq!(pattern_table_tile_at(Point::new(0.0, 0.0), 2.0), Some((0, 0)));
+    assert_eq!(pattern_table_tile_at(Point::new(17.0, 33.0), 2.0), Some((0, 2 * 16 + 1)));
+    assert_eq!(pattern_table_tile_at(Point::new(255.0, 255.0), 2.0), Some((0, 255)));
+    assert_eq!(pattern_table_tile_at(Point::new(256.0, 0.0), 2.0), Some((1, 0)));
+    assert_eq!(pattern_table_tile_at(Point::new(512.0, 0.0), 2.0), None);
+  }
+
+  #[test]
+  fn test_pause_menu_item_at() {
+    // The menu is 7 rows of 40 pixels with 10 between them, centered on the 500x500 screen
+    assert_eq!(pause_menu_item_at(Point::new(105.0, 85.0)), Some(PauseMenuItem::Resume));
+    assert_eq!(pause_menu_item_at(Point::new(200.0, 150.0)), Some(PauseMenuItem::SaveState(1)));
+    assert_eq!(pause_menu_item_at(Point::new(395.0, 215.0)), Some(PauseMenuItem::LoadState(3)));
+    assert_eq!(pause_menu_item_at(Point::new(250.0, 400.0)), Some(PauseMenuItem::Quit));
+    // Between slots, and outside the menu
+    assert_eq!(pause_menu_item_at(Point::new(172.0, 150.0)), None);
+    assert_eq!(pause_menu_item_at(Point::new(50.0, 50.0)), None);
+  }
+
+  #[test]
+  fn test_memory_cursor_status() {
+    assert_eq!(memory_cursor_status(0xFFFC, [0x00, 0xC0]), "$FFFC (RESET_VEC): $00 $C0");
+    assert_eq!(memory_cursor_status(0x2002, [0x80, 0x80]), "$2002 (PPU_STATUS): $80 $80");
+    assert_eq!(memory_cursor_status(0x0301, [0x12, 0x34]), "$0301: $12 $34");
+  }
+
+  #[test]
+  fn test_only_nes_files_can_be_dropped() {
+    assert!(is_nes_file(Path::new("/roms/Super Mario Bros.nes")));
+    assert!(is_nes_file(Path::new("ZELDA.NES")));
+    assert!(!is_nes_file(Path::new("roms.zip")));
+    assert!(!is_nes_file(Path::new("nes")));
+  }
+}
+
+#[cfg(test)]
+mod battery_ram_tests {
+  use super::{switch_battery_ram, Bus16Bit, Cartridge, EmulatorConfig};
+  use crate::test_utils::NesRomBuilder;
+
+  #[test]
+  fn test_reloading_the_same_rom_keeps_the_battery_ram() {
+    let test_directory = std::env::temp_dir().join("rustness_reload_rom_test");
+    let _ = std::fs::remove_dir_all(&test_directory);
+    let config = EmulatorConfig { saves_directory: Some(test_directory.join("saves")), ..EmulatorConfig::default() };
+    std::fs::create_dir_all(config.saves_directory()).unwrap();
+    let rom_path = test_directory.join("game.nes");
+    let mut rom = NesRomBuilder::new().build();
+    // Battery backed PRG RAM
+    rom[6] |= 0x02;
+
+    let mut cpu_bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    cpu_bus.write(0x6000, 0x12).unwrap();
+    let mut reloaded_cpu_bus = Bus16Bit::with_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    switch_battery_ram(&mut cpu_bus, &rom_path, &mut reloaded_cpu_bus, &rom_path, &config);
+    assert_eq!(reloaded_cpu_bus.peek(0x6000), Some(0x12));
+    std::fs::remove_dir_all(test_directory).unwrap();
+  }
+}
@@ -1,13 +1,111 @@
-pub trait Mapper {
+use crate::cartridge::MirroringMode;
+
+// Mapped addresses are offsets into the cartridge's PRG/CHR data, which can be larger than 64KB when banking is involved.
+// The mapping functions take &mut self, since on some mappers the accesses themselves change the banking state.
+// Send, so that a whole console can be moved to another thread
+pub trait Mapper: Send {
   fn in_cpu_address_bounds(&self, addr:u16) -> bool;
   fn in_ppu_address_bounds(&self, addr:u16) -> bool;
 
-  fn mapReadAddressFromCPU(&self, addr: u16) -> Result<u16, String>;
-  fn mapWriteAddressFromCPU(&self, addr: u16) -> Result<u16, String>;
-  fn mapReadAddressFromPPU(&self, addr: u16) -> Result<u16, String>;
-  fn mapWriteAddressFromPPU(&self, addr: u16) -> Result<u16, String>;
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String>;
+  // Returns None when the write was handled by the mapper itself (e.g. a bank select register) and shouldn't reach PRG data
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String>;
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String>;
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String>;
+  // Same as the read mappings, but without affecting the mapper state
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String>;
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String>;
+
+  // Current PRG ROM layout as seen by the CPU, as (first address, last address, offset into PRG ROM) for each bank.
+  // Only used for debugging, so it doesn't have to be fast.
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)>;
+
+  // Mappers that control the name table mirroring return the currently selected mode
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return None;
+  }
+
+  // Maps $6000-$7FFF to an offset into the cartridge's PRG RAM. Offsets past the end of the RAM wrap around (mirroring it).
+  // Returns None when the RAM is disabled or write protected, so that mappers with banked or protected RAM can override these.
+  // Writes also get the data, for mappers with registers in this range.
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapReadAddressFromPrgRam(&self, addr: u16) -> Option<u32> {
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  #[must_use = "the mapped address is the only result of mapping"]
+  fn mapWriteAddressFromPrgRam(&mut self, addr: u16, data: u8) -> Option<u32> {
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  // Mappers with their own IRQ source (e.g. the MMC3 scanline counter) hold it here until it's acknowledged
+  fn irq_pending(&self) -> bool {
+    return false;
+  }
+
+  // On boards with bus conflicts, the ROM drives the data bus during CPU writes too, so the value that reaches the
+  // mapper is ANDed with the ROM byte at the written address
+  fn has_bus_conflicts(&self) -> bool {
+    return false;
+  }
+
+  // Registers and memory in the expansion area at $4020-$5FFF, which most boards leave unconnected (MMC5 has them)
+  fn in_expansion_area_bounds(&self, addr: u16) -> bool {
+    return false;
+  }
+
+  fn read_expansion_area(&mut self, addr: u16) -> u8 {
+    return self.read_expansion_area_debug(addr);
+  }
+
+  fn read_expansion_area_debug(&self, addr: u16) -> u8 {
+    return 0;
+  }
+
+  fn write_expansion_area(&mut self, addr: u16, data: u8) {}
+
+  // Every PPU read of the name tables at $2000-$2FFF goes through here first, so mappers can watch the fetches.
+  // Mappers with their own name table memory (MMC5's ExRAM) return the data, None leaves the read to the console's
+  // name tables, arranged by mirroring_mode().
+  fn read_name_table(&mut self, addr: u16) -> Option<u8> {
+    return self.read_name_table_debug(addr);
+  }
+
+  fn read_name_table_debug(&self, addr: u16) -> Option<u8> {
+    return None;
+  }
+
+  // Returns whether the mapper took the write
+  fn write_name_table(&mut self, addr: u16, data: u8) -> bool {
+    return false;
+  }
+
+  // Some mappers watch the CPU writes to PPUCTRL ($2000), e.g. MMC5 for the sprite size
+  fn ppu_control_written(&mut self, data: u8) {}
+
+  // Called once per CPU cycle (M2), for mappers with cycle counting IRQs like VRC and FME-7
+  fn cpu_clock(&mut self) {}
+
+  // Cartridges keep their mapper as a trait object, so it can't be cloned through a derive
+  fn cloneMapper(&self) -> Box<dyn Mapper>;
 }
 
+impl Clone for Box<dyn Mapper> {
+  fn clone(&self) -> Box<dyn Mapper> {
+    return self.cloneMapper();
+  }
+}
+
+pub const PRG_RAM_START_ADDR: u16 = 0x6000;
+pub const PRG_RAM_END_ADDR: u16 = 0x7FFF;
+
+#[derive(Clone)]
 pub struct Mapper000 {
   cpu_address_bounds: (u16, u16),
   ppu_address_bounds: (u16, u16),
@@ -28,6 +126,10 @@ impl Mapper000 {
 
 impl Mapper for Mapper000 {
 
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
   fn in_cpu_address_bounds(&self, addr:u16) -> bool {
     return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
   }
@@ -36,7 +138,18 @@ impl Mapper for Mapper000 {
     return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
   }
 
-  fn mapReadAddressFromCPU(&self, addr: u16) -> Result<u16, String> {
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    if self.num_PRG_banks > 1 {
+      return vec![(0x8000, 0xFFFF, 0)];
+    }
+    return vec![(0x8000, 0xBFFF, 0), (0xC000, 0xFFFF, 0)];
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
     if self.in_cpu_address_bounds(addr) {
       // if PRGROM is 16KB (1 memory bank)
       //     CPU Address Bus          PRG ROM
@@ -46,34 +159,2404 @@ impl Mapper for Mapper000 {
       //     CPU Address Bus          PRG ROM
       //     0x8000 -> 0xFFFF: Map    0x0000 -> 0x7FFF
       let mapped_addr = if self.num_PRG_banks > 1 { addr & 0x7FFF } else { addr & 0x3FFF};
-      return Ok(mapped_addr);
+      return Ok(mapped_addr as u32);
     } else {
       return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
     }
   }
 
-  fn mapWriteAddressFromCPU(&self, addr: u16) -> Result<u16, String> {
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
     if self.in_cpu_address_bounds(addr) {
       let mapped_addr = if self.num_PRG_banks > 1 { addr & 0x7FFF } else { addr & 0x3FFF};
-      return Ok(mapped_addr);
+      return Ok(Some(mapped_addr as u32));
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU write address outside of PPU bounds!"));
+    }
+  }
+}
+
+
+// MMC1, used by The Legend of Zelda, Metroid, Mega Man 2 and many more
+// Its registers are written one bit at a time through a serial shift register: five writes to $8000-$FFFF, LSB first,
+// where the address of the fifth one selects the register that gets the value. Writing a byte with bit 7 set resets
+// the shift register instead.
+// The 512KB PRG boards (SUROM), which use the CHR bank registers for PRG banking too, aren't supported.
+// Reference: https://www.nesdev.org/wiki/MMC1
+#[derive(Clone)]
+pub struct Mapper001 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub shift_register: u8,
+  pub shift_count: u8,
+  // Bits 0-1: mirroring, bits 2-3: PRG bank mode, bit 4: CHR bank mode
+  pub control: u8,
+  pub chr_bank_0: u8,
+  pub chr_bank_1: u8,
+  // Bits 0-3: PRG bank, bit 4: PRG RAM disable
+  pub prg_bank: u8,
+}
+
+const MMC1_PRG_BANK_SIZE: u32 = 16384;
+const MMC1_CHR_BANK_SIZE: u32 = 4096;
+
+impl Mapper001 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper001 {
+    return Mapper001 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      shift_register: 0,
+      shift_count: 0,
+      // The last PRG bank is fixed at $C000 on power up, so that the reset vector is always there
+      control: 0x0C,
+      chr_bank_0: 0,
+      chr_bank_1: 0,
+      prg_bank: 0,
+    }
+  }
+
+  // Boards without CHR ROM have 8KB of CHR RAM, which is banked the same way
+  fn num_4k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks.max(1) as u32) * 2;
+  }
+
+  fn prg_ram_enabled(&self) -> bool {
+    return (self.prg_bank & 0x10) == 0;
+  }
+
+  fn write_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0x9FFF => self.control = data,
+      0xA000..=0xBFFF => self.chr_bank_0 = data,
+      0xC000..=0xDFFF => self.chr_bank_1 = data,
+      _ => self.prg_bank = data,
+    }
+  }
+}
+
+impl Mapper for Mapper001 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xC000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x3FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  //     PRG mode 0, 1: 0x8000 -> 0xFFFF: Switchable 32KB bank (the low bit of the bank number is ignored)
+  //     PRG mode 2:    0x8000 -> 0xBFFF: Fixed to the first 16KB bank, 0xC000 -> 0xFFFF: Switchable 16KB bank
+  //     PRG mode 3:    0x8000 -> 0xBFFF: Switchable 16KB bank, 0xC000 -> 0xFFFF: Fixed to the last 16KB bank
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let num_prg_banks = self.num_PRG_banks as u32;
+      let selected_bank = (self.prg_bank & 0x0F) as u32;
+      let offset = (addr & 0x3FFF) as u32;
+      let bank = match ((self.control >> 2) & 0x03, addr) {
+        (0 | 1, 0x8000..=0xBFFF) => selected_bank & !1,
+        (0 | 1, _) => selected_bank | 1,
+        (2, 0x8000..=0xBFFF) => 0,
+        (2, _) => selected_bank,
+        (_, 0x8000..=0xBFFF) => selected_bank,
+        (_, _) => num_prg_banks - 1,
+      };
+      return Ok((bank % num_prg_banks) * MMC1_PRG_BANK_SIZE + offset);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      if (data & 0x80) != 0 {
+        self.shift_register = 0;
+        self.shift_count = 0;
+        self.control |= 0x0C;
+      } else {
+        self.shift_register |= (data & 0x01) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+          self.write_register(addr, self.shift_register);
+          self.shift_register = 0;
+          self.shift_count = 0;
+        }
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  //     CHR mode 0: 0x0000 -> 0x1FFF: Switchable 8KB bank (the low bit of CHR bank 0 is ignored)
+  //     CHR mode 1: 0x0000 -> 0x0FFF: CHR bank 0, 0x1000 -> 0x1FFF: CHR bank 1, both 4KB
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let offset = (addr & 0x0FFF) as u32;
+      let bank = match ((self.control & 0x10) != 0, addr) {
+        (false, 0x0000..=0x0FFF) => self.chr_bank_0 & !1,
+        (false, _) => self.chr_bank_0 | 1,
+        (true, 0x0000..=0x0FFF) => self.chr_bank_0,
+        (true, _) => self.chr_bank_1,
+      };
+      return Ok(((bank as u32) % self.num_4k_chr_banks()) * MMC1_CHR_BANK_SIZE + offset);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.num_CHR_banks > 0 {
+      return Err(String::from("This MMC1 cartridge has CHR ROM, which can't be written to!"));
+    }
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return Some(match self.control & 0x03 {
+      0 => MirroringMode::OnscreenLo,
+      1 => MirroringMode::OnscreenHi,
+      2 => MirroringMode::Vertical,
+      _ => MirroringMode::Horizontal,
+    });
+  }
+
+  fn mapReadAddressFromPrgRam(&self, addr: u16) -> Option<u32> {
+    if !self.prg_ram_enabled() {
+      return None;
+    }
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  fn mapWriteAddressFromPrgRam(&mut self, addr: u16, data: u8) -> Option<u32> {
+    if !self.prg_ram_enabled() {
+      return None;
+    }
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+}
+
+
+// UxROM, used by Mega Man, Castlevania, Contra, DuckTales and others
+// Any write to $8000-$FFFF selects the 16KB PRG bank at $8000-$BFFF, while $C000-$FFFF is fixed to the last bank.
+// CHR is 8KB of RAM, with no banking.
+// Reference: https://www.nesdev.org/wiki/UxROM
+#[derive(Clone)]
+pub struct Mapper002 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  // Only some boards have them, and the header can't tell which ones, so they're off unless enabled
+  pub bus_conflicts: bool,
+}
+
+const UXROM_PRG_BANK_SIZE: u32 = 16384;
+
+impl Mapper002 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper002 {
+    return Mapper002 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      bus_conflicts: false,
+    }
+  }
+}
+
+impl Mapper for Mapper002 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xC000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x3FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  //     CPU Address Bus          PRG ROM
+  //     0x8000 -> 0xBFFF: Switchable 16KB bank
+  //     0xC000 -> 0xFFFF: Fixed to the last 16KB bank
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let num_prg_banks = self.num_PRG_banks as u32;
+      let bank = if addr < 0xC000 { (self.prg_bank as u32) % num_prg_banks } else { num_prg_banks - 1 };
+      return Ok(bank * UXROM_PRG_BANK_SIZE + (addr & 0x3FFF) as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      self.prg_bank = data;
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU write address outside of PPU bounds!"));
+    }
+  }
+
+  fn has_bus_conflicts(&self) -> bool {
+    return self.bus_conflicts;
+  }
+}
+
+
+// Camerica/Codemasters, used by Micro Machines, Fire Hawk and other unlicensed Codemasters games
+// Like UxROM, with a switchable 16KB PRG bank at $8000-$BFFF and the last bank fixed at $C000-$FFFF, but the bank is
+// selected by writes to $C000-$FFFF only. Fire Hawk's board also selects which of the two name tables is used for the
+// whole screen with bit 4 of writes to $9000-$9FFF. CHR is 8KB of RAM, with no banking.
+// Reference: https://www.nesdev.org/wiki/INES_Mapper_071
+#[derive(Clone)]
+pub struct Mapper071 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  // Only Fire Hawk's board has it, which is told apart by its NES 2.0 submapper
+  pub mirroring_control: bool,
+  mirroring_mode: Option<MirroringMode>,
+}
+
+const CAMERICA_PRG_BANK_SIZE: u32 = 16384;
+
+impl Mapper071 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper071 {
+    return Mapper071 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      mirroring_control: false,
+      mirroring_mode: None,
+    }
+  }
+}
+
+impl Mapper for Mapper071 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xC000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x3FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  //     CPU Address Bus          PRG ROM
+  //     0x8000 -> 0xBFFF: Switchable 16KB bank
+  //     0xC000 -> 0xFFFF: Fixed to the last 16KB bank
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let num_prg_banks = self.num_PRG_banks as u32;
+      let bank = if addr < 0xC000 { (self.prg_bank as u32) % num_prg_banks } else { num_prg_banks - 1 };
+      return Ok(bank * CAMERICA_PRG_BANK_SIZE + (addr & 0x3FFF) as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      match addr {
+        0x9000..=0x9FFF if self.mirroring_control => {
+          self.mirroring_mode = Some(if (data & 0x10) != 0 { MirroringMode::OnscreenHi } else { MirroringMode::OnscreenLo });
+        },
+        0xC000..=0xFFFF => self.prg_bank = data & 0x0F,
+        _ => {}
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
     } else {
       return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
     }
   }
 
-  fn mapReadAddressFromPPU(&self, addr: u16) -> Result<u16, String> {
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
     if self.in_ppu_address_bounds(addr) {
-      return Ok(addr);
+      return Ok(addr as u32);
     } else {
       return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
     }
   }
 
-  fn mapWriteAddressFromPPU(&self, addr: u16) -> Result<u16, String> {
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
     if self.in_ppu_address_bounds(addr) {
-      return Ok(addr);
+      return Ok(addr as u32);
     } else {
       return Err(String::from("Mapper received a PPU write address outside of PPU bounds!"));
     }
   }
-}
\ No newline at end of file
+
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return self.mirroring_mode;
+  }
+}
+
+
+// AxROM, used by Battletoads, Marble Madness and other Rare games
+// Any write to $8000-$FFFF selects the 32KB PRG bank (bits 0-2) and which of the two name tables is used for the
+// whole screen (bit 4). CHR is 8KB of RAM, with no banking.
+// Reference: https://www.nesdev.org/wiki/AxROM
+#[derive(Clone)]
+pub struct Mapper007 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  pub mirroring_mode: MirroringMode,
+  // Only the AMROM boards have them, and the header can't tell which ones, so they're off unless enabled
+  pub bus_conflicts: bool,
+}
+
+const AXROM_PRG_BANK_SIZE: u32 = 0x8000;
+
+impl Mapper007 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper007 {
+    return Mapper007 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      mirroring_mode: MirroringMode::OnscreenLo,
+      bus_conflicts: false,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while the mapper switches them in 32KB units
+  fn num_32k_prg_banks(&self) -> u32 {
+    return ((self.num_PRG_banks as u32) / 2).max(1);
+  }
+}
+
+impl Mapper for Mapper007 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return vec![(0x8000, 0xFFFF, self.mapDebugReadAddressFromCPU(0x8000).unwrap())];
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let bank = (self.prg_bank as u32) % self.num_32k_prg_banks();
+      // A single 16KB bank shows up in both halves, like on NROM
+      let offset = if (self.num_PRG_banks > 1) { addr & 0x7FFF } else { addr & 0x3FFF };
+      return Ok(bank * AXROM_PRG_BANK_SIZE + offset as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      self.prg_bank = data & 0x07;
+      self.mirroring_mode = if (data & 0x10) != 0 { MirroringMode::OnscreenHi } else { MirroringMode::OnscreenLo };
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU write address outside of PPU bounds!"));
+    }
+  }
+
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return Some(self.mirroring_mode);
+  }
+
+  fn has_bus_conflicts(&self) -> bool {
+    return self.bus_conflicts;
+  }
+}
+
+
+// Color Dreams, used by the unlicensed Color Dreams and Wisdom Tree games (e.g. Bible Adventures)
+// A single register, written anywhere in $8000-$FFFF, selects both the 32KB PRG bank (bits 0-1) and the 8KB CHR bank
+// (bits 4-7). Mirroring is hardwired, so it comes from the header.
+// Reference: https://www.nesdev.org/wiki/Color_Dreams
+#[derive(Clone)]
+pub struct Mapper011 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  pub chr_bank: u8,
+  // Every Color Dreams board has them, so they're on unless disabled
+  pub bus_conflicts: bool,
+}
+
+const COLOR_DREAMS_PRG_BANK_SIZE: u32 = 0x8000;
+const COLOR_DREAMS_CHR_BANK_SIZE: u32 = 0x2000;
+
+impl Mapper011 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper011 {
+    return Mapper011 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      chr_bank: 0,
+      bus_conflicts: true,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while the mapper switches them in 32KB units
+  fn num_32k_prg_banks(&self) -> u32 {
+    return ((self.num_PRG_banks as u32) / 2).max(1);
+  }
+}
+
+impl Mapper for Mapper011 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return vec![(0x8000, 0xFFFF, self.mapDebugReadAddressFromCPU(0x8000).unwrap())];
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let bank = (self.prg_bank as u32) % self.num_32k_prg_banks();
+      // A single 16KB bank shows up in both halves, like on NROM
+      let offset = if (self.num_PRG_banks > 1) { addr & 0x7FFF } else { addr & 0x3FFF };
+      return Ok(bank * COLOR_DREAMS_PRG_BANK_SIZE + offset as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      self.prg_bank = data & 0x03;
+      self.chr_bank = data >> 4;
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let bank = (self.chr_bank as u32) % (self.num_CHR_banks.max(1) as u32);
+      return Ok(bank * COLOR_DREAMS_CHR_BANK_SIZE + addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, _addr: u16) -> Result<u32, String> {
+    return Err(String::from("Color Dreams cartridges have no CHR RAM to write to!"));
+  }
+
+  fn has_bus_conflicts(&self) -> bool {
+    return self.bus_conflicts;
+  }
+}
+
+
+// The bank select / bank data pair, and the PRG and CHR layouts they select. Shared with the Namco 108 (mapper 206),
+// which MMC3 was derived from.
+#[derive(Clone)]
+pub struct Mmc3BankRegisters {
+  // Bits 0-2: bank register to update, bit 6: PRG bank mode, bit 7: CHR A12 inversion
+  pub bank_select: u8,
+  // R0-R7
+  pub bank_registers: [u8; 8],
+}
+
+impl Mmc3BankRegisters {
+  pub fn new() -> Mmc3BankRegisters {
+    return Mmc3BankRegisters {
+      bank_select: 0,
+      bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+    }
+  }
+
+  // Even addresses in $8000-$9FFF select the register, and odd ones write to it
+  pub fn write(&mut self, addr: u16, data: u8) {
+    if (addr & 0x01) == 0 {
+      self.bank_select = data;
+    } else {
+      self.bank_registers[(self.bank_select & 0x07) as usize] = data;
+    }
+  }
+
+  //     CPU Address Bus    PRG mode 0           PRG mode 1
+  //     0x8000 -> 0x9FFF:  R6                   Second to last bank
+  //     0xA000 -> 0xBFFF:  R7                   R7
+  //     0xC000 -> 0xDFFF:  Second to last bank  R6
+  //     0xE000 -> 0xFFFF:  Last bank            Last bank
+  pub fn prg_offset(&self, addr: u16, num_8k_prg_banks: u32) -> u32 {
+    let prg_mode_1 = (self.bank_select & 0x40) != 0;
+    let bank = match addr {
+      0x8000..=0x9FFF if prg_mode_1 => num_8k_prg_banks - 2,
+      0x8000..=0x9FFF => (self.bank_registers[6] & 0x3F) as u32,
+      0xA000..=0xBFFF => (self.bank_registers[7] & 0x3F) as u32,
+      0xC000..=0xDFFF if prg_mode_1 => (self.bank_registers[6] & 0x3F) as u32,
+      0xC000..=0xDFFF => num_8k_prg_banks - 2,
+      _ => num_8k_prg_banks - 1,
+    };
+    return (bank % num_8k_prg_banks) * MMC3_PRG_BANK_SIZE + (addr & 0x1FFF) as u32;
+  }
+
+  //     PPU Address Bus    No A12 inversion     A12 inversion
+  //     0x0000 -> 0x07FF:  R0 (2KB)             R2, R3, R4, R5 (1KB each)
+  //     0x0800 -> 0x0FFF:  R1 (2KB)
+  //     0x1000 -> 0x17FF:  R2, R3, R4, R5       R0 (2KB)
+  //     0x1800 -> 0x1FFF:  (1KB each)           R1 (2KB)
+  pub fn chr_offset(&self, addr: u16, num_1k_chr_banks: u32) -> u32 {
+    // With the inversion, the two halves of the pattern tables are swapped
+    let addr = if (self.bank_select & 0x80) != 0 { addr ^ 0x1000 } else { addr };
+    let bank = match addr {
+      0x0000..=0x07FF => (self.bank_registers[0] & 0xFE) | ((addr >> 10) & 0x01) as u8,
+      0x0800..=0x0FFF => (self.bank_registers[1] & 0xFE) | ((addr >> 10) & 0x01) as u8,
+      _ => self.bank_registers[2 + ((addr - 0x1000) >> 10) as usize],
+    };
+    return ((bank as u32) % num_1k_chr_banks) * MMC3_CHR_BANK_SIZE + (addr & 0x03FF) as u32;
+  }
+}
+
+// MMC3, used by Super Mario Bros. 3, Mega Man 3-6, Kirby's Adventure and many more
+// Eight bank registers, written through a bank select ($8000) / bank data ($8001) pair, control two switchable 8KB PRG
+// banks and six CHR banks (two of 2KB and four of 1KB). It also has a scanline counter that raises an IRQ, which
+// games use for split screens like SMB3's status bar.
+// The counter is clocked by rising edges of PPU A12, which happen once per scanline when the background and the
+// sprites use different pattern tables. This emulator's PPU fetches the sprite patterns at the end of the scanline,
+// so the IRQ fires a bit later in the scanline than on the real thing.
+// Reference: https://www.nesdev.org/wiki/MMC3
+#[derive(Clone)]
+pub struct Mapper004 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub banks: Mmc3BankRegisters,
+  mirroring_mode: Option<MirroringMode>,
+  pub prg_ram_enabled: bool,
+  pub prg_ram_write_protected: bool,
+
+  pub irq_latch: u8,
+  pub irq_counter: u8,
+  pub irq_reload: bool,
+  pub irq_enabled: bool,
+  irq_pending: bool,
+  // Older MMC3 revisions only raise the IRQ when the counter reaches 0 by being decremented or reloaded,
+  // so a latch of 0 fires once instead of on every scanline
+  pub alternate_irq_behavior: bool,
+  last_a12: bool,
+}
+
+const MMC3_PRG_BANK_SIZE: u32 = 8192;
+const MMC3_CHR_BANK_SIZE: u32 = 1024;
+
+impl Mapper004 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper004 {
+    return Mapper004 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      banks: Mmc3BankRegisters::new(),
+      mirroring_mode: None,
+      prg_ram_enabled: true,
+      prg_ram_write_protected: false,
+      irq_latch: 0,
+      irq_counter: 0,
+      irq_reload: false,
+      irq_enabled: false,
+      irq_pending: false,
+      alternate_irq_behavior: false,
+      last_a12: false,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while MMC3 switches them in 8KB units
+  fn num_8k_prg_banks(&self) -> u32 {
+    return (self.num_PRG_banks as u32) * 2;
+  }
+
+  // Boards without CHR ROM have 8KB of CHR RAM, which is banked the same way
+  fn num_1k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks.max(1) as u32) * 8;
+  }
+
+  fn clock_irq_counter(&mut self) {
+    let counter_before = self.irq_counter;
+    let reloading = self.irq_counter == 0 || self.irq_reload;
+    if reloading {
+      self.irq_counter = self.irq_latch;
+    } else {
+      self.irq_counter -= 1;
+    }
+    let counter_reached_zero = if self.alternate_irq_behavior { counter_before != 0 || self.irq_reload } else { true };
+    self.irq_reload = false;
+    if self.irq_counter == 0 && counter_reached_zero && self.irq_enabled {
+      self.irq_pending = true;
+    }
+  }
+}
+
+impl Mapper for Mapper004 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xA000, 0xC000, 0xE000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x1FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      return Ok(self.banks.prg_offset(addr, self.num_8k_prg_banks()));
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  // Registers are selected by the address range and whether the address is even or odd
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let even = (addr & 0x01) == 0;
+      match addr {
+        0x8000..=0x9FFF => self.banks.write(addr, data),
+        0xA000..=0xBFFF if even => self.mirroring_mode = Some(if (data & 0x01) != 0 { MirroringMode::Horizontal } else { MirroringMode::Vertical }),
+        0xA000..=0xBFFF => {
+          self.prg_ram_enabled = (data & 0x80) != 0;
+          self.prg_ram_write_protected = (data & 0x40) != 0;
+        },
+        0xC000..=0xDFFF if even => self.irq_latch = data,
+        0xC000..=0xDFFF => {
+          self.irq_counter = 0;
+          self.irq_reload = true;
+        },
+        // Disabling the IRQ also acknowledges it
+        _ if even => {
+          self.irq_enabled = false;
+          self.irq_pending = false;
+        },
+        _ => self.irq_enabled = true,
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let a12 = (addr & 0x1000) != 0;
+      if a12 && !self.last_a12 {
+        self.clock_irq_counter();
+      }
+      self.last_a12 = a12;
+      return self.mapDebugReadAddressFromPPU(addr);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(self.banks.chr_offset(addr, self.num_1k_chr_banks()));
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.num_CHR_banks > 0 {
+      return Err(String::from("This MMC3 cartridge has CHR ROM, which can't be written to!"));
+    }
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return self.mirroring_mode;
+  }
+
+  fn mapReadAddressFromPrgRam(&self, addr: u16) -> Option<u32> {
+    if !self.prg_ram_enabled {
+      return None;
+    }
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  fn mapWriteAddressFromPrgRam(&mut self, addr: u16, data: u8) -> Option<u32> {
+    if !self.prg_ram_enabled || self.prg_ram_write_protected {
+      return None;
+    }
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  fn irq_pending(&self) -> bool {
+    return self.irq_pending;
+  }
+}
+
+
+// DxROM / Namco 108, used by Gauntlet, Pac-Man, Ring King and other early Namco and Tengen games
+// The chip MMC3 was derived from: the same bank select / bank data pair at $8000/$8001, but with the PRG mode and the
+// CHR A12 inversion fixed to 0, and no IRQ, mirroring or PRG RAM control. Writes to $A000-$FFFF are ignored.
+// Reference: https://www.nesdev.org/wiki/INES_Mapper_206
+#[derive(Clone)]
+pub struct Mapper206 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub banks: Mmc3BankRegisters,
+}
+
+impl Mapper206 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper206 {
+    return Mapper206 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      banks: Mmc3BankRegisters::new(),
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while the mapper switches them in 8KB units
+  fn num_8k_prg_banks(&self) -> u32 {
+    return (self.num_PRG_banks as u32) * 2;
+  }
+
+  fn num_1k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks.max(1) as u32) * 8;
+  }
+}
+
+impl Mapper for Mapper206 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xA000, 0xC000, 0xE000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x1FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      return Ok(self.banks.prg_offset(addr, self.num_8k_prg_banks()));
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      if (addr <= 0x9FFF) {
+        // Only the register number is kept from bank select writes
+        let data = if (addr & 0x01) == 0 { data & 0x07 } else { data };
+        self.banks.write(addr, data);
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(self.banks.chr_offset(addr, self.num_1k_chr_banks()));
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, _addr: u16) -> Result<u32, String> {
+    return Err(String::from("Namco 108 cartridges have no CHR RAM to write to!"));
+  }
+}
+
+
+// GxROM, used by Super Mario Bros. + Duck Hunt and Dragon Power
+// Any write to $8000-$FFFF selects the 32KB PRG bank (bits 4-5) and the 8KB CHR bank (bits 0-1).
+// Reference: https://www.nesdev.org/wiki/GxROM
+#[derive(Clone)]
+pub struct Mapper066 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  pub chr_bank: u8,
+}
+
+const GXROM_PRG_BANK_SIZE: u32 = 0x8000;
+const GXROM_CHR_BANK_SIZE: u32 = 0x2000;
+
+impl Mapper066 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper066 {
+    return Mapper066 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      chr_bank: 0,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while the mapper switches them in 32KB units
+  fn num_32k_prg_banks(&self) -> u32 {
+    return ((self.num_PRG_banks as u32) / 2).max(1);
+  }
+}
+
+impl Mapper for Mapper066 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return vec![(0x8000, 0xFFFF, self.mapDebugReadAddressFromCPU(0x8000).unwrap())];
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let bank = (self.prg_bank as u32) % self.num_32k_prg_banks();
+      // A single 16KB bank shows up in both halves, like on NROM
+      let offset = if (self.num_PRG_banks > 1) { addr & 0x7FFF } else { addr & 0x3FFF };
+      return Ok(bank * GXROM_PRG_BANK_SIZE + offset as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      self.prg_bank = (data >> 4) & 0x03;
+      self.chr_bank = data & 0x03;
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let bank = (self.chr_bank as u32) % (self.num_CHR_banks.max(1) as u32);
+      return Ok(bank * GXROM_CHR_BANK_SIZE + addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, _addr: u16) -> Result<u32, String> {
+    return Err(String::from("GxROM cartridges have no CHR RAM to write to!"));
+  }
+}
+
+
+// Mapper 34 covers two unrelated boards, told apart by their CHR memory:
+// - BNROM (Deadly Towers), with CHR RAM. Any write to $8000-$FFFF selects the 32KB PRG bank.
+// - NINA-001 (Impossible Mission II), with CHR ROM. Its registers are at the top of the PRG RAM range: $7FFD selects the
+//   32KB PRG bank, and $7FFE/$7FFF the 4KB CHR banks at $0000/$1000. The writes reach the RAM underneath as well.
+// Reference: https://www.nesdev.org/wiki/INES_Mapper_034
+#[derive(Clone)]
+pub struct Mapper034 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  pub chr_bank_0: u8,
+  pub chr_bank_1: u8,
+}
+
+const BNROM_PRG_BANK_SIZE: u32 = 0x8000;
+const NINA_CHR_BANK_SIZE: u32 = 0x1000;
+
+impl Mapper034 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper034 {
+    return Mapper034 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      chr_bank_0: 0,
+      chr_bank_1: 1,
+    }
+  }
+
+  pub fn is_nina_001(&self) -> bool {
+    return self.num_CHR_banks > 0;
+  }
+
+  // The header counts PRG banks in 16KB units, while the mapper switches them in 32KB units
+  fn num_32k_prg_banks(&self) -> u32 {
+    return ((self.num_PRG_banks as u32) / 2).max(1);
+  }
+
+  fn num_4k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks as u32) * 2;
+  }
+}
+
+impl Mapper for Mapper034 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return vec![(0x8000, 0xFFFF, self.mapDebugReadAddressFromCPU(0x8000).unwrap())];
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let bank = (self.prg_bank as u32) % self.num_32k_prg_banks();
+      // A single 16KB bank shows up in both halves, like on NROM
+      let offset = if (self.num_PRG_banks > 1) { addr & 0x7FFF } else { addr & 0x3FFF };
+      return Ok(bank * BNROM_PRG_BANK_SIZE + offset as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      if (!self.is_nina_001()) {
+        self.prg_bank = data;
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPrgRam(&mut self, addr: u16, data: u8) -> Option<u32> {
+    if (self.is_nina_001()) {
+      match addr {
+        0x7FFD => self.prg_bank = data & 0x01,
+        0x7FFE => self.chr_bank_0 = data & 0x0F,
+        0x7FFF => self.chr_bank_1 = data & 0x0F,
+        _ => {}
+      }
+    }
+    return Some((addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  //     PPU Address Bus          CHR
+  //     0x0000 -> 0x0FFF: 4KB bank selected by $7FFE (NINA-001), or the first half of CHR RAM (BNROM)
+  //     0x1000 -> 0x1FFF: 4KB bank selected by $7FFF (NINA-001), or the second half of CHR RAM (BNROM)
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      if (!self.is_nina_001()) {
+        return Ok(addr as u32);
+      }
+      let bank = if addr < 0x1000 { self.chr_bank_0 } else { self.chr_bank_1 };
+      return Ok(((bank as u32) % self.num_4k_chr_banks()) * NINA_CHR_BANK_SIZE + (addr & 0x0FFF) as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if (self.is_nina_001()) {
+      return Err(String::from("NINA-001 cartridges have no CHR RAM to write to!"));
+    }
+    if self.in_ppu_address_bounds(addr) {
+      return Ok(addr as u32);
+    } else {
+      return Err(String::from("Mapper received a PPU write address outside of PPU bounds!"));
+    }
+  }
+}
+
+
+// MMC2, used by Punch-Out!!
+// Besides the usual bank select registers, it has two latches that switch CHR banks on their own when the PPU
+// fetches specific tiles ($FD or $FE), which lets games change the graphics mid-frame without any CPU timing.
+// Reference: https://www.nesdev.org/wiki/MMC2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChrLatch {
+  FD,
+  FE,
+}
+
+#[derive(Clone)]
+pub struct Mapper009 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_bank: u8,
+  pub chr_bank_0_fd: u8,
+  pub chr_bank_0_fe: u8,
+  pub chr_bank_1_fd: u8,
+  pub chr_bank_1_fe: u8,
+  pub latch_0: ChrLatch,
+  pub latch_1: ChrLatch,
+  mirroring_mode: Option<MirroringMode>,
+}
+
+const MMC2_PRG_BANK_SIZE: u32 = 8192;
+const MMC2_CHR_BANK_SIZE: u32 = 4096;
+
+impl Mapper009 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper009 {
+    return Mapper009 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      prg_bank: 0,
+      chr_bank_0_fd: 0,
+      chr_bank_0_fe: 0,
+      chr_bank_1_fd: 0,
+      chr_bank_1_fe: 0,
+      latch_0: ChrLatch::FE,
+      latch_1: ChrLatch::FE,
+      mirroring_mode: None,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while MMC2 switches them in 8KB units
+  fn num_8k_prg_banks(&self) -> u32 {
+    return (self.num_PRG_banks as u32) * 2;
+  }
+
+  fn num_4k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks as u32) * 2;
+  }
+}
+
+impl Mapper for Mapper009 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xA000, 0xC000, 0xE000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x1FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  //     CPU Address Bus          PRG ROM
+  //     0x8000 -> 0x9FFF: Switchable 8KB bank
+  //     0xA000 -> 0xFFFF: Fixed to the last three 8KB banks
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let offset = (addr & 0x1FFF) as u32;
+      let bank = match addr {
+        0x8000..=0x9FFF => (self.prg_bank as u32) % self.num_8k_prg_banks(),
+        0xA000..=0xBFFF => self.num_8k_prg_banks() - 3,
+        0xC000..=0xDFFF => self.num_8k_prg_banks() - 2,
+        _ => self.num_8k_prg_banks() - 1,
+      };
+      return Ok(bank * MMC2_PRG_BANK_SIZE + offset);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      match addr {
+        0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+        0xB000..=0xBFFF => self.chr_bank_0_fd = data & 0x1F,
+        0xC000..=0xCFFF => self.chr_bank_0_fe = data & 0x1F,
+        0xD000..=0xDFFF => self.chr_bank_1_fd = data & 0x1F,
+        0xE000..=0xEFFF => self.chr_bank_1_fe = data & 0x1F,
+        0xF000..=0xFFFF => self.mirroring_mode = Some(if (data & 0x01) != 0 { MirroringMode::Horizontal } else { MirroringMode::Vertical }),
+        _ => {}
+      }
+      // PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  //     PPU Address Bus          CHR ROM
+  //     0x0000 -> 0x0FFF: 4KB bank selected by latch 0
+  //     0x1000 -> 0x1FFF: 4KB bank selected by latch 1
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let mapped_addr = self.mapDebugReadAddressFromPPU(addr)?;
+
+      // The latches switch after the tile has been fetched, so the read above still uses the previous bank.
+      // Latch 0 only reacts to the exact addresses, while latch 1 reacts to the whole 8 byte range.
+      match addr {
+        0x0FD8 => self.latch_0 = ChrLatch::FD,
+        0x0FE8 => self.latch_0 = ChrLatch::FE,
+        0x1FD8..=0x1FDF => self.latch_1 = ChrLatch::FD,
+        0x1FE8..=0x1FEF => self.latch_1 = ChrLatch::FE,
+        _ => {}
+      }
+      return Ok(mapped_addr);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, _addr: u16) -> Result<u32, String> {
+    return Err(String::from("MMC2 cartridges have no CHR RAM to write to!"));
+  }
+
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let offset = (addr & 0x0FFF) as u32;
+      let bank = if addr < 0x1000 {
+        if self.latch_0 == ChrLatch::FD { self.chr_bank_0_fd } else { self.chr_bank_0_fe }
+      } else {
+        if self.latch_1 == ChrLatch::FD { self.chr_bank_1_fd } else { self.chr_bank_1_fe }
+      };
+      return Ok(((bank as u32) % self.num_4k_chr_banks()) * MMC2_CHR_BANK_SIZE + offset);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    return self.mirroring_mode;
+  }
+}
+
+
+// MMC5, used by Castlevania III, Just Breed, Uncharted Waters and most of Koei's games
+// Only part of it is emulated: the PRG and CHR banking, ExRAM, the name table mapping and the scanline IRQ.
+// The audio channels, the extended attribute mode, the vertical split and PRG RAM banked into $8000-$DFFF aren't.
+// Its registers live in the expansion area at $5000-$5FFF, and it watches the PPU fetches to tell which scanline is
+// being drawn and whether the sprites or the background are being fetched.
+// Reference: https://www.nesdev.org/wiki/MMC5
+#[derive(Clone)]
+pub struct Mapper005 {
+  cpu_address_bounds: (u16, u16),
+  ppu_address_bounds: (u16, u16),
+  num_PRG_banks: u8,
+  num_CHR_banks: u8,
+
+  pub prg_mode: u8,
+  pub chr_mode: u8,
+  pub prg_ram_protect: [u8; 2],
+  pub ex_ram_mode: u8,
+  // Two bits per name table: 0 and 1 are the console's name tables, 2 is ExRAM and 3 is the fill mode tile
+  pub name_table_mapping: u8,
+  pub fill_tile: u8,
+  pub fill_attribute: u8,
+  pub prg_ram_bank: u8,
+  // $5114-$5117, in 8KB units. Bit 7 (ROM or RAM) is ignored, since RAM can't be banked into $8000-$DFFF here.
+  pub prg_banks: [u8; 4],
+  // $5120-$5127 (set A, sprites) and $5128-$512B (set B, background in 8x16 sprite mode)
+  pub chr_banks: [u8; 12],
+  // In 8x8 sprite mode, the set that was written last is used for every fetch
+  last_chr_set_written_b: bool,
+  pub ex_ram: Vec<u8>,
+  pub irq_target: u8,
+  pub irq_enabled: bool,
+  irq_pending: bool,
+  pub multiplicand: u8,
+  pub multiplier: u8,
+
+  // Snooped from the CPU writes to PPUCTRL
+  sprite_size_8x16: bool,
+  // Scanline detection: the PPU reads the same name table byte three times in a row at the start of each scanline
+  pub in_frame: bool,
+  pub scanline: u8,
+  last_ppu_read_addr: u16,
+  name_table_repeats: u8,
+  // Sprite detection: after the 32 background tiles, the pattern fetches are for the sprites until the next name table fetch
+  tiles_fetched: u8,
+  pattern_reads_since_name_table_read: u8,
+}
+
+const MMC5_PRG_BANK_SIZE: u32 = 8192;
+const MMC5_CHR_BANK_SIZE: u32 = 1024;
+const MMC5_EX_RAM_START_ADDR: u16 = 0x5C00;
+const MMC5_EX_RAM_SIZE: usize = 1024;
+// Reading the NMI vector means the frame is over
+const NMI_VECTOR_ADDRS: [u16; 2] = [0xFFFA, 0xFFFB];
+
+impl Mapper005 {
+  pub fn new(num_PRG_banks: u8, num_CHR_banks: u8) -> Mapper005 {
+    return Mapper005 {
+      cpu_address_bounds: (0x8000, 0xFFFF),
+      ppu_address_bounds: (0x0000, 0x1FFF),
+      num_PRG_banks,
+      num_CHR_banks,
+      // At power on, the last 8KB bank is mapped at $E000 so that the reset vector can be found
+      prg_mode: 3,
+      chr_mode: 0,
+      prg_ram_protect: [0, 0],
+      ex_ram_mode: 0,
+      name_table_mapping: 0,
+      fill_tile: 0,
+      fill_attribute: 0,
+      prg_ram_bank: 0,
+      prg_banks: [0, 0, 0, 0xFF],
+      chr_banks: [0; 12],
+      last_chr_set_written_b: false,
+      ex_ram: vec![0; MMC5_EX_RAM_SIZE],
+      irq_target: 0,
+      irq_enabled: false,
+      irq_pending: false,
+      multiplicand: 0xFF,
+      multiplier: 0xFF,
+      sprite_size_8x16: false,
+      in_frame: false,
+      scanline: 0,
+      last_ppu_read_addr: 0,
+      name_table_repeats: 0,
+      tiles_fetched: 0,
+      pattern_reads_since_name_table_read: 0,
+    }
+  }
+
+  // The header counts PRG banks in 16KB units, while MMC5 switches them in 8KB units
+  fn num_8k_prg_banks(&self) -> u32 {
+    return (self.num_PRG_banks as u32) * 2;
+  }
+
+  // Boards without CHR ROM have 8KB of CHR RAM, which is banked the same way
+  fn num_1k_chr_banks(&self) -> u32 {
+    return (self.num_CHR_banks.max(1) as u32) * 8;
+  }
+
+  // In 8x16 sprite mode the sprites use set A and the background set B, but outside of rendering (e.g. $2007
+  // accesses) and in 8x8 sprite mode the last set written is used
+  fn uses_chr_set_b(&self) -> bool {
+    if (self.sprite_size_8x16 && self.in_frame) {
+      return !self.fetching_sprites();
+    }
+    return self.last_chr_set_written_b;
+  }
+
+  fn fetching_sprites(&self) -> bool {
+    return self.tiles_fetched == 32 && self.pattern_reads_since_name_table_read > 2;
+  }
+
+  // Which of the four name table mappings an address in $2000-$2FFF uses
+  fn name_table_source(&self, addr: u16) -> u8 {
+    let logical_index = (addr >> 10) & 0x03;
+    return (self.name_table_mapping >> (logical_index * 2)) & 0x03;
+  }
+
+  fn watch_ppu_read(&mut self, addr: u16) {
+    let is_name_table_read = (addr & 0x3000) == 0x2000;
+    if (is_name_table_read) {
+      self.name_table_repeats = if (addr == self.last_ppu_read_addr) { self.name_table_repeats.saturating_add(1) } else { 0 };
+      self.pattern_reads_since_name_table_read = 0;
+      if (self.name_table_repeats == 2) {
+        self.start_scanline();
+      } else if ((addr & 0x03FF) < 0x03C0) {
+        self.tiles_fetched = self.tiles_fetched.saturating_add(1);
+      }
+    } else {
+      self.name_table_repeats = 0;
+      self.pattern_reads_since_name_table_read = self.pattern_reads_since_name_table_read.saturating_add(1);
+    }
+    self.last_ppu_read_addr = addr;
+  }
+
+  fn start_scanline(&mut self) {
+    // The read that completes the detection is also the first tile fetch of the scanline
+    self.tiles_fetched = 1;
+    if (!self.in_frame) {
+      self.in_frame = true;
+      self.scanline = 0;
+      self.irq_pending = false;
+    } else {
+      self.scanline = self.scanline.wrapping_add(1);
+      if (self.scanline == self.irq_target) {
+        self.irq_pending = true;
+      }
+    }
+  }
+
+  fn write_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x5100 => self.prg_mode = data & 0x03,
+      0x5101 => self.chr_mode = data & 0x03,
+      0x5102 => self.prg_ram_protect[0] = data & 0x03,
+      0x5103 => self.prg_ram_protect[1] = data & 0x03,
+      0x5104 => self.ex_ram_mode = data & 0x03,
+      0x5105 => self.name_table_mapping = data,
+      0x5106 => self.fill_tile = data,
+      0x5107 => self.fill_attribute = data & 0x03,
+      0x5113 => self.prg_ram_bank = data & 0x07,
+      0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = data & 0x7F,
+      0x5120..=0x512B => {
+        self.chr_banks[(addr - 0x5120) as usize] = data;
+        self.last_chr_set_written_b = addr >= 0x5128;
+      },
+      0x5203 => self.irq_target = data,
+      0x5204 => self.irq_enabled = (data & 0x80) != 0,
+      0x5205 => self.multiplicand = data,
+      0x5206 => self.multiplier = data,
+      // ExRAM can only be written by the CPU in modes 0-2, since mode 3 makes it read only
+      MMC5_EX_RAM_START_ADDR..=0x5FFF if self.ex_ram_mode != 3 => self.ex_ram[(addr - MMC5_EX_RAM_START_ADDR) as usize] = data,
+      // Audio and the registers that aren't emulated
+      _ => {}
+    }
+  }
+}
+
+impl Mapper for Mapper005 {
+
+  fn cloneMapper(&self) -> Box<dyn Mapper> {
+    return Box::new(self.clone());
+  }
+
+  fn in_cpu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.cpu_address_bounds.0 && addr <= self.cpu_address_bounds.1;
+  }
+
+  fn in_ppu_address_bounds(&self, addr:u16) -> bool {
+    return addr >= self.ppu_address_bounds.0 && addr <= self.ppu_address_bounds.1;
+  }
+
+  fn current_prg_banks(&self) -> Vec<(u16, u16, u32)> {
+    return [0x8000, 0xA000, 0xC000, 0xE000].iter()
+      .map(|start_addr| (*start_addr, *start_addr + 0x1FFF, self.mapDebugReadAddressFromCPU(*start_addr).unwrap()))
+      .collect();
+  }
+
+  fn mapReadAddressFromCPU(&mut self, addr: u16) -> Result<u32, String> {
+    if NMI_VECTOR_ADDRS.contains(&addr) {
+      self.in_frame = false;
+    }
+    return self.mapDebugReadAddressFromCPU(addr);
+  }
+
+  //     PRG mode     0x8000 -> 0x9FFF    0xA000 -> 0xBFFF    0xC000 -> 0xDFFF    0xE000 -> 0xFFFF
+  //        0         32KB bank selected by $5117
+  //        1         16KB bank selected by $5115             16KB bank selected by $5117
+  //        2         16KB bank selected by $5115             8KB bank ($5116)    8KB bank ($5117)
+  //        3         8KB bank ($5114)    8KB bank ($5115)    8KB bank ($5116)    8KB bank ($5117)
+  fn mapDebugReadAddressFromCPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_cpu_address_bounds(addr) {
+      let window = ((addr - 0x8000) >> 13) as u8;
+      let bank = match (self.prg_mode, window) {
+        (0, _) => (self.prg_banks[3] & 0x7C) | window,
+        (1, 0..=1) | (2, 0..=1) => (self.prg_banks[1] & 0x7E) | (window & 0x01),
+        (1, _) => (self.prg_banks[3] & 0x7E) | (window & 0x01),
+        _ => self.prg_banks[window as usize],
+      };
+      return Ok(((bank as u32) % self.num_8k_prg_banks()) * MMC5_PRG_BANK_SIZE + (addr & 0x1FFF) as u32);
+    } else {
+      return Err(String::from("Mapper received a CPU read address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromCPU(&mut self, addr: u16, data: u8) -> Result<Option<u32>, String> {
+    if self.in_cpu_address_bounds(addr) {
+      // All the registers are in the expansion area, and PRG is ROM, so writes never reach it
+      return Ok(None);
+    } else {
+      return Err(String::from("Mapper received a CPU write address outside of CPU bounds!"));
+    }
+  }
+
+  fn mapReadAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      self.watch_ppu_read(addr);
+      return self.mapDebugReadAddressFromPPU(addr);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  // The CHR mode splits the 8KB into 1 to 8 banks, each one selected by the register of its last KB
+  // ($5127 for 8KB banks, $5123/$5127 for 4KB ones, and so on). Set B only has four registers, so it covers 4KB and is repeated.
+  fn mapDebugReadAddressFromPPU(&self, addr: u16) -> Result<u32, String> {
+    if self.in_ppu_address_bounds(addr) {
+      let bank_size_kb = 8 >> self.chr_mode;
+      let bank_size = MMC5_CHR_BANK_SIZE * bank_size_kb;
+      let register = if self.uses_chr_set_b() {
+        8 + (((((addr & 0x0FFF) as u32) / bank_size + 1) * bank_size_kb - 1) & 0x03)
+      } else {
+        ((addr as u32) / bank_size + 1) * bank_size_kb - 1
+      };
+      let num_banks = (self.num_1k_chr_banks() / bank_size_kb).max(1);
+      let bank = (self.chr_banks[register as usize] as u32) % num_banks;
+      return Ok(bank * bank_size + (addr as u32) % bank_size);
+    } else {
+      return Err(String::from("Mapper received a PPU read address outside of PPU bounds!"));
+    }
+  }
+
+  fn mapWriteAddressFromPPU(&mut self, addr: u16) -> Result<u32, String> {
+    if self.num_CHR_banks > 0 {
+      return Err(String::from("This MMC5 cartridge has CHR ROM, which can't be written to!"));
+    }
+    return self.mapDebugReadAddressFromPPU(addr);
+  }
+
+  // Only the name tables that point to the console's memory go through the mirroring mode. The ones mapped to ExRAM
+  // or the fill mode never reach it, so they're counted as table 0.
+  fn mirroring_mode(&self) -> Option<MirroringMode> {
+    let tables = [0, 1, 2, 3].map(|logical_index| {
+      let source = (self.name_table_mapping >> (logical_index * 2)) & 0x03;
+      if (source <= 1) { source } else { 0 }
+    });
+    return Some(match tables {
+      [0, 1, 0, 1] => MirroringMode::Vertical,
+      [0, 0, 1, 1] => MirroringMode::Horizontal,
+      [0, 0, 0, 0] => MirroringMode::OnscreenLo,
+      [1, 1, 1, 1] => MirroringMode::OnscreenHi,
+      _ => MirroringMode::PerTable(tables),
+    });
+  }
+
+  fn mapReadAddressFromPrgRam(&self, addr: u16) -> Option<u32> {
+    return Some((self.prg_ram_bank as u32) * MMC5_PRG_BANK_SIZE + (addr - PRG_RAM_START_ADDR) as u32);
+  }
+
+  // Writes are only allowed after the game writes the two magic values to $5102 and $5103
+  fn mapWriteAddressFromPrgRam(&mut self, addr: u16, data: u8) -> Option<u32> {
+    if self.prg_ram_protect != [0b10, 0b01] {
+      return None;
+    }
+    return self.mapReadAddressFromPrgRam(addr);
+  }
+
+  fn irq_pending(&self) -> bool {
+    return self.irq_pending && self.irq_enabled;
+  }
+
+  fn in_expansion_area_bounds(&self, addr: u16) -> bool {
+    return addr >= 0x5000 && addr <= 0x5FFF;
+  }
+
+  // Reading the IRQ status acknowledges the IRQ
+  fn read_expansion_area(&mut self, addr: u16) -> u8 {
+    let data = self.read_expansion_area_debug(addr);
+    if (addr == 0x5204) {
+      self.irq_pending = false;
+    }
+    return data;
+  }
+
+  fn read_expansion_area_debug(&self, addr: u16) -> u8 {
+    return match addr {
+      0x5204 => ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6),
+      0x5205 => ((self.multiplicand as u16) * (self.multiplier as u16)) as u8,
+      0x5206 => (((self.multiplicand as u16) * (self.multiplier as u16)) >> 8) as u8,
+      // The CPU can only read ExRAM in modes 2 and 3, the other two keep it for the PPU
+      MMC5_EX_RAM_START_ADDR..=0x5FFF if self.ex_ram_mode >= 2 => self.ex_ram[(addr - MMC5_EX_RAM_START_ADDR) as usize],
+      _ => 0,
+    };
+  }
+
+  fn write_expansion_area(&mut self, addr: u16, data: u8) {
+    self.write_register(addr, data);
+  }
+
+  fn read_name_table(&mut self, addr: u16) -> Option<u8> {
+    self.watch_ppu_read(addr);
+    return self.read_name_table_debug(addr);
+  }
+
+  // ExRAM only works as a name table in modes 0 and 1, and reads as 0 otherwise.
+  // The fill mode attribute repeats its two bits for the four tiles each attribute byte covers.
+  fn read_name_table_debug(&self, addr: u16) -> Option<u8> {
+    let offset = (addr & 0x03FF) as usize;
+    return match self.name_table_source(addr) {
+      2 => Some(if self.ex_ram_mode <= 1 { self.ex_ram[offset] } else { 0 }),
+      3 => Some(if offset < 0x03C0 { self.fill_tile } else { self.fill_attribute * 0x55 }),
+      _ => None,
+    };
+  }
+
+  fn write_name_table(&mut self, addr: u16, data: u8) -> bool {
+    return match self.name_table_source(addr) {
+      2 => {
+        if (self.ex_ram_mode <= 1) {
+          self.ex_ram[(addr & 0x03FF) as usize] = data;
+        }
+        true
+      },
+      // The fill mode isn't memory, so writes go nowhere
+      3 => true,
+      _ => false,
+    };
+  }
+
+  fn ppu_control_written(&mut self, data: u8) {
+    self.sprite_size_8x16 = (data & 0x20) != 0;
+  }
+}
+
+
+#[cfg(test)]
+mod mapper_tests {
+  use super::{Mapper, Mapper000, Mapper001, Mapper002, Mapper004, Mapper005, Mapper007, Mapper009, Mapper011, Mapper034, Mapper066, Mapper071, Mapper206, ChrLatch};
+  use crate::cartridge::MirroringMode;
+
+  // Writes a whole MMC1 register through the shift register, one bit per write
+  fn write_mmc1_register(mapper: &mut Mapper001, addr: u16, value: u8) {
+    for i in 0..5 {
+      assert_eq!(mapper.mapWriteAddressFromCPU(addr, (value >> i) & 0x01).unwrap(), None);
+    }
+  }
+
+  #[test]
+  fn test_mmc1_serial_writes() {
+    let mut mapper = Mapper001::new(8, 16);
+    // Only the fifth write reaches the register, and the address of the others doesn't matter
+    for addr in [0xE000, 0x8000, 0xA000, 0xC000] {
+      mapper.mapWriteAddressFromCPU(addr, 0x01).unwrap();
+      assert_eq!(mapper.prg_bank, 0);
+    }
+    mapper.mapWriteAddressFromCPU(0xE000, 0x00).unwrap();
+    assert_eq!(mapper.prg_bank, 0x0F);
+    assert_eq!(mapper.shift_count, 0);
+
+    // Only bit 0 of each write is shifted in
+    write_mmc1_register(&mut mapper, 0xA000, 0x15);
+    assert_eq!(mapper.chr_bank_0, 0x15);
+    for _ in 0..5 {
+      mapper.mapWriteAddressFromCPU(0xC000, 0xFE).unwrap();
+    }
+    assert_eq!(mapper.chr_bank_1, 0);
+  }
+
+  #[test]
+  fn test_mmc1_reset_bit() {
+    let mut mapper = Mapper001::new(8, 16);
+    write_mmc1_register(&mut mapper, 0x8000, 0x00);
+    assert_eq!(mapper.control, 0x00);
+
+    // Writing bit 7 drops the bits shifted in so far, and goes back to PRG mode 3
+    mapper.mapWriteAddressFromCPU(0xE000, 0x01).unwrap();
+    mapper.mapWriteAddressFromCPU(0xE000, 0x01).unwrap();
+    mapper.mapWriteAddressFromCPU(0x8000, 0x80).unwrap();
+    assert_eq!(mapper.shift_count, 0);
+    assert_eq!(mapper.control, 0x0C);
+    write_mmc1_register(&mut mapper, 0xE000, 0x02);
+    assert_eq!(mapper.prg_bank, 0x02);
+  }
+
+  #[test]
+  fn test_mmc1_prg_banking() {
+    // 128KB of PRG ROM, like Zelda
+    let mut mapper = Mapper001::new(8, 0);
+    // Mode 3 on power up: switchable bank at $8000, last bank at $C000
+    write_mmc1_register(&mut mapper, 0xE000, 5);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 5 * 0x4000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xBFFF).unwrap(), 5 * 0x4000 + 0x3FFF);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xC000).unwrap(), 7 * 0x4000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 7 * 0x4000 + 0x3FFF);
+
+    // Mode 2: first bank at $8000, switchable bank at $C000
+    write_mmc1_register(&mut mapper, 0x8000, 0x08);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xBFFF, 0), (0xC000, 0xFFFF, 5 * 0x4000)]);
+
+    // Modes 0 and 1: 32KB banks, ignoring the low bit of the bank number
+    for control in [0x00, 0x04] {
+      write_mmc1_register(&mut mapper, 0x8000, control);
+      assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xBFFF, 4 * 0x4000), (0xC000, 0xFFFF, 5 * 0x4000)]);
+    }
+
+    // Bank numbers past the end of PRG ROM wrap around
+    write_mmc1_register(&mut mapper, 0x8000, 0x0C);
+    write_mmc1_register(&mut mapper, 0xE000, 9);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 1 * 0x4000);
+  }
+
+  #[test]
+  fn test_mmc1_chr_banking() {
+    // 128KB of CHR ROM
+    let mut mapper = Mapper001::new(8, 16);
+    write_mmc1_register(&mut mapper, 0xA000, 3);
+    write_mmc1_register(&mut mapper, 0xC000, 6);
+
+    // 8KB mode ignores CHR bank 1 and the low bit of CHR bank 0
+    write_mmc1_register(&mut mapper, 0x8000, 0x0C);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 2 * 0x1000 + 0x10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 3 * 0x1000 + 0x10);
+
+    // 4KB mode
+    write_mmc1_register(&mut mapper, 0x8000, 0x1C);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 3 * 0x1000 + 0x10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 6 * 0x1000 + 0x10);
+
+    // CHR ROM can't be written, but CHR RAM can, and it's banked the same way
+    assert!(mapper.mapWriteAddressFromPPU(0x0010).is_err());
+    let mut mapper = Mapper001::new(8, 0);
+    write_mmc1_register(&mut mapper, 0x8000, 0x1C);
+    write_mmc1_register(&mut mapper, 0xA000, 1);
+    assert_eq!(mapper.mapWriteAddressFromPPU(0x0010).unwrap(), 0x1000 + 0x10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 0x0010);
+  }
+
+  #[test]
+  fn test_mmc1_mirroring() {
+    let mut mapper = Mapper001::new(8, 16);
+    for (control, mirroring_mode) in [(0x00, MirroringMode::OnscreenLo), (0x01, MirroringMode::OnscreenHi),
+                                       (0x02, MirroringMode::Vertical), (0x03, MirroringMode::Horizontal)] {
+      write_mmc1_register(&mut mapper, 0x8000, control);
+      assert_eq!(mapper.mirroring_mode(), Some(mirroring_mode));
+    }
+  }
+
+  #[test]
+  fn test_mmc1_prg_ram_enable() {
+    let mut mapper = Mapper001::new(8, 0);
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x6010, 0), Some(0x10));
+    write_mmc1_register(&mut mapper, 0xE000, 0x10);
+    assert_eq!(mapper.mapReadAddressFromPrgRam(0x6010), None);
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x6010, 0), None);
+    write_mmc1_register(&mut mapper, 0xE000, 0x00);
+    assert_eq!(mapper.mapReadAddressFromPrgRam(0x6010), Some(0x10));
+  }
+
+  // 128KB of PRG ROM and 128KB of CHR ROM, like Punch-Out!!
+  fn mmc2_with_chr_banks() -> Mapper009 {
+    let mut mapper = Mapper009::new(8, 16);
+    mapper.mapWriteAddressFromCPU(0xB000, 1).unwrap();
+    mapper.mapWriteAddressFromCPU(0xC000, 2).unwrap();
+    mapper.mapWriteAddressFromCPU(0xD000, 3).unwrap();
+    mapper.mapWriteAddressFromCPU(0xE000, 4).unwrap();
+    return mapper;
+  }
+
+  #[test]
+  fn test_uxrom_prg_banking() {
+    let mut mapper = Mapper002::new(8, 0);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xBFFF, 0), (0xC000, 0xFFFF, 7 * 0x4000)]);
+    assert_eq!(mapper.mapWriteAddressFromCPU(0x8000, 3).unwrap(), None);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 3 * 0x4000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xBFFF).unwrap(), 3 * 0x4000 + 0x3FFF);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xC000).unwrap(), 7 * 0x4000);
+    // CHR RAM isn't banked
+    assert_eq!(mapper.mapWriteAddressFromPPU(0x1234).unwrap(), 0x1234);
+  }
+
+  // 256KB of PRG ROM and 128KB of CHR ROM, like SMB3
+  fn mmc3_with_banks() -> Mapper004 {
+    let mut mapper = Mapper004::new(16, 16);
+    for (register, bank) in [0x10, 0x13, 0x20, 0x21, 0x22, 0x23, 5, 9].iter().enumerate() {
+      mapper.mapWriteAddressFromCPU(0x8000, register as u8).unwrap();
+      assert_eq!(mapper.mapWriteAddressFromCPU(0x8001, *bank).unwrap(), None);
+    }
+    return mapper;
+  }
+
+  #[test]
+  fn test_mmc3_prg_banking() {
+    let mut mapper = mmc3_with_banks();
+    assert_eq!(mapper.current_prg_banks(), vec![
+      (0x8000, 0x9FFF, 5 * 0x2000),
+      (0xA000, 0xBFFF, 9 * 0x2000),
+      (0xC000, 0xDFFF, 30 * 0x2000),
+      (0xE000, 0xFFFF, 31 * 0x2000),
+    ]);
+    // PRG mode 1 swaps $8000 and $C000
+    mapper.mapWriteAddressFromCPU(0x8000, 0x40).unwrap();
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 30 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xA000).unwrap(), 9 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xDFFF).unwrap(), 5 * 0x2000 + 0x1FFF);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xE000).unwrap(), 31 * 0x2000);
+  }
+
+  #[test]
+  fn test_mmc3_chr_banking() {
+    let mut mapper = mmc3_with_banks();
+    // The 2KB banks ignore the low bit of their register
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0000).unwrap(), 0x10 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0400).unwrap(), 0x11 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0C05).unwrap(), 0x13 * 0x400 + 0x05);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1000).unwrap(), 0x20 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1FFF).unwrap(), 0x23 * 0x400 + 0x3FF);
+
+    // With the A12 inversion, the 1KB banks come first
+    mapper.mapWriteAddressFromCPU(0x8000, 0x80).unwrap();
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0000).unwrap(), 0x20 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0C00).unwrap(), 0x23 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1000).unwrap(), 0x10 * 0x400);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1C00).unwrap(), 0x13 * 0x400);
+  }
+
+  #[test]
+  fn test_namco_108_banking_matches_mmc3() {
+    let mut mmc3 = mmc3_with_banks();
+    let mut namco_108 = Mapper206::new(16, 16);
+    for (register, bank) in [0x10, 0x13, 0x20, 0x21, 0x22, 0x23, 5, 9].iter().enumerate() {
+      namco_108.mapWriteAddressFromCPU(0x8000, register as u8).unwrap();
+      assert_eq!(namco_108.mapWriteAddressFromCPU(0x8001, *bank).unwrap(), None);
+    }
+    assert_eq!(namco_108.current_prg_banks(), mmc3.current_prg_banks());
+    for addr in (0x0000..=0x1FFF).step_by(0x100) {
+      assert_eq!(namco_108.mapReadAddressFromPPU(addr).unwrap(), mmc3.mapReadAddressFromPPU(addr).unwrap());
+    }
+    assert!(namco_108.mapWriteAddressFromPPU(0x0000).is_err());
+
+    // There's no PRG mode or A12 inversion to select, nor any of the other MMC3 registers
+    namco_108.mapWriteAddressFromCPU(0x8000, 0xC6).unwrap();
+    assert_eq!(namco_108.banks.bank_select, 0x06);
+    assert_eq!(namco_108.current_prg_banks()[0], (0x8000, 0x9FFF, 5 * 0x2000));
+    assert_eq!(namco_108.mapReadAddressFromPPU(0x0000).unwrap(), 0x10 * 0x400);
+    for addr in [0xA000, 0xA001, 0xC000, 0xC001, 0xE000, 0xE001] {
+      namco_108.mapWriteAddressFromCPU(addr, 0xFF).unwrap();
+    }
+    assert!(namco_108.mirroring_mode().is_none());
+    assert!(!namco_108.irq_pending());
+    assert_eq!(namco_108.mapWriteAddressFromPrgRam(0x6000, 0), Some(0));
+    assert_eq!(namco_108.banks.bank_registers, [0x10, 0x13, 0x20, 0x21, 0x22, 0x23, 5, 9]);
+  }
+
+  #[test]
+  fn test_mmc3_mirroring_and_prg_ram_protect() {
+    let mut mapper = Mapper004::new(16, 16);
+    assert!(mapper.mirroring_mode().is_none());
+    mapper.mapWriteAddressFromCPU(0xA000, 0x01).unwrap();
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::Horizontal));
+    mapper.mapWriteAddressFromCPU(0xBFFE, 0x00).unwrap();
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::Vertical));
+
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x6000, 0), Some(0));
+    // Enabled, but write protected
+    mapper.mapWriteAddressFromCPU(0xA001, 0xC0).unwrap();
+    assert_eq!(mapper.mapReadAddressFromPrgRam(0x6000), Some(0));
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x6000, 0), None);
+    mapper.mapWriteAddressFromCPU(0xA001, 0x00).unwrap();
+    assert_eq!(mapper.mapReadAddressFromPrgRam(0x6000), None);
+  }
+
+  // One scanline's worth of pattern fetches: background from $0000 and sprites from $1000
+  fn fetch_scanline(mapper: &mut Mapper004) {
+    mapper.mapReadAddressFromPPU(0x0123).unwrap();
+    mapper.mapReadAddressFromPPU(0x1FF0).unwrap();
+    mapper.mapReadAddressFromPPU(0x1FF8).unwrap();
+  }
+
+  #[test]
+  fn test_mmc3_irq_counter() {
+    let mut mapper = Mapper004::new(16, 16);
+    mapper.mapWriteAddressFromCPU(0xC000, 3).unwrap();
+    mapper.mapWriteAddressFromCPU(0xC001, 0).unwrap();
+    mapper.mapWriteAddressFromCPU(0xE001, 0).unwrap();
+
+    // The first rise reloads the counter, and the IRQ fires once it counts down to 0
+    let mut irq_scanlines = vec![];
+    for scanline in 0..10 {
+      fetch_scanline(&mut mapper);
+      if mapper.irq_pending() {
+        irq_scanlines.push(scanline);
+        // Acknowledge it, and enable it again
+        mapper.mapWriteAddressFromCPU(0xE000, 0).unwrap();
+        mapper.mapWriteAddressFromCPU(0xE001, 0).unwrap();
+      }
+    }
+    assert_eq!(irq_scanlines, vec![3, 7]);
+
+    // A12 staying high doesn't clock the counter
+    let counter = mapper.irq_counter;
+    mapper.mapReadAddressFromPPU(0x1000).unwrap();
+    mapper.mapReadAddressFromPPU(0x1008).unwrap();
+    assert_eq!(mapper.irq_counter, counter);
+    // Neither do debug reads
+    mapper.mapReadAddressFromPPU(0x0000).unwrap();
+    mapper.mapDebugReadAddressFromPPU(0x1000).unwrap();
+    assert_eq!(mapper.irq_counter, counter);
+
+    // While disabled, the counter keeps going but no IRQ is raised
+    mapper.mapWriteAddressFromCPU(0xE000, 0).unwrap();
+    for _ in 0..8 {
+      fetch_scanline(&mut mapper);
+      assert!(!mapper.irq_pending());
+    }
+  }
+
+  #[test]
+  fn test_mmc3_irq_with_zero_latch() {
+    for (alternate_irq_behavior, expected_irqs) in [(false, 5), (true, 1)] {
+      let mut mapper = Mapper004::new(16, 16);
+      mapper.alternate_irq_behavior = alternate_irq_behavior;
+      mapper.mapWriteAddressFromCPU(0xC000, 0).unwrap();
+      mapper.mapWriteAddressFromCPU(0xC001, 0).unwrap();
+      mapper.mapWriteAddressFromCPU(0xE001, 0).unwrap();
+      let mut irqs = 0;
+      for _ in 0..5 {
+        fetch_scanline(&mut mapper);
+        if mapper.irq_pending() {
+          irqs += 1;
+          mapper.mapWriteAddressFromCPU(0xE000, 0).unwrap();
+          mapper.mapWriteAddressFromCPU(0xE001, 0).unwrap();
+        }
+      }
+      assert_eq!(irqs, expected_irqs);
+    }
+  }
+
+  #[test]
+  fn test_axrom_banking_and_mirroring() {
+    // 256KB of PRG ROM, like Battletoads
+    let mut mapper = Mapper007::new(16, 0);
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::OnscreenLo));
+    assert_eq!(mapper.mapWriteAddressFromCPU(0x8000, 0x15).unwrap(), None);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 5 * 0x8000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 5 * 0x8000 + 0x7FFF);
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::OnscreenHi));
+    mapper.mapWriteAddressFromCPU(0xFFFF, 0x07).unwrap();
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xFFFF, 7 * 0x8000)]);
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::OnscreenLo));
+  }
+
+  #[test]
+  fn test_color_dreams_prg_banking() {
+    // 128KB of PRG ROM, the most the register can address
+    let mut mapper = Mapper011::new(8, 16);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 0);
+    assert_eq!(mapper.mapWriteAddressFromCPU(0xC123, 0x03).unwrap(), None);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 3 * 0x8000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 3 * 0x8000 + 0x7FFF);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xFFFF, 3 * 0x8000)]);
+
+    // Bank numbers past the end of PRG ROM wrap around
+    let mut mapper = Mapper011::new(4, 1);
+    mapper.mapWriteAddressFromCPU(0x8000, 0x03).unwrap();
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 0x8000);
+  }
+
+  #[test]
+  fn test_color_dreams_chr_banking() {
+    let mut mapper = Mapper011::new(8, 16);
+    // The upper nibble selects the CHR bank, and doesn't affect the PRG bank
+    mapper.mapWriteAddressFromCPU(0x8000, 0xA1).unwrap();
+    assert_eq!(mapper.prg_bank, 1);
+    assert_eq!(mapper.chr_bank, 10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0000).unwrap(), 10 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1FFF).unwrap(), 10 * 0x2000 + 0x1FFF);
+    assert!(mapper.mapWriteAddressFromPPU(0x0000).is_err());
+    assert!(mapper.mirroring_mode().is_none());
+  }
+
+  #[test]
+  fn test_bnrom_banking() {
+    // 128KB of PRG ROM and CHR RAM
+    let mut mapper = Mapper034::new(8, 0);
+    assert!(!mapper.is_nina_001());
+    assert_eq!(mapper.mapWriteAddressFromCPU(0x8000, 0x02).unwrap(), None);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xFFFF, 2 * 0x8000)]);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 2 * 0x8000 + 0x7FFF);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1234).unwrap(), 0x1234);
+    assert_eq!(mapper.mapWriteAddressFromPPU(0x1234).unwrap(), 0x1234);
+
+    // The NINA-001 registers are plain RAM here
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x7FFD, 0x01), Some(0x1FFD));
+    assert_eq!(mapper.prg_bank, 2);
+  }
+
+  #[test]
+  fn test_nina_001_banking() {
+    // 64KB of PRG ROM and 64KB of CHR ROM
+    let mut mapper = Mapper034::new(4, 8);
+    assert!(mapper.is_nina_001());
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x7FFD, 0x01), Some(0x1FFD));
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x7FFE, 0x05), Some(0x1FFE));
+    assert_eq!(mapper.mapWriteAddressFromPrgRam(0x7FFF, 0x0C), Some(0x1FFF));
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 0x8000);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0000).unwrap(), 5 * 0x1000);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1FFF).unwrap(), 12 * 0x1000 + 0xFFF);
+    assert!(mapper.mapWriteAddressFromPPU(0x0000).is_err());
+
+    // Writes to ROM don't select banks on this board
+    mapper.mapWriteAddressFromCPU(0x8000, 0x00).unwrap();
+    assert_eq!(mapper.prg_bank, 1);
+  }
+
+  #[test]
+  fn test_camerica_banking() {
+    let mut mapper = Mapper071::new(8, 0);
+    // Writes below $C000 don't select the bank
+    mapper.mapWriteAddressFromCPU(0x8000, 0x03).unwrap();
+    assert_eq!(mapper.prg_bank, 0);
+    assert_eq!(mapper.mapWriteAddressFromCPU(0xC000, 0x03).unwrap(), None);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xBFFF, 3 * 0x4000), (0xC000, 0xFFFF, 7 * 0x4000)]);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xBFFF).unwrap(), 3 * 0x4000 + 0x3FFF);
+    assert_eq!(mapper.mapWriteAddressFromPPU(0x1234).unwrap(), 0x1234);
+
+    mapper.mapWriteAddressFromCPU(0x9000, 0x10).unwrap();
+    assert!(mapper.mirroring_mode().is_none());
+    mapper.mirroring_control = true;
+    mapper.mapWriteAddressFromCPU(0x9000, 0x10).unwrap();
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::OnscreenHi));
+    mapper.mapWriteAddressFromCPU(0x9FFF, 0x00).unwrap();
+    assert_eq!(mapper.mirroring_mode(), Some(MirroringMode::OnscreenLo));
+    assert_eq!(mapper.prg_bank, 3);
+  }
+
+  #[test]
+  fn test_gxrom_banking() {
+    // 128KB of PRG ROM and 32KB of CHR ROM, the most the register can address
+    let mut mapper = Mapper066::new(8, 4);
+    assert_eq!(mapper.mapWriteAddressFromCPU(0x8000, 0x21).unwrap(), None);
+    assert_eq!(mapper.current_prg_banks(), vec![(0x8000, 0xFFFF, 2 * 0x8000)]);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 2 * 0x8000 + 0x7FFF);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0000).unwrap(), 0x2000);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1FFF).unwrap(), 0x2000 + 0x1FFF);
+    assert!(mapper.mapWriteAddressFromPPU(0x0000).is_err());
+  }
+
+  #[test]
+  fn test_mmc2_prg_banking() {
+    let mut mapper = Mapper009::new(8, 16);
+    assert_eq!(mapper.mapWriteAddressFromCPU(0xA000, 5).unwrap(), None);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x8000).unwrap(), 5 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0x9FFF).unwrap(), 5 * 0x2000 + 0x1FFF);
+    // The last three 8KB banks are fixed
+    assert_eq!(mapper.mapReadAddressFromCPU(0xA000).unwrap(), 13 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xC000).unwrap(), 14 * 0x2000);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xFFFF).unwrap(), 15 * 0x2000 + 0x1FFF);
+  }
+
+  #[test]
+  fn test_prg_bank_layout() {
+    assert_eq!(Mapper000::new(1, 1).current_prg_banks(), vec![(0x8000, 0xBFFF, 0), (0xC000, 0xFFFF, 0)]);
+    assert_eq!(Mapper000::new(2, 1).current_prg_banks(), vec![(0x8000, 0xFFFF, 0)]);
+
+    let mut mapper = Mapper009::new(8, 16);
+    mapper.mapWriteAddressFromCPU(0xA000, 5).unwrap();
+    assert_eq!(mapper.current_prg_banks(), vec![
+      (0x8000, 0x9FFF, 5 * 0x2000),
+      (0xA000, 0xBFFF, 13 * 0x2000),
+      (0xC000, 0xDFFF, 14 * 0x2000),
+      (0xE000, 0xFFFF, 15 * 0x2000),
+    ]);
+  }
+
+  #[test]
+  fn test_mmc2_latch_0_switching() {
+    let mut mapper = mmc2_with_chr_banks();
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 2 * 0x1000 + 0x10);
+
+    // The read that triggers the latch still uses the previous bank
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0FD8).unwrap(), 2 * 0x1000 + 0xFD8);
+    assert_eq!(mapper.latch_0, ChrLatch::FD);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 1 * 0x1000 + 0x10);
+
+    // Only the exact address triggers latch 0
+    mapper.mapReadAddressFromPPU(0x0FE9).unwrap();
+    assert_eq!(mapper.latch_0, ChrLatch::FD);
+    mapper.mapReadAddressFromPPU(0x0FE8).unwrap();
+    assert_eq!(mapper.latch_0, ChrLatch::FE);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 2 * 0x1000 + 0x10);
+
+    // Latch 1 is unaffected
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 4 * 0x1000 + 0x10);
+  }
+
+  #[test]
+  fn test_mmc2_latch_1_switching() {
+    let mut mapper = mmc2_with_chr_banks();
+    mapper.mapReadAddressFromPPU(0x1FDD).unwrap();
+    assert_eq!(mapper.latch_1, ChrLatch::FD);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 3 * 0x1000 + 0x10);
+    mapper.mapReadAddressFromPPU(0x1FEF).unwrap();
+    assert_eq!(mapper.latch_1, ChrLatch::FE);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 4 * 0x1000 + 0x10);
+    assert_eq!(mapper.latch_0, ChrLatch::FE);
+  }
+
+  #[test]
+  fn test_mmc2_debug_reads_leave_latches_alone() {
+    let mut mapper = mmc2_with_chr_banks();
+    assert_eq!(mapper.mapDebugReadAddressFromPPU(0x0FD8).unwrap(), 2 * 0x1000 + 0xFD8);
+    assert_eq!(mapper.mapDebugReadAddressFromPPU(0x1FD8).unwrap(), 4 * 0x1000 + 0xFD8);
+    assert_eq!(mapper.latch_0, ChrLatch::FE);
+    assert_eq!(mapper.latch_1, ChrLatch::FE);
+  }
+
+  #[test]
+  fn test_mmc2_mirroring() {
+    let mut mapper = Mapper009::new(8, 16);
+    assert!(mapper.mirroring_mode().is_none());
+    mapper.mapWriteAddressFromCPU(0xF000, 1).unwrap();
+    assert!(matches!(mapper.mirroring_mode(), Some(MirroringMode::Horizontal)));
+    mapper.mapWriteAddressFromCPU(0xF000, 0).unwrap();
+    assert!(matches!(mapper.mirroring_mode(), Some(MirroringMode::Vertical)));
+  }
+
+  fn prg_bank_offsets(mapper: &Mapper005) -> Vec<u32> {
+    return mapper.current_prg_banks().iter().map(|(_, _, offset)| offset / 0x2000).collect();
+  }
+
+  #[test]
+  fn test_mmc5_prg_banking() {
+    // 256KB of PRG ROM, so 32 banks of 8KB
+    let mut mapper = Mapper005::new(16, 16);
+    // The last bank is mapped at $E000 on power on
+    assert_eq!(prg_bank_offsets(&mapper)[3], 31);
+
+    mapper.write_expansion_area(0x5114, 0x81);
+    mapper.write_expansion_area(0x5115, 0x85);
+    mapper.write_expansion_area(0x5116, 0x87);
+    mapper.write_expansion_area(0x5117, 0x8B);
+    // Mode 0: a 32KB bank, ignoring the two low bits of $5117
+    mapper.write_expansion_area(0x5100, 0);
+    assert_eq!(prg_bank_offsets(&mapper), vec![8, 9, 10, 11]);
+    // Mode 1: two 16KB banks, ignoring the low bit of $5115 and $5117
+    mapper.write_expansion_area(0x5100, 1);
+    assert_eq!(prg_bank_offsets(&mapper), vec![4, 5, 10, 11]);
+    // Mode 2: a 16KB bank and two 8KB ones
+    mapper.write_expansion_area(0x5100, 2);
+    assert_eq!(prg_bank_offsets(&mapper), vec![4, 5, 7, 11]);
+    // Mode 3: four 8KB banks
+    mapper.write_expansion_area(0x5100, 3);
+    assert_eq!(prg_bank_offsets(&mapper), vec![1, 5, 7, 11]);
+    assert_eq!(mapper.mapReadAddressFromCPU(0xA123).unwrap(), 5 * 0x2000 + 0x0123);
+  }
+
+  #[test]
+  fn test_mmc5_chr_banking() {
+    // 128KB of CHR ROM, so 128 banks of 1KB
+    let mut mapper = Mapper005::new(16, 16);
+    for register in 0..4 {
+      mapper.write_expansion_area(0x5128 + register, 0x20 + register as u8);
+    }
+    for register in 0..8 {
+      mapper.write_expansion_area(0x5120 + register, 0x10 + register as u8);
+    }
+
+    // Set A was written last, so it's used for everything in 8x8 sprite mode
+    mapper.write_expansion_area(0x5101, 3);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0410).unwrap(), 0x11 * 0x400 + 0x10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1C10).unwrap(), 0x17 * 0x400 + 0x10);
+    // The bigger banks use the register of their last KB, in units of their own size
+    mapper.write_expansion_area(0x5101, 2);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0810).unwrap(), 0x13 * 0x800 + 0x10);
+    mapper.write_expansion_area(0x5101, 1);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), 0x17 * 0x1000 + 0x10);
+    mapper.write_expansion_area(0x5101, 0);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1010).unwrap(), (0x17 % 16) * 0x2000 + 0x1010);
+
+    // Set B covers 4KB, repeated in both pattern tables
+    mapper.write_expansion_area(0x5101, 3);
+    mapper.write_expansion_area(0x512B, 0x23);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0410).unwrap(), 0x21 * 0x400 + 0x10);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1410).unwrap(), 0x21 * 0x400 + 0x10);
+    mapper.write_expansion_area(0x5101, 2);
+    assert_eq!(mapper.mapReadAddressFromPPU(0x1810).unwrap(), 0x23 * 0x800 + 0x10);
+  }
+
+  // The fetches of a rendered scanline: the two dummy reads of the first tile's name table byte, which make MMC5
+  // detect the scanline along with the first real one, then 32 background tiles and 8 sprites.
+  // Returns the CHR offsets of the first background and sprite fetches.
+  fn fetch_mmc5_scanline(mapper: &mut Mapper005, pattern_addr: u16) -> (u32, u32) {
+    mapper.read_name_table(0x2000);
+    mapper.read_name_table(0x2000);
+    let mut background_offset = 0;
+    for tile in 0..32 {
+      mapper.read_name_table(0x2000 + tile);
+      mapper.read_name_table(0x23C0 + tile / 4);
+      let offset = mapper.mapReadAddressFromPPU(pattern_addr).unwrap();
+      mapper.mapReadAddressFromPPU(pattern_addr + 8).unwrap();
+      if (tile == 0) {
+        background_offset = offset;
+      }
+    }
+    let sprite_offset = mapper.mapReadAddressFromPPU(pattern_addr).unwrap();
+    for _ in 0..15 {
+      mapper.mapReadAddressFromPPU(pattern_addr).unwrap();
+    }
+    return (background_offset, sprite_offset);
+  }
+
+  #[test]
+  fn test_mmc5_8x16_sprites_use_their_own_chr_banks() {
+    let mut mapper = Mapper005::new(16, 16);
+    mapper.write_expansion_area(0x5101, 0);
+    mapper.write_expansion_area(0x5127, 1);
+    mapper.write_expansion_area(0x512B, 2);
+    // In 8x8 sprite mode, set B was written last, so both use it
+    assert_eq!(fetch_mmc5_scanline(&mut mapper, 0x0010), (2 * 0x2000 + 0x10, 2 * 0x2000 + 0x10));
+
+    mapper.ppu_control_written(0x20);
+    assert_eq!(fetch_mmc5_scanline(&mut mapper, 0x0010), (2 * 0x2000 + 0x10, 1 * 0x2000 + 0x10));
+    // Outside of the frame (e.g. for $2007 accesses), the set written last is used
+    mapper.mapReadAddressFromCPU(0xFFFA).unwrap();
+    assert_eq!(mapper.mapReadAddressFromPPU(0x0010).unwrap(), 2 * 0x2000 + 0x10);
+  }
+
+  #[test]
+  fn test_mmc5_scanline_irq() {
+    let mut mapper = Mapper005::new(16, 16);
+    mapper.write_expansion_area(0x5203, 3);
+    mapper.write_expansion_area(0x5204, 0x80);
+    assert_eq!(mapper.read_expansion_area(0x5204), 0x00);
+
+    let mut irq_scanlines = vec![];
+    for scanline in 0..10 {
+      fetch_mmc5_scanline(&mut mapper, 0x0000);
+      if mapper.irq_pending() {
+        irq_scanlines.push(scanline);
+        // Reading the status acknowledges the IRQ, but the frame goes on
+        assert_eq!(mapper.read_expansion_area(0x5204), 0xC0);
+        assert!(!mapper.irq_pending());
+      }
+    }
+    assert_eq!(irq_scanlines, vec![3]);
+    assert_eq!(mapper.read_expansion_area(0x5204), 0x40);
+
+    // The NMI vector fetch ends the frame, so the next scanline is 0 again
+    mapper.mapReadAddressFromCPU(0xFFFA).unwrap();
+    assert_eq!(mapper.read_expansion_area(0x5204), 0x00);
+    for _ in 0..4 {
+      fetch_mmc5_scanline(&mut mapper, 0x0000);
+    }
+    assert!(mapper.irq_pending());
+
+    // The multiplier is in the same register block
+    mapper.write_expansion_area(0x5205, 200);
+    mapper.write_expansion_area(0x5206, 100);
+    assert_eq!(mapper.read_expansion_area(0x5205), (20000 & 0xFF) as u8);
+    assert_eq!(mapper.read_expansion_area(0x5206), (20000 >> 8) as u8);
+  }
+}
@@ -1,44 +1,196 @@
-use crate::device::Device;
+use crate::device::{Device, DeviceError, private::Sealed};
+use rand::RngCore;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
-const RAM_SIZE: u16 = 2 * 1024;
-pub struct Ram2K {
-  pub memory: [u8; RAM_SIZE as usize],
-  pub memory_bounds: (u16, u16)
+// The console's internal RAM, which is mirrored 4 times over $0000-$1FFF
+pub const SYSTEM_RAM_SIZE: usize = 2 * 1024;
+pub type Ram2K = Ram<SYSTEM_RAM_SIZE>;
+
+// Used in tests in place of a cartridge, so programs can be written straight into $8000-$FFFF
+pub const CARTRIDGE_SPACE_SIZE: usize = 32 * 1024;
+pub type FlatRam = Ram<CARTRIDGE_SPACE_SIZE>;
+
+// The console's RAM isn't cleared on power up, and its contents depend on the chip.
+// Initializing it with something other than zeroes helps catch games that rely on specific power-on values.
+// Reference: https://www.nesdev.org/wiki/CPU_power_up_state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RamInitMode {
+  #[default]
+  Zero,
+  RandomPattern,
+  NesPattern,
+}
+
+// SIZE bytes of RAM mapped over memory_bounds. When the bounds are larger than the memory, mirrored RAM repeats
+// every SIZE bytes, and unmirrored RAM leaves the rest of the range unmapped.
+#[derive(Clone)]
+pub struct Ram<const SIZE: usize> {
+  // On the heap, since the console gets cloned and moved around and bigger RAMs would fill up the stack
+  memory: Vec<u8>,
+  pub memory_bounds: (u16, u16),
+  pub mirror: bool,
 }
 
-impl Ram2K {
-  pub fn new(memory_bounds: (u16, u16)) -> Ram2K {
-    return Ram2K {
-      memory: [0; 2* 1024],
-      memory_bounds
+impl<const SIZE: usize> Ram<SIZE> {
+  pub fn new(memory_bounds: (u16, u16)) -> Ram<SIZE> {
+    return Ram::with_init_mode(memory_bounds, RamInitMode::Zero);
+  }
+
+  pub fn unmirrored(memory_bounds: (u16, u16)) -> Ram<SIZE> {
+    let mut ram = Ram::new(memory_bounds);
+    ram.mirror = false;
+    return ram;
+  }
+
+  pub fn with_init_mode(memory_bounds: (u16, u16), init_mode: RamInitMode) -> Ram<SIZE> {
+    return Ram::with_init_mode_and_rng(memory_bounds, init_mode, &mut rand::thread_rng());
+  }
+
+  // Always fills RandomPattern RAM with the same bytes for the same seed, so tests can check what they get
+  #[cfg(test)]
+  pub fn with_seeded_init_mode(memory_bounds: (u16, u16), init_mode: RamInitMode, seed: u64) -> Ram<SIZE> {
+    use rand::{SeedableRng, rngs::StdRng};
+    return Ram::with_init_mode_and_rng(memory_bounds, init_mode, &mut StdRng::seed_from_u64(seed));
+  }
+
+  fn with_init_mode_and_rng(memory_bounds: (u16, u16), init_mode: RamInitMode, rng: &mut impl RngCore) -> Ram<SIZE> {
+    let mut memory = vec![0; SIZE];
+    match init_mode {
+      RamInitMode::Zero => {},
+      RamInitMode::RandomPattern => {
+        rng.fill_bytes(&mut memory);
+      },
+      RamInitMode::NesPattern => {
+        // Alternating blocks of 8 bytes of 0xFF and 8 bytes of 0x00
+        for (i, byte) in memory.iter_mut().enumerate() {
+          *byte = if (i / 8) % 2 == 0 { 0xFF } else { 0x00 };
+        }
+      }
     }
+    return Ram {
+      memory,
+      memory_bounds,
+      mirror: true,
+    }
+  }
+
+  // The whole memory, without going through the bus one byte at a time (e.g. for save states or searching RAM)
+  pub fn raw(&self) -> &[u8] {
+    return &self.memory;
+  }
+
+  fn index(&self, addr: u16) -> Option<usize> {
+    if !self.in_memory_bounds(addr) {
+      return None;
+    }
+    let offset = (addr - self.memory_bounds.0) as usize;
+    if self.mirror {
+      return Some(offset % SIZE);
+    }
+    if offset < SIZE {
+      return Some(offset);
+    }
+    return None;
   }
 }
 
-impl Device for Ram2K {
+impl<const SIZE: usize> Sealed for Ram<SIZE> {}
+
+impl<const SIZE: usize> Device for Ram<SIZE> {
+
+  fn clone_device(&self) -> Box<dyn Device + Send> {
+    return Box::new(self.clone());
+  }
 
   fn in_memory_bounds(&self, addr: u16)-> bool {
-    if addr >= self.memory_bounds.0 && addr <= self.memory_bounds.1 {
-      return true;
-    } else {
-      return false;
-    }
+    return addr >= self.memory_bounds.0 && addr <= self.memory_bounds.1;
   }
 
-  fn write(&mut self, addr: u16, content: u8) -> Result<(), String> {
-    if self.in_memory_bounds(addr) {
-      self.memory[(addr % RAM_SIZE) as usize] = content;
-      return Ok(());
-    } else {
-      return Err(String::from("Tried to write outside RAM bounds!"));
-    }
+  fn write(&mut self, addr: u16, content: u8) -> Result<(), DeviceError> {
+    let index = self.index(addr).ok_or(DeviceError::OutOfBounds)?;
+    self.memory[index] = content;
+    return Ok(());
+  }
+
+  fn read(&mut self, addr: u16) -> Result<u8, DeviceError> {
+    return self.read_debug(addr);
+  }
+
+  fn read_debug(&self, addr: u16) -> Result<u8, DeviceError> {
+    let index = self.index(addr).ok_or(DeviceError::OutOfBounds)?;
+    return Ok(self.memory[index]);
+  }
+}
+
+
+#[cfg(test)]
+mod ram_tests {
+  use super::{Ram, Ram2K, RamInitMode};
+  use crate::device::{Device, DeviceError};
+
+  #[test]
+  fn test_zero_init() {
+    let ram = Ram2K::new((0x0000, 0x1FFF));
+    assert!(ram.raw().iter().all(|byte| *byte == 0));
   }
 
-  fn read(&mut self, addr: u16) -> Result<u8, String> {
-    if self.in_memory_bounds(addr) {
-      return Ok(self.memory[(addr % RAM_SIZE) as usize]);
-    } else {
-      return Err(String::from("Tried to read outside RAM bounds!"));
+  #[test]
+  fn test_nes_pattern_init() {
+    let mut ram = Ram2K::with_init_mode((0x0000, 0x1FFF), RamInitMode::NesPattern);
+    assert_eq!(ram.read(0x0000).unwrap(), 0xFF);
+    assert_eq!(ram.read(0x0007).unwrap(), 0xFF);
+    assert_eq!(ram.read(0x0008).unwrap(), 0x00);
+    assert_eq!(ram.read(0x000F).unwrap(), 0x00);
+    assert_eq!(ram.read(0x0010).unwrap(), 0xFF);
+    // Mirrors see the same pattern
+    assert_eq!(ram.read(0x0808).unwrap(), 0x00);
+  }
+
+  #[test]
+  fn test_random_init() {
+    let ram = Ram2K::with_seeded_init_mode((0x0000, 0x1FFF), RamInitMode::RandomPattern, 42);
+    assert!(ram.raw().iter().any(|byte| *byte != 0));
+
+    let same_seed_ram = Ram2K::with_seeded_init_mode((0x0000, 0x1FFF), RamInitMode::RandomPattern, 42);
+    assert_eq!(ram.raw(), same_seed_ram.raw());
+    let other_seed_ram = Ram2K::with_seeded_init_mode((0x0000, 0x1FFF), RamInitMode::RandomPattern, 43);
+    assert_ne!(ram.raw(), other_seed_ram.raw());
+
+    // The seed only matters for random RAM
+    let zero_ram = Ram2K::with_seeded_init_mode((0x0000, 0x1FFF), RamInitMode::Zero, 42);
+    assert!(zero_ram.raw().iter().all(|byte| *byte == 0));
+  }
+
+  #[test]
+  fn test_system_ram_is_mirrored() {
+    let mut ram = Ram2K::new((0x0000, 0x1FFF));
+    ram.write(0x0005, 0x42).unwrap();
+    for mirror_addr in [0x0805, 0x1005, 0x1805] {
+      assert_eq!(ram.read(mirror_addr).unwrap(), 0x42);
     }
+    ram.write(0x1FFF, 0x24).unwrap();
+    assert_eq!(ram.read(0x07FF).unwrap(), 0x24);
+    assert_eq!(ram.raw().len(), 0x0800);
+    assert_eq!(ram.raw()[0x0005], 0x42);
+    assert!(ram.read(0x2000).is_err());
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_mirroring_is_relative_to_the_start_of_the_bounds() {
+    let mut ram: Ram<4> = Ram::new((0x6002, 0x600F));
+    ram.write(0x6002, 0x11).unwrap();
+    assert_eq!(ram.read(0x6006).unwrap(), 0x11);
+    assert_eq!(ram.raw(), &[0x11, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_unmirrored_ram_leaves_the_rest_of_the_bounds_unmapped() {
+    let mut ram: Ram<4> = Ram::unmirrored((0x6000, 0x600F));
+    ram.write(0x6003, 0x11).unwrap();
+    assert_eq!(ram.read(0x6003).unwrap(), 0x11);
+    assert_eq!(ram.write(0x6004, 0x22), Err(DeviceError::OutOfBounds));
+    assert_eq!(ram.read(0x6007), Err(DeviceError::OutOfBounds));
+  }
+}
@@ -0,0 +1,106 @@
+/*
+
+Dynamic rate control for the audio output.
+
+The emulation runs at (roughly) the NES frame rate, paced by the UI timer, while the sound device consumes samples
+at its own clock. Even a tiny mismatch between both adds up, slowly draining the audio ring buffer (underruns)
+or filling it up (latency growth, and eventually dropped samples).
+
+To avoid it, we measure the fill level of the ring buffer once per frame and nudge the APU resampling ratio by a
+fraction of a percent, producing slightly more samples when the buffer is below the target and slightly fewer when
+it's above. The adjustment is small enough that the change in pitch is not audible.
+
+Reference: "Dynamic Rate Control for Retro Game Emulators", Hans-Kristian Arntzen (2012)
+
+*/
+
+pub const DEFAULT_TARGET_FILL_LEVEL: f32 = 0.5;
+// Maximum deviation from the nominal rate, 0.5% as suggested in the reference
+pub const DEFAULT_MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+pub struct DynamicRateControl {
+  target_fill_level: f32,
+  max_rate_adjustment: f64,
+}
+
+impl DynamicRateControl {
+  pub fn new() -> DynamicRateControl {
+    return DynamicRateControl {
+      target_fill_level: DEFAULT_TARGET_FILL_LEVEL,
+      max_rate_adjustment: DEFAULT_MAX_RATE_ADJUSTMENT,
+    }
+  }
+
+  // Receives the current fill level of the ring buffer (0.0-1.0) and returns the rate adjustment for the resampler,
+  // where 0.01 means producing 1% more samples than the nominal rate
+  pub fn update(&mut self, fill_level: f32) -> f64 {
+    let error = ((self.target_fill_level - fill_level) / self.target_fill_level).clamp(-1.0, 1.0);
+    return error as f64 * self.max_rate_adjustment;
+  }
+}
+
+
+#[cfg(test)]
+mod rate_control_tests {
+  use crate::apu::Resampler;
+  use super::DynamicRateControl;
+
+  const INPUT_RATE: f64 = 441000.0;
+  const OUTPUT_RATE: f64 = 44100.0;
+  const FRAMES_PER_SECOND: f64 = 60.0;
+  const BUFFER_CAPACITY: f64 = 4096.0;
+
+  // Simulates an emulator feeding a ring buffer that is drained by a sound device running 0.2% faster than expected.
+  // Returns the fill level of the buffer after each frame.
+  fn simulate_mismatched_consumer(frames: u32, rate_control: Option<DynamicRateControl>) -> Vec<f32> {
+    let mut rate_control = rate_control;
+    let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+    let consumed_samples_per_frame = OUTPUT_RATE * 1.002 / FRAMES_PER_SECOND;
+    let input_samples_per_frame = (INPUT_RATE / FRAMES_PER_SECOND) as u32;
+
+    let mut buffered_samples = BUFFER_CAPACITY / 2.0;
+    let mut fill_levels = vec![];
+    for _ in 0..frames {
+      for _ in 0..input_samples_per_frame {
+        if resampler.push(0.0).is_some() {
+          buffered_samples += 1.0;
+        }
+      }
+      buffered_samples = (buffered_samples - consumed_samples_per_frame).clamp(0.0, BUFFER_CAPACITY);
+
+      let fill_level = (buffered_samples / BUFFER_CAPACITY) as f32;
+      if let Some(rate_control) = rate_control.as_mut() {
+        resampler.set_rate_adjustment(rate_control.update(fill_level));
+      }
+      fill_levels.push(fill_level);
+    }
+    return fill_levels;
+  }
+
+  #[test]
+  fn test_fill_level_drains_without_rate_control() {
+    let fill_levels = simulate_mismatched_consumer(2000, None);
+    assert_eq!(*fill_levels.last().unwrap(), 0.0);
+  }
+
+  #[test]
+  fn test_fill_level_converges_with_rate_control() {
+    let fill_levels = simulate_mismatched_consumer(2000, Some(DynamicRateControl::new()));
+    assert!(fill_levels.iter().all(|fill_level| *fill_level > 0.0));
+
+    // The controller is purely proportional, so it settles a bit below the target, where the adjustment
+    // cancels out the 0.2% mismatch: 0.5 * (1 - 0.2 / 0.5) = 0.3
+    let last_fill_levels = &fill_levels[1800..];
+    for fill_level in last_fill_levels {
+      assert!((fill_level - 0.3).abs() < 0.03, "Fill level {} didn't converge", fill_level);
+    }
+  }
+
+  #[test]
+  fn test_adjustment_is_bounded() {
+    let mut rate_control = DynamicRateControl::new();
+    assert_eq!(rate_control.update(0.5), 0.0);
+    assert_eq!(rate_control.update(0.0), super::DEFAULT_MAX_RATE_ADJUSTMENT);
+    assert_eq!(rate_control.update(1.0), -super::DEFAULT_MAX_RATE_ADJUSTMENT);
+  }
+}
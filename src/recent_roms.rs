@@ -0,0 +1,124 @@
+/*
+
+Keeps track of the most recently loaded ROMs, so that they can be reopened from the UI
+without browsing the filesystem again.
+
+The list is ordered most-recent-first, contains no duplicates, and is persisted as a JSON array of paths.
+Builds without the serde feature only remember it until the emulator exits.
+
+*/
+
+use std::{path::{Path, PathBuf}, fmt};
+#[cfg(feature = "serde")]
+use std::fs;
+
+pub const RECENT_ROMS_FILE_PATH: &str = "recent_roms.json";
+pub const MAX_RECENT_ROMS: usize = 10;
+
+pub struct RecentRoms {
+  file_path: PathBuf,
+  pub paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+  // A missing or unreadable history file just means we start with an empty list
+  pub fn load(file_path: &Path) -> RecentRoms {
+    #[cfg(feature = "serde")]
+    let paths = match fs::read_to_string(file_path) {
+      Ok(contents) => serde_json::from_str(&contents).unwrap_or(vec![]),
+      Err(_) => vec![],
+    };
+    #[cfg(not(feature = "serde"))]
+    let paths = vec![];
+    return RecentRoms {
+      file_path: file_path.to_path_buf(),
+      paths,
+    }
+  }
+
+  #[cfg(feature = "serde")]
+  pub fn save(&self) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(&self.paths).map_err(|e| e.to_string())?;
+    return fs::write(&self.file_path, contents).map_err(|e| format!("Error writing recent ROMs file: {}", e));
+  }
+
+  #[cfg(not(feature = "serde"))]
+  pub fn save(&self) -> Result<(), String> {
+    return Err(format!("Can't save {}, the emulator was built without the serde feature", self.file_path.display()));
+  }
+
+  pub fn add(&mut self, rom_path: &Path) {
+    self.paths.retain(|path| path != rom_path);
+    self.paths.insert(0, rom_path.to_path_buf());
+    self.paths.truncate(MAX_RECENT_ROMS);
+  }
+
+  pub fn entries(&self) -> Vec<RecentRomEntry> {
+    return self.paths.iter().map(|path| RecentRomEntry(path.clone())).collect();
+  }
+}
+
+// Entry of the recent ROMs dropdown, which only displays the file name of the ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentRomEntry(pub PathBuf);
+
+impl fmt::Display for RecentRomEntry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let file_name = match self.0.file_name() {
+      Some(name) => name.to_string_lossy().to_string(),
+      None => self.0.to_string_lossy().to_string(),
+    };
+    return write!(f, "{}", file_name);
+  }
+}
+
+
+#[cfg(test)]
+mod recent_roms_tests {
+  use std::path::{Path, PathBuf};
+  use super::{RecentRoms, RecentRomEntry, MAX_RECENT_ROMS};
+
+  fn empty_history() -> RecentRoms {
+    return RecentRoms { file_path: PathBuf::from("unused.json"), paths: vec![] };
+  }
+
+  #[test]
+  fn test_add_deduplicates_most_recent_first() {
+    let mut recent_roms = empty_history();
+    recent_roms.add(Path::new("roms/a.nes"));
+    recent_roms.add(Path::new("roms/b.nes"));
+    recent_roms.add(Path::new("roms/a.nes"));
+    assert_eq!(recent_roms.paths, vec![PathBuf::from("roms/a.nes"), PathBuf::from("roms/b.nes")]);
+  }
+
+  #[test]
+  fn test_add_keeps_at_most_max_entries() {
+    let mut recent_roms = empty_history();
+    for i in 0..(MAX_RECENT_ROMS + 5) {
+      recent_roms.add(&PathBuf::from(format!("game_{}.nes", i)));
+    }
+    assert_eq!(recent_roms.paths.len(), MAX_RECENT_ROMS);
+    assert_eq!(recent_roms.paths[0], PathBuf::from(format!("game_{}.nes", MAX_RECENT_ROMS + 4)));
+  }
+
+  #[test]
+  fn test_entry_displays_file_name_only() {
+    let entry = RecentRomEntry(PathBuf::from("some/long/directory/Super Mario Bros.nes"));
+    assert_eq!(entry.to_string(), "Super Mario Bros.nes");
+  }
+
+  #[test]
+  #[cfg(feature = "serde")]
+  fn test_save_and_load_round_trip() {
+    let file_path = std::env::temp_dir().join("rustness_recent_roms_test.json");
+    let mut recent_roms = RecentRoms::load(&file_path);
+    recent_roms.paths.clear();
+    recent_roms.add(Path::new("roms/a.nes"));
+    recent_roms.add(Path::new("roms/b.nes"));
+    recent_roms.save().unwrap();
+
+    let loaded = RecentRoms::load(&file_path);
+    assert_eq!(loaded.paths, recent_roms.paths);
+    std::fs::remove_file(file_path).unwrap();
+  }
+}
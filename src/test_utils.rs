@@ -0,0 +1,265 @@
+/*
+
+Helpers for writing unit tests.
+
+assemble() encodes 6502 instructions so test programs can be written with mnemonics instead of raw opcodes.
+It's not a full assembler: there are no labels, and operands are given as the bytes that follow the opcode.
+The addressing mode goes after the mnemonic, using the same notation as the disassembler:
+
+  ("LDA #", vec![0x42])         LDA #$42
+  ("LDA zp", vec![0x10])        LDA $10
+  ("LDA zp,X", vec![0x10])      LDA $10,X
+  ("LDA abs", vec![0x00, 0x80]) LDA $8000
+  ("LDA abs,Y", ...), ("JMP (abs)", ...), ("LDA (zp,X)", ...), ("LDA (zp),Y", ...), ("ASL A", vec![])
+
+Without a mode, it's guessed from the number of operand bytes: implied/accumulator for none, immediate (or relative,
+for branches) for one, and absolute for two. So ("LDA", vec![0x42]) is LDA #$42, but ("STA", vec![0x10]) is STA $10.
+
+NesRomBuilder puts together iNES files in memory, so that tests can run programs from a real cartridge without
+reading ROM files:
+
+  Cartridge::from_bytes(&NesRomBuilder::new().set_reset_vector(0x8000).write_prg(0, &[0xA9, 0x42]).build())
+
+*/
+
+use crate::ben6502::{AddressingMode, Instruction, INSTRUCTION_TABLE, bytes_required_for_address};
+
+fn parse_addressing_mode(mode: &str) -> Result<AddressingMode, String> {
+  return match mode {
+    "#" => Ok(AddressingMode::IMM),
+    "A" => Ok(AddressingMode::ACC),
+    "zp" => Ok(AddressingMode::ZP0),
+    "zp,X" => Ok(AddressingMode::ZPX),
+    "zp,Y" => Ok(AddressingMode::ZPY),
+    "abs" => Ok(AddressingMode::ABS),
+    "abs,X" => Ok(AddressingMode::ABX),
+    "abs,Y" => Ok(AddressingMode::ABY),
+    "(abs)" => Ok(AddressingMode::IND),
+    "(zp,X)" => Ok(AddressingMode::INX),
+    "(zp),Y" => Ok(AddressingMode::INY),
+    "rel" => Ok(AddressingMode::REL),
+    _ => Err(format!("Unknown addressing mode: {}", mode)),
+  };
+}
+
+// Modes that take the given amount of operand bytes, in the order they're tried when none is given
+fn default_addressing_modes(operand_bytes: usize) -> Vec<AddressingMode> {
+  return match operand_bytes {
+    0 => vec![AddressingMode::IMP, AddressingMode::ACC],
+    1 => vec![AddressingMode::IMM, AddressingMode::REL, AddressingMode::ZP0],
+    2 => vec![AddressingMode::ABS],
+    _ => vec![],
+  };
+}
+
+fn find_opcode(mnemonic: &str, addressing_mode: &AddressingMode) -> Option<u8> {
+  // The table also has unofficial NOPs at $DA and $FA, so the official one is tried first
+  return std::iter::once(0xEA).chain(0..=0xFF).find(|opcode| {
+    let instruction_data = &INSTRUCTION_TABLE[*opcode as usize];
+    return !matches!(instruction_data.instruction, Instruction::XXX) &&
+      format!("{:?}", instruction_data.instruction) == mnemonic &&
+      instruction_data.addressing_mode == *addressing_mode;
+  });
+}
+
+pub fn try_assemble(instructions: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, String> {
+  let mut result = vec![];
+  for (instruction, operands) in instructions {
+    let (mnemonic, mode) = match instruction.split_once(' ') {
+      Some((mnemonic, mode)) => (mnemonic, Some(mode.trim())),
+      None => (*instruction, None),
+    };
+    let candidate_modes = match mode {
+      Some(mode) => vec![parse_addressing_mode(mode)?],
+      None => default_addressing_modes(operands.len()),
+    };
+
+    let opcode = candidate_modes.iter()
+      .find_map(|addressing_mode| find_opcode(&mnemonic.to_uppercase(), addressing_mode).map(|opcode| (opcode, addressing_mode)));
+    let Some((opcode, addressing_mode)) = opcode else {
+      return Err(format!("No opcode for {} with {} operand byte(s)", instruction, operands.len()));
+    };
+    if bytes_required_for_address(addressing_mode) as usize != operands.len() {
+      return Err(format!("{} takes {} operand byte(s), got {}", instruction, bytes_required_for_address(addressing_mode), operands.len()));
+    }
+    result.push(opcode);
+    result.extend(operands);
+  }
+  return Ok(result);
+}
+
+// Panics on unknown instructions, which is what tests want anyway
+pub fn assemble(instructions: &[(&str, Vec<u8>)]) -> Vec<u8> {
+  return try_assemble(instructions).unwrap();
+}
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// Defaults to mapper 0 with 16KB of PRG ROM and 8KB of CHR ROM, all zeroes, and horizontal mirroring
+pub struct NesRomBuilder {
+  prg_banks: u8,
+  chr_banks: u8,
+  mapper: u8,
+  vertical_mirroring: bool,
+  prg_writes: Vec<(usize, Vec<u8>)>,
+  chr_writes: Vec<(usize, Vec<u8>)>,
+  reset_vector: Option<u16>,
+}
+
+impl NesRomBuilder {
+  pub fn new() -> NesRomBuilder {
+    return NesRomBuilder {
+      prg_banks: 1,
+      chr_banks: 1,
+      mapper: 0,
+      vertical_mirroring: false,
+      prg_writes: vec![],
+      chr_writes: vec![],
+      reset_vector: None,
+    };
+  }
+
+  // In 16KB banks
+  pub fn prg_size(mut self, banks: u8) -> NesRomBuilder {
+    self.prg_banks = banks;
+    return self;
+  }
+
+  // In 8KB banks. 0 means the cartridge has CHR RAM instead.
+  pub fn chr_size(mut self, banks: u8) -> NesRomBuilder {
+    self.chr_banks = banks;
+    return self;
+  }
+
+  pub fn mapper(mut self, mapper: u8) -> NesRomBuilder {
+    self.mapper = mapper;
+    return self;
+  }
+
+  pub fn mirroring_vertical(mut self) -> NesRomBuilder {
+    self.vertical_mirroring = true;
+    return self;
+  }
+
+  // The offset is into the whole PRG ROM, not into the CPU address space
+  pub fn write_prg(mut self, offset: usize, data: &[u8]) -> NesRomBuilder {
+    self.prg_writes.push((offset, data.to_vec()));
+    return self;
+  }
+
+  pub fn write_chr(mut self, offset: usize, data: &[u8]) -> NesRomBuilder {
+    self.chr_writes.push((offset, data.to_vec()));
+    return self;
+  }
+
+  // Fills every byte of the first count PRG banks of bank_size bytes with the bank's number, so reads show which bank
+  // is mapped in. bank_size is the mapper's bank size, not the 16KB of prg_size.
+  pub fn numbered_prg_banks(mut self, bank_size: usize, count: u8) -> NesRomBuilder {
+    for bank in 0..count {
+      self.prg_writes.push((bank as usize * bank_size, vec![bank; bank_size]));
+    }
+    return self;
+  }
+
+  // Same as numbered_prg_banks, for CHR ROM
+  pub fn numbered_chr_banks(mut self, bank_size: usize, count: u8) -> NesRomBuilder {
+    for bank in 0..count {
+      self.chr_writes.push((bank as usize * bank_size, vec![bank; bank_size]));
+    }
+    return self;
+  }
+
+  // Goes at the end of the last PRG bank, which is the one at $C000-$FFFF on power up for every supported mapper
+  pub fn set_reset_vector(mut self, addr: u16) -> NesRomBuilder {
+    self.reset_vector = Some(addr);
+    return self;
+  }
+
+  pub fn build(self) -> Vec<u8> {
+    let flags6 = ((self.mapper & 0x0F) << 4) | (self.vertical_mirroring as u8);
+    let flags7 = self.mapper & 0xF0;
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, self.prg_banks, self.chr_banks, flags6, flags7, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    let mut prg_rom = vec![0; self.prg_banks as usize * PRG_BANK_SIZE];
+    for (offset, data) in self.prg_writes.iter() {
+      prg_rom[*offset..*offset + data.len()].copy_from_slice(data);
+    }
+    if let Some(reset_vector) = self.reset_vector {
+      let vector_offset = prg_rom.len() - 4;
+      prg_rom[vector_offset..vector_offset + 2].copy_from_slice(&reset_vector.to_le_bytes());
+    }
+    let mut chr_rom = vec![0; self.chr_banks as usize * CHR_BANK_SIZE];
+    for (offset, data) in self.chr_writes.iter() {
+      chr_rom[*offset..*offset + data.len()].copy_from_slice(data);
+    }
+
+    rom.extend(prg_rom);
+    rom.extend(chr_rom);
+    return rom;
+  }
+}
+
+
+#[cfg(test)]
+mod test_utils_tests {
+  use super::{assemble, try_assemble, NesRomBuilder};
+  use crate::{cartridge::{Cartridge, MirroringMode}, bus::Bus16Bit, ben6502::Ben6502, device::Device};
+
+  #[test]
+  fn test_assemble_with_explicit_modes() {
+    let program = assemble(&[
+      ("LDA #", vec![0x42]),
+      ("STA zp", vec![0x00]),
+      ("LDX abs,Y", vec![0x00, 0x80]),
+      ("JMP (abs)", vec![0xFC, 0xFF]),
+      ("LDA (zp),Y", vec![0x10]),
+      ("ASL A", vec![]),
+      ("BRK", vec![]),
+    ]);
+    assert_eq!(program, vec![0xA9, 0x42, 0x85, 0x00, 0xBE, 0x00, 0x80, 0x6C, 0xFC, 0xFF, 0xB1, 0x10, 0x0A, 0x00]);
+  }
+
+  #[test]
+  fn test_assemble_guesses_modes_from_operands() {
+    let program = assemble(&[
+      ("LDA", vec![0x42]),
+      ("STA", vec![0x00]),
+      ("BNE", vec![0xFC]),
+      ("JMP", vec![0x00, 0x80]),
+      ("LSR", vec![]),
+      ("nop", vec![]),
+    ]);
+    assert_eq!(program, vec![0xA9, 0x42, 0x85, 0x00, 0xD0, 0xFC, 0x4C, 0x00, 0x80, 0x4A, 0xEA]);
+  }
+
+  #[test]
+  fn test_rom_builder() {
+    let rom = NesRomBuilder::new().mapper(0x42).mirroring_vertical().prg_size(2).chr_size(0).build();
+    assert_eq!(rom[..8], [b'N', b'E', b'S', 0x1A, 2, 0, 0x21, 0x40]);
+    assert_eq!(rom.len(), 16 + 2 * 0x4000);
+
+    let rom = NesRomBuilder::new()
+      .set_reset_vector(0x8000)
+      .write_prg(0, &assemble(&[("LDA #", vec![0x42]), ("STA zp", vec![0x10])]))
+      .write_chr(0x10, &[0xFF])
+      .build();
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.current_mirroring(), MirroringMode::Horizontal);
+    let mut cpu = Ben6502::new(Bus16Bit::with_cartridge(cartridge));
+    assert_eq!(cpu.registers.pc, 0x8000);
+    // The reset sequence, LDA and STA
+    for _ in 0..(8 + 2 + 3) {
+      cpu.clock_cycle().unwrap();
+    }
+    assert_eq!(cpu.bus.read_debug(0x0010), 0x42);
+    assert_eq!(cpu.bus.cartridge().read_debug(0x0010).unwrap(), 0xFF);
+  }
+
+  #[test]
+  fn test_assemble_rejects_bad_instructions() {
+    assert!(try_assemble(&[("FOO", vec![])]).is_err());
+    assert!(try_assemble(&[("STA #", vec![0x42])]).is_err());
+    assert!(try_assemble(&[("LDA abs", vec![0x42])]).is_err());
+  }
+}
@@ -0,0 +1,119 @@
+/*
+
+Watch expressions for the debug panels: named memory addresses whose values are shown in a chosen format, so the
+variables a game keeps (lives, timers, the player's position...) can be followed without digging through the
+memory visualizer.
+
+Expressions are typed as `[name] $address [format]`, like `lives $075A dec`. Without a name, the address is used.
+
+*/
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayFormat {
+  Hex,
+  Decimal,
+  Binary,
+  // Two's complement, for velocities and other values that can go below 0
+  Signed,
+}
+
+impl DisplayFormat {
+  pub const ALL: [DisplayFormat; 4] = [DisplayFormat::Hex, DisplayFormat::Decimal, DisplayFormat::Binary, DisplayFormat::Signed];
+
+  pub fn format_value(&self, value: u8) -> String {
+    return match self {
+      DisplayFormat::Hex => format!("${:02X}", value),
+      DisplayFormat::Decimal => value.to_string(),
+      DisplayFormat::Binary => format!("%{:08b}", value),
+      DisplayFormat::Signed => (value as i8).to_string(),
+    };
+  }
+
+  fn from_name(name: &str) -> Option<DisplayFormat> {
+    return DisplayFormat::ALL.into_iter().find(|format| format.to_string().eq_ignore_ascii_case(name));
+  }
+}
+
+// Also the names accepted when typing an expression
+impl fmt::Display for DisplayFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      DisplayFormat::Hex => "hex",
+      DisplayFormat::Decimal => "dec",
+      DisplayFormat::Binary => "bin",
+      DisplayFormat::Signed => "signed",
+    };
+    return write!(f, "{}", name);
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchExpression {
+  pub name: String,
+  pub address: u16,
+  pub format: DisplayFormat,
+}
+
+impl WatchExpression {
+  pub fn new(name: &str, address: u16, format: DisplayFormat) -> WatchExpression {
+    return WatchExpression { name: String::from(name), address, format };
+  }
+
+  pub fn parse(input: &str) -> Result<WatchExpression, String> {
+    let tokens: Vec<&str> = input.split_whitespace().collect();
+    let address_index = tokens.iter().position(|token| token.starts_with('$'))
+      .ok_or(format!("\"{}\" has no address (addresses start with $)", input.trim()))?;
+    let address = u16::from_str_radix(&tokens[address_index][1..], 16)
+      .map_err(|_| format!("{} isn't a valid address", tokens[address_index]))?;
+    let format = match &tokens[address_index + 1..] {
+      [] => DisplayFormat::Hex,
+      [format_name] => DisplayFormat::from_name(format_name)
+        .ok_or(format!("Unknown format {} (it can be hex, dec, bin or signed)", format_name))?,
+      _ => return Err(format!("Unexpected text after the format in \"{}\"", input.trim())),
+    };
+    let name = if address_index == 0 { format!("${:04X}", address) } else { tokens[..address_index].join(" ") };
+    return Ok(WatchExpression { name, address, format });
+  }
+
+  pub fn format_value(&self, value: u8) -> String {
+    return self.format.format_value(value);
+  }
+}
+
+// What the watch panel starts with. The CPU registers are already in the status panel.
+pub fn default_watch_expressions() -> Vec<WatchExpression> {
+  return vec![
+    WatchExpression::new("Zero page $00", 0x0000, DisplayFormat::Hex),
+    WatchExpression::new("PPU status", 0x2002, DisplayFormat::Binary),
+  ];
+}
+
+
+#[cfg(test)]
+mod watch_tests {
+  use super::{DisplayFormat, WatchExpression};
+
+  #[test]
+  fn test_format_value() {
+    assert_eq!(DisplayFormat::Hex.format_value(0xA5), "$A5");
+    assert_eq!(DisplayFormat::Decimal.format_value(0xA5), "165");
+    assert_eq!(DisplayFormat::Binary.format_value(0xA5), "%10100101");
+    assert_eq!(DisplayFormat::Signed.format_value(0xA5), "-91");
+    assert_eq!(DisplayFormat::Signed.format_value(0x7F), "127");
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(WatchExpression::parse("$075A"), Ok(WatchExpression::new("$075A", 0x075A, DisplayFormat::Hex)));
+    assert_eq!(WatchExpression::parse("lives $75a dec"), Ok(WatchExpression::new("lives", 0x075A, DisplayFormat::Decimal)));
+    assert_eq!(WatchExpression::parse("  player x speed $0057 SIGNED "), Ok(WatchExpression::new("player x speed", 0x0057, DisplayFormat::Signed)));
+
+    assert!(WatchExpression::parse("lives").is_err());
+    assert!(WatchExpression::parse("lives $10000").is_err());
+    assert!(WatchExpression::parse("lives $XY").is_err());
+    assert!(WatchExpression::parse("lives $075A octal").is_err());
+    assert!(WatchExpression::parse("lives $075A dec hex").is_err());
+  }
+}
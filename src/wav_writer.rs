@@ -0,0 +1,108 @@
+/*
+
+Minimal WAV writer for 16-bit mono PCM audio, used to record the APU output.
+
+The header is written up front with empty sizes, so the file is valid (if empty) from the very beginning,
+and the RIFF and data chunk sizes are fixed up when the recording is finished.
+
+Reference: http://soundfile.sapp.org/doc/WaveFormat/
+
+*/
+
+use std::{fs::File, io::{BufWriter, Seek, SeekFrom, Write}, path::Path};
+
+pub const WAV_HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+const NUM_CHANNELS: u16 = 1;
+
+pub struct WavWriter {
+  writer: BufWriter<File>,
+  samples_written: u32,
+}
+
+impl WavWriter {
+  pub fn create(path: &Path, sample_rate: u32) -> Result<WavWriter, String> {
+    let file = File::create(path).map_err(|e| format!("Error creating WAV file: {}", e))?;
+    let mut wav_writer = WavWriter {
+      writer: BufWriter::new(file),
+      samples_written: 0,
+    };
+    wav_writer.write_header(sample_rate).map_err(|e| format!("Error writing WAV header: {}", e))?;
+    return Ok(wav_writer);
+  }
+
+  fn write_header(&mut self, sample_rate: u32) -> std::io::Result<()> {
+    let block_align = NUM_CHANNELS * BITS_PER_SAMPLE / 8;
+    self.writer.write_all(b"RIFF")?;
+    self.writer.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+    self.writer.write_all(b"WAVE")?;
+
+    self.writer.write_all(b"fmt ")?;
+    self.writer.write_all(&16u32.to_le_bytes())?; // Size of the rest of the fmt chunk
+    self.writer.write_all(&1u16.to_le_bytes())?; // PCM
+    self.writer.write_all(&NUM_CHANNELS.to_le_bytes())?;
+    self.writer.write_all(&sample_rate.to_le_bytes())?;
+    self.writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?; // Byte rate
+    self.writer.write_all(&block_align.to_le_bytes())?;
+    self.writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    self.writer.write_all(b"data")?;
+    self.writer.write_all(&0u32.to_le_bytes())?;
+    return Ok(());
+  }
+
+  // Receives samples in the range -1.0 to 1.0, anything outside of it is clipped
+  pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), String> {
+    for sample in samples {
+      let pcm_sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+      self.writer.write_all(&pcm_sample.to_le_bytes()).map_err(|e| format!("Error writing WAV samples: {}", e))?;
+    }
+    self.samples_written += samples.len() as u32;
+    return Ok(());
+  }
+
+  // Fixes up the chunk sizes in the header and flushes everything to disk
+  pub fn finish(mut self) -> Result<(), String> {
+    let data_size = self.samples_written * (BITS_PER_SAMPLE / 8) as u32;
+    let fixup = |writer: &mut BufWriter<File>| -> std::io::Result<()> {
+      writer.seek(SeekFrom::Start(4))?;
+      writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+      writer.seek(SeekFrom::Start(40))?;
+      writer.write_all(&data_size.to_le_bytes())?;
+      return writer.flush();
+    };
+    return fixup(&mut self.writer).map_err(|e| format!("Error finishing WAV file: {}", e));
+  }
+}
+
+
+#[cfg(test)]
+mod wav_writer_tests {
+  use super::{WavWriter, WAV_HEADER_SIZE};
+
+  #[test]
+  fn test_empty_recording_is_valid() {
+    let file_path = std::env::temp_dir().join("rustness_empty_recording_test.wav");
+    WavWriter::create(&file_path, 44100).unwrap().finish().unwrap();
+
+    let contents = std::fs::read(&file_path).unwrap();
+    assert_eq!(contents.len() as u32, WAV_HEADER_SIZE);
+    assert_eq!(&contents[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(contents[4..8].try_into().unwrap()), 36);
+    assert_eq!(u32::from_le_bytes(contents[40..44].try_into().unwrap()), 0);
+    std::fs::remove_file(file_path).unwrap();
+  }
+
+  #[test]
+  fn test_samples_are_clipped_16_bit_pcm() {
+    let file_path = std::env::temp_dir().join("rustness_clipped_recording_test.wav");
+    let mut wav_writer = WavWriter::create(&file_path, 44100).unwrap();
+    wav_writer.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+    wav_writer.finish().unwrap();
+
+    let contents = std::fs::read(&file_path).unwrap();
+    let data: Vec<i16> = contents[44..].chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+    assert_eq!(data, vec![0, i16::MAX, -i16::MAX, i16::MAX]);
+    std::fs::remove_file(file_path).unwrap();
+  }
+}